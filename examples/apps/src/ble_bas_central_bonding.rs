@@ -94,15 +94,15 @@ async fn load_bonding_info<S: NorFlash>(storage: &mut S) -> Option<BondInformati
     let mut cache = NoCache::new();
     let mut iter = sequential_storage::map::fetch_all_items::<StoredAddr, _, _>(storage, flash_range::<S>(), &mut cache, &mut buffer).await.ok()?;
     while let Some((key, value)) = iter.next::<StoredBondInformation>(&mut buffer).await.ok()? {
-        return Some(BondInformation {
-            identity: Identity {
+        return Some(BondInformation::new(
+            Identity {
                 bd_addr: key.0,
                 irk: None,
             },
-            security_level: value.security_level,
-            is_bonded: true,
-            ltk: value.ltk
-        });
+            value.ltk,
+            value.security_level,
+            true,
+        ));
     }
     None
 }
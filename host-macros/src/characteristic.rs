@@ -48,6 +48,26 @@ pub struct AccessArgs {
     pub indicate: bool,
 }
 
+/// Security requirements of a characteristic value.
+#[derive(Debug, Default)]
+pub struct PermissionArgs {
+    /// Security level required to read the value, if not open.
+    pub read: Option<TokenStream>,
+    /// Security level required to write the value, if not open.
+    pub write: Option<TokenStream>,
+    /// If true, reads must be authorized by the application.
+    pub read_authorization: bool,
+    /// If true, writes must be authorized by the application.
+    pub write_authorization: bool,
+}
+
+impl PermissionArgs {
+    /// Returns true if any security requirement has been specified.
+    pub fn is_set(&self) -> bool {
+        self.read.is_some() || self.write.is_some() || self.read_authorization || self.write_authorization
+    }
+}
+
 /// Descriptor attribute arguments.
 ///
 /// Descriptors are optional and can be used to add additional metadata to the characteristic.
@@ -79,6 +99,7 @@ pub struct CharacteristicArgs {
     /// Any '///' comments on each field, parsed in super::check_for_characteristic.
     pub doc_string: String,
     pub access: AccessArgs,
+    pub permissions: PermissionArgs,
 }
 
 /// Check if this bool type has been specified more than once.
@@ -115,6 +136,27 @@ pub fn parse_uuid(meta: &ParseNestedMeta<'_>) -> Result<TokenStream> {
     }
 }
 
+/// Parse a security level, i.e. read_security = "encrypted"
+fn parse_security(meta: &ParseNestedMeta<'_>, name: &str) -> Result<TokenStream> {
+    let value: LitStr = meta.value().and_then(|value| value.parse()).map_err(|_| {
+        meta.error(format!(
+            "'{name}' must be followed by '= [level]'.  i.e. {name} = \"encrypted\""
+        ))
+    })?;
+    let level = match value.value().as_str() {
+        "open" => quote::quote!(Open),
+        "encrypted" => quote::quote!(Encrypted),
+        "authenticated" => quote::quote!(Authenticated),
+        "secure_connections" => quote::quote!(SecureConnections),
+        other => {
+            return Err(meta.error(format!(
+                "Invalid security level: '{other}'.\nSupported levels are: open, encrypted, authenticated, secure_connections"
+            )))
+        }
+    };
+    Ok(quote::quote!(trouble_host::attribute::PermissionLevel::#level))
+}
+
 impl CharacteristicArgs {
     /// Parse the arguments of a characteristic attribute
     pub fn parse(attribute: &syn::Attribute) -> Result<Self> {
@@ -125,6 +167,10 @@ impl CharacteristicArgs {
         let mut indicate: Option<bool> = None;
         let mut default_value: Option<syn::Expr> = None;
         let mut write_without_response: Option<bool> = None;
+        let mut read_security: Option<TokenStream> = None;
+        let mut write_security: Option<TokenStream> = None;
+        let mut read_authorization: Option<bool> = None;
+        let mut write_authorization: Option<bool> = None;
         attribute.parse_nested_meta(|meta| {
             match meta.path.get_ident().ok_or(meta.error("no ident"))?.to_string().as_str() {
                 "uuid" => check_multi(&mut uuid, "uuid", &meta, parse_uuid(&meta)?)?,
@@ -133,6 +179,10 @@ impl CharacteristicArgs {
                 "notify" => check_multi(&mut notify, "notify", &meta, true)?,
                 "indicate" => check_multi(&mut indicate, "indicate", &meta, true)?,
                 "write_without_response" => check_multi(&mut write_without_response, "write_without_response", &meta, true)?,
+                "read_security" => check_multi(&mut read_security, "read_security", &meta, parse_security(&meta, "read_security")?)?,
+                "write_security" => check_multi(&mut write_security, "write_security", &meta, parse_security(&meta, "write_security")?)?,
+                "read_authorization" => check_multi(&mut read_authorization, "read_authorization", &meta, true)?,
+                "write_authorization" => check_multi(&mut write_authorization, "write_authorization", &meta, true)?,
                 "value" => {
                     let value = meta
                         .value()
@@ -144,7 +194,7 @@ impl CharacteristicArgs {
                 other => return Err(
                     meta.error(
                         format!(
-                            "Unsupported characteristic property: '{other}'.\nSupported properties are:\nuuid, read, write, write_without_response, notify, indicate, value\n"
+                            "Unsupported characteristic property: '{other}'.\nSupported properties are:\nuuid, read, write, write_without_response, notify, indicate, value,\nread_security, write_security, read_authorization, write_authorization\n"
                         ))),
             };
             Ok(())
@@ -161,6 +211,12 @@ impl CharacteristicArgs {
                 write: write.unwrap_or_default(),
                 read: read.unwrap_or_default(),
             },
+            permissions: PermissionArgs {
+                read: read_security,
+                write: write_security,
+                read_authorization: read_authorization.unwrap_or_default(),
+                write_authorization: write_authorization.unwrap_or_default(),
+            },
         })
     }
 }
@@ -138,7 +138,7 @@ impl ServerBuilder {
         let cccd_table_size = if let Some(value) = self.arguments.cccd_table_size {
             value
         } else {
-            parse_quote!(0 #code_cccd_summation)
+            parse_quote!(trouble_host::gap::GAP_SERVICE_CCCD_COUNT #code_cccd_summation)
         };
 
         let connections_max = if let Some(value) = self.arguments.connections_max {
@@ -160,6 +160,28 @@ impl ServiceBuilder {
             Some(val) => quote!(#val),                                       // if set by user
             None => quote_spanned!(characteristic.span => <#ty>::default()), // or default otherwise
         };
+        let permissions = &characteristic.args.permissions;
+        let code_permissions = if permissions.is_set() {
+            let level = |level: &Option<TokenStream2>| {
+                level
+                    .clone()
+                    .unwrap_or(quote!(trouble_host::attribute::PermissionLevel::Open))
+            };
+            let read = level(&permissions.read);
+            let write = level(&permissions.write);
+            let read_authorization = permissions.read_authorization;
+            let write_authorization = permissions.write_authorization;
+            quote! {
+                builder.set_permissions(trouble_host::attribute::AttributePermissions {
+                    read: #read,
+                    write: #write,
+                    read_authorization: #read_authorization,
+                    write_authorization: #write_authorization,
+                });
+            }
+        } else {
+            TokenStream2::new()
+        };
 
         self.code_build_chars.extend(quote_spanned! {characteristic.span=>
             let (#char_name, #(#named_descriptors),*) = {
@@ -167,6 +189,7 @@ impl ServiceBuilder {
                 let store = #name_screaming.init([0; <#ty as trouble_host::types::gatt_traits::AsGatt>::MAX_SIZE]);
                 let mut builder = service
                     .add_characteristic(#uuid, &[#(#properties),*], #default_value, store);
+                #code_permissions
                 #code_descriptors
 
                 (builder.build(), #(#named_descriptors),*)
@@ -13,6 +13,8 @@ static CONFIGS: &[(&str, usize)] = &[
     ("DEFAULT_PACKET_POOL_MTU", 251),
     ("GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS", 1),
     ("GATT_CLIENT_NOTIFICATION_QUEUE_SIZE", 1),
+    ("GATT_PREPARE_WRITE_QUEUE_SIZE", 512),
+    ("GATT_CLIENT_CACHE_SIZE", 16),
     // END AUTOGENERATED CONFIG FEATURES
 ];
 
@@ -58,6 +58,12 @@ feature("gatt_client_notification_max_subscribers",
 feature("gatt_client_notification_queue_size",
         "When using the GATT client, this controls how many notifications can be queued for each subscriber.",
         default=1, min=1, max=512, pow2=True)
+feature("gatt_prepare_write_queue_size",
+        "Controls the size in bytes of the per-connection queue of prepared writes in the GATT server.",
+        default=512, vals=[0, 64, 128, 256, 512, 1024, 2048, 4096])
+feature("gatt_client_cache_size",
+        "When using the GATT client, this controls how many characteristics can be kept in the discovered database cache.",
+        default=16, min=0, max=256, pow2=True)
 
 # ========= Update Cargo.toml
 
@@ -15,6 +15,7 @@ pub(crate) const ATT_READ_REQ: u8 = 0x0a;
 pub(crate) const ATT_READ_RSP: u8 = 0x0b;
 pub(crate) const ATT_WRITE_REQ: u8 = 0x12;
 pub(crate) const ATT_WRITE_CMD: u8 = 0x52;
+pub(crate) const ATT_SIGNED_WRITE_CMD: u8 = 0xd2;
 pub(crate) const ATT_WRITE_RSP: u8 = 0x13;
 pub(crate) const ATT_EXCHANGE_MTU_REQ: u8 = 0x02;
 pub(crate) const ATT_EXCHANGE_MTU_RSP: u8 = 0x03;
@@ -26,13 +27,16 @@ pub(crate) const ATT_PREPARE_WRITE_REQ: u8 = 0x16;
 pub(crate) const ATT_PREPARE_WRITE_RSP: u8 = 0x17;
 pub(crate) const ATT_EXECUTE_WRITE_REQ: u8 = 0x18;
 pub(crate) const ATT_EXECUTE_WRITE_RSP: u8 = 0x19;
-pub(crate) const ATT_READ_MULTIPLE_REQ: u8 = 0x20;
-pub(crate) const ATT_READ_MULTIPLE_RSP: u8 = 0x21;
+pub(crate) const ATT_READ_MULTIPLE_REQ: u8 = 0x0e;
+pub(crate) const ATT_READ_MULTIPLE_RSP: u8 = 0x0f;
+pub(crate) const ATT_READ_MULTIPLE_VARIABLE_REQ: u8 = 0x20;
+pub(crate) const ATT_READ_MULTIPLE_VARIABLE_RSP: u8 = 0x21;
 pub(crate) const ATT_READ_BLOB_REQ: u8 = 0x0c;
 pub(crate) const ATT_READ_BLOB_RSP: u8 = 0x0d;
 pub(crate) const ATT_HANDLE_VALUE_NTF: u8 = 0x1b;
 pub(crate) const ATT_HANDLE_VALUE_IND: u8 = 0x1d;
 pub(crate) const ATT_HANDLE_VALUE_CFM: u8 = 0x1e;
+pub(crate) const ATT_MULTIPLE_HANDLE_VALUE_NTF: u8 = 0x23;
 
 /// Attribute Error Code
 ///
@@ -174,7 +178,7 @@ pub enum AttClient<'d> {
 
 /// ATT Request PDU
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AttReq<'d> {
     /// Read By Group Type Request
     ReadByGroupType {
@@ -248,6 +252,11 @@ pub enum AttReq<'d> {
         /// Attribute handles
         handles: &'d [u8],
     },
+    /// Read Multiple Variable Length Request
+    ReadMultipleVariable {
+        /// Attribute handles
+        handles: &'d [u8],
+    },
     /// Read Blob Request
     ReadBlob {
         /// Attribute handle
@@ -268,6 +277,15 @@ pub enum AttCmd<'d> {
         /// Attribute value
         data: &'d [u8],
     },
+    /// Signed Write Command
+    SignedWrite {
+        /// Attribute handle
+        handle: u16,
+        /// Attribute value
+        data: &'d [u8],
+        /// Authentication signature, the sign counter followed by the MAC
+        signature: [u8; 12],
+    },
 }
 
 /// ATT Confirmation PDU
@@ -307,6 +325,11 @@ pub enum AttRsp<'d> {
         /// Iterator over the found handles and UUIDs
         it: FindInformationIter<'d>,
     },
+    /// Read By Group Type Response
+    ReadByGroupType {
+        /// Iterator over the found groups
+        it: ReadByGroupTypeIter<'d>,
+    },
     /// Error Response
     Error {
         /// Request opcode
@@ -331,6 +354,27 @@ pub enum AttRsp<'d> {
         /// Attribute value part
         data: &'d [u8],
     },
+    /// Read Multiple Response
+    ReadMultiple {
+        /// Concatenated attribute values
+        data: &'d [u8],
+    },
+    /// Prepare Write Response
+    PrepareWrite {
+        /// Attribute handle
+        handle: u16,
+        /// Attribute offset
+        offset: u16,
+        /// Attribute value
+        value: &'d [u8],
+    },
+    /// Execute Write Response
+    ExecuteWrite,
+    /// Read Multiple Variable Length Response
+    ReadMultipleVariable {
+        /// Iterator over the length prefixed attribute values
+        it: ReadMultipleVariableIter<'d>,
+    },
     /// Write Response
     Write,
 }
@@ -353,6 +397,11 @@ pub enum AttUns<'d> {
         /// Attribute value
         data: &'d [u8],
     },
+    /// Multiple Handle Value Notification
+    MultipleNotify {
+        /// Iterator over the notified attribute handles and values
+        it: MultipleHandleValueIter<'d>,
+    },
 }
 
 /// ATT Protocol Data Unit (PDU)
@@ -418,6 +467,90 @@ impl<'d> ReadByTypeIter<'d> {
     }
 }
 
+/// An Iterator-like type for iterating over the found attribute groups
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Debug)]
+pub struct ReadByGroupTypeIter<'d> {
+    item_len: usize,
+    cursor: ReadCursor<'d>,
+}
+
+impl<'d> ReadByGroupTypeIter<'d> {
+    /// Get the next attribute handle, end group handle and attribute value
+    #[allow(clippy::should_implement_trait, clippy::type_complexity)]
+    pub fn next(&mut self) -> Option<Result<(u16, u16, &'d [u8]), crate::Error>> {
+        if self.item_len >= 4 && self.cursor.available() >= self.item_len {
+            let res = (|| {
+                let handle: u16 = self.cursor.read()?;
+                let end: u16 = self.cursor.read()?;
+                let item = self.cursor.slice(self.item_len - 4)?;
+                Ok((handle, end, item))
+            })();
+            Some(res)
+        } else {
+            None
+        }
+    }
+}
+
+/// An Iterator-like type for iterating over the values in a Read Multiple Variable Length Response
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Debug)]
+pub struct ReadMultipleVariableIter<'d> {
+    cursor: ReadCursor<'d>,
+}
+
+impl<'d> ReadMultipleVariableIter<'d> {
+    /// Get the next attribute value
+    ///
+    /// The last value may be truncated if the response did not fit in the ATT MTU.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<&'d [u8], crate::Error>> {
+        if self.cursor.available() >= 2 {
+            let res = (|| {
+                let len: u16 = self.cursor.read()?;
+                let len = (len as usize).min(self.cursor.available());
+                Ok(self.cursor.slice(len)?)
+            })();
+            Some(res)
+        } else {
+            None
+        }
+    }
+}
+
+/// An Iterator-like type for iterating over the values in a Multiple Handle Value Notification
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Debug)]
+pub struct MultipleHandleValueIter<'d> {
+    cursor: ReadCursor<'d>,
+}
+
+impl<'d> MultipleHandleValueIter<'d> {
+    /// Iterate over handle, length and value tuples.
+    pub(crate) fn new(data: &'d [u8]) -> Self {
+        Self {
+            cursor: ReadCursor::new(data),
+        }
+    }
+
+    /// Get the next pair of attribute handle and attribute value
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<(u16, &'d [u8]), crate::Error>> {
+        if self.cursor.available() >= 4 {
+            let res = (|| {
+                let handle: u16 = self.cursor.read()?;
+                let len: u16 = self.cursor.read()?;
+                let value = self.cursor.slice(len as usize)?;
+                Ok((handle, value))
+            })();
+            Some(res)
+        } else {
+            None
+        }
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug, Copy, Clone)]
 enum FindInformationUuidFormat {
@@ -487,7 +620,9 @@ impl<'d> AttServer<'d> {
 
     fn decode_with_opcode(opcode: u8, r: ReadCursor<'d>) -> Result<Self, codec::Error> {
         let decoded = match opcode {
-            ATT_HANDLE_VALUE_NTF | ATT_HANDLE_VALUE_IND => Self::Unsolicited(AttUns::decode_with_opcode(opcode, r)?),
+            ATT_HANDLE_VALUE_NTF | ATT_HANDLE_VALUE_IND | ATT_MULTIPLE_HANDLE_VALUE_NTF => {
+                Self::Unsolicited(AttUns::decode_with_opcode(opcode, r)?)
+            }
             _ => Self::Response(AttRsp::decode_with_opcode(opcode, r)?),
         };
         Ok(decoded)
@@ -503,7 +638,12 @@ impl<'d> AttRsp<'d> {
             Self::Error { .. } => 4,
             Self::Read { data } => data.len(),
             Self::ReadBlob { data } => data.len(),
+            Self::ReadMultiple { data } => data.len(),
+            Self::ReadMultipleVariable { it } => it.cursor.available(),
+            Self::PrepareWrite { value, .. } => 4 + value.len(),
+            Self::ExecuteWrite => 0,
             Self::ReadByType { it } => it.cursor.len(),
+            Self::ReadByGroupType { it } => 1 + it.cursor.available(),
             Self::Write => 0,
         }
     }
@@ -547,6 +687,16 @@ impl<'d> AttRsp<'d> {
                     w.append(item)?;
                 }
             }
+            Self::ReadByGroupType { it } => {
+                w.write(ATT_READ_BY_GROUP_TYPE_RSP)?;
+                w.write(it.item_len as u8)?;
+                let mut it = it.clone();
+                while let Some(Ok((handle, end, item))) = it.next() {
+                    w.write(handle)?;
+                    w.write(end)?;
+                    w.append(item)?;
+                }
+            }
             Self::Read { data } => {
                 w.write(ATT_READ_RSP)?;
                 w.append(data)?;
@@ -555,6 +705,27 @@ impl<'d> AttRsp<'d> {
                 w.write(ATT_READ_BLOB_RSP)?;
                 w.append(data)?;
             }
+            Self::ReadMultiple { data } => {
+                w.write(ATT_READ_MULTIPLE_RSP)?;
+                w.append(data)?;
+            }
+            Self::ReadMultipleVariable { it } => {
+                w.write(ATT_READ_MULTIPLE_VARIABLE_RSP)?;
+                let mut it = it.clone();
+                while let Some(Ok(value)) = it.next() {
+                    w.write(value.len() as u16)?;
+                    w.append(value)?;
+                }
+            }
+            Self::PrepareWrite { handle, offset, value } => {
+                w.write(ATT_PREPARE_WRITE_RSP)?;
+                w.write(*handle)?;
+                w.write(*offset)?;
+                w.append(value)?;
+            }
+            Self::ExecuteWrite => {
+                w.write(ATT_EXECUTE_WRITE_RSP)?;
+            }
             Self::Write => {
                 w.write(ATT_WRITE_RSP)?;
             }
@@ -585,6 +756,20 @@ impl<'d> AttRsp<'d> {
             }
             ATT_READ_RSP => Ok(Self::Read { data: r.remaining() }),
             ATT_READ_BLOB_RSP => Ok(Self::ReadBlob { data: r.remaining() }),
+            ATT_READ_MULTIPLE_RSP => Ok(Self::ReadMultiple { data: r.remaining() }),
+            ATT_PREPARE_WRITE_RSP => {
+                let handle = r.read()?;
+                let offset = r.read()?;
+                Ok(Self::PrepareWrite {
+                    handle,
+                    offset,
+                    value: r.remaining(),
+                })
+            }
+            ATT_EXECUTE_WRITE_RSP => Ok(Self::ExecuteWrite),
+            ATT_READ_MULTIPLE_VARIABLE_RSP => Ok(Self::ReadMultipleVariable {
+                it: ReadMultipleVariableIter { cursor: r },
+            }),
             ATT_READ_BY_TYPE_RSP => {
                 let item_len: u8 = r.read()?;
                 Ok(Self::ReadByType {
@@ -594,6 +779,15 @@ impl<'d> AttRsp<'d> {
                     },
                 })
             }
+            ATT_READ_BY_GROUP_TYPE_RSP => {
+                let item_len: u8 = r.read()?;
+                Ok(Self::ReadByGroupType {
+                    it: ReadByGroupTypeIter {
+                        item_len: item_len as usize,
+                        cursor: r,
+                    },
+                })
+            }
             ATT_WRITE_RSP => Ok(Self::Write),
             _ => Err(codec::Error::InvalidValue),
         }
@@ -605,6 +799,7 @@ impl<'d> AttUns<'d> {
         1 + match self {
             Self::Notify { data, .. } => 2 + data.len(),
             Self::Indicate { data, .. } => 2 + data.len(),
+            Self::MultipleNotify { it } => it.cursor.available(),
         }
     }
 
@@ -621,6 +816,15 @@ impl<'d> AttUns<'d> {
                 w.write(*handle)?;
                 w.append(data)?;
             }
+            Self::MultipleNotify { it } => {
+                w.write(ATT_MULTIPLE_HANDLE_VALUE_NTF)?;
+                let mut it = it.clone();
+                while let Some(Ok((handle, value))) = it.next() {
+                    w.write(handle)?;
+                    w.write(value.len() as u16)?;
+                    w.append(value)?;
+                }
+            }
         }
         Ok(())
     }
@@ -641,6 +845,9 @@ impl<'d> AttUns<'d> {
                     data: r.remaining(),
                 })
             }
+            ATT_MULTIPLE_HANDLE_VALUE_NTF => Ok(Self::MultipleNotify {
+                it: MultipleHandleValueIter { cursor: r },
+            }),
             _ => Err(codec::Error::InvalidValue),
         }
     }
@@ -665,7 +872,7 @@ impl<'d> AttClient<'d> {
 
     fn decode_with_opcode(opcode: u8, r: ReadCursor<'d>) -> Result<Self, codec::Error> {
         let decoded = match opcode {
-            ATT_WRITE_CMD => Self::Command(AttCmd::decode_with_opcode(opcode, r)?),
+            ATT_WRITE_CMD | ATT_SIGNED_WRITE_CMD => Self::Command(AttCmd::decode_with_opcode(opcode, r)?),
             ATT_HANDLE_VALUE_CFM => Self::Confirmation(AttCfm::decode_with_opcode(opcode, r)?),
             _ => Self::Request(AttReq::decode_with_opcode(opcode, r)?),
         };
@@ -674,6 +881,24 @@ impl<'d> AttClient<'d> {
 }
 
 impl<'d> AttReq<'d> {
+    /// Opcode of the request.
+    pub(crate) fn opcode(&self) -> u8 {
+        match self {
+            Self::ExchangeMtu { .. } => ATT_EXCHANGE_MTU_REQ,
+            Self::FindByTypeValue { .. } => ATT_FIND_BY_TYPE_VALUE_REQ,
+            Self::FindInformation { .. } => ATT_FIND_INFORMATION_REQ,
+            Self::ReadByType { .. } => ATT_READ_BY_TYPE_REQ,
+            Self::ReadByGroupType { .. } => ATT_READ_BY_GROUP_TYPE_REQ,
+            Self::Read { .. } => ATT_READ_REQ,
+            Self::ReadBlob { .. } => ATT_READ_BLOB_REQ,
+            Self::ReadMultiple { .. } => ATT_READ_MULTIPLE_REQ,
+            Self::ReadMultipleVariable { .. } => ATT_READ_MULTIPLE_VARIABLE_REQ,
+            Self::PrepareWrite { .. } => ATT_PREPARE_WRITE_REQ,
+            Self::ExecuteWrite { .. } => ATT_EXECUTE_WRITE_REQ,
+            Self::Write { .. } => ATT_WRITE_REQ,
+        }
+    }
+
     fn size(&self) -> usize {
         1 + match self {
             Self::ExchangeMtu { .. } => 2,
@@ -692,10 +917,14 @@ impl<'d> AttReq<'d> {
                 end,
                 attribute_type,
             } => 4 + attribute_type.as_raw().len(),
+            Self::ReadByGroupType { group_type, .. } => 4 + group_type.as_raw().len(),
             Self::Read { .. } => 2,
             Self::ReadBlob { .. } => 4, // handle (2 bytes) + offset (2 bytes)
+            Self::ReadMultiple { handles } => handles.len(),
+            Self::ReadMultipleVariable { handles } => handles.len(),
             Self::Write { handle, data } => 2 + data.len(),
-            _ => unimplemented!(),
+            Self::PrepareWrite { value, .. } => 4 + value.len(),
+            Self::ExecuteWrite { .. } => 1,
         }
     }
     fn encode(&self, dest: &mut [u8]) -> Result<(), codec::Error> {
@@ -735,6 +964,12 @@ impl<'d> AttReq<'d> {
                 w.write(*end)?;
                 w.write_ref(attribute_type)?;
             }
+            Self::ReadByGroupType { start, end, group_type } => {
+                w.write(ATT_READ_BY_GROUP_TYPE_REQ)?;
+                w.write(*start)?;
+                w.write(*end)?;
+                w.write_ref(group_type)?;
+            }
             Self::Read { handle } => {
                 w.write(ATT_READ_REQ)?;
                 w.write(*handle)?;
@@ -744,12 +979,29 @@ impl<'d> AttReq<'d> {
                 w.write(*handle)?;
                 w.write(*offset)?;
             }
+            Self::ReadMultiple { handles } => {
+                w.write(ATT_READ_MULTIPLE_REQ)?;
+                w.append(handles)?;
+            }
+            Self::ReadMultipleVariable { handles } => {
+                w.write(ATT_READ_MULTIPLE_VARIABLE_REQ)?;
+                w.append(handles)?;
+            }
+            Self::PrepareWrite { handle, offset, value } => {
+                w.write(ATT_PREPARE_WRITE_REQ)?;
+                w.write(*handle)?;
+                w.write(*offset)?;
+                w.append(value)?;
+            }
+            Self::ExecuteWrite { flags } => {
+                w.write(ATT_EXECUTE_WRITE_REQ)?;
+                w.write(*flags)?;
+            }
             Self::Write { handle, data } => {
                 w.write(ATT_WRITE_REQ)?;
                 w.write(*handle)?;
                 w.append(data)?;
             }
-            _ => unimplemented!(),
         }
         Ok(())
     }
@@ -846,6 +1098,7 @@ impl<'d> AttReq<'d> {
                 Ok(Self::ExecuteWrite { flags })
             }
             ATT_READ_MULTIPLE_REQ => Ok(Self::ReadMultiple { handles: payload }),
+            ATT_READ_MULTIPLE_VARIABLE_REQ => Ok(Self::ReadMultipleVariable { handles: payload }),
             ATT_READ_BLOB_REQ => {
                 let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
                 let offset = (payload[2] as u16) + ((payload[3] as u16) << 8);
@@ -863,6 +1116,7 @@ impl<'d> AttCmd<'d> {
     fn size(&self) -> usize {
         1 + match self {
             Self::Write { handle, data } => 2 + data.len(),
+            Self::SignedWrite { data, .. } => 2 + data.len() + 12,
         }
     }
 
@@ -874,6 +1128,16 @@ impl<'d> AttCmd<'d> {
                 w.write(*handle)?;
                 w.append(data)?;
             }
+            Self::SignedWrite {
+                handle,
+                data,
+                signature,
+            } => {
+                w.write(ATT_SIGNED_WRITE_CMD)?;
+                w.write(*handle)?;
+                w.append(data)?;
+                w.append(signature)?;
+            }
         }
         Ok(())
     }
@@ -887,6 +1151,20 @@ impl<'d> AttCmd<'d> {
 
                 Ok(Self::Write { handle, data })
             }
+            ATT_SIGNED_WRITE_CMD => {
+                if payload.len() < 14 {
+                    return Err(codec::Error::InvalidValue);
+                }
+                let handle = (payload[0] as u16) + ((payload[1] as u16) << 8);
+                let (data, signature) = payload[2..].split_at(payload.len() - 14);
+                let signature = signature.try_into().map_err(|_| codec::Error::InvalidValue)?;
+
+                Ok(Self::SignedWrite {
+                    handle,
+                    data,
+                    signature,
+                })
+            }
             code => {
                 warn!("[att] unknown opcode {:x}", code);
                 Err(codec::Error::InvalidValue)
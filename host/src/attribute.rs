@@ -13,11 +13,12 @@ use crate::att::{AttErrorCode, AttUns};
 use crate::gatt;
 
 use crate::attribute_server::AttributeServer;
+use crate::connection::SecurityLevel;
 use crate::cursor::{ReadCursor, WriteCursor};
 use crate::prelude::{AsGatt, FixedGattValue, FromGatt, GattConnection};
 use crate::types::gatt_traits::FromGattError;
 pub use crate::types::uuid::Uuid;
-use crate::{Error, PacketPool, MAX_INVALID_DATA_LEN};
+use crate::{Error, Identity, PacketPool, MAX_INVALID_DATA_LEN};
 
 /// Characteristic properties
 #[derive(Debug, Clone, Copy)]
@@ -41,12 +42,96 @@ pub enum CharacteristicProp {
     Extended = 0x80,
 }
 
+/// Security level required to access an attribute.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PermissionLevel {
+    /// No security is required.
+    #[default]
+    Open,
+    /// The connection must be encrypted.
+    Encrypted,
+    /// The connection must be encrypted with an authenticated (MITM protected) key.
+    Authenticated,
+    /// The connection must be encrypted with an authenticated key from LE Secure Connections pairing.
+    SecureConnections,
+}
+
+/// Kind of access to an attribute.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeAccess {
+    /// The attribute value is read.
+    Read,
+    /// The attribute value is written.
+    Write,
+}
+
+/// Callback deciding whether a client is authorized to access an attribute.
+///
+/// It is called with the identity of the client, the attribute handle and the kind of access,
+/// for attributes requiring authorization.
+pub type AuthorizeCallback = fn(&Identity, u16, AttributeAccess) -> bool;
+
+/// Permissions required to access an attribute.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AttributePermissions {
+    /// Security level required to read the attribute.
+    pub read: PermissionLevel,
+    /// Security level required to write the attribute.
+    pub write: PermissionLevel,
+    /// Reads must be authorized by the application.
+    pub read_authorization: bool,
+    /// Writes must be authorized by the application.
+    pub write_authorization: bool,
+}
+
+impl AttributePermissions {
+    /// Check whether a connection with the given security level can access the attribute.
+    ///
+    /// `secure_connections` tells whether the link key comes from LE Secure Connections pairing.
+    /// Authorization is checked separately.
+    pub(crate) fn check(
+        &self,
+        access: AttributeAccess,
+        security: SecurityLevel,
+        secure_connections: bool,
+    ) -> Result<(), AttErrorCode> {
+        let level = match access {
+            AttributeAccess::Read => self.read,
+            AttributeAccess::Write => self.write,
+        };
+        match level {
+            PermissionLevel::Open => Ok(()),
+            PermissionLevel::Encrypted if !security.encrypted() => Err(AttErrorCode::INSUFFICIENT_ENCRYPTION),
+            PermissionLevel::Authenticated if !security.authenticated() => {
+                Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+            }
+            // A key from legacy pairing is insufficient even if authenticated, the client has to pair again.
+            PermissionLevel::SecureConnections if !security.authenticated() || !secure_connections => {
+                Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check whether the access must be authorized by the application.
+    pub(crate) fn needs_authorization(&self, access: AttributeAccess) -> bool {
+        match access {
+            AttributeAccess::Read => self.read_authorization,
+            AttributeAccess::Write => self.write_authorization,
+        }
+    }
+}
+
 /// Attribute metadata.
 pub struct Attribute<'a> {
     pub(crate) uuid: Uuid,
     pub(crate) handle: u16,
     pub(crate) last_handle_in_group: u16,
     pub(crate) data: AttributeData<'a>,
+    pub(crate) permissions: AttributePermissions,
 }
 
 impl<'a> Attribute<'a> {
@@ -66,6 +151,14 @@ impl<'a> Attribute<'a> {
 
         self.data.write(offset, data)
     }
+
+    pub(crate) fn validate_write(&self, offset: usize, len: usize) -> Result<(), AttErrorCode> {
+        if !self.data.writable() {
+            return Err(AttErrorCode::WRITE_NOT_PERMITTED);
+        }
+
+        self.data.validate_write(offset, len)
+    }
 }
 
 pub(crate) enum AttributeData<'d> {
@@ -118,6 +211,13 @@ impl AttributeData<'_> {
         }
     }
 
+    pub(crate) fn signed_writable(&self) -> bool {
+        match self {
+            Self::Data { props, .. } => props.0 & (CharacteristicProp::AuthenticatedWrite as u8) != 0,
+            _ => false,
+        }
+    }
+
     fn read(&self, offset: usize, data: &mut [u8]) -> Result<usize, AttErrorCode> {
         if !self.readable() {
             return Err(AttErrorCode::READ_NOT_PERMITTED);
@@ -206,6 +306,30 @@ impl AttributeData<'_> {
         }
     }
 
+    fn validate_write(&self, offset: usize, len: usize) -> Result<(), AttErrorCode> {
+        match self {
+            Self::Data { value, .. } => {
+                if offset > value.len() {
+                    Err(AttErrorCode::INVALID_OFFSET)
+                } else if offset + len > value.len() {
+                    Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
+                } else {
+                    Ok(())
+                }
+            }
+            Self::Cccd { .. } => {
+                if offset > 0 {
+                    Err(AttErrorCode::INVALID_OFFSET)
+                } else if len == 0 {
+                    Err(AttErrorCode::UNLIKELY_ERROR)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Err(AttErrorCode::WRITE_NOT_PERMITTED),
+        }
+    }
+
     fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), AttErrorCode> {
         let writable = self.writable();
 
@@ -224,8 +348,10 @@ impl AttributeData<'_> {
                     value[offset..offset + data.len()].copy_from_slice(data);
                     *len = (offset + data.len()) as u16;
                     Ok(())
-                } else {
+                } else if offset > value.len() {
                     Err(AttErrorCode::INVALID_OFFSET)
+                } else {
+                    Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
                 }
             }
             Self::Cccd {
@@ -284,6 +410,7 @@ impl<'a> Attribute<'a> {
             handle: 0,
             data,
             last_handle_in_group: 0xffff,
+            permissions: AttributePermissions::default(),
         }
     }
 }
@@ -356,6 +483,7 @@ impl<'d, M: RawMutex, const MAX: usize> AttributeTable<'d, M, MAX> {
             uuid: PRIMARY_SERVICE.into(),
             handle: 0,
             last_handle_in_group: 0,
+            permissions: AttributePermissions::default(),
             data: AttributeData::Service { uuid: service.uuid },
         });
         ServiceBuilder {
@@ -524,6 +652,7 @@ impl<'d, M: RawMutex, const MAX: usize> ServiceBuilder<'_, 'd, M, MAX> {
             uuid: CHARACTERISTIC.into(),
             handle: 0,
             last_handle_in_group: 0,
+            permissions: AttributePermissions::default(),
             data: AttributeData::Declaration {
                 props,
                 handle: next,
@@ -536,6 +665,7 @@ impl<'d, M: RawMutex, const MAX: usize> ServiceBuilder<'_, 'd, M, MAX> {
             uuid,
             handle: 0,
             last_handle_in_group: 0,
+            permissions: AttributePermissions::default(),
             data,
         });
 
@@ -545,6 +675,7 @@ impl<'d, M: RawMutex, const MAX: usize> ServiceBuilder<'_, 'd, M, MAX> {
                 uuid: CLIENT_CHARACTERISTIC_CONFIGURATION.into(),
                 handle: 0,
                 last_handle_in_group: 0,
+                permissions: AttributePermissions::default(),
                 data: AttributeData::Cccd {
                     notifications: false,
                     indications: false,
@@ -660,7 +791,11 @@ impl<T: FromGatt> Characteristic<T> {
             handle: self.handle,
             data: value,
         };
-        let pdu = gatt::assemble(connection, crate::att::AttServer::Unsolicited(uns))?;
+        let pdu = gatt::assemble(
+            connection,
+            gatt::AttBearer::Unenhanced,
+            crate::att::AttServer::Unsolicited(uns),
+        )?;
         connection.send(pdu).await;
         Ok(())
     }
@@ -693,7 +828,11 @@ impl<T: FromGatt> Characteristic<T> {
             handle: self.handle,
             data: value,
         };
-        let pdu = gatt::assemble(connection, crate::att::AttServer::Unsolicited(uns))?;
+        let pdu = gatt::assemble(
+            connection,
+            gatt::AttBearer::Unenhanced,
+            crate::att::AttServer::Unsolicited(uns),
+        )?;
         connection.send(pdu).await;
         Ok(())
     }
@@ -755,6 +894,7 @@ impl<'d, T: AsGatt, M: RawMutex, const MAX: usize> CharacteristicBuilder<'_, 'd,
             uuid,
             handle: 0,
             last_handle_in_group: 0,
+            permissions: AttributePermissions::default(),
             data,
         });
 
@@ -791,6 +931,16 @@ impl<'d, T: AsGatt, M: RawMutex, const MAX: usize> CharacteristicBuilder<'_, 'd,
         self.add_descriptor_internal(uuid.into(), props, AttributeData::ReadOnlyData { props, value: data })
     }
 
+    /// Set the permissions required to access the characteristic value.
+    pub fn set_permissions(&mut self, permissions: AttributePermissions) {
+        let handle = self.handle.handle;
+        self.table.with_inner(|inner| {
+            for att in inner.attributes.iter_mut().filter(|att| att.handle == handle) {
+                att.permissions = permissions;
+            }
+        });
+    }
+
     /// Return the built characteristic.
     pub fn build(self) -> Characteristic<T> {
         self.handle
@@ -846,7 +996,8 @@ impl Service {
 }
 
 /// Properties of a characteristic.
-#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct CharacteristicProps(u8);
 
 impl<'a> From<&'a [CharacteristicProp]> for CharacteristicProps {
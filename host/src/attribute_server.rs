@@ -1,26 +1,129 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::marker::PhantomData;
 
+use bt_hci::param::ConnHandle;
+use bt_hci::uuid::characteristic::{CLIENT_SUPPORTED_FEATURES, DATABASE_HASH, SERVICE_CHANGED};
+#[cfg(feature = "security")]
+use bt_hci::uuid::declarations::{CHARACTERISTIC, INCLUDE, PRIMARY_SERVICE, SECONDARY_SERVICE};
+#[cfg(feature = "security")]
+use bt_hci::uuid::descriptors::{
+    CHARACTERISTIC_AGGREGATE_FORMAT, CHARACTERISTIC_EXTENDED_PROPERTIES, CHARACTERISTIC_PRESENTATION_FORMAT,
+    CHARACTERISTIC_USER_DESCRIPTION, CLIENT_CHARACTERISTIC_CONFIGURATION, SERVER_CHARACTERISTIC_CONFIGURATION,
+};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::blocking_mutex::Mutex;
+use heapless::Vec;
 
 use crate::att::{self, AttClient, AttCmd, AttErrorCode, AttReq};
-use crate::attribute::{Attribute, AttributeData, AttributeTable, CCCD};
+use crate::attribute::{Attribute, AttributeAccess, AttributeData, AttributeTable, AuthorizeCallback, CCCD};
+use crate::connection::SecurityLevel;
 use crate::cursor::WriteCursor;
 use crate::prelude::Connection;
+#[cfg(feature = "security")]
+use crate::security_manager::AesCmac;
 use crate::types::uuid::Uuid;
-use crate::{codec, Error, Identity, PacketPool};
+use crate::{codec, config, Error, Identity, PacketPool};
+
+/// Client Supported Features bit for robust caching.
+const ROBUST_CACHING: u8 = 0x01;
+/// Client Supported Features bit for Enhanced ATT bearers.
+pub(crate) const ENHANCED_ATT: u8 = 0x02;
+/// Client Supported Features bit for Multiple Handle Value Notifications.
+pub(crate) const MULTIPLE_NOTIFICATIONS: u8 = 0x04;
+/// Client Supported Features bits known to the server.
+const CLIENT_FEATURES: u8 = 0x07;
+/// Server Supported Features bit for Enhanced ATT bearers.
+pub(crate) const SERVER_ENHANCED_ATT: u8 = 0x01;
 
 #[derive(Default)]
 struct Client {
     identity: Identity,
     is_connected: bool,
+    /// Client Supported Features enabled by the client.
+    features: u8,
+    #[cfg(feature = "security")]
+    sync: DatabaseSync,
 }
 
 impl Client {
     fn set_identity(&mut self, identity: Identity) {
         self.identity = identity;
     }
+
+    fn write_features(&mut self, offset: usize, data: &[u8]) -> Result<(), AttErrorCode> {
+        if offset > 0 {
+            return Err(AttErrorCode::INVALID_OFFSET);
+        }
+        let features = data.first().ok_or(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)? & CLIENT_FEATURES;
+        // Features can't be disabled once enabled
+        if self.features & !features != 0 {
+            return Err(AttErrorCode::VALUE_NOT_ALLOWED);
+        }
+        self.features = features;
+        Ok(())
+    }
+}
+
+/// Robust caching state of a client that may not know the current attribute table.
+#[cfg(feature = "security")]
+#[derive(Default)]
+struct DatabaseSync {
+    /// Hash of the attribute table known to the client, when it is change-unaware.
+    stale_hash: Option<[u8; 16]>,
+    /// A Service Changed indication is due.
+    indicate: bool,
+    /// A Service Changed indication was sent and awaits confirmation.
+    indicated: bool,
+    /// The client was told that it is out of sync, and becomes change-aware with its next request.
+    notified: bool,
+}
+
+#[cfg(feature = "security")]
+impl DatabaseSync {
+    /// Check whether a client must be told that it is out of sync rather than handling its request.
+    fn out_of_sync(&mut self, features: u8, packet: &AttClient) -> bool {
+        if self.stale_hash.is_none() {
+            return false;
+        }
+        match packet {
+            AttClient::Confirmation(_) => {
+                if self.indicated {
+                    *self = Self::default();
+                }
+                false
+            }
+            AttClient::Request(AttReq::ExchangeMtu { .. }) => false,
+            _ if features & ROBUST_CACHING == 0 => false,
+            AttClient::Request(AttReq::ReadByType { attribute_type, .. })
+                if *attribute_type == DATABASE_HASH.into() =>
+            {
+                self.notified = true;
+                false
+            }
+            AttClient::Request(_) if self.notified => {
+                *self = Self::default();
+                false
+            }
+            AttClient::Request(_) => {
+                self.notified = true;
+                true
+            }
+            AttClient::Command(_) => true,
+        }
+    }
+}
+
+/// GATT caching state of a client.
+///
+/// The state of a bonded client should be stored along with its bond, and restored when it reconnects.
+#[cfg(feature = "security")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CachingState {
+    /// Database Hash of the attribute table known to the client.
+    pub database_hash: [u8; 16],
+    /// Client Supported Features enabled by the client.
+    pub client_features: u8,
 }
 
 /// A table of CCCD values.
@@ -64,6 +167,16 @@ impl<const ENTRIES: usize> CccdTable<ENTRIES> {
         }
     }
 
+    #[cfg(feature = "security")]
+    fn set(&mut self, cccd_handle: u16, cccd: CCCD) {
+        for (handle, value) in self.inner.iter_mut() {
+            if *handle == cccd_handle {
+                *value = cccd;
+                break;
+            }
+        }
+    }
+
     fn get_raw(&self, cccd_handle: u16) -> Option<[u8; 2]> {
         for (handle, value) in self.inner.iter() {
             if *handle == cccd_handle {
@@ -159,9 +272,10 @@ impl<M: RawMutex, const CCCD_MAX: usize, const CONN_MAX: usize> CccdTables<M, CC
             for (client, table) in n.iter_mut() {
                 if !client.is_connected {
                     trace!("[server] booting disconnected peer {:?}", client.identity);
+                    // erase the previous client's config
+                    *client = Client::default();
                     client.is_connected = true;
                     client.set_identity(*peer_identity);
-                    // erase the previous client's config
                     table.disable_all();
                     return Ok(());
                 }
@@ -270,6 +384,42 @@ impl<M: RawMutex, const CCCD_MAX: usize, const CONN_MAX: usize> CccdTables<M, CC
         })
     }
 
+    /// Restore the stored CCCD values of a bonded peer, before it is connected to the server.
+    #[cfg(feature = "security")]
+    fn restore(&self, peer_identity: &Identity, stored: &CccdTable<CCCD_MAX>) -> Result<(), Error> {
+        self.state.lock(|n| {
+            let mut n = n.borrow_mut();
+            let empty_slot = Identity::default();
+            let index = n
+                .iter()
+                .position(|(client, _)| client.identity.match_identity(peer_identity))
+                .or_else(|| n.iter().position(|(client, _)| client.identity == empty_slot))
+                .or_else(|| n.iter().position(|(client, _)| !client.is_connected))
+                .ok_or(Error::ConnectionLimitReached)?;
+            let (client, table) = &mut n[index];
+            if !client.identity.match_identity(peer_identity) {
+                *client = Client::default();
+                client.set_identity(*peer_identity);
+                table.disable_all();
+            }
+            for (handle, value) in stored.inner().iter() {
+                if *handle != 0 {
+                    table.set(*handle, *value);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn with_client<R>(&self, peer_identity: &Identity, f: impl FnOnce(&mut Client) -> R) -> Option<R> {
+        self.state.lock(|n| {
+            let mut n = n.borrow_mut();
+            n.iter_mut()
+                .find(|(client, _)| client.identity.match_identity(peer_identity))
+                .map(|(client, _)| f(client))
+        })
+    }
+
     fn update_identity(&self, identity: Identity) -> Result<(), Error> {
         self.state.lock(|n| {
             let mut n = n.borrow_mut();
@@ -284,6 +434,207 @@ impl<M: RawMutex, const CCCD_MAX: usize, const CONN_MAX: usize> CccdTables<M, CC
     }
 }
 
+/// Writes prepared by a client, waiting to be executed.
+///
+/// Each write is stored as handle, offset and value length followed by the value.
+#[derive(Default)]
+struct PrepareQueue {
+    conn: Option<ConnHandle>,
+    data: Vec<u8, { config::GATT_PREPARE_WRITE_QUEUE_SIZE }>,
+    /// Attribute and error code of a combined write rejected by the application.
+    rejected: Option<(u16, AttErrorCode)>,
+}
+
+impl PrepareQueue {
+    const HEADER_LEN: usize = 6;
+
+    /// Queue a write, checked against the attribute when the queue is executed.
+    ///
+    /// The writes to each attribute are reported combined into a value that must fit in the queue.
+    fn push(&mut self, handle: u16, offset: u16, value: &[u8]) -> Result<(), AttErrorCode> {
+        if self.data.capacity() - self.data.len() < Self::HEADER_LEN + value.len()
+            || offset as usize + value.len() > self.data.capacity()
+        {
+            return Err(AttErrorCode::PREPARE_QUEUE_FULL);
+        }
+        for field in [handle, offset, value.len() as u16] {
+            let _ = self.data.extend_from_slice(&field.to_le_bytes());
+        }
+        let _ = self.data.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn writes(&self) -> PreparedWrites<'_> {
+        PreparedWrites { data: &self.data }
+    }
+
+    /// Drop the queued writes, failing their execution with `code`.
+    fn reject(&mut self, handle: u16, code: AttErrorCode) {
+        self.data.clear();
+        self.rejected = Some((handle, code));
+    }
+
+    fn clear(&mut self) {
+        self.conn = None;
+        self.data.clear();
+        self.rejected = None;
+    }
+}
+
+/// Iterator over the (handle, offset, value) of prepared writes, in the order they were queued.
+#[derive(Clone)]
+struct PreparedWrites<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for PreparedWrites<'a> {
+    type Item = (u16, u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < PrepareQueue::HEADER_LEN {
+            return None;
+        }
+        let (header, rest) = self.data.split_at(PrepareQueue::HEADER_LEN);
+        let handle = u16::from_le_bytes([header[0], header[1]]);
+        let offset = u16::from_le_bytes([header[2], header[3]]);
+        let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let (value, rest) = rest.split_at(len);
+        self.data = rest;
+        Some((handle, offset, value))
+    }
+}
+
+/// Queues of prepared writes for each connected client.
+struct PrepareQueues<M: RawMutex, const CONN_MAX: usize> {
+    state: Mutex<M, RefCell<[PrepareQueue; CONN_MAX]>>,
+}
+
+impl<M: RawMutex, const CONN_MAX: usize> PrepareQueues<M, CONN_MAX> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(core::array::from_fn(|_| PrepareQueue::default()))),
+        }
+    }
+
+    fn push(&self, conn: ConnHandle, handle: u16, offset: u16, value: &[u8]) -> Result<(), AttErrorCode> {
+        self.state.lock(|n| {
+            let mut n = n.borrow_mut();
+            let slot = match n.iter().position(|q| q.conn == Some(conn)) {
+                Some(slot) => slot,
+                None => n
+                    .iter()
+                    .position(|q| q.conn.is_none())
+                    .ok_or(AttErrorCode::PREPARE_QUEUE_FULL)?,
+            };
+            let queue = &mut n[slot];
+            queue.conn = Some(conn);
+            queue.push(handle, offset, value)
+        })
+    }
+
+    /// Run `f` on the writes prepared for this connection, if any.
+    fn with<R>(&self, conn: ConnHandle, f: impl FnOnce(PreparedWrites<'_>) -> R) -> Option<R> {
+        self.state.lock(|n| {
+            let n = n.borrow();
+            n.iter().find(|q| q.conn == Some(conn)).map(|q| f(q.writes()))
+        })
+    }
+
+    /// Attribute and error code of a combined write rejected by the application, if any.
+    fn rejected(&self, conn: ConnHandle) -> Option<(u16, AttErrorCode)> {
+        self.state.lock(|n| {
+            let n = n.borrow();
+            n.iter().find(|q| q.conn == Some(conn)).and_then(|q| q.rejected)
+        })
+    }
+
+    fn reject(&self, conn: ConnHandle, handle: u16, code: AttErrorCode) {
+        self.state.lock(|n| {
+            let mut n = n.borrow_mut();
+            for queue in n.iter_mut() {
+                if queue.conn == Some(conn) {
+                    queue.reject(handle, code);
+                }
+            }
+        })
+    }
+
+    fn clear(&self, conn: ConnHandle) {
+        self.state.lock(|n| {
+            let mut n = n.borrow_mut();
+            for queue in n.iter_mut() {
+                if queue.conn == Some(conn) {
+                    queue.clear();
+                }
+            }
+        })
+    }
+}
+
+/// Handles of the GATT service characteristics managed by the server.
+#[derive(Default)]
+struct GattServiceHandles {
+    /// Value and CCCD handles of the Service Changed characteristic.
+    service_changed: Option<(u16, u16)>,
+    client_features: Option<u16>,
+    database_hash: Option<u16>,
+}
+
+impl GattServiceHandles {
+    fn new<M: RawMutex, const ATT_MAX: usize>(att_table: &AttributeTable<'_, M, ATT_MAX>) -> Self {
+        let mut handles = Self::default();
+        att_table.iterate(|mut it| {
+            while let Some(att) = it.next() {
+                if att.uuid == SERVICE_CHANGED.into() {
+                    let handle = att.handle;
+                    if let Some(cccd) = it.next().filter(|att| matches!(att.data, AttributeData::Cccd { .. })) {
+                        handles.service_changed = Some((handle, cccd.handle));
+                    }
+                } else if att.uuid == CLIENT_SUPPORTED_FEATURES.into() {
+                    handles.client_features = Some(att.handle);
+                } else if att.uuid == DATABASE_HASH.into() {
+                    handles.database_hash = Some(att.handle);
+                }
+            }
+        });
+        handles
+    }
+}
+
+/// Compute the Database Hash of an attribute table ([Vol 3] Part G, Section 7.3.1).
+#[cfg(feature = "security")]
+fn database_hash<M: RawMutex, const ATT_MAX: usize>(att_table: &AttributeTable<'_, M, ATT_MAX>) -> [u8; 16] {
+    let with_value = [
+        PRIMARY_SERVICE,
+        SECONDARY_SERVICE,
+        INCLUDE,
+        CHARACTERISTIC,
+        CHARACTERISTIC_EXTENDED_PROPERTIES,
+    ];
+    let without_value = [
+        CHARACTERISTIC_USER_DESCRIPTION,
+        CLIENT_CHARACTERISTIC_CONFIGURATION,
+        SERVER_CHARACTERISTIC_CONFIGURATION,
+        CHARACTERISTIC_PRESENTATION_FORMAT,
+        CHARACTERISTIC_AGGREGATE_FORMAT,
+    ];
+    let mut cmac = AesCmac::db_hash();
+    att_table.iterate(|mut it| {
+        let mut value = [0; 19];
+        while let Some(att) = it.next() {
+            if with_value.iter().any(|uuid| att.uuid == (*uuid).into()) {
+                let len = att.read(0, &mut value).unwrap_or(0);
+                cmac.update(att.handle.to_le_bytes())
+                    .update(att.uuid.as_raw())
+                    .update(&value[..len]);
+            } else if without_value.iter().any(|uuid| att.uuid == (*uuid).into()) {
+                cmac.update(att.handle.to_le_bytes()).update(att.uuid.as_raw());
+            }
+        }
+    });
+    cmac.finalize().to_le_bytes()
+}
+
 /// A GATT server capable of processing the GATT protocol using the provided table of attributes.
 pub struct AttributeServer<
     'values,
@@ -295,6 +646,11 @@ pub struct AttributeServer<
 > {
     att_table: AttributeTable<'values, M, ATT_MAX>,
     cccd_tables: CccdTables<M, CCCD_MAX, CONN_MAX>,
+    prepare_queues: PrepareQueues<M, CONN_MAX>,
+    gatt_handles: GattServiceHandles,
+    #[cfg(feature = "security")]
+    database_hash: [u8; 16],
+    authorize: Mutex<M, Cell<Option<AuthorizeCallback>>>,
     _p: PhantomData<P>,
 }
 
@@ -312,8 +668,15 @@ pub(crate) mod sealed {
         ) -> Result<Option<usize>, Error>;
         fn should_notify(&self, connection: &Connection<'_, P>, cccd_handle: u16) -> bool;
         fn should_indicate(&self, connection: &Connection<'_, P>, cccd_handle: u16) -> bool;
+        fn client_features(&self, connection: &Connection<'_, P>) -> u8;
         fn set(&self, characteristic: u16, input: &[u8]) -> Result<(), Error>;
         fn update_identity(&self, identity: Identity) -> Result<(), Error>;
+        fn prepared_write(&self, connection: &Connection<'_, P>, handle: u16, buf: &mut [u8]) -> Option<usize>;
+        fn prepared_write_handle(&self, connection: &Connection<'_, P>, after: u16) -> Option<u16>;
+        fn reject_prepared_writes(&self, connection: &Connection<'_, P>, handle: u16, code: AttErrorCode);
+        fn cancel_prepared_writes(&self, connection: &Connection<'_, P>);
+        #[cfg(feature = "security")]
+        fn service_changed_indication(&self, connection: &Connection<'_, P>) -> Option<u16>;
     }
 }
 
@@ -333,6 +696,7 @@ impl<M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX: usize, co
 
     fn disconnect(&self, connection: &Connection<'_, P>) {
         self.cccd_tables.disconnect(&connection.peer_identity());
+        self.prepare_queues.clear(connection.handle());
     }
 
     fn process(
@@ -352,6 +716,10 @@ impl<M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX: usize, co
         AttributeServer::should_indicate(self, connection, cccd_handle)
     }
 
+    fn client_features(&self, connection: &Connection<'_, P>) -> u8 {
+        AttributeServer::client_features(self, connection)
+    }
+
     fn set(&self, characteristic: u16, input: &[u8]) -> Result<(), Error> {
         self.att_table.set_raw(characteristic, input)
     }
@@ -359,6 +727,27 @@ impl<M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX: usize, co
     fn update_identity(&self, identity: Identity) -> Result<(), Error> {
         self.cccd_tables.update_identity(identity)
     }
+
+    fn prepared_write(&self, connection: &Connection<'_, P>, handle: u16, buf: &mut [u8]) -> Option<usize> {
+        AttributeServer::prepared_write(self, connection, handle, buf)
+    }
+
+    fn prepared_write_handle(&self, connection: &Connection<'_, P>, after: u16) -> Option<u16> {
+        AttributeServer::prepared_write_handle(self, connection, after)
+    }
+
+    fn reject_prepared_writes(&self, connection: &Connection<'_, P>, handle: u16, code: AttErrorCode) {
+        self.prepare_queues.reject(connection.handle(), handle, code);
+    }
+
+    fn cancel_prepared_writes(&self, connection: &Connection<'_, P>) {
+        self.prepare_queues.clear(connection.handle());
+    }
+
+    #[cfg(feature = "security")]
+    fn service_changed_indication(&self, connection: &Connection<'_, P>) -> Option<u16> {
+        AttributeServer::service_changed_indication(self, connection)
+    }
 }
 
 impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX: usize, const CONN_MAX: usize>
@@ -369,17 +758,83 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         att_table: AttributeTable<'values, M, ATT_MAX>,
     ) -> AttributeServer<'values, M, P, ATT_MAX, CCCD_MAX, CONN_MAX> {
         let cccd_tables = CccdTables::new(&att_table);
+        let gatt_handles = GattServiceHandles::new(&att_table);
+        #[cfg(feature = "security")]
+        let database_hash = database_hash(&att_table);
+        #[cfg(feature = "security")]
+        if let Some(handle) = gatt_handles.database_hash {
+            let _ = att_table.set_raw(handle, &database_hash);
+        }
         AttributeServer {
             att_table,
             cccd_tables,
+            prepare_queues: PrepareQueues::new(),
+            gatt_handles,
+            #[cfg(feature = "security")]
+            database_hash,
+            authorize: Mutex::new(Cell::new(None)),
             _p: PhantomData,
         }
     }
 
+    /// Set the callback deciding whether clients are authorized to access attributes requiring authorization.
+    ///
+    /// Without a callback, such accesses are always rejected.
+    pub fn set_authorize_callback(&self, callback: AuthorizeCallback) {
+        self.authorize.lock(|authorize| authorize.set(Some(callback)));
+    }
+
     pub(crate) fn connect(&self, connection: &Connection<'_, P>) -> Result<(), Error> {
+        self.prepare_queues.clear(connection.handle());
         self.cccd_tables.connect(&connection.peer_identity())
     }
 
+    /// Combine the writes prepared by a connection for the attribute `handle` into `buf`.
+    ///
+    /// The combined value starts at offset 0, parts that aren't written keep the current value of the
+    /// attribute. Returns the length of the value, or `None` if executing the prepared writes would fail.
+    pub(crate) fn prepared_write(&self, connection: &Connection<'_, P>, handle: u16, buf: &mut [u8]) -> Option<usize> {
+        self.prepare_queues
+            .with(connection.handle(), |writes| {
+                self.validate_prepared_writes(writes.clone()).ok()?;
+                let len = writes
+                    .clone()
+                    .filter(|(h, _, _)| *h == handle)
+                    .map(|(_, offset, value)| offset as usize + value.len())
+                    .max()?;
+                let buf = buf.get_mut(..len)?;
+                self.att_table.iterate(|mut it| {
+                    while let Some(att) = it.next() {
+                        if att.handle == handle {
+                            if let AttributeData::Data { value, .. } = &att.data {
+                                let n = len.min(value.len());
+                                buf[..n].copy_from_slice(&value[..n]);
+                            }
+                            break;
+                        }
+                    }
+                });
+                for (_, offset, value) in writes.filter(|(h, _, _)| *h == handle) {
+                    let offset = offset as usize;
+                    buf[offset..offset + value.len()].copy_from_slice(value);
+                }
+                Some(len)
+            })
+            .flatten()
+    }
+
+    /// Lowest attribute handle above `after` with writes prepared by a connection.
+    pub(crate) fn prepared_write_handle(&self, connection: &Connection<'_, P>, after: u16) -> Option<u16> {
+        self.prepare_queues
+            .with(connection.handle(), |writes| {
+                writes
+                    .map(|(handle, _, _)| handle)
+                    .filter(|handle| *handle > after)
+                    .min()
+            })
+            .flatten()
+    }
+
     pub(crate) fn should_notify(&self, connection: &Connection<'_, P>, cccd_handle: u16) -> bool {
         self.cccd_tables.should_notify(&connection.peer_identity(), cccd_handle)
     }
@@ -389,6 +844,35 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
             .should_indicate(&connection.peer_identity(), cccd_handle)
     }
 
+    /// Client Supported Features enabled by the client of a connection.
+    pub(crate) fn client_features(&self, connection: &Connection<'_, P>) -> u8 {
+        self.cccd_tables
+            .with_client(&connection.peer_identity(), |client| client.features)
+            .unwrap_or(0)
+    }
+
+    /// Check the security level and authorization of a connection against the permissions of an attribute.
+    fn check_permissions(
+        &self,
+        connection: &Connection<'_, P>,
+        att: &Attribute<'values>,
+        access: AttributeAccess,
+    ) -> Result<(), AttErrorCode> {
+        let level = connection.security_level().unwrap_or(SecurityLevel::NoEncryption);
+        let secure_connections = connection.secure_connections().unwrap_or(false);
+        att.permissions.check(access, level, secure_connections)?;
+        if att.permissions.needs_authorization(access) {
+            let authorized = self
+                .authorize
+                .lock(|authorize| authorize.get())
+                .is_some_and(|authorize| authorize(&connection.peer_identity(), att.handle, access));
+            if !authorized {
+                return Err(AttErrorCode::INSUFFICIENT_AUTHORISATION);
+            }
+        }
+        Ok(())
+    }
+
     fn read_attribute_data(
         &self,
         connection: &Connection<'_, P>,
@@ -396,6 +880,7 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         att: &mut Attribute<'values>,
         data: &mut [u8],
     ) -> Result<usize, AttErrorCode> {
+        self.check_permissions(connection, att, AttributeAccess::Read)?;
         if let AttributeData::Cccd { .. } = att.data {
             // CCCD values for each connected client are held in the CCCD tables:
             // the value is written back into att.data so att.read() has the final
@@ -404,6 +889,14 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
                 let _ = att.write(0, value.as_slice());
             }
         }
+        if Some(att.handle) == self.gatt_handles.client_features {
+            // Client Supported Features are held for each connected client like CCCD values
+            let features = self
+                .cccd_tables
+                .with_client(&connection.peer_identity(), |client| client.features)
+                .unwrap_or(0);
+            let _ = att.write(0, &[features]);
+        }
         att.read(offset, data)
     }
 
@@ -414,6 +907,14 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         att: &mut Attribute<'values>,
         data: &[u8],
     ) -> Result<(), AttErrorCode> {
+        self.check_permissions(connection, att, AttributeAccess::Write)?;
+        if Some(att.handle) == self.gatt_handles.client_features {
+            self.cccd_tables
+                .with_client(&connection.peer_identity(), |client| {
+                    client.write_features(offset, data)
+                })
+                .unwrap_or(Err(AttErrorCode::UNLIKELY_ERROR))?;
+        }
         let err = att.write(offset, data);
         if err.is_ok() {
             if let AttributeData::Cccd {
@@ -636,6 +1137,28 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         Ok(0)
     }
 
+    fn handle_signed_write_cmd(
+        &self,
+        connection: &Connection<'_, P>,
+        buf: &mut [u8],
+        handle: u16,
+        data: &[u8],
+    ) -> Result<usize, codec::Error> {
+        self.att_table.iterate(|mut it| {
+            while let Some(att) = it.next() {
+                if att.handle == handle {
+                    // Only characteristics with the authenticated signed writes property accept them,
+                    // the signature is verified before the command reaches the server.
+                    if att.data.signed_writable() {
+                        let _ = self.write_attribute_data(connection, 0, att, data);
+                    }
+                    break;
+                }
+            }
+        });
+        Ok(0)
+    }
+
     fn handle_write_req(
         &self,
         connection: &Connection<'_, P>,
@@ -768,11 +1291,16 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         w.write(offset)?;
 
         let err = self.att_table.iterate(|mut it| {
-            let mut err = Err(AttErrorCode::ATTRIBUTE_NOT_FOUND);
+            let mut err = Err(AttErrorCode::INVALID_HANDLE);
             while let Some(att) = it.next() {
                 if att.handle == handle {
-                    err = self.write_attribute_data(connection, offset as usize, att, value);
-                    w.append(value)?;
+                    // The offset and length are validated when the queue is executed.
+                    err = if att.data.writable() {
+                        self.check_permissions(connection, att, AttributeAccess::Write)
+                            .and_then(|_| self.prepare_queues.push(connection.handle(), handle, offset, value))
+                    } else {
+                        Err(AttErrorCode::WRITE_NOT_PERMITTED)
+                    };
                     break;
                 }
             }
@@ -780,15 +1308,79 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         });
 
         match err {
-            Ok(()) => Ok(w.len()),
+            Ok(()) => {
+                w.append(value)?;
+                Ok(w.len())
+            }
             Err(e) => Ok(Self::error_response(w, att::ATT_PREPARE_WRITE_REQ, handle, e)?),
         }
     }
 
-    fn handle_execute_write(&self, buf: &mut [u8], _flags: u8) -> Result<usize, codec::Error> {
+    fn handle_execute_write(
+        &self,
+        connection: &Connection<'_, P>,
+        buf: &mut [u8],
+        flags: u8,
+    ) -> Result<usize, codec::Error> {
         let mut w = WriteCursor::new(buf);
         w.write(att::ATT_EXECUTE_WRITE_RSP)?;
-        Ok(w.len())
+
+        // Flags 0x00 cancels all prepared writes, 0x01 writes all of them.
+        let err = match flags {
+            0x00 => Ok(()),
+            0x01 => match self.prepare_queues.rejected(connection.handle()) {
+                Some(rejected) => Err(rejected),
+                None => self
+                    .prepare_queues
+                    .with(connection.handle(), |writes| self.execute_writes(connection, writes))
+                    .unwrap_or(Ok(())),
+            },
+            _ => Err((0, AttErrorCode::INVALID_PDU)),
+        };
+        self.prepare_queues.clear(connection.handle());
+
+        match err {
+            Ok(()) => Ok(w.len()),
+            Err((handle, e)) => Ok(Self::error_response(w, att::ATT_EXECUTE_WRITE_REQ, handle, e)?),
+        }
+    }
+
+    /// Write all prepared writes, or none of them if any is invalid.
+    fn execute_writes(
+        &self,
+        connection: &Connection<'_, P>,
+        writes: PreparedWrites<'_>,
+    ) -> Result<(), (u16, AttErrorCode)> {
+        self.validate_prepared_writes(writes.clone())?;
+
+        self.att_table.iterate(|mut it| {
+            while let Some(att) = it.next() {
+                for (handle, offset, value) in writes.clone() {
+                    if att.handle == handle {
+                        self.write_attribute_data(connection, offset as usize, att, value)
+                            .map_err(|e| (handle, e))?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Check the offset and length of each prepared write against its attribute.
+    fn validate_prepared_writes(&self, writes: PreparedWrites<'_>) -> Result<(), (u16, AttErrorCode)> {
+        for (handle, offset, value) in writes {
+            self.att_table
+                .iterate(|mut it| {
+                    while let Some(att) = it.next() {
+                        if att.handle == handle {
+                            return att.validate_write(offset as usize, value.len());
+                        }
+                    }
+                    Err(AttErrorCode::INVALID_HANDLE)
+                })
+                .map_err(|e| (handle, e))?;
+        }
+        Ok(())
     }
 
     fn handle_read_blob(
@@ -821,14 +1413,66 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         }
     }
 
-    fn handle_read_multiple(&self, buf: &mut [u8], handles: &[u8]) -> Result<usize, codec::Error> {
-        let w = WriteCursor::new(buf);
-        Self::error_response(
-            w,
-            att::ATT_READ_MULTIPLE_REQ,
-            u16::from_le_bytes([handles[0], handles[1]]),
-            AttErrorCode::ATTRIBUTE_NOT_FOUND,
-        )
+    fn handle_read_multiple(
+        &self,
+        connection: &Connection<'_, P>,
+        buf: &mut [u8],
+        opcode: u8,
+        handles: &[u8],
+    ) -> Result<usize, codec::Error> {
+        let variable = opcode == att::ATT_READ_MULTIPLE_VARIABLE_REQ;
+
+        // Values that don't fit within the ATT MTU are truncated.
+        let mtu = (connection.get_att_mtu() as usize).min(buf.len());
+        let mut w = WriteCursor::new(&mut buf[..mtu]);
+        w.write(if variable {
+            att::ATT_READ_MULTIPLE_VARIABLE_RSP
+        } else {
+            att::ATT_READ_MULTIPLE_RSP
+        })?;
+
+        if handles.len() < 4 || !handles.len().is_multiple_of(2) {
+            return Self::error_response(w, opcode, 0, AttErrorCode::INVALID_PDU);
+        }
+
+        for handle in handles.chunks_exact(2) {
+            let handle = u16::from_le_bytes([handle[0], handle[1]]);
+            let err = self.att_table.iterate(|mut it| {
+                let mut err = Err(AttErrorCode::INVALID_HANDLE);
+                while let Some(att) = it.next() {
+                    if att.handle == handle {
+                        let buf = w.write_buf();
+                        let header = if variable { 2 } else { 0 };
+                        err = if buf.len() <= header {
+                            // No space left for the value, but permissions must still be checked
+                            if att.data.readable() {
+                                self.check_permissions(connection, att, AttributeAccess::Read)
+                            } else {
+                                Err(AttErrorCode::READ_NOT_PERMITTED)
+                            }
+                        } else {
+                            let (len, value) = buf.split_at_mut(header);
+                            match self.read_attribute_data(connection, 0, att, value) {
+                                Ok(n) => {
+                                    len.copy_from_slice(&(n as u16).to_le_bytes()[..header]);
+                                    w.commit(header + n)?;
+                                    Ok(())
+                                }
+                                Err(e) => Err(e),
+                            }
+                        };
+                        break;
+                    }
+                }
+                err
+            });
+
+            if let Err(e) = err {
+                return Self::error_response(w, opcode, handle, e);
+            }
+        }
+
+        Ok(w.len())
     }
 
     /// Process an event and produce a response if necessary
@@ -838,6 +1482,18 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
         packet: &AttClient,
         rx: &mut [u8],
     ) -> Result<Option<usize>, codec::Error> {
+        #[cfg(feature = "security")]
+        if self.out_of_sync(connection, packet) {
+            return match packet {
+                AttClient::Request(req) => {
+                    let w = WriteCursor::new(rx);
+                    let len = Self::error_response(w, req.opcode(), 0, AttErrorCode::DATABASE_OUT_OF_SYNC)?;
+                    Ok(Some(len))
+                }
+                _ => Ok(None),
+            };
+        }
+
         let len = match packet {
             AttClient::Request(AttReq::ReadByType {
                 start,
@@ -860,6 +1516,11 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
                 0
             }
 
+            AttClient::Command(AttCmd::SignedWrite { handle, data, .. }) => {
+                self.handle_signed_write_cmd(connection, rx, *handle, data)?;
+                0
+            }
+
             AttClient::Request(AttReq::Write { handle, data }) => {
                 self.handle_write_req(connection, rx, *handle, data)?
             }
@@ -877,13 +1538,19 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
                 self.handle_prepare_write(connection, rx, *handle, *offset, value)?
             }
 
-            AttClient::Request(AttReq::ExecuteWrite { flags }) => self.handle_execute_write(rx, *flags)?,
+            AttClient::Request(AttReq::ExecuteWrite { flags }) => self.handle_execute_write(connection, rx, *flags)?,
 
             AttClient::Request(AttReq::ReadBlob { handle, offset }) => {
                 self.handle_read_blob(connection, rx, *handle, *offset)?
             }
 
-            AttClient::Request(AttReq::ReadMultiple { handles }) => self.handle_read_multiple(rx, handles)?,
+            AttClient::Request(AttReq::ReadMultiple { handles }) => {
+                self.handle_read_multiple(connection, rx, att::ATT_READ_MULTIPLE_REQ, handles)?
+            }
+
+            AttClient::Request(AttReq::ReadMultipleVariable { handles }) => {
+                self.handle_read_multiple(connection, rx, att::ATT_READ_MULTIPLE_VARIABLE_REQ, handles)?
+            }
 
             AttClient::Confirmation(_) => 0,
         };
@@ -908,6 +1575,78 @@ impl<'values, M: RawMutex, P: PacketPool, const ATT_MAX: usize, const CCCD_MAX:
     pub fn set_cccd_table(&self, connection: &Connection<'_, P>, table: CccdTable<CCCD_MAX>) {
         self.cccd_tables.set_cccd_table(&connection.peer_identity(), table);
     }
+
+    /// Get the CCCD table of a peer, connected or not
+    #[cfg(feature = "security")]
+    pub(crate) fn cccd_table(&self, identity: &Identity) -> Option<CccdTable<CCCD_MAX>> {
+        self.cccd_tables.get_cccd_table(identity)
+    }
+
+    /// Restore the stored CCCD table of a bonded peer
+    #[cfg(feature = "security")]
+    pub(crate) fn restore_cccd_table(&self, identity: &Identity, table: &CccdTable<CCCD_MAX>) -> Result<(), Error> {
+        self.cccd_tables.restore(identity, table)
+    }
+
+    /// Database Hash of the attribute table.
+    #[cfg(feature = "security")]
+    pub fn database_hash(&self) -> [u8; 16] {
+        self.database_hash
+    }
+
+    /// Get the GATT caching state for a connection
+    #[cfg(feature = "security")]
+    pub fn get_caching_state(&self, connection: &Connection<'_, P>) -> Option<CachingState> {
+        self.cccd_tables
+            .with_client(&connection.peer_identity(), |client| CachingState {
+                database_hash: client.sync.stale_hash.unwrap_or(self.database_hash),
+                client_features: client.features,
+            })
+    }
+
+    /// Set the GATT caching state for a connection
+    ///
+    /// If the attribute table changed since the state was saved, the client is sent a Service Changed
+    /// indication once it has enabled them. Until then, a client using robust caching is told that it is
+    /// out of sync when it makes a request.
+    #[cfg(feature = "security")]
+    pub fn set_caching_state(&self, connection: &Connection<'_, P>, state: CachingState) {
+        self.cccd_tables.with_client(&connection.peer_identity(), |client| {
+            client.features = state.client_features & CLIENT_FEATURES;
+            client.sync = DatabaseSync::default();
+            if state.database_hash != self.database_hash {
+                client.sync.stale_hash = Some(state.database_hash);
+                client.sync.indicate = true;
+            }
+        });
+    }
+
+    /// Value handle of the Service Changed characteristic, if an indication is due for the connection.
+    #[cfg(feature = "security")]
+    pub(crate) fn service_changed_indication(&self, connection: &Connection<'_, P>) -> Option<u16> {
+        let (handle, cccd_handle) = self.gatt_handles.service_changed?;
+        if !self.should_indicate(connection, cccd_handle) {
+            return None;
+        }
+        self.cccd_tables
+            .with_client(&connection.peer_identity(), |client| {
+                let sync = &mut client.sync;
+                let due = sync.indicate;
+                sync.indicate = false;
+                sync.indicated |= due;
+                due.then_some(handle)
+            })
+            .flatten()
+    }
+
+    #[cfg(feature = "security")]
+    fn out_of_sync(&self, connection: &Connection<'_, P>, packet: &AttClient) -> bool {
+        self.cccd_tables
+            .with_client(&connection.peer_identity(), |client| {
+                client.sync.out_of_sync(client.features, packet)
+            })
+            .unwrap_or(false)
+    }
 }
 
 #[cfg(test)]
@@ -918,6 +1657,7 @@ mod tests {
     use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 
     use super::*;
+    use crate::att::{Att, AttRsp, AttServer};
     use crate::connection_manager::tests::{setup, ADDR_1};
     use crate::prelude::*;
 
@@ -964,14 +1704,14 @@ mod tests {
             // Add a first service, contents don't really matter, but the issue doesn't manifest without this.
             {
                 let svc = table.add_service(Service {
-                    uuid: Uuid::new_long([10; 16]).into(),
+                    uuid: Uuid::new_long([10; 16]),
                 });
             }
 
             // Add an interior service that has a varying length.
             {
                 let mut svc = table.add_service(Service {
-                    uuid: Uuid::new_long([0; 16]).into(),
+                    uuid: Uuid::new_long([0; 16]),
                 });
 
                 for c in 0..interior_handle_count {
@@ -983,7 +1723,7 @@ mod tests {
             // Now add the service at the end, contents don't really matter.
             {
                 table.add_service(Service {
-                    uuid: Uuid::new_long([8; 16]).into(),
+                    uuid: Uuid::new_long([8; 16]),
                 });
             }
 
@@ -1049,4 +1789,474 @@ mod tests {
             };
         }
     }
+    #[test]
+    fn test_attribute_server_read_multiple() {
+        let _ = env_logger::try_init();
+        const MAX_ATTRIBUTES: usize = 64;
+        const CONNECTIONS_MAX: usize = 3;
+        const CCCD_MAX: usize = 64;
+
+        let first = [1u8; 10];
+        let second = [2u8; 10];
+        let third = [3u8; 10];
+        let mut write_only = [0u8; 2];
+
+        let mut table: AttributeTable<'_, NoopRawMutex, { MAX_ATTRIBUTES }> = AttributeTable::new();
+        let (first, second, third, write_only) = {
+            let mut svc = table.add_service(Service {
+                uuid: Uuid::new_long([0; 16]),
+            });
+            let first = svc.add_characteristic_ro(Uuid::new_long([1; 16]), &first).build();
+            let second = svc.add_characteristic_ro(Uuid::new_long([2; 16]), &second).build();
+            let third = svc.add_characteristic_ro(Uuid::new_long([3; 16]), &third).build();
+            let write_only = svc
+                .add_characteristic(
+                    Uuid::new_long([4; 16]),
+                    &[CharacteristicProp::Write],
+                    [0u8; 2],
+                    &mut write_only,
+                )
+                .build();
+            (first.handle, second.handle, third.handle, write_only.handle)
+        };
+
+        let server = AttributeServer::<_, DefaultPacketPool, MAX_ATTRIBUTES, CCCD_MAX, CONNECTIONS_MAX>::new(table);
+        let mgr = setup();
+        assert!(mgr.poll_accept(LeConnRole::Peripheral, &[], None).is_pending());
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(conn) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let mtu = conn.att_mtu() as usize;
+
+        let mut handles = [0u8; 6];
+        handles[0..2].copy_from_slice(&first.to_le_bytes());
+        handles[2..4].copy_from_slice(&second.to_le_bytes());
+        handles[4..6].copy_from_slice(&third.to_le_bytes());
+
+        // Values are concatenated, and truncated to the ATT MTU.
+        let mut buffer = [0u8; 64];
+        let len = unwrap!(server.handle_read_multiple(&conn, &mut buffer, att::ATT_READ_MULTIPLE_REQ, &handles));
+        assert_eq!(len, mtu);
+        assert_eq!(buffer[0], att::ATT_READ_MULTIPLE_RSP);
+        assert_eq!(&buffer[1..11], &[1; 10]);
+        assert_eq!(&buffer[11..21], &[2; 10]);
+        assert_eq!(&buffer[21..mtu], &[3; 2]);
+
+        // Values are prefixed with their length, the last one truncated to the ATT MTU.
+        let len =
+            unwrap!(server.handle_read_multiple(&conn, &mut buffer, att::ATT_READ_MULTIPLE_VARIABLE_REQ, &handles));
+        assert_eq!(len, mtu);
+        let Ok(Att::Server(AttServer::Response(AttRsp::ReadMultipleVariable { mut it }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(unwrap!(unwrap!(it.next())), &[1; 10]);
+        assert_eq!(unwrap!(unwrap!(it.next())), &[2; 8]);
+        assert!(it.next().is_none());
+
+        // The first handle that can't be read is reported.
+        handles[2..4].copy_from_slice(&write_only.to_le_bytes());
+        let len = unwrap!(server.handle_read_multiple(&conn, &mut buffer, att::ATT_READ_MULTIPLE_REQ, &handles));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error { request, handle, code }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(request, att::ATT_READ_MULTIPLE_REQ);
+        assert_eq!(handle, write_only);
+        assert_eq!(code, AttErrorCode::READ_NOT_PERMITTED);
+
+        handles[2..4].copy_from_slice(&0xfff0u16.to_le_bytes());
+        let len =
+            unwrap!(server.handle_read_multiple(&conn, &mut buffer, att::ATT_READ_MULTIPLE_VARIABLE_REQ, &handles));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error { request, handle, code }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(request, att::ATT_READ_MULTIPLE_VARIABLE_REQ);
+        assert_eq!(handle, 0xfff0);
+        assert_eq!(code, AttErrorCode::INVALID_HANDLE);
+    }
+    #[test]
+    fn test_attribute_server_prepare_write() {
+        let _ = env_logger::try_init();
+        const MAX_ATTRIBUTES: usize = 64;
+        const CONNECTIONS_MAX: usize = 3;
+        const CCCD_MAX: usize = 64;
+
+        let mut store = [0u8; 32];
+        let mut other_store = [0u8; 8];
+        let mut table: AttributeTable<'_, NoopRawMutex, { MAX_ATTRIBUTES }> = AttributeTable::new();
+        let (handle, other) = {
+            let mut svc = table.add_service(Service {
+                uuid: Uuid::new_long([0; 16]),
+            });
+            let handle = svc
+                .add_characteristic(
+                    Uuid::new_long([1; 16]),
+                    &[CharacteristicProp::Read, CharacteristicProp::Write],
+                    [0u8; 32],
+                    &mut store,
+                )
+                .build()
+                .handle;
+            let other = svc
+                .add_characteristic(
+                    Uuid::new_long([2; 16]),
+                    &[CharacteristicProp::Read, CharacteristicProp::Write],
+                    [0u8; 8],
+                    &mut other_store,
+                )
+                .build()
+                .handle;
+            (handle, other)
+        };
+
+        let server = AttributeServer::<_, DefaultPacketPool, MAX_ATTRIBUTES, CCCD_MAX, CONNECTIONS_MAX>::new(table);
+        let mgr = setup();
+        assert!(mgr.poll_accept(LeConnRole::Peripheral, &[], None).is_pending());
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(conn) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+
+        let mut buffer = [0u8; 64];
+        let read = |server: &AttributeServer<_, _, MAX_ATTRIBUTES, CCCD_MAX, CONNECTIONS_MAX>| {
+            let mut value = [0u8; 64];
+            let len = unwrap!(server.handle_read_req(&conn, &mut value, handle));
+            value[1..len].to_vec()
+        };
+
+        // Prepared writes are echoed, and not applied until executed.
+        let len = unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 0, &[1; 16]));
+        assert_eq!(buffer[0], att::ATT_PREPARE_WRITE_RSP);
+        assert_eq!(&buffer[5..len], &[1; 16]);
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 16, &[2; 8]));
+        assert_eq!(read(&server), [0u8; 32]);
+
+        // The queued writes can be combined into the full value.
+        assert_eq!(server.prepared_write_handle(&conn, 0), Some(handle));
+        assert_eq!(server.prepared_write(&conn, handle, &mut buffer), Some(24));
+        assert_eq!(&buffer[..16], &[1; 16]);
+        assert_eq!(&buffer[16..24], &[2; 8]);
+
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        assert_eq!(&buffer[..len], &[att::ATT_EXECUTE_WRITE_RSP]);
+        let value = read(&server);
+        assert_eq!(&value[..16], &[1; 16]);
+        assert_eq!(&value[16..], &[2; 8]);
+
+        // Cancelled writes are dropped.
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 0, &[3; 16]));
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x00));
+        assert_eq!(&buffer[..len], &[att::ATT_EXECUTE_WRITE_RSP]);
+        assert_eq!(server.prepared_write_handle(&conn, 0), None);
+        assert_eq!(read(&server), value);
+
+        // No write is applied if any of them is invalid.
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 0, &[4; 16]));
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 16, &[4; 17]));
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error {
+            request,
+            handle: h,
+            code,
+        }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(request, att::ATT_EXECUTE_WRITE_REQ);
+        assert_eq!(h, handle);
+        assert_eq!(code, AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+        assert_eq!(read(&server), value);
+
+        // Writes to several attributes, at any offset, are queued and executed together.
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 4, &[6; 4]));
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, other, 4, &[7; 4]));
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 8, &[8; 8]));
+        assert_eq!(server.prepared_write_handle(&conn, 0), Some(handle));
+        assert_eq!(server.prepared_write_handle(&conn, handle), Some(other));
+        assert_eq!(server.prepared_write_handle(&conn, other), None);
+
+        // The combined value of each attribute keeps its current content where it isn't written.
+        assert_eq!(server.prepared_write(&conn, handle, &mut buffer), Some(16));
+        assert_eq!(&buffer[..16], &[1, 1, 1, 1, 6, 6, 6, 6, 8, 8, 8, 8, 8, 8, 8, 8]);
+        assert_eq!(server.prepared_write(&conn, other, &mut buffer), Some(8));
+        assert_eq!(&buffer[..8], &[0, 0, 0, 0, 7, 7, 7, 7]);
+
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        assert_eq!(&buffer[..len], &[att::ATT_EXECUTE_WRITE_RSP]);
+        let value = read(&server);
+        assert_eq!(value, [1, 1, 1, 1, 6, 6, 6, 6, 8, 8, 8, 8, 8, 8, 8, 8]);
+        let mut other_value = [0u8; 16];
+        let len = unwrap!(server.handle_read_req(&conn, &mut other_value, other));
+        assert_eq!(&other_value[1..len], &[0, 0, 0, 0, 7, 7, 7, 7]);
+
+        // Invalid offsets are reported when executing, and nothing is written.
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, other, 0, &[9; 4]));
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 40, &[9; 4]));
+        assert_eq!(buffer[0], att::ATT_PREPARE_WRITE_RSP);
+        assert_eq!(server.prepared_write(&conn, other, &mut buffer), None);
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error { handle: h, code, .. }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(h, handle);
+        assert_eq!(code, AttErrorCode::INVALID_OFFSET);
+        assert_eq!(read(&server), value);
+        let len = unwrap!(server.handle_read_req(&conn, &mut other_value, other));
+        assert_eq!(&other_value[1..len], &[0, 0, 0, 0, 7, 7, 7, 7]);
+
+        // A combined write rejected by the application fails the execution.
+        unwrap!(server.handle_prepare_write(&conn, &mut buffer, other, 0, &[9; 4]));
+        server
+            .prepare_queues
+            .reject(conn.handle(), other, AttErrorCode::VALUE_NOT_ALLOWED);
+        assert_eq!(server.prepared_write_handle(&conn, 0), None);
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error { handle: h, code, .. }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(h, other);
+        assert_eq!(code, AttErrorCode::VALUE_NOT_ALLOWED);
+        let len = unwrap!(server.handle_execute_write(&conn, &mut buffer, 0x01));
+        assert_eq!(&buffer[..len], &[att::ATT_EXECUTE_WRITE_RSP]);
+
+        // Writes that don't fit in the queue are rejected.
+        let mut queued = 0;
+        loop {
+            let len = unwrap!(server.handle_prepare_write(&conn, &mut buffer, handle, 0, &[5; 16]));
+            if buffer[0] == att::ATT_ERROR_RSP {
+                let Ok(Att::Server(AttServer::Response(AttRsp::Error { code, .. }))) = Att::decode(&buffer[..len])
+                else {
+                    panic!("unexpected response");
+                };
+                assert_eq!(code, AttErrorCode::PREPARE_QUEUE_FULL);
+                break;
+            }
+            queued += 1;
+        }
+        assert_eq!(
+            queued,
+            config::GATT_PREPARE_WRITE_QUEUE_SIZE / (PrepareQueue::HEADER_LEN + 16)
+        );
+    }
+
+    #[test]
+    fn test_attribute_server_permissions() {
+        let _ = env_logger::try_init();
+        const MAX_ATTRIBUTES: usize = 64;
+        const CONNECTIONS_MAX: usize = 3;
+        const CCCD_MAX: usize = 8;
+
+        let mut secure_store = [0u8; 4];
+        let mut authorized_store = [0u8; 4];
+        let mut table: AttributeTable<'_, NoopRawMutex, { MAX_ATTRIBUTES }> = AttributeTable::new();
+        let (secure, authorized) = {
+            let mut svc = table.add_service(Service {
+                uuid: Uuid::new_long([0; 16]),
+            });
+            let props = [CharacteristicProp::Read, CharacteristicProp::Write];
+            let mut builder = svc.add_characteristic(Uuid::new_long([1; 16]), &props, [0u8; 4], &mut secure_store);
+            builder.set_permissions(AttributePermissions {
+                read: PermissionLevel::Encrypted,
+                write: PermissionLevel::Authenticated,
+                ..Default::default()
+            });
+            let secure = builder.build().handle;
+            let mut builder = svc.add_characteristic(Uuid::new_long([2; 16]), &props, [0u8; 4], &mut authorized_store);
+            builder.set_permissions(AttributePermissions {
+                read_authorization: true,
+                write_authorization: true,
+                ..Default::default()
+            });
+            (secure, builder.build().handle)
+        };
+
+        let server = AttributeServer::<_, DefaultPacketPool, MAX_ATTRIBUTES, CCCD_MAX, CONNECTIONS_MAX>::new(table);
+        let mgr = setup();
+        assert!(mgr.poll_accept(LeConnRole::Peripheral, &[], None).is_pending());
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(conn) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        unwrap!(server.connect(&conn));
+
+        let mut buffer = [0u8; 64];
+        let mut process = |packet: AttClient| {
+            let len = unwrap!(server.process(&conn, &packet, &mut buffer)).unwrap_or(0);
+            match Att::decode(&buffer[..len]) {
+                Ok(Att::Server(AttServer::Response(AttRsp::Error { code, .. }))) => Err(code),
+                _ => Ok(()),
+            }
+        };
+
+        // The link is not encrypted.
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: secure })),
+            Err(AttErrorCode::INSUFFICIENT_ENCRYPTION)
+        );
+        assert_eq!(
+            process(AttClient::Request(AttReq::Write {
+                handle: secure,
+                data: &[1; 4]
+            })),
+            Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+        );
+        assert_eq!(
+            process(AttClient::Request(AttReq::PrepareWrite {
+                handle: secure,
+                offset: 0,
+                value: &[1; 4]
+            })),
+            Err(AttErrorCode::INSUFFICIENT_AUTHENTICATION)
+        );
+
+        // Authorization is denied without a callback.
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: authorized })),
+            Err(AttErrorCode::INSUFFICIENT_AUTHORISATION)
+        );
+
+        fn authorize(_identity: &Identity, _handle: u16, access: AttributeAccess) -> bool {
+            access == AttributeAccess::Read
+        }
+        server.set_authorize_callback(authorize);
+        assert_eq!(process(AttClient::Request(AttReq::Read { handle: authorized })), Ok(()));
+        assert_eq!(
+            process(AttClient::Request(AttReq::Write {
+                handle: authorized,
+                data: &[1; 4]
+            })),
+            Err(AttErrorCode::INSUFFICIENT_AUTHORISATION)
+        );
+
+        // Permissions are checked for values truncated from a read multiple response.
+        let mut handles = [0u8; 4];
+        handles[0..2].copy_from_slice(&authorized.to_le_bytes());
+        handles[2..4].copy_from_slice(&secure.to_le_bytes());
+        let mut buffer = [0u8; 5];
+        let len = unwrap!(server.handle_read_multiple(&conn, &mut buffer, att::ATT_READ_MULTIPLE_REQ, &handles));
+        let Ok(Att::Server(AttServer::Response(AttRsp::Error { handle, code, .. }))) = Att::decode(&buffer[..len])
+        else {
+            panic!("unexpected response");
+        };
+        assert_eq!(handle, secure);
+        assert_eq!(code, AttErrorCode::INSUFFICIENT_ENCRYPTION);
+    }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn test_attribute_server_robust_caching() {
+        let _ = env_logger::try_init();
+        const MAX_ATTRIBUTES: usize = 64;
+        const CONNECTIONS_MAX: usize = 3;
+        const CCCD_MAX: usize = 8;
+
+        let mut table: AttributeTable<'_, NoopRawMutex, { MAX_ATTRIBUTES }> = AttributeTable::new();
+        unwrap!(crate::gap::GapConfig::default("test").build(&mut table));
+        let server = AttributeServer::<_, DefaultPacketPool, MAX_ATTRIBUTES, CCCD_MAX, CONNECTIONS_MAX>::new(table);
+        let (service_changed, cccd) = unwrap!(server.gatt_handles.service_changed);
+        let features = unwrap!(server.gatt_handles.client_features);
+
+        let mgr = setup();
+        assert!(mgr.poll_accept(LeConnRole::Peripheral, &[], None).is_pending());
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(conn) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        unwrap!(server.connect(&conn));
+
+        let mut buffer = [0u8; 64];
+        let mut process = |packet: AttClient| {
+            let len = unwrap!(server.process(&conn, &packet, &mut buffer)).unwrap_or(0);
+            buffer[..len].to_vec()
+        };
+        let out_of_sync = [
+            att::ATT_ERROR_RSP,
+            att::ATT_READ_REQ,
+            0,
+            0,
+            0x12, // Database out of sync
+        ];
+
+        // The Database Hash can be read by type.
+        let rsp = process(AttClient::Request(AttReq::ReadByType {
+            start: 1,
+            end: 0xffff,
+            attribute_type: DATABASE_HASH.into(),
+        }));
+        assert_eq!(rsp[0], att::ATT_READ_BY_TYPE_RSP);
+        assert_eq!(&rsp[4..], &server.database_hash());
+
+        // Client features can be enabled but not disabled.
+        let rsp = process(AttClient::Request(AttReq::Write {
+            handle: features,
+            data: &[ROBUST_CACHING],
+        }));
+        assert_eq!(rsp, [att::ATT_WRITE_RSP]);
+        let rsp = process(AttClient::Request(AttReq::Write {
+            handle: features,
+            data: &[0],
+        }));
+        assert_eq!(rsp[4], 0x13); // Value not allowed
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: features })),
+            [att::ATT_READ_RSP, 1]
+        );
+
+        // A client that knew another table is told once that it is out of sync.
+        let state = unwrap!(server.get_caching_state(&conn));
+        assert_eq!(state.database_hash, server.database_hash());
+        let stale = CachingState {
+            database_hash: [0; 16],
+            ..state
+        };
+        server.set_caching_state(&conn, stale);
+        assert_eq!(server.get_caching_state(&conn), Some(stale));
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: features })),
+            out_of_sync
+        );
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: features })),
+            [att::ATT_READ_RSP, 1]
+        );
+        assert_eq!(server.get_caching_state(&conn), Some(state));
+
+        // Or sent a Service Changed indication once it enabled them.
+        server.set_caching_state(&conn, stale);
+        assert_eq!(server.service_changed_indication(&conn), None);
+        server.cccd_tables.set_indicate(&conn.peer_identity(), cccd, true);
+        assert_eq!(server.service_changed_indication(&conn), Some(service_changed));
+        assert_eq!(server.service_changed_indication(&conn), None);
+        process(AttClient::Confirmation(att::AttCfm::ConfirmIndication));
+        assert_eq!(server.get_caching_state(&conn), Some(state));
+        assert_eq!(
+            process(AttClient::Request(AttReq::Read { handle: features })),
+            [att::ATT_READ_RSP, 1]
+        );
+    }
 }
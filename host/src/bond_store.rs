@@ -0,0 +1,689 @@
+//! ## Bond store
+//!
+//! Persistent storage of the bonds of the security manager, along with the CCCD values of
+//! bonded clients.
+//!
+//! A bond store is attached with [`Stack::run_bond_store`](crate::Stack::run_bond_store), which
+//! loads the stored bonds and keeps the store up to date while the stack runs: bonds are saved when
+//! pairing completes and when a bonded peer disconnects, and removed when they are deleted from
+//! the stack.
+
+use core::future::Future;
+
+use heapless::Vec;
+
+use crate::attribute_server::CccdTable;
+use crate::security_manager::{BondInformation, BondUsage};
+use crate::{Error, Identity};
+
+/// A bond along with the CCCD values of the bonded client.
+#[derive(Clone, Debug)]
+pub struct StoredBond<const CCCD_MAX: usize> {
+    /// Bond information of the peer
+    pub bond: BondInformation,
+    /// Usage of the bond by the eviction policy
+    pub usage: BondUsage,
+    /// CCCD values written by the peer
+    pub cccd_table: CccdTable<CCCD_MAX>,
+}
+
+/// Persistent storage of bonds.
+///
+/// Bonds are looked up by identity, matching an identity address or an identity resolving key.
+pub trait BondStore<const CCCD_MAX: usize> {
+    /// Load the bond with the peer, `None` if the peer is not bonded.
+    fn load(&mut self, identity: &Identity) -> impl Future<Output = Result<Option<StoredBond<CCCD_MAX>>, Error>>;
+
+    /// Save a bond, replacing the bond with the same peer.
+    fn save(&mut self, bond: &StoredBond<CCCD_MAX>) -> impl Future<Output = Result<(), Error>>;
+
+    /// Remove the bond with the peer, if any.
+    fn remove(&mut self, identity: &Identity) -> impl Future<Output = Result<(), Error>>;
+
+    /// Call `f` for every stored bond.
+    fn iterate<F: FnMut(StoredBond<CCCD_MAX>)>(&mut self, f: F) -> impl Future<Output = Result<(), Error>>;
+}
+
+/// Bond store keeping up to `N` bonds in RAM.
+///
+/// Bonds do not survive a reset, this is mostly useful for tests and for devices with their
+/// own persistence of [`StoredBond`].
+pub struct MemoryBondStore<const N: usize, const CCCD_MAX: usize> {
+    bonds: Vec<StoredBond<CCCD_MAX>, N>,
+}
+
+impl<const N: usize, const CCCD_MAX: usize> Default for MemoryBondStore<N, CCCD_MAX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const CCCD_MAX: usize> MemoryBondStore<N, CCCD_MAX> {
+    /// Create an empty bond store.
+    pub fn new() -> Self {
+        Self { bonds: Vec::new() }
+    }
+
+    /// Stored bonds
+    pub fn bonds(&self) -> &[StoredBond<CCCD_MAX>] {
+        &self.bonds
+    }
+}
+
+impl<const N: usize, const CCCD_MAX: usize> BondStore<CCCD_MAX> for MemoryBondStore<N, CCCD_MAX> {
+    async fn load(&mut self, identity: &Identity) -> Result<Option<StoredBond<CCCD_MAX>>, Error> {
+        Ok(self
+            .bonds
+            .iter()
+            .find(|stored| stored.bond.identity.match_identity(identity))
+            .cloned())
+    }
+
+    async fn save(&mut self, bond: &StoredBond<CCCD_MAX>) -> Result<(), Error> {
+        match self
+            .bonds
+            .iter_mut()
+            .find(|stored| stored.bond.identity.match_identity(&bond.bond.identity))
+        {
+            Some(stored) => *stored = bond.clone(),
+            None => self.bonds.push(bond.clone()).map_err(|_| Error::OutOfMemory)?,
+        }
+        Ok(())
+    }
+
+    async fn remove(&mut self, identity: &Identity) -> Result<(), Error> {
+        self.bonds
+            .retain(|stored| !stored.bond.identity.match_identity(identity));
+        Ok(())
+    }
+
+    async fn iterate<F: FnMut(StoredBond<CCCD_MAX>)>(&mut self, mut f: F) -> Result<(), Error> {
+        for stored in self.bonds.iter() {
+            f(stored.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bond-store-flash")]
+pub use flash::FlashBondStore;
+
+#[cfg(feature = "bond-store-flash")]
+mod flash {
+    use core::ops::Range;
+
+    use bt_hci::param::{AddrKind, BdAddr};
+    use embedded_storage_async::nor_flash::NorFlash;
+
+    use super::{BondStore, StoredBond};
+    use crate::attribute::CCCD;
+    use crate::attribute_server::CccdTable;
+    use crate::connection::SecurityLevel;
+    use crate::cursor::{ReadCursor, WriteCursor};
+    use crate::security_manager::{
+        BondInformation, BondUsage, CentralIdentification, ConnectionSignatureResolvingKey, IdentityResolvingKey,
+        LinkKey, LongTermKey, SigningKey,
+    };
+    use crate::{Error, Identity};
+
+    /// Marks a slot holding a bond, followed by the record version and sequence number
+    const MAGIC: [u8; 4] = *b"TBND";
+    const VERSION: u8 = 1;
+    const HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+    /// Largest record, including padding to the flash write and read sizes
+    const RECORD_MAX: usize = 256;
+
+    /// Bond store keeping bonds in a region of NOR flash.
+    ///
+    /// Each bond takes one erase sector of the region, so the region holds as many bonds as it has
+    /// sectors. A bond record is `139 + 4 * CCCD_MAX` bytes, and has to fit in an erase sector.
+    ///
+    /// An updated bond is written to a free sector before the sector of the previous record is
+    /// erased, so a reset while saving never loses the bond. Records carry a sequence number to tell
+    /// the newest one if both survive. With no free sector left, the bond is updated in place.
+    pub struct FlashBondStore<F: NorFlash, const CCCD_MAX: usize> {
+        flash: F,
+        range: Range<u32>,
+    }
+
+    impl<F: NorFlash, const CCCD_MAX: usize> FlashBondStore<F, CCCD_MAX> {
+        /// Create a bond store in the `range` of the flash, aligned to erase sectors.
+        pub fn new(flash: F, range: Range<u32>) -> Result<Self, Error> {
+            let sector = F::ERASE_SIZE as u32;
+            if !range.start.is_multiple_of(sector) || !range.end.is_multiple_of(sector) || range.end <= range.start {
+                return Err(Error::InvalidValue);
+            }
+            if Self::record_len() > F::ERASE_SIZE.min(RECORD_MAX) {
+                return Err(Error::InsufficientSpace);
+            }
+            Ok(Self { flash, range })
+        }
+
+        /// Release the flash
+        pub fn release(self) -> F {
+            self.flash
+        }
+
+        /// Number of bonds the region holds
+        pub fn capacity(&self) -> usize {
+            ((self.range.end - self.range.start) / F::ERASE_SIZE as u32) as usize
+        }
+
+        fn record_len() -> usize {
+            let unaligned = 139 + 4 * CCCD_MAX;
+            let align = F::WRITE_SIZE.max(F::READ_SIZE);
+            unaligned.div_ceil(align) * align
+        }
+
+        fn slot_offset(&self, slot: usize) -> u32 {
+            self.range.start + (slot * F::ERASE_SIZE) as u32
+        }
+
+        /// Read the record of a slot, `None` if the slot is free
+        async fn read_record(&mut self, slot: usize, buf: &mut [u8; RECORD_MAX]) -> Result<Option<u32>, Error> {
+            let len = Self::record_len();
+            let offset = self.slot_offset(slot);
+            self.flash
+                .read(offset, &mut buf[..len])
+                .await
+                .map_err(|_| Error::Storage)?;
+            if buf[..MAGIC.len()] != MAGIC || buf[MAGIC.len()] != VERSION {
+                return Ok(None);
+            }
+            Ok(Some(u32::from_le_bytes(
+                buf[MAGIC.len() + 1..HEADER_LEN].try_into().unwrap(),
+            )))
+        }
+
+        async fn read_slot(&mut self, slot: usize) -> Result<Option<(u32, StoredBond<CCCD_MAX>)>, Error> {
+            let mut buf = [0; RECORD_MAX];
+            match self.read_record(slot, &mut buf).await? {
+                Some(sequence) => Ok(Some((sequence, decode(&buf[HEADER_LEN..Self::record_len()])?))),
+                None => Ok(None),
+            }
+        }
+
+        async fn erase_slot(&mut self, slot: usize) -> Result<(), Error> {
+            let offset = self.slot_offset(slot);
+            self.flash
+                .erase(offset, offset + F::ERASE_SIZE as u32)
+                .await
+                .map_err(|_| Error::Storage)
+        }
+
+        /// Scan the region for the newest record of the peer, the first free slot and the next
+        /// sequence number
+        async fn scan(&mut self, identity: &Identity) -> Result<Scan, Error> {
+            let mut scan = Scan {
+                newest: None,
+                free: None,
+                next_sequence: 0,
+            };
+            for slot in 0..self.capacity() {
+                match self.read_slot(slot).await? {
+                    Some((sequence, stored)) => {
+                        scan.next_sequence = scan.next_sequence.max(sequence.saturating_add(1));
+                        if stored.bond.identity.match_identity(identity)
+                            && scan.newest.is_none_or(|(_, newest)| sequence > newest)
+                        {
+                            scan.newest = Some((slot, sequence));
+                        }
+                    }
+                    None => {
+                        scan.free.get_or_insert(slot);
+                    }
+                }
+            }
+            Ok(scan)
+        }
+    }
+
+    struct Scan {
+        /// Slot and sequence number of the newest record of the peer
+        newest: Option<(usize, u32)>,
+        free: Option<usize>,
+        next_sequence: u32,
+    }
+
+    impl<F: NorFlash, const CCCD_MAX: usize> BondStore<CCCD_MAX> for FlashBondStore<F, CCCD_MAX> {
+        async fn load(&mut self, identity: &Identity) -> Result<Option<StoredBond<CCCD_MAX>>, Error> {
+            match self.scan(identity).await?.newest {
+                Some((slot, _)) => Ok(self.read_slot(slot).await?.map(|(_, stored)| stored)),
+                None => Ok(None),
+            }
+        }
+
+        async fn save(&mut self, bond: &StoredBond<CCCD_MAX>) -> Result<(), Error> {
+            let scan = self.scan(&bond.bond.identity).await?;
+            let len = Self::record_len();
+            let mut buf = [0xff; RECORD_MAX];
+            encode(bond, &mut buf[HEADER_LEN..len])?;
+
+            if let Some((slot, _)) = scan.newest {
+                // Nothing to write if the stored bond is unchanged
+                let mut stored = [0xff; RECORD_MAX];
+                self.read_record(slot, &mut stored).await?;
+                if stored[HEADER_LEN..len] == buf[HEADER_LEN..len] {
+                    return Ok(());
+                }
+            }
+
+            let slot = match (scan.free, scan.newest) {
+                (Some(free), _) => free,
+                (None, Some((slot, _))) => {
+                    self.erase_slot(slot).await?;
+                    slot
+                }
+                (None, None) => return Err(Error::OutOfMemory),
+            };
+            buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+            buf[MAGIC.len()] = VERSION;
+            buf[MAGIC.len() + 1..HEADER_LEN].copy_from_slice(&scan.next_sequence.to_le_bytes());
+            let offset = self.slot_offset(slot);
+            self.flash
+                .write(offset, &buf[..len])
+                .await
+                .map_err(|_| Error::Storage)?;
+
+            // Only now drop the previous record, along with any left by an interrupted save
+            for old in 0..self.capacity() {
+                if old == slot {
+                    continue;
+                }
+                if let Some((_, stored)) = self.read_slot(old).await? {
+                    if stored.bond.identity.match_identity(&bond.bond.identity) {
+                        self.erase_slot(old).await?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn remove(&mut self, identity: &Identity) -> Result<(), Error> {
+            for slot in 0..self.capacity() {
+                if let Some((_, stored)) = self.read_slot(slot).await? {
+                    if stored.bond.identity.match_identity(identity) {
+                        self.erase_slot(slot).await?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        async fn iterate<G: FnMut(StoredBond<CCCD_MAX>)>(&mut self, mut f: G) -> Result<(), Error> {
+            for slot in 0..self.capacity() {
+                if let Some((_, stored)) = self.read_slot(slot).await? {
+                    // Skip a record superseded by a save interrupted before erasing it
+                    if self
+                        .scan(&stored.bond.identity)
+                        .await?
+                        .newest
+                        .is_some_and(|(newest, _)| newest == slot)
+                    {
+                        f(stored);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn encode_signing_key(w: &mut WriteCursor<'_>, key: &Option<SigningKey>) -> Result<(), Error> {
+        match key {
+            Some(key) => {
+                w.write(1u8)?;
+                w.append(&key.csrk.to_le_bytes())?;
+                w.write(key.counter)?;
+            }
+            None => {
+                w.write(0u8)?;
+                w.append(&[0; 20])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_signing_key(r: &mut ReadCursor<'_>) -> Result<Option<SigningKey>, Error> {
+        let present: u8 = r.read()?;
+        let csrk = ConnectionSignatureResolvingKey::from_le_bytes(r.slice(16)?.try_into().unwrap());
+        let counter: u32 = r.read()?;
+        Ok((present != 0).then_some(SigningKey { csrk, counter }))
+    }
+
+    fn encode<const CCCD_MAX: usize>(stored: &StoredBond<CCCD_MAX>, buf: &mut [u8]) -> Result<(), Error> {
+        let bond = &stored.bond;
+        let mut w = WriteCursor::new(buf);
+        w.append(bond.identity.bd_addr.raw())?;
+        w.write(bond.identity_kind.as_raw())?;
+        match bond.identity.irk {
+            Some(irk) => {
+                w.write(1u8)?;
+                w.append(&irk.to_le_bytes())?;
+            }
+            None => {
+                w.write(0u8)?;
+                w.append(&[0; 16])?;
+            }
+        }
+        w.append(&bond.ltk.to_le_bytes())?;
+        w.write(bond.encryption_key_size)?;
+        w.write(match bond.security_level {
+            SecurityLevel::NoEncryption => 0u8,
+            SecurityLevel::Encrypted => 1,
+            SecurityLevel::EncryptedAuthenticated => 2,
+        })?;
+        w.write(bond.is_bonded as u8)?;
+        match bond.central_identification {
+            Some(id) => {
+                w.write(1u8)?;
+                w.write(id.ediv)?;
+                w.append(&id.rand)?;
+            }
+            None => {
+                w.write(0u8)?;
+                w.append(&[0; 10])?;
+            }
+        }
+        encode_signing_key(&mut w, &bond.local_signing_key)?;
+        encode_signing_key(&mut w, &bond.peer_signing_key)?;
+        match bond.link_key {
+            Some(link_key) => {
+                w.write(1u8)?;
+                w.append(&link_key.to_le_bytes())?;
+            }
+            None => {
+                w.write(0u8)?;
+                w.append(&[0; 16])?;
+            }
+        }
+        w.write(stored.usage.pinned as u8)?;
+        w.append(&stored.usage.created.to_le_bytes())?;
+        w.append(&stored.usage.last_used.to_le_bytes())?;
+        for (handle, cccd) in stored.cccd_table.inner().iter() {
+            w.write(*handle)?;
+            w.write(cccd.raw())?;
+        }
+        Ok(())
+    }
+
+    fn decode<const CCCD_MAX: usize>(buf: &[u8]) -> Result<StoredBond<CCCD_MAX>, Error> {
+        let mut r = ReadCursor::new(buf);
+        let bd_addr = BdAddr::new(r.slice(6)?.try_into().unwrap());
+        let identity_kind = match r.read::<u8>()? {
+            0 => AddrKind::PUBLIC,
+            1 => AddrKind::RANDOM,
+            _ => return Err(Error::Storage),
+        };
+        let has_irk: u8 = r.read()?;
+        let irk = IdentityResolvingKey::from_le_bytes(r.slice(16)?.try_into().unwrap());
+        let ltk = LongTermKey::from_le_bytes(r.slice(16)?.try_into().unwrap());
+        let encryption_key_size: u8 = r.read()?;
+        if !(7..=16).contains(&encryption_key_size) {
+            return Err(Error::Storage);
+        }
+        let security_level = match r.read::<u8>()? {
+            0 => SecurityLevel::NoEncryption,
+            1 => SecurityLevel::Encrypted,
+            2 => SecurityLevel::EncryptedAuthenticated,
+            _ => return Err(Error::Storage),
+        };
+        let is_bonded = r.read::<u8>()? != 0;
+        let has_central_identification: u8 = r.read()?;
+        let ediv: u16 = r.read()?;
+        let rand: [u8; 8] = r.slice(8)?.try_into().unwrap();
+        let local_signing_key = decode_signing_key(&mut r)?;
+        let peer_signing_key = decode_signing_key(&mut r)?;
+        let has_link_key: u8 = r.read()?;
+        let link_key = LinkKey::from_le_bytes(r.slice(16)?.try_into().unwrap());
+        let usage = BondUsage {
+            pinned: r.read::<u8>()? != 0,
+            created: u64::from_le_bytes(r.slice(8)?.try_into().unwrap()),
+            last_used: u64::from_le_bytes(r.slice(8)?.try_into().unwrap()),
+        };
+        let mut cccd_values = [(0, CCCD::default()); CCCD_MAX];
+        for (handle, cccd) in cccd_values.iter_mut() {
+            *handle = r.read()?;
+            *cccd = CCCD::from(r.read::<u16>()?);
+        }
+        Ok(StoredBond {
+            bond: BondInformation {
+                ltk,
+                encryption_key_size,
+                identity: Identity {
+                    bd_addr,
+                    irk: (has_irk != 0).then_some(irk),
+                },
+                identity_kind,
+                is_bonded,
+                security_level,
+                central_identification: (has_central_identification != 0)
+                    .then_some(CentralIdentification { ediv, rand }),
+                local_signing_key,
+                peer_signing_key,
+                link_key: (has_link_key != 0).then_some(link_key),
+            },
+            usage,
+            cccd_table: CccdTable::new(cccd_values),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::attribute::CCCD;
+    use crate::connection::SecurityLevel;
+    use crate::prelude::*;
+
+    fn stored_bond(addr: u8, ltk: u128) -> StoredBond<2> {
+        let identity = Identity {
+            bd_addr: BdAddr::new([addr, 2, 3, 4, 5, 6]),
+            irk: Some(IdentityResolvingKey::new(0x1234 + addr as u128)),
+        };
+        let mut bond = BondInformation::new(identity, LongTermKey::new(ltk), SecurityLevel::Encrypted, true);
+        bond.peer_signing_key = Some(SigningKey::new(ConnectionSignatureResolvingKey::new(ltk + 1)));
+        bond.link_key = Some(LinkKey::new(ltk + 2));
+        StoredBond {
+            bond,
+            usage: BondUsage {
+                pinned: addr == 2,
+                created: ltk as u64,
+                last_used: ltk as u64 + 100,
+            },
+            cccd_table: CccdTable::new([(3, CCCD::from(1)), (7, CCCD::from(2))]),
+        }
+    }
+
+    #[test]
+    fn memory_store_replaces_bond_of_same_peer() {
+        let mut store: MemoryBondStore<2, 2> = MemoryBondStore::new();
+        block_on(async {
+            store.save(&stored_bond(1, 10)).await.unwrap();
+            store.save(&stored_bond(2, 20)).await.unwrap();
+            store.save(&stored_bond(1, 11)).await.unwrap();
+            assert_eq!(store.bonds().len(), 2);
+
+            let loaded = store.load(&stored_bond(1, 0).bond.identity).await.unwrap().unwrap();
+            assert_eq!(loaded.bond.ltk, LongTermKey::new(11));
+            assert_eq!(loaded.cccd_table.inner()[1], (7, CCCD::from(2)));
+
+            // Full, a new peer does not fit
+            assert!(store.save(&stored_bond(3, 30)).await.is_err());
+
+            store.remove(&stored_bond(2, 0).bond.identity).await.unwrap();
+            assert!(store.load(&stored_bond(2, 0).bond.identity).await.unwrap().is_none());
+            let mut count = 0;
+            store.iterate(|_| count += 1).await.unwrap();
+            assert_eq!(count, 1);
+        });
+    }
+
+    #[cfg(feature = "bond-store-flash")]
+    mod flash {
+        use embedded_storage_async::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+        use super::*;
+
+        const SECTOR: usize = 256;
+
+        /// RAM backed flash of 4 sectors, checking erase before write
+        struct MockFlash {
+            data: [u8; 4 * SECTOR],
+        }
+
+        impl MockFlash {
+            fn new() -> Self {
+                Self {
+                    data: [0xff; 4 * SECTOR],
+                }
+            }
+        }
+
+        impl ErrorType for MockFlash {
+            type Error = NorFlashErrorKind;
+        }
+
+        impl ReadNorFlash for MockFlash {
+            const READ_SIZE: usize = 1;
+
+            async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                let offset = offset as usize;
+                let data = self
+                    .data
+                    .get(offset..offset + bytes.len())
+                    .ok_or(NorFlashErrorKind::OutOfBounds)?;
+                bytes.copy_from_slice(data);
+                Ok(())
+            }
+
+            fn capacity(&self) -> usize {
+                self.data.len()
+            }
+        }
+
+        impl NorFlash for MockFlash {
+            const WRITE_SIZE: usize = 4;
+            const ERASE_SIZE: usize = SECTOR;
+
+            async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+                if !(from as usize).is_multiple_of(SECTOR) || !(to as usize).is_multiple_of(SECTOR) {
+                    return Err(NorFlashErrorKind::NotAligned);
+                }
+                self.data[from as usize..to as usize].fill(0xff);
+                Ok(())
+            }
+
+            async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+                let offset = offset as usize;
+                if !offset.is_multiple_of(Self::WRITE_SIZE) || !bytes.len().is_multiple_of(Self::WRITE_SIZE) {
+                    return Err(NorFlashErrorKind::NotAligned);
+                }
+                for (cell, byte) in self.data[offset..offset + bytes.len()].iter_mut().zip(bytes) {
+                    // NOR flash only clears bits
+                    assert_eq!(*cell, 0xff, "write to flash that is not erased");
+                    *cell = *byte;
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn flash_store_round_trip() {
+            let mut store: FlashBondStore<MockFlash, 2> =
+                FlashBondStore::new(MockFlash::new(), SECTOR as u32..3 * SECTOR as u32).unwrap();
+            assert_eq!(store.capacity(), 2);
+            block_on(async {
+                let mut legacy = stored_bond(1, 10);
+                legacy.bond.central_identification = Some(CentralIdentification {
+                    ediv: 0x1234,
+                    rand: [1, 2, 3, 4, 5, 6, 7, 8],
+                });
+                legacy.bond.identity.irk = None;
+                legacy.bond.identity_kind = AddrKind::RANDOM;
+                legacy.bond.link_key = None;
+                store.save(&legacy).await.unwrap();
+                store.save(&stored_bond(2, 20)).await.unwrap();
+                // No free sector left, overwrites the bond of the same peer in place
+                store.save(&stored_bond(2, 21)).await.unwrap();
+                assert!(store.save(&stored_bond(3, 30)).await.is_err());
+
+                let loaded = store.load(&legacy.bond.identity).await.unwrap().unwrap();
+                assert_eq!(loaded.bond, legacy.bond);
+                assert_eq!(loaded.cccd_table.inner(), legacy.cccd_table.inner());
+                let loaded = store.load(&stored_bond(2, 0).bond.identity).await.unwrap().unwrap();
+                assert_eq!(loaded.bond, stored_bond(2, 21).bond);
+                assert_eq!(loaded.usage, stored_bond(2, 21).usage);
+
+                store.remove(&legacy.bond.identity).await.unwrap();
+                let mut count = 0;
+                store.iterate(|_| count += 1).await.unwrap();
+                assert_eq!(count, 1);
+            });
+
+            // Bonds survive recreating the store, and the region is the only flash touched
+            let flash = store.release();
+            assert!(flash.data[..SECTOR].iter().all(|b| *b == 0xff));
+            assert!(flash.data[3 * SECTOR..].iter().all(|b| *b == 0xff));
+            let mut store: FlashBondStore<MockFlash, 2> =
+                FlashBondStore::new(flash, SECTOR as u32..3 * SECTOR as u32).unwrap();
+            block_on(async {
+                let loaded = store.load(&stored_bond(2, 0).bond.identity).await.unwrap().unwrap();
+                assert_eq!(loaded.bond.ltk, LongTermKey::new(21));
+            });
+        }
+
+        #[test]
+        fn flash_store_writes_update_before_erasing() {
+            let mut store: FlashBondStore<MockFlash, 2> =
+                FlashBondStore::new(MockFlash::new(), 0..4 * SECTOR as u32).unwrap();
+            let identity = stored_bond(1, 0).bond.identity;
+            block_on(async {
+                store.save(&stored_bond(1, 10)).await.unwrap();
+                store.save(&stored_bond(1, 11)).await.unwrap();
+            });
+            // The update went to the next sector, then the first one was erased
+            let mut flash = store.release();
+            assert!(flash.data[..SECTOR].iter().all(|b| *b == 0xff));
+            let superseded: [u8; SECTOR] = flash.data[SECTOR..2 * SECTOR].try_into().unwrap();
+
+            let mut store: FlashBondStore<MockFlash, 2> = FlashBondStore::new(flash, 0..4 * SECTOR as u32).unwrap();
+            block_on(async {
+                store.save(&stored_bond(1, 12)).await.unwrap();
+            });
+            // Unchanged bonds are not written again
+            flash = store.release();
+            let saved = flash.data;
+            let mut store: FlashBondStore<MockFlash, 2> = FlashBondStore::new(flash, 0..4 * SECTOR as u32).unwrap();
+            block_on(async {
+                store.save(&stored_bond(1, 12)).await.unwrap();
+            });
+            flash = store.release();
+            assert_eq!(flash.data, saved);
+
+            // A reset before erasing leaves the previous record behind, the newest one wins
+            flash.data[2 * SECTOR..3 * SECTOR].copy_from_slice(&superseded);
+            let mut store: FlashBondStore<MockFlash, 2> = FlashBondStore::new(flash, 0..4 * SECTOR as u32).unwrap();
+            block_on(async {
+                let loaded = store.load(&identity).await.unwrap().unwrap();
+                assert_eq!(loaded.bond.ltk, LongTermKey::new(12));
+                let mut ltks = Vec::<LongTermKey, 4>::new();
+                store
+                    .iterate(|stored| ltks.push(stored.bond.ltk).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(ltks.as_slice(), &[LongTermKey::new(12)]);
+
+                store.remove(&identity).await.unwrap();
+                assert!(store.load(&identity).await.unwrap().is_none());
+            });
+            assert!(store.release().data.iter().all(|b| *b == 0xff));
+        }
+
+        #[test]
+        fn flash_store_rejects_record_larger_than_sector() {
+            assert!(FlashBondStore::<MockFlash, 64>::new(MockFlash::new(), 0..SECTOR as u32).is_err());
+        }
+    }
+}
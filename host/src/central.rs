@@ -1,5 +1,7 @@
 //! Functionality for the BLE central role.
-use bt_hci::cmd::le::{LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeCreateConn, LeExtCreateConn};
+use bt_hci::cmd::le::{
+    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeCreateConn, LeExtCreateConn, LeSetRandomAddr,
+};
 use bt_hci::controller::{Controller, ControllerCmdAsync, ControllerCmdSync};
 use bt_hci::param::{AddrKind, BdAddr, InitiatingPhy, LeConnRole, PhyParams};
 use embassy_futures::select::{select, Either};
@@ -22,6 +24,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
     where
         C: ControllerCmdSync<LeClearFilterAcceptList>
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetRandomAddr>
             + ControllerCmdAsync<LeCreateConn>,
     {
         if config.scan_config.filter_accept_list.is_empty() {
@@ -33,6 +36,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
             host.connect_command_state.cancel(true);
         });
         host.connect_command_state.request().await;
+        host.update_random_address().await?;
 
         self.set_accept_filter(config.scan_config.filter_accept_list).await?;
 
@@ -42,7 +46,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
             true,
             AddrKind::PUBLIC,
             BdAddr::default(),
-            host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_address().map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
             bt_hci_duration(config.connect_params.min_connection_interval),
             bt_hci_duration(config.connect_params.max_connection_interval),
             config.connect_params.max_latency,
@@ -75,6 +79,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
     where
         C: ControllerCmdSync<LeClearFilterAcceptList>
             + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetRandomAddr>
             + ControllerCmdAsync<LeExtCreateConn>,
     {
         if config.scan_config.filter_accept_list.is_empty() {
@@ -87,6 +92,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
             host.connect_command_state.cancel(true);
         });
         host.connect_command_state.request().await;
+        host.update_random_address().await?;
 
         self.set_accept_filter(config.scan_config.filter_accept_list).await?;
 
@@ -104,7 +110,7 @@ impl<'stack, C: Controller, P: PacketPool> Central<'stack, C, P> {
 
         host.async_command(LeExtCreateConn::new(
             true,
-            host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_address().map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
             AddrKind::PUBLIC,
             BdAddr::default(),
             phy_params,
@@ -4,33 +4,73 @@ use core::task::{Context, Poll};
 
 use bt_hci::controller::{blocking, Controller};
 use bt_hci::param::ConnHandle;
-use bt_hci::FromHciBytes;
+use bt_hci::{FromHciBytes, WriteHci};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, WithTimeout};
+use heapless::Vec;
 
+#[cfg(any(feature = "gatt", not(feature = "security")))]
+use crate::connection::SecurityLevel;
 use crate::connection_manager::ConnectionManager;
 use crate::cursor::WriteCursor;
 use crate::host::BleHost;
 #[cfg(not(feature = "l2cap-sdu-reassembly-optimization"))]
 use crate::l2cap::sar::PacketReassembly;
-use crate::l2cap::L2capChannel;
+use crate::l2cap::{L2capChannel, L2capConnectError, L2capConnectRequest, L2capSecurity, L2CAP_MAX_ENHANCED_CHANNELS};
 use crate::pdu::{Pdu, Sdu};
 use crate::prelude::{ConnectionEvent, L2capChannelConfig};
+#[cfg(feature = "gatt")]
+use crate::types::l2cap::L2CAP_PSM_EATT;
 use crate::types::l2cap::{
-    CommandRejectRes, ConnParamUpdateReq, ConnParamUpdateRes, DisconnectionReq, DisconnectionRes, L2capSignalCode,
-    L2capSignalHeader, LeCreditConnReq, LeCreditConnRes, LeCreditConnResultCode, LeCreditFlowInd,
+    CommandRejectReason, CommandRejectRes, ConnParamUpdateReq, ConnParamUpdateRes, CreditConnReconfigReq,
+    CreditConnReconfigRes, CreditConnReconfigResultCode, CreditConnReq, CreditConnRes, DisconnectionReq,
+    DisconnectionRes, EchoReq, EchoRes, InformationReq, InformationRes, L2capHeader, L2capSignal, L2capSignalCode,
+    L2capSignalHeader, LeCreditConnReq, LeCreditConnRes, LeCreditConnResultCode, LeCreditFlowInd, L2CAP_ECFC_MIN_MTU,
+    L2CAP_LE_SIGNAL_MTU,
 };
 use crate::{config, BleHostError, Error, PacketPool};
 
 const BASE_ID: u16 = 0x40;
 
+/// Response timeout (RTX) of signaling requests sent by us, after which the link is disconnected.
+const L2CAP_RTX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of signaling requests that can be awaiting a response at the same time.
+const L2CAP_PENDING_SIGNALS: usize = 4;
+
+type ChannelGroup<'d, P> = Vec<L2capChannel<'d, P>, L2CAP_MAX_ENHANCED_CHANNELS>;
+type ChannelSdu<P> = (ChannelIndex, Pdu<P>);
+
 struct State<'d, P> {
     next_req_id: u8,
     channels: &'d mut [ChannelStorage<P>],
     accept_waker: WakerRegistration,
     create_waker: WakerRegistration,
     disconnect_waker: WakerRegistration,
+    reconfigure: Option<PendingReconfigure>,
+    reconfigure_waker: WakerRegistration,
+    reconfigure_response: Option<ReconfigureResponse>,
+    response_waker: WakerRegistration,
+    pending: Vec<PendingSignal, L2CAP_PENDING_SIGNALS>,
+    pending_changed: bool,
+    pending_waker: WakerRegistration,
+    param_update_requests: Vec<(ConnHandle, u8), L2CAP_PENDING_SIGNALS>,
+}
+
+// Signaling request sent by us, awaiting the peer's response.
+struct PendingSignal {
+    conn: ConnHandle,
+    identifier: u8,
+    deadline: Instant,
+}
+
+// Reconfiguration request sent by us, awaiting the peer's result.
+struct PendingReconfigure {
+    conn: ConnHandle,
+    identifier: u8,
+    result: Option<u16>,
 }
 
 /// Channel manager for L2CAP channels used directly by clients.
@@ -90,6 +130,63 @@ impl<P> State<'_, P> {
         next
     }
 
+    // Start the response timeout of a request we sent.
+    fn expect_response(&mut self, conn: ConnHandle, identifier: u8) {
+        let pending = PendingSignal {
+            conn,
+            identifier,
+            deadline: Instant::now() + L2CAP_RTX_TIMEOUT,
+        };
+        if self.pending.push(pending).is_err() {
+            warn!(
+                "[l2cap][conn = {:?}] too many pending signals, not tracking request {}",
+                conn, identifier
+            );
+            return;
+        }
+        self.pending_changed = true;
+        self.pending_waker.wake();
+    }
+
+    fn reconfigure_result(&self, conn: ConnHandle, req: &CreditConnReconfigReq) -> CreditConnReconfigResultCode {
+        if req.mtu < L2CAP_ECFC_MIN_MTU || req.mps < L2CAP_ECFC_MIN_MTU {
+            return CreditConnReconfigResultCode::UnacceptableParameters;
+        }
+        for dcid in req.dcids.iter() {
+            let Some(chan) = self.channels.iter().find(|chan| {
+                chan.state == ChannelState::Connected
+                    && chan.enhanced
+                    && chan.conn == Some(conn)
+                    && chan.peer_cid == *dcid
+            }) else {
+                return CreditConnReconfigResultCode::InvalidDestinationCid;
+            };
+            if req.mtu < chan.mtu {
+                return CreditConnReconfigResultCode::MtuReductionNotAllowed;
+            }
+            if req.dcids.len() > 1 && req.mps < chan.mps {
+                return CreditConnReconfigResultCode::MpsReductionNotAllowed;
+            }
+        }
+        CreditConnReconfigResultCode::Success
+    }
+
+    fn enhanced_connect_result(&self, conn: ConnHandle, req: &CreditConnReq) -> LeCreditConnResultCode {
+        if req.mtu < L2CAP_ECFC_MIN_MTU || req.mps < L2CAP_ECFC_MIN_MTU {
+            return LeCreditConnResultCode::UnacceptableParameters;
+        }
+        for (i, scid) in req.scids.iter().enumerate() {
+            let allocated = req.scids[..i].contains(scid)
+                || self.channels.iter().any(|chan| {
+                    chan.state != ChannelState::Disconnected && chan.conn == Some(conn) && chan.peer_cid == *scid
+                });
+            if allocated {
+                return LeCreditConnResultCode::ScidAlreadyAllocated;
+            }
+        }
+        LeCreditConnResultCode::Success
+    }
+
     fn inc_ref(&mut self, index: ChannelIndex) {
         let state = &mut self.channels[index.0 as usize];
         state.refcount = unwrap!(state.refcount.checked_add(1), "Too many references to the same channel");
@@ -105,6 +202,14 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
                 accept_waker: WakerRegistration::new(),
                 create_waker: WakerRegistration::new(),
                 disconnect_waker: WakerRegistration::new(),
+                reconfigure: None,
+                reconfigure_waker: WakerRegistration::new(),
+                reconfigure_response: None,
+                response_waker: WakerRegistration::new(),
+                pending: Vec::new(),
+                pending_changed: false,
+                pending_waker: WakerRegistration::new(),
+                param_update_requests: Vec::new(),
             }),
         }
     }
@@ -113,6 +218,10 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         self.state.borrow_mut().next_request_id()
     }
 
+    fn expect_response(&self, conn: ConnHandle, identifier: u8) {
+        self.state.borrow_mut().expect_response(conn, identifier)
+    }
+
     pub(crate) fn psm(&self, index: ChannelIndex) -> u16 {
         self.with_mut(|state| {
             let chan = &mut state.channels[index.0 as usize];
@@ -120,6 +229,17 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         })
     }
 
+    pub(crate) fn is_connected(&self, index: ChannelIndex) -> bool {
+        self.with_mut(|state| state.channels[index.0 as usize].state == ChannelState::Connected)
+    }
+
+    pub(crate) fn mtu(&self, index: ChannelIndex) -> u16 {
+        self.with_mut(|state| {
+            let chan = &mut state.channels[index.0 as usize];
+            chan.mtu
+        })
+    }
+
     pub(crate) fn disconnect(&self, index: ChannelIndex) {
         self.with_mut(|state| {
             let chan = &mut state.channels[index.0 as usize];
@@ -145,8 +265,14 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
                 storage.close();
             }
         }
+        if matches!(&state.reconfigure_response, Some(response) if response.handle == conn) {
+            state.reconfigure_response = None;
+        }
+        state.pending.retain(|pending| pending.conn != conn);
+        state.param_update_requests.retain(|(handle, _)| *handle != conn);
         state.accept_waker.wake();
         state.create_waker.wake();
+        state.reconfigure_waker.wake();
         Ok(())
     }
 
@@ -168,11 +294,19 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         Err(Error::NoChannelAvailable)
     }
 
+    // Release channels allocated for a request that was never completed.
+    fn release(&self, indices: &[ChannelIndex]) {
+        let mut state = self.state.borrow_mut();
+        for index in indices {
+            state.channels[index.0 as usize].close();
+        }
+    }
+
     pub(crate) async fn accept<T: Controller>(
         &'d self,
         conn: ConnHandle,
         psm: &[u16],
-        config: &L2capChannelConfig,
+        config: &L2capChannelConfig<'_>,
         ble: &BleHost<'d, T, P>,
     ) -> Result<L2capChannel<'d, P>, BleHostError<T::Error>> {
         let L2capChannelConfig {
@@ -180,6 +314,7 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             mps,
             flow_policy,
             initial_credits,
+            ..
         } = config;
 
         let mtu = mtu.unwrap_or(P::MTU as u16 - 6);
@@ -188,64 +323,93 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             return Err(Error::InsufficientSpace.into());
         }
 
-        // Wait until we find a channel for our connection in the connecting state matching our PSM.
-        let (channel, req_id, mps, mtu, cid, credits) = poll_fn(|cx| {
-            let mut state = self.state.borrow_mut();
-            state.accept_waker.register(cx.waker());
-            for (idx, chan) in state.channels.iter_mut().enumerate() {
-                match chan.state {
-                    ChannelState::PeerConnecting(req_id) if chan.conn == Some(conn) && psm.contains(&chan.psm) => {
-                        chan.mtu = chan.mtu.min(mtu);
-                        chan.mps = chan.mps.min(mps);
-                        chan.flow_control = CreditFlowControl::new(
-                            *flow_policy,
-                            initial_credits.unwrap_or(config::L2CAP_RX_QUEUE_SIZE.min(P::capacity()) as u16),
-                        );
-                        chan.state = ChannelState::Connected;
-                        let mps = chan.mps;
-                        let mtu = chan.mtu;
-                        let cid = chan.cid;
-                        let available = chan.flow_control.available();
-                        if chan.refcount != 0 {
-                            state.print(true);
-                            panic!("unexpected refcount");
-                        }
-                        assert_eq!(chan.refcount, 0);
-                        let index = ChannelIndex(idx as u8);
+        let mut tx = [0; 18];
+        loop {
+            // Wait until we find a channel for our connection in the connecting state matching our PSM.
+            let accepted = poll_fn(|cx| {
+                let mut state = self.state.borrow_mut();
+                state.accept_waker.register(cx.waker());
+                for (idx, chan) in state.channels.iter_mut().enumerate() {
+                    match chan.state {
+                        ChannelState::PeerConnecting(req_id)
+                            if !chan.enhanced && chan.conn == Some(conn) && psm.contains(&chan.psm) =>
+                        {
+                            let result = check_security(&ble.connections, conn, chan.psm, &config.security(chan.psm));
+                            if result != LeCreditConnResultCode::Success {
+                                chan.close();
+                                return Poll::Ready(Err((req_id, result)));
+                            }
+                            chan.mtu = chan.mtu.min(mtu);
+                            chan.mps = chan.mps.min(mps);
+                            chan.flow_control = CreditFlowControl::new(
+                                *flow_policy,
+                                initial_credits.unwrap_or(config::L2CAP_RX_QUEUE_SIZE.min(P::capacity()) as u16),
+                            );
+                            chan.state = ChannelState::Connected;
+                            let mps = chan.mps;
+                            let mtu = chan.mtu;
+                            let cid = chan.cid;
+                            let available = chan.flow_control.available();
+                            if chan.refcount != 0 {
+                                state.print(true);
+                                panic!("unexpected refcount");
+                            }
+                            assert_eq!(chan.refcount, 0);
+                            let index = ChannelIndex(idx as u8);
 
-                        state.inc_ref(index);
-                        return Poll::Ready((L2capChannel::new(index, self), req_id, mps, mtu, cid, available));
+                            state.inc_ref(index);
+                            return Poll::Ready(Ok((L2capChannel::new(index, self), req_id, mps, mtu, cid, available)));
+                        }
+                        _ => {}
                     }
-                    _ => {}
+                }
+                Poll::Pending
+            })
+            .await;
+
+            match accepted {
+                Ok((channel, req_id, mps, mtu, cid, credits)) => {
+                    // Respond that we accept the channel.
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &LeCreditConnRes {
+                            mps,
+                            dcid: cid,
+                            mtu,
+                            credits,
+                            result: LeCreditConnResultCode::Success,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
+                    return Ok(channel);
+                }
+                Err((req_id, result)) => {
+                    warn!("[l2cap][conn = {:?}] refusing channel: {:?}", conn, result);
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &LeCreditConnRes {
+                            mps: 0,
+                            dcid: 0,
+                            mtu: 0,
+                            credits: 0,
+                            result,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
                 }
             }
-            Poll::Pending
-        })
-        .await;
-
-        let mut tx = [0; 18];
-        // Respond that we accept the channel.
-        ble.l2cap_signal(
-            conn,
-            req_id,
-            &LeCreditConnRes {
-                mps,
-                dcid: cid,
-                mtu,
-                credits,
-                result: LeCreditConnResultCode::Success,
-            },
-            &mut tx[..],
-        )
-        .await?;
-        Ok(channel)
+        }
     }
 
     pub(crate) async fn create<T: Controller>(
         &'d self,
         conn: ConnHandle,
         psm: u16,
-        config: &L2capChannelConfig,
+        config: &L2capChannelConfig<'_>,
         ble: &BleHost<'_, T, P>,
     ) -> Result<L2capChannel<'d, P>, BleHostError<T::Error>> {
         let L2capChannelConfig {
@@ -253,6 +417,7 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             mps,
             flow_policy,
             initial_credits,
+            ..
         } = config;
 
         let req_id = self.next_request_id();
@@ -286,6 +451,7 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             credits,
         };
         ble.l2cap_signal(conn, req_id, &command, &mut tx[..]).await?;
+        self.expect_response(conn, req_id);
 
         // Wait until a response is accepted.
         poll_fn(|cx| self.poll_created(conn, idx, ble, Some(cx))).await
@@ -312,9 +478,13 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         assert_eq!(Some(conn), storage.conn);
 
         match storage.state {
-            ChannelState::Disconnecting | ChannelState::PeerDisconnecting => {
+            ChannelState::Disconnecting | ChannelState::PeerDisconnecting(_) => {
                 return Poll::Ready(Err(Error::Disconnected.into()));
             }
+            ChannelState::Refused(reason) => {
+                storage.close();
+                return Poll::Ready(Err(Error::L2capConnectionRefused(reason).into()));
+            }
             ChannelState::Connected => {
                 if storage.refcount != 0 {
                     state.print(true);
@@ -329,6 +499,344 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         Poll::Pending
     }
 
+    pub(crate) async fn accept_group<T: Controller>(
+        &'d self,
+        conn: ConnHandle,
+        psm: &[u16],
+        config: &L2capChannelConfig<'_>,
+        ble: &BleHost<'d, T, P>,
+    ) -> Result<ChannelGroup<'d, P>, BleHostError<T::Error>> {
+        let L2capChannelConfig {
+            mtu,
+            mps,
+            flow_policy,
+            initial_credits,
+            ..
+        } = config;
+
+        let mtu = mtu.unwrap_or(P::MTU as u16 - 6);
+        let mps = mps.unwrap_or(P::MTU as u16 - 4);
+        if mps > P::MTU as u16 - 4 {
+            return Err(Error::InsufficientSpace.into());
+        }
+        if mtu < L2CAP_ECFC_MIN_MTU || mps < L2CAP_ECFC_MIN_MTU {
+            return Err(Error::InvalidValue.into());
+        }
+
+        let mut tx = [0; 26];
+        loop {
+            // Wait until we find an enhanced request for our connection matching our PSM, and accept
+            // every channel that is part of it.
+            let accepted = poll_fn(|cx| {
+                let mut state = self.state.borrow_mut();
+                state.accept_waker.register(cx.waker());
+                let Some((req_id, req_psm)) = state.channels.iter().find_map(|chan| match chan.state {
+                    ChannelState::PeerConnecting(req_id)
+                        if chan.enhanced && chan.conn == Some(conn) && psm.contains(&chan.psm) =>
+                    {
+                        Some((req_id, chan.psm))
+                    }
+                    _ => None,
+                }) else {
+                    return Poll::Pending;
+                };
+
+                let result = check_security(&ble.connections, conn, req_psm, &config.security(req_psm));
+                if result != LeCreditConnResultCode::Success {
+                    // Every channel of the request is refused.
+                    let mut dcids = Vec::new();
+                    for chan in state.channels.iter_mut() {
+                        if chan.enhanced
+                            && chan.conn == Some(conn)
+                            && chan.state == ChannelState::PeerConnecting(req_id)
+                        {
+                            chan.close();
+                            let _ = dcids.push(0);
+                        }
+                    }
+                    return Poll::Ready(Err((req_id, result, dcids)));
+                }
+
+                let credits = initial_credits.unwrap_or(config::L2CAP_RX_QUEUE_SIZE.min(P::capacity()) as u16);
+                let mut channels = Vec::new();
+                let mut dcids = Vec::new();
+                let (mut res_mtu, mut res_mps) = (mtu, mps);
+                for idx in 0..state.channels.len() {
+                    let chan = &mut state.channels[idx];
+                    if channels.is_full()
+                        || !chan.enhanced
+                        || chan.conn != Some(conn)
+                        || chan.state != ChannelState::PeerConnecting(req_id)
+                    {
+                        continue;
+                    }
+                    chan.mtu = chan.mtu.min(mtu);
+                    chan.mps = chan.mps.min(mps);
+                    chan.flow_control = CreditFlowControl::new(*flow_policy, credits);
+                    chan.state = ChannelState::Connected;
+                    res_mtu = chan.mtu;
+                    res_mps = chan.mps;
+                    let cid = chan.cid;
+                    if chan.refcount != 0 {
+                        state.print(true);
+                        panic!("unexpected refcount");
+                    }
+                    let index = ChannelIndex(idx as u8);
+                    state.inc_ref(index);
+                    let _ = dcids.push(cid);
+                    let _ = channels.push(L2capChannel::new(index, self));
+                }
+                Poll::Ready(Ok((channels, req_id, res_mps, res_mtu, credits, dcids)))
+            })
+            .await;
+
+            match accepted {
+                Ok((channels, req_id, mps, mtu, credits, dcids)) => {
+                    // Respond that we accept all channels of the request.
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &CreditConnRes {
+                            mtu,
+                            mps,
+                            credits,
+                            result: LeCreditConnResultCode::Success,
+                            dcids,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
+                    return Ok(channels);
+                }
+                Err((req_id, result, dcids)) => {
+                    warn!("[l2cap][conn = {:?}] refusing enhanced channels: {:?}", conn, result);
+                    ble.l2cap_signal(
+                        conn,
+                        req_id,
+                        &CreditConnRes {
+                            mtu: 0,
+                            mps: 0,
+                            credits: 0,
+                            result,
+                            dcids,
+                        },
+                        &mut tx[..],
+                    )
+                    .await?;
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn create_group<T: Controller>(
+        &'d self,
+        conn: ConnHandle,
+        psm: u16,
+        count: usize,
+        config: &L2capChannelConfig<'_>,
+        ble: &BleHost<'_, T, P>,
+    ) -> Result<ChannelGroup<'d, P>, BleHostError<T::Error>> {
+        let L2capChannelConfig {
+            mtu,
+            mps,
+            flow_policy,
+            initial_credits,
+            ..
+        } = config;
+
+        if count == 0 || count > L2CAP_MAX_ENHANCED_CHANNELS {
+            return Err(Error::InvalidValue.into());
+        }
+        let mtu = mtu.unwrap_or(P::MTU as u16 - 6);
+        let mps = mps.unwrap_or(P::MTU as u16 - 4);
+        if mps > P::MTU as u16 - 4 {
+            return Err(Error::InsufficientSpace.into());
+        }
+        if mtu < L2CAP_ECFC_MIN_MTU || mps < L2CAP_ECFC_MIN_MTU {
+            return Err(Error::InvalidValue.into());
+        }
+
+        let req_id = self.next_request_id();
+        let credits = initial_credits.unwrap_or(config::L2CAP_RX_QUEUE_SIZE.min(P::capacity()) as u16);
+
+        // Allocate space for all channels before sending anything.
+        let mut indices: Vec<ChannelIndex, L2CAP_MAX_ENHANCED_CHANNELS> = Vec::new();
+        let mut scids = Vec::new();
+        for _ in 0..count {
+            let mut cid = 0;
+            let allocated = self.alloc(conn, |storage| {
+                cid = storage.cid;
+                storage.psm = psm;
+                storage.mtu = mtu;
+                storage.mps = mps;
+                storage.flow_control = CreditFlowControl::new(*flow_policy, credits);
+                storage.enhanced = true;
+                storage.state = ChannelState::Connecting(req_id);
+            });
+            match allocated {
+                Ok(idx) => {
+                    unwrap!(indices.push(idx));
+                    unwrap!(scids.push(cid));
+                }
+                Err(e) => {
+                    self.release(&indices);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let mut tx = [0; 26];
+        let command = CreditConnReq {
+            spsm: psm,
+            mtu,
+            mps,
+            credits,
+            scids,
+        };
+        if let Err(e) = ble.l2cap_signal(conn, req_id, &command, &mut tx[..]).await {
+            self.release(&indices);
+            return Err(e);
+        }
+        self.expect_response(conn, req_id);
+
+        // Wait until a response is accepted.
+        poll_fn(|cx| self.poll_created_group(conn, req_id, &indices, ble, Some(cx))).await
+    }
+
+    fn poll_created_group<T: Controller>(
+        &'d self,
+        conn: ConnHandle,
+        req_id: u8,
+        indices: &[ChannelIndex],
+        ble: &BleHost<'_, T, P>,
+        cx: Option<&mut Context<'_>>,
+    ) -> Poll<Result<ChannelGroup<'d, P>, BleHostError<T::Error>>> {
+        let mut state = self.state.borrow_mut();
+        if let Some(cx) = cx {
+            state.create_waker.register(cx.waker());
+        }
+        // Check if we've been disconnected while waiting
+        if !ble.connections.is_handle_connected(conn) {
+            return Poll::Ready(Err(Error::Disconnected.into()));
+        }
+
+        if indices
+            .iter()
+            .any(|idx| state.channels[idx.0 as usize].state == ChannelState::Connecting(req_id))
+        {
+            return Poll::Pending;
+        }
+
+        let mut channels = Vec::new();
+        let mut refusal = None;
+        for idx in indices {
+            let storage = &mut state.channels[idx.0 as usize];
+            if storage.conn != Some(conn) {
+                continue;
+            }
+            match storage.state {
+                ChannelState::Connected => {
+                    if storage.refcount != 0 {
+                        state.print(true);
+                        panic!("unexpected refcount");
+                    }
+                    state.inc_ref(*idx);
+                    let _ = channels.push(L2capChannel::new(*idx, self));
+                }
+                ChannelState::Refused(reason) => {
+                    storage.close();
+                    refusal = Some(reason);
+                }
+                _ => {}
+            }
+        }
+        if channels.is_empty() {
+            return Poll::Ready(Err(refusal
+                .map_or(Error::NotSupported, Error::L2capConnectionRefused)
+                .into()));
+        }
+        Poll::Ready(Ok(channels))
+    }
+
+    pub(crate) async fn reconfigure<T: Controller>(
+        &self,
+        indices: &[ChannelIndex],
+        mtu: u16,
+        mps: u16,
+        ble: &BleHost<'_, T, P>,
+    ) -> Result<(), BleHostError<T::Error>> {
+        if indices.is_empty() || indices.len() > L2CAP_MAX_ENHANCED_CHANNELS {
+            return Err(Error::InvalidValue.into());
+        }
+        if mps > P::MTU as u16 - 4 {
+            return Err(Error::InsufficientSpace.into());
+        }
+        if mtu < L2CAP_ECFC_MIN_MTU || mps < L2CAP_ECFC_MIN_MTU {
+            return Err(Error::InvalidValue.into());
+        }
+
+        let (conn, identifier, dcids) = self.with_mut(|state| {
+            // Only one reconfiguration may be outstanding at a time.
+            if state.reconfigure.is_some() {
+                return Err(Error::Busy);
+            }
+            let conn = state.channels[indices[0].0 as usize].conn;
+            let mut dcids = Vec::new();
+            for idx in indices {
+                let chan = &state.channels[idx.0 as usize];
+                if chan.state != ChannelState::Connected || !chan.enhanced || chan.conn != conn {
+                    return Err(Error::InvalidState);
+                }
+                unwrap!(dcids.push(chan.cid));
+            }
+            let conn = conn.ok_or(Error::InvalidState)?;
+            let identifier = state.next_request_id();
+            state.reconfigure.replace(PendingReconfigure {
+                conn,
+                identifier,
+                result: None,
+            });
+            Ok((conn, identifier, dcids))
+        })?;
+
+        let _drop = crate::host::OnDrop::new(|| {
+            self.with_mut(|state| {
+                state.reconfigure.take();
+            })
+        });
+
+        let mut tx = [0; 26];
+        ble.l2cap_signal(
+            conn,
+            identifier,
+            &CreditConnReconfigReq { mtu, mps, dcids },
+            &mut tx[..],
+        )
+        .await?;
+        self.expect_response(conn, identifier);
+
+        let result = poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            state.reconfigure_waker.register(cx.waker());
+            if !ble.connections.is_handle_connected(conn) {
+                return Poll::Ready(Err(Error::Disconnected));
+            }
+            match &state.reconfigure {
+                Some(PendingReconfigure {
+                    result: Some(result), ..
+                }) => Poll::Ready(Ok(*result)),
+                _ => Poll::Pending,
+            }
+        })
+        .await?;
+
+        if result != CreditConnReconfigResultCode::Success as u16 {
+            warn!("[l2cap][conn = {:?}] reconfigure rejected: {}", conn, result);
+            return Err(Error::InvalidValue.into());
+        }
+        Ok(())
+    }
+
     pub(crate) fn received(&self, channel: u16, credits: u16) -> Result<(), Error> {
         if channel < BASE_ID {
             return Err(Error::InvalidChannelId);
@@ -363,7 +871,10 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         })
     }
 
-    pub(crate) fn dispatch(&self, channel: u16, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+    /// Dispatch a K-frame to its channel.
+    ///
+    /// Complete SDUs of Enhanced ATT bearers are handled by the host rather than a channel user, and are returned.
+    pub(crate) fn dispatch(&self, channel: u16, pdu: Pdu<P::Packet>) -> Result<Option<ChannelSdu<P::Packet>>, Error> {
         if channel < BASE_ID {
             return Err(Error::InvalidChannelId);
         }
@@ -422,10 +933,14 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             }
 
             if let Some(sdu) = sdu {
+                #[cfg(feature = "gatt")]
+                if storage.enhanced && storage.psm == L2CAP_PSM_EATT {
+                    return Ok(Some((ChannelIndex(chan as u8), sdu)));
+                }
                 storage.inbound.try_send(sdu)?;
             }
 
-            Ok(())
+            Ok(None)
         })
     }
 
@@ -436,7 +951,52 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         data: &[u8],
         manager: &ConnectionManager<'_, P>,
     ) -> Result<(), Error> {
+        // Signals without a complete header can't be answered, so they are dropped.
+        if data.len() < 4 {
+            return Err(Error::InvalidValue);
+        }
+        let identifier = data[1];
+        if data.len() > L2CAP_LE_SIGNAL_MTU as usize {
+            warn!(
+                "[l2cap][conn = {:?}] signal of {} bytes exceeds signaling MTU",
+                conn,
+                data.len()
+            );
+            let reject = CommandRejectRes::new(CommandRejectReason::SignalingMtuExceeded, &[L2CAP_LE_SIGNAL_MTU]);
+            return self.try_signal(conn, identifier, &reject, manager);
+        }
+        let Ok(code) = L2capSignalCode::try_from(data[0]) else {
+            warn!("[l2cap][conn = {:?}] unknown signal code {}", conn, data[0]);
+            let reject = CommandRejectRes::new(CommandRejectReason::CommandNotUnderstood, &[]);
+            return self.try_signal(conn, identifier, &reject, manager);
+        };
+
         let (header, data) = L2capSignalHeader::from_hci_bytes(data)?;
+        if code.is_response() {
+            self.with_mut(|state| {
+                state
+                    .pending
+                    .retain(|pending| pending.conn != conn || pending.identifier != identifier)
+            });
+        }
+        match self.handle_signal(conn, &header, data, manager) {
+            // Requests we can't make sense of are rejected, so the peer doesn't wait for a response.
+            Err(Error::HciDecode(_) | Error::NotSupported) if !code.is_response() => {
+                warn!("[l2cap][conn = {:?}] rejecting signal {:?}", conn, code);
+                let reject = CommandRejectRes::new(CommandRejectReason::CommandNotUnderstood, &[]);
+                self.try_signal(conn, identifier, &reject, manager)
+            }
+            result => result,
+        }
+    }
+
+    fn handle_signal(
+        &self,
+        conn: ConnHandle,
+        header: &L2capSignalHeader,
+        data: &[u8],
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
         //trace!(
         //    "[l2cap][conn = {:?}] received signal (req {}) code {:?}",
         //    conn,
@@ -452,18 +1012,50 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
                 let res = LeCreditConnRes::from_hci_bytes_complete(data)?;
                 self.handle_connect_response(conn, header.identifier, &res)?;
             }
+            L2capSignalCode::CreditConnReq => {
+                let req = CreditConnReq::from_hci_bytes_complete(data)?;
+                let result = self.state.borrow().enhanced_connect_result(conn, &req);
+                if result != LeCreditConnResultCode::Success {
+                    self.refuse_enhanced_connect(conn, header.identifier, &req, result, manager)?;
+                    return Ok(());
+                }
+                #[cfg(feature = "gatt")]
+                if req.spsm == L2CAP_PSM_EATT {
+                    self.accept_att_bearers(conn, header.identifier, &req, manager)?;
+                    return Ok(());
+                }
+                self.handle_enhanced_connect_request(conn, header.identifier, &req)?;
+            }
+            L2capSignalCode::CreditConnRes => {
+                let res = CreditConnRes::from_hci_bytes_complete(data)?;
+                self.handle_enhanced_connect_response(conn, header.identifier, &res)?;
+            }
+            L2capSignalCode::CreditConnReconfigReq => {
+                let req = CreditConnReconfigReq::from_hci_bytes_complete(data)?;
+                debug!("[l2cap][conn = {:?}] reconfigure request: {:?}", conn, req);
+                self.handle_reconfigure_request(conn, header.identifier, &req);
+            }
+            L2capSignalCode::CreditConnReconfigRes => {
+                let res = CreditConnReconfigRes::from_hci_bytes_complete(data)?;
+                debug!("[l2cap][conn = {:?}] reconfigure response: {}", conn, res.result);
+                self.handle_reconfigure_response(conn, header.identifier, &res)?;
+            }
             L2capSignalCode::LeCreditFlowInd => {
                 let req = LeCreditFlowInd::from_hci_bytes_complete(data)?;
                 //trace!("[l2cap] credit flow: {:?}", req);
                 self.handle_credit_flow(conn, &req)?;
             }
             L2capSignalCode::CommandRejectRes => {
-                let (reject, _) = CommandRejectRes::from_hci_bytes(data)?;
+                let reject = CommandRejectRes::from_hci_bytes_complete(data)?;
+                warn!(
+                    "[l2cap][conn = {:?}] request {} rejected: {:?}",
+                    conn, header.identifier, reject
+                );
             }
             L2capSignalCode::DisconnectionReq => {
                 let req = DisconnectionReq::from_hci_bytes_complete(data)?;
                 debug!("[l2cap][conn = {:?}, cid = {}] disconnect request", conn, req.dcid);
-                self.handle_disconnect_request(req.dcid)?;
+                self.handle_disconnect_request(conn, header.identifier, &req, manager)?;
             }
             L2capSignalCode::DisconnectionRes => {
                 let res = DisconnectionRes::from_hci_bytes_complete(data)?;
@@ -473,6 +1065,10 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             L2capSignalCode::ConnParamUpdateReq => {
                 let req = ConnParamUpdateReq::from_hci_bytes_complete(data)?;
                 debug!("[l2cap][conn = {:?}] connection param update request: {:?}", conn, req);
+                self.with_mut(|state| {
+                    state.param_update_requests.retain(|(handle, _)| *handle != conn);
+                    let _ = state.param_update_requests.push((conn, header.identifier));
+                });
                 let interval_min: bt_hci::param::Duration<1_250> = bt_hci::param::Duration::from_u16(req.interval_min);
                 let interva_max: bt_hci::param::Duration<1_250> = bt_hci::param::Duration::from_u16(req.interval_max);
                 let timeout: bt_hci::param::Duration<10_000> = bt_hci::param::Duration::from_u16(req.timeout);
@@ -494,6 +1090,16 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
                     conn, res.result,
                 );
             }
+            L2capSignalCode::EchoReq => {
+                let req = EchoReq::from_hci_bytes_complete(data)?;
+                self.try_signal(conn, header.identifier, &EchoRes { data: req.data }, manager)?;
+            }
+            L2capSignalCode::InformationReq => {
+                let req = InformationReq::from_hci_bytes_complete(data)?;
+                debug!("[l2cap][conn = {:?}] information request: {}", conn, req.info_type);
+                self.try_signal(conn, header.identifier, &InformationRes::new(req.info_type), manager)?;
+            }
+            L2capSignalCode::EchoRes | L2capSignalCode::InformationRes => {}
             r => {
                 warn!("[l2cap][conn = {:?}] unsupported signal: {:?}", conn, r);
                 return Err(Error::NotSupported);
@@ -523,7 +1129,9 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
                 let mut state = self.state.borrow_mut();
                 for storage in state.channels.iter_mut() {
                     match storage.state {
-                        ChannelState::Connecting(req_id) if identifier == req_id && Some(conn) == storage.conn => {
+                        ChannelState::Connecting(req_id)
+                            if identifier == req_id && !storage.enhanced && Some(conn) == storage.conn =>
+                        {
                             storage.peer_cid = res.dcid;
                             storage.peer_credits = res.credits;
                             storage.mps = storage.mps.min(res.mps);
@@ -544,11 +1152,229 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
             }
             other => {
                 warn!("Channel open request failed: {:?}", other);
-                Err(Error::NotSupported)
+                let mut state = self.state.borrow_mut();
+                for storage in state.channels.iter_mut() {
+                    match storage.state {
+                        ChannelState::Connecting(req_id)
+                            if identifier == req_id && !storage.enhanced && Some(conn) == storage.conn =>
+                        {
+                            storage.state = other
+                                .refusal()
+                                .map_or(ChannelState::Disconnected, ChannelState::Refused);
+                            state.create_waker.wake();
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+                }
+                Err(Error::NotFound)
             }
         }
     }
 
+    fn handle_enhanced_connect_request(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &CreditConnReq,
+    ) -> Result<(), Error> {
+        let mut allocated: Vec<ChannelIndex, L2CAP_MAX_ENHANCED_CHANNELS> = Vec::new();
+        for scid in req.scids.iter() {
+            let index = self.alloc(conn, |storage| {
+                storage.psm = req.spsm;
+                storage.peer_cid = *scid;
+                storage.peer_credits = req.credits;
+                storage.mps = req.mps;
+                storage.mtu = req.mtu;
+                storage.enhanced = true;
+                storage.state = ChannelState::PeerConnecting(identifier);
+            });
+            match index {
+                Ok(index) => unwrap!(allocated.push(index)),
+                Err(e) => {
+                    self.release(&allocated);
+                    return Err(e);
+                }
+            }
+        }
+        self.state.borrow_mut().accept_waker.wake();
+        Ok(())
+    }
+
+    /// Refuse all channels of an enhanced connection request with `result`.
+    fn refuse_enhanced_connect(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &CreditConnReq,
+        result: LeCreditConnResultCode,
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
+        warn!("[l2cap][conn = {:?}] refusing enhanced channels: {:?}", conn, result);
+        let response = CreditConnRes {
+            mtu: 0,
+            mps: 0,
+            credits: 0,
+            result,
+            dcids: req.scids.iter().map(|_| 0).collect(),
+        };
+        self.try_signal(conn, identifier, &response, manager)
+    }
+
+    // Enhanced ATT bearers are used by the host itself, so they are accepted as soon as they are requested,
+    // provided the link is encrypted as Enhanced ATT requires.
+    #[cfg(feature = "gatt")]
+    fn accept_att_bearers(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &CreditConnReq,
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
+        let security = L2capSecurity {
+            level: SecurityLevel::Encrypted,
+            ..Default::default()
+        };
+        let result = check_security(manager, conn, req.spsm, &security);
+        if result != LeCreditConnResultCode::Success {
+            return self.refuse_enhanced_connect(conn, identifier, req, result, manager);
+        }
+        let mtu = P::MTU as u16 - 6;
+        let mps = P::MTU as u16 - 4;
+        let credits = config::L2CAP_RX_QUEUE_SIZE.min(P::capacity()) as u16;
+        let mut allocated: Vec<ChannelIndex, L2CAP_MAX_ENHANCED_CHANNELS> = Vec::new();
+        let mut dcids = Vec::new();
+        for scid in req.scids.iter() {
+            let mut cid = 0;
+            let index = self.alloc(conn, |storage| {
+                cid = storage.cid;
+                storage.psm = req.spsm;
+                storage.peer_cid = *scid;
+                storage.peer_credits = req.credits;
+                storage.mps = req.mps.min(mps);
+                storage.mtu = req.mtu.min(mtu);
+                storage.flow_control = CreditFlowControl::new(CreditFlowPolicy::default(), credits);
+                storage.enhanced = true;
+                storage.state = ChannelState::Connected;
+            });
+            // Channels that can't be allocated are refused individually.
+            match index {
+                Ok(index) => unwrap!(allocated.push(index)),
+                Err(_) => cid = 0,
+            }
+            unwrap!(dcids.push(cid));
+        }
+
+        let result = if allocated.len() == dcids.len() {
+            LeCreditConnResultCode::Success
+        } else {
+            warn!("[l2cap][conn = {:?}] no resources for all requested ATT bearers", conn);
+            LeCreditConnResultCode::NoResources
+        };
+        let response = CreditConnRes {
+            mtu,
+            mps,
+            credits,
+            result,
+            dcids,
+        };
+        if let Err(e) = self.try_signal(conn, identifier, &response, manager) {
+            self.release(&allocated);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn handle_enhanced_connect_response(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        res: &CreditConnRes,
+    ) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        // Destination channel ids are listed in the same order as our request, which allocated
+        // channels in index order.
+        let mut dcids = res.dcids.iter();
+        let mut found = false;
+        for storage in state.channels.iter_mut() {
+            match storage.state {
+                ChannelState::Connecting(req_id)
+                    if identifier == req_id && storage.enhanced && Some(conn) == storage.conn =>
+                {
+                    found = true;
+                    match dcids.next() {
+                        Some(&dcid) if dcid != 0 => {
+                            storage.peer_cid = dcid;
+                            storage.peer_credits = res.credits;
+                            storage.mps = storage.mps.min(res.mps);
+                            storage.mtu = storage.mtu.min(res.mtu);
+                            storage.state = ChannelState::Connected;
+                        }
+                        _ => match res.result.refusal() {
+                            Some(reason) => storage.state = ChannelState::Refused(reason),
+                            None => storage.close(),
+                        },
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !found {
+            debug!(
+                "[l2cap][handle_enhanced_connect_response][link = {}] request with id {} not found",
+                conn.raw(),
+                identifier
+            );
+            return Err(Error::NotFound);
+        }
+        if !matches!(res.result, LeCreditConnResultCode::Success) {
+            warn!("Enhanced channel open request refused: {:?}", res.result);
+        }
+        state.create_waker.wake();
+        Ok(())
+    }
+
+    fn handle_reconfigure_request(&self, conn: ConnHandle, identifier: u8, req: &CreditConnReconfigReq) {
+        let mut state = self.state.borrow_mut();
+        let result = state.reconfigure_result(conn, req);
+        if result == CreditConnReconfigResultCode::Success {
+            for chan in state.channels.iter_mut() {
+                if chan.state == ChannelState::Connected
+                    && chan.enhanced
+                    && chan.conn == Some(conn)
+                    && req.dcids.contains(&chan.peer_cid)
+                {
+                    // Outbound SDUs and frames follow the peer's new receive sizes, bounded by our packet size.
+                    chan.mtu = req.mtu;
+                    chan.mps = req.mps.min(P::MTU as u16 - 4);
+                }
+            }
+        }
+        state.reconfigure_response.replace(ReconfigureResponse {
+            handle: conn,
+            identifier,
+            result,
+        });
+        state.response_waker.wake();
+    }
+
+    fn handle_reconfigure_response(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        res: &CreditConnReconfigRes,
+    ) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        match &mut state.reconfigure {
+            Some(pending) if pending.conn == conn && pending.identifier == identifier => {
+                pending.result.replace(res.result);
+                state.reconfigure_waker.wake();
+                Ok(())
+            }
+            _ => Err(Error::NotFound),
+        }
+    }
+
     fn handle_credit_flow(&self, conn: ConnHandle, req: &LeCreditFlowInd) -> Result<(), Error> {
         let mut state = self.state.borrow_mut();
         for storage in state.channels.iter_mut() {
@@ -571,17 +1397,27 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         Err(Error::NotFound)
     }
 
-    fn handle_disconnect_request(&self, cid: u16) -> Result<(), Error> {
-        let mut state = self.state.borrow_mut();
-        for (idx, storage) in state.channels.iter_mut().enumerate() {
-            if cid == storage.cid {
-                storage.state = ChannelState::PeerDisconnecting;
-                let _ = storage.inbound.close();
-                state.disconnect_waker.wake();
-                break;
+    fn handle_disconnect_request(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        req: &DisconnectionReq,
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
+        {
+            let mut state = self.state.borrow_mut();
+            for storage in state.channels.iter_mut() {
+                if Some(conn) == storage.conn && req.dcid == storage.cid && storage.state != ChannelState::Disconnected
+                {
+                    storage.state = ChannelState::PeerDisconnecting(identifier);
+                    let _ = storage.inbound.close();
+                    state.disconnect_waker.wake();
+                    return Ok(());
+                }
             }
         }
-        Ok(())
+        let reject = CommandRejectRes::new(CommandRejectReason::InvalidCid, &[req.dcid, req.scid]);
+        self.try_signal(conn, identifier, &reject, manager)
     }
 
     fn handle_disconnect_response(&self, cid: u16) -> Result<(), Error> {
@@ -748,7 +1584,9 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
     ) -> Result<(), BleHostError<T::Error>> {
         let identifier = self.next_request_id();
         let mut tx = [0; 16];
-        host.l2cap_signal(handle, identifier, param, &mut tx[..]).await
+        host.l2cap_signal(handle, identifier, param, &mut tx[..]).await?;
+        self.expect_response(handle, identifier);
+        Ok(())
     }
 
     pub(crate) async fn send_conn_param_update_res<T: Controller>(
@@ -757,7 +1595,14 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         host: &BleHost<'d, T, P>,
         param: &ConnParamUpdateRes,
     ) -> Result<(), BleHostError<T::Error>> {
-        let identifier = self.next_request_id();
+        // Answer with the identifier of the peer's request, if there is one.
+        let identifier =
+            self.with_mut(
+                |state| match state.param_update_requests.iter().position(|(conn, _)| *conn == handle) {
+                    Some(pos) => state.param_update_requests.swap_remove(pos).1,
+                    None => state.next_request_id(),
+                },
+            );
         let mut tx = [0; 16];
         host.l2cap_signal(handle, identifier, param, &mut tx[..]).await
     }
@@ -809,6 +1654,61 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         Ok(())
     }
 
+    /// Grant credits for an SDU of an Enhanced ATT bearer, which the host consumed as soon as it was received.
+    #[cfg(feature = "gatt")]
+    pub(crate) fn att_flow_control(
+        &self,
+        index: ChannelIndex,
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
+        let (conn, cid, credits) = self.with_mut(|state| {
+            let chan = &mut state.channels[index.0 as usize];
+            if chan.state == ChannelState::Connected {
+                return Ok((chan.conn.unwrap(), chan.cid, chan.flow_control.process()));
+            }
+            Err(Error::NotFound)
+        })?;
+
+        if let Some(credits) = credits {
+            let identifier = self.next_request_id();
+            self.try_signal(conn, identifier, &LeCreditFlowInd { cid, credits }, manager)?;
+            self.with_mut(|state| {
+                let chan = &mut state.channels[index.0 as usize];
+                if chan.state == ChannelState::Connected {
+                    chan.flow_control.confirm_granted(credits);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // Queue a signal for sending, for use where the controller can't be awaited.
+    fn try_signal<D: L2capSignal>(
+        &self,
+        conn: ConnHandle,
+        identifier: u8,
+        signal: &D,
+        manager: &ConnectionManager<'_, P>,
+    ) -> Result<(), Error> {
+        let header = L2capSignalHeader {
+            identifier,
+            code: D::code(),
+            length: signal.size() as u16,
+        };
+        let l2cap = L2capHeader {
+            channel: D::channel(),
+            length: header.size() as u16 + header.length,
+        };
+
+        let mut packet = P::allocate().ok_or(Error::OutOfMemory)?;
+        let mut w = WriteCursor::new(packet.as_mut());
+        w.write_hci(&l2cap)?;
+        w.write_hci(&header)?;
+        w.write_hci(signal)?;
+        let len = w.len();
+        manager.try_outbound(conn, Pdu::new(packet, len))
+    }
+
     fn with_mut<F: FnOnce(&mut State<'d, P::Packet>) -> R, R>(&self, f: F) -> R {
         let mut state = self.state.borrow_mut();
         f(&mut state)
@@ -848,7 +1748,7 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         }
         for (idx, storage) in state.channels.iter().enumerate() {
             match storage.state {
-                ChannelState::Disconnecting | ChannelState::PeerDisconnecting => {
+                ChannelState::Disconnecting | ChannelState::PeerDisconnecting(_) => {
                     return Poll::Ready(DisconnectRequest {
                         index: ChannelIndex(idx as u8),
                         handle: storage.conn.unwrap(),
@@ -861,6 +1761,51 @@ impl<'d, P: PacketPool> ChannelManager<'d, P> {
         Poll::Pending
     }
 
+    pub(crate) fn poll_reconfigure_response(&self, cx: Option<&mut Context<'_>>) -> Poll<ReconfigureResponse> {
+        let mut state = self.state.borrow_mut();
+        if let Some(cx) = cx {
+            state.response_waker.register(cx.waker());
+        }
+        match state.reconfigure_response.take() {
+            Some(response) => Poll::Ready(response),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Wait for a signaling request sent by us to go unanswered for longer than the RTX timeout.
+    ///
+    /// Returns the connection of the request, which should be disconnected.
+    pub(crate) async fn signal_timeout(&self) -> ConnHandle {
+        loop {
+            let deadline = {
+                let mut state = self.state.borrow_mut();
+                let now = Instant::now();
+                if let Some(expired) = state.pending.iter().find(|pending| pending.deadline <= now) {
+                    let conn = expired.conn;
+                    state.pending.retain(|pending| pending.conn != conn);
+                    return conn;
+                }
+                state.pending_changed = false;
+                state.pending.iter().map(|pending| pending.deadline).min()
+            };
+            let changed = poll_fn(|cx| {
+                let mut state = self.state.borrow_mut();
+                state.pending_waker.register(cx.waker());
+                if state.pending_changed {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            });
+            match deadline {
+                Some(deadline) => {
+                    let _ = changed.with_deadline(deadline).await;
+                }
+                None => changed.await,
+            }
+        }
+    }
+
     pub(crate) fn inc_ref(&self, index: ChannelIndex) {
         self.with_mut(|state| {
             state.inc_ref(index);
@@ -925,15 +1870,17 @@ impl<'a, 'd, P: PacketPool> DisconnectRequest<'a, 'd, P> {
 
         let mut tx = [0; 18];
         match state {
-            ChannelState::PeerDisconnecting => {
+            ChannelState::PeerDisconnecting(identifier) => {
                 assert_eq!(Some(self.handle), conn);
-                host.l2cap_signal(self.handle, identifier, &DisconnectionRes { dcid, scid }, &mut tx[..])
-                    .await?;
+                // The response identifies the endpoints from our side of the channel.
+                let res = DisconnectionRes { dcid: scid, scid: dcid };
+                host.l2cap_signal(self.handle, identifier, &res, &mut tx[..]).await?;
             }
             ChannelState::Disconnecting => {
                 assert_eq!(Some(self.handle), conn);
                 host.l2cap_signal(self.handle, identifier, &DisconnectionReq { dcid, scid }, &mut tx[..])
                     .await?;
+                self.state.borrow_mut().expect_response(self.handle, identifier);
             }
             _ => {}
         }
@@ -945,6 +1892,80 @@ impl<'a, 'd, P: PacketPool> DisconnectRequest<'a, 'd, P> {
     }
 }
 
+pub struct ReconfigureResponse {
+    handle: ConnHandle,
+    identifier: u8,
+    result: CreditConnReconfigResultCode,
+}
+
+impl ReconfigureResponse {
+    pub async fn send<T: Controller, P: PacketPool>(
+        &self,
+        host: &BleHost<'_, T, P>,
+    ) -> Result<(), BleHostError<T::Error>> {
+        let mut tx = [0; 10];
+        host.l2cap_signal(
+            self.handle,
+            self.identifier,
+            &CreditConnReconfigRes {
+                result: self.result as u16,
+            },
+            &mut tx[..],
+        )
+        .await
+    }
+}
+
+/// Check an incoming connection request against the security requirements of its PSM.
+fn check_security<P: PacketPool>(
+    connections: &ConnectionManager<'_, P>,
+    conn: ConnHandle,
+    psm: u16,
+    security: &L2capSecurity,
+) -> LeCreditConnResultCode {
+    let link = connections.with_connected_handle(conn, |storage| {
+        #[cfg(feature = "security")]
+        let (security_level, key_size) = (storage.security_level, storage.encryption_key_size);
+        #[cfg(not(feature = "security"))]
+        let (security_level, key_size) = (SecurityLevel::NoEncryption, 0);
+        let request = L2capConnectRequest {
+            handle: conn,
+            identity: storage.peer_identity.ok_or(Error::InvalidValue)?,
+            psm,
+            security_level,
+        };
+        Ok((request, key_size))
+    });
+    let Ok((request, key_size)) = link else {
+        return LeCreditConnResultCode::NoResources;
+    };
+
+    if request.security_level < security.level {
+        // A bonded peer only has to encrypt the link, others have to pair first
+        #[cfg(feature = "security")]
+        let bonded = connections
+            .security_manager
+            .get_peer_bond_information(&request.identity)
+            .is_some();
+        #[cfg(not(feature = "security"))]
+        let bonded = false;
+        return if !request.security_level.encrypted() && bonded {
+            LeCreditConnResultCode::InsufficientEncryption
+        } else {
+            LeCreditConnResultCode::InsufficientAuthentication
+        };
+    }
+    if security.level.encrypted() && key_size < security.min_key_size {
+        return LeCreditConnResultCode::EncryptionKeyTooShort;
+    }
+    if let Some(authorize) = security.authorize {
+        if !authorize(&request) {
+            return LeCreditConnResultCode::InsufficientAuthorization;
+        }
+    }
+    LeCreditConnResultCode::Success
+}
+
 fn encode(data: &[u8], packet: &mut [u8], peer_cid: u16, header: Option<u16>) -> Result<usize, Error> {
     let mut w = WriteCursor::new(packet);
     if header.is_some() {
@@ -971,6 +1992,8 @@ pub struct ChannelStorage<P> {
     mtu: u16,
     flow_control: CreditFlowControl,
     refcount: u8,
+    // Opened with an enhanced credit based request.
+    enhanced: bool,
 
     peer_cid: u16,
     peer_credits: u16,
@@ -1056,7 +2079,8 @@ impl<P> core::fmt::Debug for ChannelStorage<P> {
             .field("mtu", &self.mtu)
             .field("peer_credits", &self.peer_credits)
             .field("available", &self.flow_control.available())
-            .field("refcount", &self.refcount);
+            .field("refcount", &self.refcount)
+            .field("enhanced", &self.enhanced);
         #[cfg(feature = "channel-metrics")]
         let d = d.field("metrics", &self.metrics);
         d.finish()
@@ -1099,6 +2123,7 @@ impl<P> ChannelStorage<P> {
             peer_credits: 0,
             credit_waker: WakerRegistration::new(),
             refcount: 0,
+            enhanced: false,
             inbound: PacketChannel::new(),
             #[cfg(not(feature = "l2cap-sdu-reassembly-optimization"))]
             reassembly: PacketReassembly::new(),
@@ -1117,6 +2142,7 @@ impl<P> ChannelStorage<P> {
         self.peer_cid = 0;
         self.flow_control = CreditFlowControl::new(CreditFlowPolicy::Every(1), 0);
         self.peer_credits = 0;
+        self.enhanced = false;
     }
 }
 
@@ -1126,8 +2152,10 @@ pub enum ChannelState {
     Disconnected,
     Connecting(u8),
     PeerConnecting(u8),
+    /// The peer refused our request, until the channel is released by the request.
+    Refused(L2capConnectError),
     Connected,
-    PeerDisconnecting,
+    PeerDisconnecting(u8),
     Disconnecting,
 }
 
@@ -1245,6 +2273,7 @@ mod tests {
     use bt_hci::param::{AddrKind, BdAddr, LeConnRole, Status};
 
     use super::*;
+    use crate::connection::SecurityLevel;
     use crate::mock_controller::MockController;
     use crate::prelude::DefaultPacketPool;
     use crate::HostResources;
@@ -1280,4 +2309,395 @@ mod tests {
             Poll::Ready(Err(BleHostError::BleHost(Error::Disconnected)))
         ));
     }
+
+    #[test]
+    fn enhanced_channels_refused_by_peer_are_released() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Central)
+            .unwrap();
+        let mut indices = [ChannelIndex(0); 2];
+        for index in indices.iter_mut() {
+            *index = ble
+                .channels
+                .alloc(conn, |storage| {
+                    storage.mtu = 100;
+                    storage.mps = 100;
+                    storage.enhanced = true;
+                    storage.state = ChannelState::Connecting(7);
+                })
+                .unwrap();
+        }
+
+        let chan = ble.channels.poll_created_group(conn, 7, &indices, &ble, None);
+        assert!(matches!(chan, Poll::Pending));
+
+        // Credit based connection response: mtu 80, mps 70, 3 credits, some refused, dcids [0x0041, 0].
+        let res = [0x18, 7, 12, 0, 80, 0, 70, 0, 3, 0, 0x04, 0, 0x41, 0, 0x00, 0];
+        ble.channels.signal(conn, &res, &ble.connections).unwrap();
+
+        let Poll::Ready(Ok(channels)) = ble.channels.poll_created_group(conn, 7, &indices, &ble, None) else {
+            panic!("expected channels to be created");
+        };
+        assert_eq!(channels.len(), 1);
+        ble.channels.with_mut(|state| {
+            let opened = &state.channels[indices[0].0 as usize];
+            assert_eq!(opened.state, ChannelState::Connected);
+            assert_eq!(opened.peer_cid, 0x41);
+            assert_eq!(opened.mtu, 80);
+            assert_eq!(opened.mps, 70);
+            assert_eq!(opened.peer_credits, 3);
+            assert_eq!(state.channels[indices[1].0 as usize].state, ChannelState::Disconnected);
+        });
+    }
+
+    #[test]
+    fn enhanced_channel_reconfigure_request() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+        let idx = ble
+            .channels
+            .alloc(conn, |storage| {
+                storage.peer_cid = 0x50;
+                storage.mtu = 64;
+                storage.mps = 64;
+                storage.enhanced = true;
+                storage.state = ChannelState::Connected;
+            })
+            .unwrap();
+
+        // Reconfigure request: mtu 128, mps 64, dcids [0x0050].
+        let req = [0x19, 3, 6, 0, 128, 0, 64, 0, 0x50, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        let Poll::Ready(response) = ble.channels.poll_reconfigure_response(None) else {
+            panic!("expected a reconfigure response");
+        };
+        assert_eq!(response.identifier, 3);
+        assert_eq!(response.result, CreditConnReconfigResultCode::Success);
+        assert_eq!(ble.channels.with_mut(|state| state.channels[idx.0 as usize].mtu), 128);
+
+        // Reducing the MTU again is rejected and leaves the channel untouched.
+        let req = [0x19, 4, 6, 0, 100, 0, 64, 0, 0x50, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        let Poll::Ready(response) = ble.channels.poll_reconfigure_response(None) else {
+            panic!("expected a reconfigure response");
+        };
+        assert_eq!(response.result, CreditConnReconfigResultCode::MtuReductionNotAllowed);
+        assert_eq!(ble.channels.with_mut(|state| state.channels[idx.0 as usize].mtu), 128);
+
+        // Unknown channels are rejected.
+        let req = [0x19, 5, 6, 0, 200, 0, 64, 0, 0x51, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        let Poll::Ready(response) = ble.channels.poll_reconfigure_response(None) else {
+            panic!("expected a reconfigure response");
+        };
+        assert_eq!(response.result, CreditConnReconfigResultCode::InvalidDestinationCid);
+    }
+
+    #[test]
+    fn channel_refused_by_peer() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Central)
+            .unwrap();
+        let idx = ble
+            .channels
+            .alloc(conn, |storage| {
+                storage.state = ChannelState::Connecting(9);
+            })
+            .unwrap();
+
+        // LE credit based connection response: refused with insufficient authentication.
+        let res = [0x15, 9, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x05, 0];
+        ble.channels.signal(conn, &res, &ble.connections).unwrap();
+
+        let chan = ble.channels.poll_created(conn, idx, &ble, None);
+        assert!(matches!(
+            chan,
+            Poll::Ready(Err(BleHostError::BleHost(Error::L2capConnectionRefused(
+                L2capConnectError::InsufficientAuthentication
+            ))))
+        ));
+        assert_eq!(
+            ble.channels
+                .with_mut(|state| state.channels[idx.0 as usize].state.clone()),
+            ChannelState::Disconnected
+        );
+    }
+
+    #[test]
+    fn connect_request_security() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+
+        let security = L2capSecurity::default();
+        let result = check_security(&ble.connections, conn, 0x81, &security);
+        assert_eq!(result, LeCreditConnResultCode::Success);
+
+        let security = L2capSecurity {
+            level: SecurityLevel::Encrypted,
+            ..Default::default()
+        };
+        let result = check_security(&ble.connections, conn, 0x81, &security);
+        assert_eq!(result, LeCreditConnResultCode::InsufficientAuthentication);
+
+        let security = L2capSecurity {
+            authorize: Some(|request| request.psm != 0x81),
+            ..Default::default()
+        };
+        let result = check_security(&ble.connections, conn, 0x81, &security);
+        assert_eq!(result, LeCreditConnResultCode::InsufficientAuthorization);
+        let result = check_security(&ble.connections, conn, 0x83, &security);
+        assert_eq!(result, LeCreditConnResultCode::Success);
+
+        // Requirements are looked up by PSM, unlisted PSMs have the default requirements.
+        let security = [(
+            0x81,
+            L2capSecurity {
+                level: SecurityLevel::Encrypted,
+                ..Default::default()
+            },
+        )];
+        let config = L2capChannelConfig {
+            security: &security,
+            ..Default::default()
+        };
+        let result = check_security(&ble.connections, conn, 0x81, &config.security(0x81));
+        assert_eq!(result, LeCreditConnResultCode::InsufficientAuthentication);
+        let result = check_security(&ble.connections, conn, 0x83, &config.security(0x83));
+        assert_eq!(result, LeCreditConnResultCode::Success);
+    }
+
+    fn next_signal(ble: &BleHost<'_, MockController, DefaultPacketPool>) -> std::vec::Vec<u8> {
+        let (_, pdu) = embassy_futures::block_on(ble.connections.outbound());
+        // Skip the basic L2CAP header.
+        pdu.as_ref()[4..].to_vec()
+    }
+
+    #[test]
+    fn enhanced_connect_request_refused() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+        ble.channels
+            .alloc(conn, |storage| {
+                storage.peer_cid = 0x40;
+                storage.enhanced = true;
+                storage.state = ChannelState::Connected;
+            })
+            .unwrap();
+
+        // Credit based connection request: spsm 0x81, mtu 32, mps 64, 4 credits, scids [0x0041].
+        let req = [0x17, 2, 10, 0, 0x81, 0, 32, 0, 64, 0, 4, 0, 0x41, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        // Refused with unacceptable parameters.
+        assert_eq!(next_signal(&ble), [0x18, 2, 10, 0, 0, 0, 0, 0, 0, 0, 0x0b, 0, 0, 0]);
+
+        // scids [0x0041, 0x0040], the second one is in use.
+        let req = [0x17, 3, 12, 0, 0x81, 0, 100, 0, 100, 0, 4, 0, 0x41, 0, 0x40, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        // Refused with source CID already allocated.
+        assert_eq!(
+            next_signal(&ble),
+            [0x18, 3, 12, 0, 0, 0, 0, 0, 0, 0, 0x0a, 0, 0, 0, 0, 0]
+        );
+
+        // scids [0x0041, 0x0041], the same CID twice.
+        let req = [0x17, 4, 12, 0, 0x81, 0, 100, 0, 100, 0, 4, 0, 0x41, 0, 0x41, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(
+            next_signal(&ble),
+            [0x18, 4, 12, 0, 0, 0, 0, 0, 0, 0, 0x0a, 0, 0, 0, 0, 0]
+        );
+
+        // Nothing was allocated for the refused requests, a valid one waits to be accepted.
+        let req = [0x17, 5, 10, 0, 0x81, 0, 100, 0, 100, 0, 4, 0, 0x41, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        ble.channels.with_mut(|state| {
+            let mut connecting = state
+                .channels
+                .iter()
+                .filter(|chan| matches!(chan.state, ChannelState::PeerConnecting(_)));
+            assert_eq!(
+                connecting.next().map(|chan| chan.state.clone()),
+                Some(ChannelState::PeerConnecting(5))
+            );
+            assert!(connecting.next().is_none());
+        });
+    }
+
+    #[cfg(feature = "gatt")]
+    #[test]
+    fn att_bearers_require_encryption() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+
+        // Credit based connection request: spsm 0x27 (EATT), mtu 100, mps 100, 4 credits, scids [0x0041].
+        let req = [0x17, 2, 10, 0, 0x27, 0, 100, 0, 100, 0, 4, 0, 0x41, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        // Refused with insufficient authentication on an unencrypted link.
+        assert_eq!(next_signal(&ble), [0x18, 2, 10, 0, 0, 0, 0, 0, 0, 0, 0x05, 0, 0, 0]);
+        ble.channels.with_mut(|state| {
+            assert!(state
+                .channels
+                .iter()
+                .all(|chan| chan.state == ChannelState::Disconnected));
+        });
+
+        #[cfg(feature = "security")]
+        {
+            ble.connections
+                .with_connected_handle(conn, |storage| {
+                    storage.security_level = SecurityLevel::Encrypted;
+                    storage.encryption_key_size = 16;
+                    Ok(())
+                })
+                .unwrap();
+            let req = [0x17, 3, 10, 0, 0x27, 0, 100, 0, 100, 0, 4, 0, 0x41, 0];
+            ble.channels.signal(conn, &req, &ble.connections).unwrap();
+            let res = next_signal(&ble);
+            assert_eq!(res[..2], [0x18, 3]);
+            // Accepted once the link is encrypted.
+            assert_eq!(res[10..12], [0, 0]);
+        }
+    }
+
+    #[test]
+    fn signal_command_reject() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+
+        // Unknown signal code.
+        ble.channels.signal(conn, &[0x30, 1, 0, 0], &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x01, 1, 2, 0, 0x00, 0x00]);
+
+        // BR/EDR only requests are not understood on the LE signaling channel.
+        let req = [0x02, 2, 4, 0, 0x01, 0, 0x40, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x01, 2, 2, 0, 0x00, 0x00]);
+
+        // Signals larger than the signaling MTU.
+        let mut req = [0; 28];
+        req[..4].copy_from_slice(&[0x08, 3, 24, 0]);
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x01, 3, 4, 0, 0x01, 0x00, 23, 0]);
+
+        // Disconnection request for a channel that doesn't exist.
+        let req = [0x06, 4, 4, 0, 0x40, 0, 0x50, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x01, 4, 6, 0, 0x02, 0x00, 0x40, 0, 0x50, 0]);
+    }
+
+    #[test]
+    fn signal_echo_and_information() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Peripheral)
+            .unwrap();
+
+        let req = [0x08, 5, 3, 0, 1, 2, 3];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x09, 5, 3, 0, 1, 2, 3]);
+
+        // Fixed channels.
+        let req = [0x0A, 6, 2, 0, 0x03, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(
+            next_signal(&ble),
+            [0x0B, 6, 12, 0, 0x03, 0, 0x00, 0, 0x70, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // Connectionless MTU is not supported.
+        let req = [0x0A, 7, 2, 0, 0x01, 0];
+        ble.channels.signal(conn, &req, &ble.connections).unwrap();
+        assert_eq!(next_signal(&ble), [0x0B, 7, 4, 0, 0x01, 0, 0x01, 0]);
+    }
+
+    #[test]
+    fn signal_response_timeout() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let ble = MockController::new();
+
+        let builder = crate::new(ble, &mut resources);
+        let ble = builder.host;
+
+        let conn = ConnHandle::new(33);
+        ble.connections
+            .connect(conn, AddrKind::PUBLIC, BdAddr::new([0; 6]), LeConnRole::Central)
+            .unwrap();
+
+        // A response, or a reject, stops the timer of the request.
+        ble.channels.expect_response(conn, 8);
+        ble.channels.expect_response(conn, 9);
+        let res = [0x13, 8, 2, 0, 0, 0];
+        ble.channels.signal(conn, &res, &ble.connections).unwrap();
+        let reject = [0x01, 9, 2, 0, 0, 0];
+        ble.channels.signal(conn, &reject, &ble.connections).unwrap();
+        assert!(ble.channels.with_mut(|state| state.pending.is_empty()));
+
+        // An unanswered request times out the connection.
+        ble.channels.with_mut(|state| {
+            state.expect_response(conn, 10);
+            state.pending[0].deadline = Instant::now();
+        });
+        assert_eq!(embassy_futures::block_on(ble.channels.signal_timeout()), conn);
+        assert!(ble.channels.with_mut(|state| state.pending.is_empty()));
+    }
 }
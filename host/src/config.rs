@@ -71,3 +71,21 @@ pub const GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS: usize = raw::GATT_CLIENT_NOT
 ///
 /// Default: 1.
 pub const GATT_CLIENT_NOTIFICATION_QUEUE_SIZE: usize = raw::GATT_CLIENT_NOTIFICATION_QUEUE_SIZE;
+
+/// GATT prepare write queue size.
+///
+/// This is the number of bytes available to each connection for queueing
+/// prepared writes until they are executed. Every queued write uses 6 bytes
+/// in addition to the value being written. It also bounds the length of the
+/// combined value reported for each written attribute.
+///
+/// Default: 512.
+pub const GATT_PREPARE_WRITE_QUEUE_SIZE: usize = raw::GATT_PREPARE_WRITE_QUEUE_SIZE;
+
+/// GATT client database cache size.
+///
+/// This is the number of discovered characteristics each GATT client can
+/// remember, so that they can be reused on reconnection to a bonded peer.
+///
+/// Default: 16.
+pub const GATT_CLIENT_CACHE_SIZE: usize = raw::GATT_CLIENT_CACHE_SIZE;
@@ -18,11 +18,13 @@ use embassy_time::Duration;
 use crate::connection_manager::ConnectionManager;
 #[cfg(feature = "connection-metrics")]
 pub use crate::connection_manager::Metrics as ConnectionMetrics;
+#[cfg(feature = "gatt")]
+use crate::gatt::AttBearer;
 use crate::pdu::Pdu;
 #[cfg(feature = "gatt")]
 use crate::prelude::{AttributeServer, GattConnection};
 #[cfg(feature = "security")]
-use crate::security_manager::{BondInformation, PassKey};
+use crate::security_manager::{BondInformation, KeypressNotification, PassKey};
 #[cfg(feature = "connection-params-update")]
 use crate::types::l2cap::ConnParamUpdateRes;
 use crate::{bt_hci_duration, BleHostError, Error, Identity, PacketPool, Stack};
@@ -190,6 +192,9 @@ pub enum ConnectionEvent {
     /// Request to make the user input the pass key
     PassKeyInput,
     #[cfg(feature = "security")]
+    /// Progress of the pass key input on the peer
+    PassKeyKeypress(KeypressNotification),
+    #[cfg(feature = "security")]
     /// Pairing completed
     PairingComplete {
         /// Security level of this pairing
@@ -200,6 +205,14 @@ pub enum ConnectionEvent {
     #[cfg(feature = "security")]
     /// Pairing completed
     PairingFailed(Error),
+    #[cfg(feature = "security")]
+    /// A bond was evicted to store the bond created by pairing on this connection.
+    ///
+    /// See [`BondEvictionPolicy`](crate::BondEvictionPolicy).
+    BondEvicted {
+        /// Identity of the peer of the evicted bond
+        identity: Identity,
+    },
 }
 
 impl Default for ConnectParams {
@@ -266,13 +279,30 @@ impl<'stack, P: PacketPool> Connection<'stack, P> {
         self.manager.next(self.index).await
     }
 
+    /// Send an ATT PDU on one of the ATT bearers of the connection.
     #[cfg(feature = "gatt")]
-    pub(crate) async fn next_gatt(&self) -> Pdu<P::Packet> {
+    pub(crate) async fn send_att(&self, bearer: AttBearer, pdu: Pdu<P::Packet>) {
+        match bearer {
+            AttBearer::Unenhanced => self.send(pdu).await,
+            AttBearer::Enhanced { index, .. } => self.manager.send_att(index, pdu).await,
+        }
+    }
+
+    #[cfg(feature = "gatt")]
+    pub(crate) fn try_send_att(&self, bearer: AttBearer, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+        match bearer {
+            AttBearer::Unenhanced => self.try_send(pdu),
+            AttBearer::Enhanced { index, .. } => self.manager.try_send_att(index, pdu),
+        }
+    }
+
+    #[cfg(feature = "gatt")]
+    pub(crate) async fn next_gatt(&self) -> (AttBearer, Pdu<P::Packet>) {
         self.manager.next_gatt(self.index).await
     }
 
     #[cfg(feature = "gatt")]
-    pub(crate) async fn next_gatt_client(&self) -> Pdu<P::Packet> {
+    pub(crate) async fn next_gatt_client(&self) -> (AttBearer, Pdu<P::Packet>) {
         self.manager.next_gatt_client(self.index).await
     }
 
@@ -321,11 +351,38 @@ impl<'stack, P: PacketPool> Connection<'stack, P> {
         self.manager.request_security(self.index)
     }
 
+    /// Request security and wait until encryption is enabled or pairing completes or fails.
+    ///
+    /// Returns the resulting security level.
+    #[cfg(feature = "security")]
+    pub(crate) async fn escalate_security(&self) -> Result<SecurityLevel, Error> {
+        let changes = self.manager.escalate_security(self.index)?;
+        core::future::poll_fn(|cx| self.manager.poll_security_change(self.index, changes, cx)).await
+    }
+
+    /// Sign a data PDU with the signing key distributed to the peer when bonding.
+    #[cfg(feature = "security")]
+    pub(crate) fn sign(&self, message: &[u8]) -> Result<[u8; 12], Error> {
+        self.manager.sign(self.index, message)
+    }
+
     /// Get the encrypted state of the connection
     pub fn security_level(&self) -> Result<SecurityLevel, Error> {
         self.manager.get_security_level(self.index)
     }
 
+    /// Get the size in bytes of the key encrypting the connection, 0 if it is not encrypted.
+    pub fn encryption_key_size(&self) -> Result<u8, Error> {
+        self.manager.get_encryption_key_size(self.index)
+    }
+
+    /// Get whether the connection is encrypted with a key from LE Secure Connections pairing.
+    ///
+    /// False for unencrypted links and for keys from LE legacy pairing.
+    pub fn secure_connections(&self) -> Result<bool, Error> {
+        self.manager.get_secure_connections(self.index)
+    }
+
     /// Get whether the connection is set as bondable or not.
     ///
     /// This is only relevant before pairing has started.
@@ -335,7 +392,8 @@ impl<'stack, P: PacketPool> Connection<'stack, P> {
 
     /// Set whether the connection is bondable or not.
     ///
-    /// By default a connection is **not** bondable.
+    /// By default a connection is **not** bondable, unless [`SecurityPolicy::bondable`](crate::SecurityPolicy::bondable)
+    /// is set.
     ///
     /// This must be set before pairing is initiated. Once the pairing procedure has started
     /// this field is ignored.
@@ -366,6 +424,14 @@ impl<'stack, P: PacketPool> Connection<'stack, P> {
         self.manager.pass_key_input(self.index, pass_key)
     }
 
+    #[cfg(feature = "security")]
+    /// Notify the peer of the progress of the pass key input.
+    ///
+    /// Only possible while the pass key is input, and if both devices support keypress notifications.
+    pub fn send_keypress_notification(&self, notification: KeypressNotification) -> Result<(), Error> {
+        self.manager.send_keypress_notification(self.index, notification)
+    }
+
     /// Request connection to be disconnected.
     pub fn disconnect(&self) {
         self.manager
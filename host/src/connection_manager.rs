@@ -1,3 +1,5 @@
+#[cfg(feature = "security")]
+use core::cell::Cell;
 use core::cell::RefCell;
 use core::future::poll_fn;
 #[cfg(feature = "security")]
@@ -11,12 +13,18 @@ use embassy_sync::waitqueue::WakerRegistration;
 #[cfg(feature = "security")]
 use embassy_time::TimeoutError;
 
+#[cfg(feature = "gatt")]
+use crate::channel_manager::ChannelIndex;
 use crate::connection::{Connection, ConnectionEvent, SecurityLevel};
+#[cfg(feature = "gatt")]
+use crate::gatt::AttBearer;
 use crate::host::EventHandler;
 use crate::pdu::Pdu;
 use crate::prelude::sar::PacketReassembly;
 #[cfg(feature = "security")]
 use crate::security_manager::{SecurityEventData, SecurityManager};
+#[cfg(feature = "security")]
+use crate::Address;
 use crate::{config, Error, Identity, PacketPool};
 
 struct State<'d, P> {
@@ -47,11 +55,15 @@ impl<P> State<'_, P> {
 }
 
 type EventChannel = Channel<NoopRawMutex, ConnectionEvent, { config::CONNECTION_EVENT_QUEUE_SIZE }>;
-type GattChannel<P> = Channel<NoopRawMutex, Pdu<P>, { config::L2CAP_RX_QUEUE_SIZE }>;
+#[cfg(feature = "gatt")]
+type GattChannel<P> = Channel<NoopRawMutex, (AttBearer, Pdu<P>), { config::L2CAP_RX_QUEUE_SIZE }>;
 
 pub(crate) struct ConnectionManager<'d, P: PacketPool> {
     state: RefCell<State<'d, P::Packet>>,
     outbound: Channel<NoopRawMutex, (ConnHandle, Pdu<P::Packet>), { config::L2CAP_TX_QUEUE_SIZE }>,
+    /// ATT PDUs to send on Enhanced ATT bearers, with room for the basic L2CAP header like on the fixed channel.
+    #[cfg(feature = "gatt")]
+    att_outbound: Channel<NoopRawMutex, (ChannelIndex, Pdu<P::Packet>), { config::L2CAP_TX_QUEUE_SIZE }>,
     #[cfg(feature = "security")]
     pub(crate) security_manager: SecurityManager<{ crate::BI_COUNT }>,
 }
@@ -68,6 +80,8 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                 default_att_mtu,
             }),
             outbound: Channel::new(),
+            #[cfg(feature = "gatt")]
+            att_outbound: Channel::new(),
             #[cfg(feature = "security")]
             security_manager: SecurityManager::new(),
         }
@@ -99,7 +113,7 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
     }
 
     #[cfg(feature = "gatt")]
-    pub(crate) async fn next_gatt(&self, index: u8) -> Pdu<P::Packet> {
+    pub(crate) async fn next_gatt(&self, index: u8) -> (AttBearer, Pdu<P::Packet>) {
         poll_fn(|cx| self.with_mut(|state| state.connections[index as usize].gatt.poll_receive(cx))).await
     }
 
@@ -121,11 +135,11 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
     }
 
     #[cfg(feature = "gatt")]
-    pub(crate) fn post_gatt(&self, handle: ConnHandle, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+    pub(crate) fn post_gatt(&self, handle: ConnHandle, bearer: AttBearer, pdu: Pdu<P::Packet>) -> Result<(), Error> {
         self.with_mut(|state| {
             for entry in state.connections.iter() {
                 if entry.state == ConnectionState::Connected && Some(handle) == entry.handle {
-                    entry.gatt.try_send(pdu).map_err(|_| Error::OutOfMemory)?;
+                    entry.gatt.try_send((bearer, pdu)).map_err(|_| Error::OutOfMemory)?;
                     return Ok(());
                 }
             }
@@ -134,11 +148,19 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
     }
 
     #[cfg(feature = "gatt")]
-    pub(crate) fn post_gatt_client(&self, handle: ConnHandle, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+    pub(crate) fn post_gatt_client(
+        &self,
+        handle: ConnHandle,
+        bearer: AttBearer,
+        pdu: Pdu<P::Packet>,
+    ) -> Result<(), Error> {
         self.with_mut(|state| {
             for entry in state.connections.iter() {
                 if entry.state == ConnectionState::Connected && Some(handle) == entry.handle {
-                    entry.gatt_client.try_send(pdu).map_err(|_| Error::OutOfMemory)?;
+                    entry
+                        .gatt_client
+                        .try_send((bearer, pdu))
+                        .map_err(|_| Error::OutOfMemory)?;
                     return Ok(());
                 }
             }
@@ -146,8 +168,25 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         })
     }
 
+    /// Verify the authentication signature of a signed write command received from a bonded peer.
+    #[cfg(all(feature = "gatt", feature = "security"))]
+    pub(crate) fn verify_signed_write(&self, handle: ConnHandle, pdu: &[u8]) -> Result<(), Error> {
+        let identity = self.with_mut(|state| {
+            state
+                .connections
+                .iter()
+                .find(|entry| entry.state == ConnectionState::Connected && Some(handle) == entry.handle)
+                .and_then(|entry| entry.peer_identity)
+                .ok_or(Error::NotFound)
+        })?;
+        // The signature covers the opcode, handle and value preceding it
+        let (message, signature) = pdu.split_at(pdu.len().checked_sub(12).ok_or(Error::InvalidValue)?);
+        let signature = signature.try_into().map_err(|_| Error::InvalidValue)?;
+        self.security_manager.verify_signature(&identity, message, &signature)
+    }
+
     #[cfg(feature = "gatt")]
-    pub(crate) async fn next_gatt_client(&self, index: u8) -> Pdu<P::Packet> {
+    pub(crate) async fn next_gatt_client(&self, index: u8) -> (AttBearer, Pdu<P::Packet>) {
         poll_fn(|cx| self.with_mut(|state| state.connections[index as usize].gatt_client.poll_receive(cx))).await
     }
 
@@ -292,7 +331,10 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                 #[cfg(feature = "security")]
                 {
                     storage.security_level = SecurityLevel::NoEncryption;
+                    storage.encryption_key_size = 0;
+                    storage.secure_connections = false;
                     storage.bondable = false;
+                    storage.security_changed();
                     let _ = self.security_manager.disconnect(h, storage.peer_identity);
                 }
                 return Ok(());
@@ -327,6 +369,14 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                     #[cfg(feature = "security")]
                     irk: None,
                 });
+                #[cfg(feature = "security")]
+                {
+                    storage.peer_resolvable_address = None;
+                    storage.bondable = self.security_manager.default_bondable();
+                    if let Some(identity) = storage.peer_identity.as_ref() {
+                        self.security_manager.peer_connected(identity);
+                    }
+                }
                 storage.role.replace(role);
 
                 match role {
@@ -344,6 +394,18 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         Err(Error::NotFound)
     }
 
+    /// Record the resolvable private address of a peer the controller resolved to its identity address.
+    #[cfg(feature = "security")]
+    pub(crate) fn set_peer_resolvable_address(&self, handle: ConnHandle, address: BdAddr) {
+        let mut state = self.state.borrow_mut();
+        for storage in state.connections.iter_mut() {
+            if storage.state == ConnectionState::Connecting && storage.handle == Some(handle) {
+                storage.peer_resolvable_address = Some(address);
+                return;
+            }
+        }
+    }
+
     pub(crate) fn poll_accept(
         &'d self,
         role: LeConnRole,
@@ -509,6 +571,21 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         self.outbound.receive().await
     }
 
+    #[cfg(feature = "gatt")]
+    pub(crate) async fn send_att(&self, index: ChannelIndex, pdu: Pdu<P::Packet>) {
+        self.att_outbound.send((index, pdu)).await
+    }
+
+    #[cfg(feature = "gatt")]
+    pub(crate) fn try_send_att(&self, index: ChannelIndex, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+        self.att_outbound.try_send((index, pdu)).map_err(|_| Error::OutOfMemory)
+    }
+
+    #[cfg(feature = "gatt")]
+    pub(crate) async fn att_outbound(&self) -> (ChannelIndex, Pdu<P::Packet>) {
+        self.att_outbound.receive().await
+    }
+
     pub(crate) fn get_att_mtu_handle(&self, conn: ConnHandle) -> u16 {
         let mut state = self.state.borrow_mut();
         for storage in state.connections.iter_mut() {
@@ -572,6 +649,23 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         Err(Error::NotSupported)
     }
 
+    #[cfg(feature = "security")]
+    pub(crate) fn send_keypress_notification(
+        &self,
+        index: u8,
+        notification: crate::security_manager::KeypressNotification,
+    ) -> Result<(), Error> {
+        if self.state.borrow_mut().connections[index as usize].state == ConnectionState::Connected {
+            self.security_manager.send_keypress_notification(
+                notification,
+                self,
+                &self.state.borrow().connections[index as usize],
+            )
+        } else {
+            Err(Error::Disconnected)
+        }
+    }
+
     pub(crate) fn request_security(&self, index: u8) -> Result<(), Error> {
         #[cfg(feature = "security")]
         {
@@ -586,6 +680,43 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         Err(Error::NotSupported)
     }
 
+    /// Request security and return the number of security changes seen before the request.
+    #[cfg(feature = "security")]
+    pub(crate) fn escalate_security(&self, index: u8) -> Result<u8, Error> {
+        let changes = self.state.borrow().connections[index as usize].security_changes.get();
+        self.request_security(index)?;
+        Ok(changes)
+    }
+
+    /// Sign a data PDU for the peer of a connection with the local signing key of its bond.
+    #[cfg(feature = "security")]
+    pub(crate) fn sign(&self, index: u8, message: &[u8]) -> Result<[u8; 12], Error> {
+        let identity = self.state.borrow().connections[index as usize]
+            .peer_identity
+            .ok_or(Error::NotFound)?;
+        self.security_manager.sign(&identity, message)
+    }
+
+    /// Poll for the security of a connection to change, or fail to, after `changes` changes.
+    #[cfg(feature = "security")]
+    pub(crate) fn poll_security_change(
+        &self,
+        index: u8,
+        changes: u8,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<SecurityLevel, Error>> {
+        let state = self.state.borrow();
+        let storage = &state.connections[index as usize];
+        if storage.state != ConnectionState::Connected {
+            Poll::Ready(Err(Error::Disconnected))
+        } else if storage.security_changes.get() != changes {
+            Poll::Ready(Ok(storage.security_level))
+        } else {
+            storage.security_waker.borrow_mut().register(cx.waker());
+            Poll::Pending
+        }
+    }
+
     pub(crate) fn get_security_level(&self, index: u8) -> Result<SecurityLevel, Error> {
         let state = self.state.borrow();
         match state.connections[index as usize].state {
@@ -601,6 +732,36 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
         }
     }
 
+    pub(crate) fn get_encryption_key_size(&self, index: u8) -> Result<u8, Error> {
+        let state = self.state.borrow();
+        match state.connections[index as usize].state {
+            ConnectionState::Connected => {
+                #[cfg(feature = "security")]
+                {
+                    Ok(state.connections[index as usize].encryption_key_size)
+                }
+                #[cfg(not(feature = "security"))]
+                Ok(0)
+            }
+            _ => Err(Error::Disconnected),
+        }
+    }
+
+    pub(crate) fn get_secure_connections(&self, index: u8) -> Result<bool, Error> {
+        let state = self.state.borrow();
+        match state.connections[index as usize].state {
+            ConnectionState::Connected => {
+                #[cfg(feature = "security")]
+                {
+                    Ok(state.connections[index as usize].secure_connections)
+                }
+                #[cfg(not(feature = "security"))]
+                Ok(false)
+            }
+            _ => Err(Error::Disconnected),
+        }
+    }
+
     pub(crate) fn get_bondable(&self, index: u8) -> Result<bool, Error> {
         let state = self.state.borrow();
         match state.connections[index as usize].state {
@@ -682,13 +843,18 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
     where
         C: crate::ControllerCmdSync<bt_hci::cmd::le::LeLongTermKeyRequestReply>
             + crate::ControllerCmdAsync<bt_hci::cmd::le::LeEnableEncryption>
+            + crate::ControllerCmdSync<bt_hci::cmd::le::LeSetAddrResolutionEnable>
+            + crate::ControllerCmdSync<bt_hci::cmd::le::LeClearResolvingList>
+            + crate::ControllerCmdSync<bt_hci::cmd::le::LeAddDeviceToResolvingList>
+            + crate::ControllerCmdSync<bt_hci::cmd::le::LeSetPrivacyMode>
+            + crate::ControllerCmdSync<bt_hci::cmd::le::LeSetResolvablePrivateAddrTimeout>
             + crate::ControllerCmdSync<bt_hci::cmd::link_control::Disconnect>,
     {
         use bt_hci::cmd::le::{LeEnableEncryption, LeLongTermKeyRequestReply};
         use bt_hci::cmd::link_control::Disconnect;
 
         match _event {
-            crate::security_manager::SecurityEventData::SendLongTermKey(handle) => {
+            crate::security_manager::SecurityEventData::SendLongTermKey(handle, central_identification) => {
                 let conn_info = self.state.borrow().connections.iter().find_map(|connection| {
                     match (connection.handle, connection.peer_identity) {
                         (Some(connection_handle), Some(identity)) => {
@@ -703,9 +869,13 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                 });
 
                 if let Some((conn, identity)) = conn_info {
-                    if let Some(ltk) = self.security_manager.get_peer_long_term_key(&identity) {
+                    let bond = self
+                        .security_manager
+                        .get_peer_bond_information(&identity)
+                        .filter(|bond| bond.central_identification.unwrap_or_default() == central_identification);
+                    if let Some(bond) = bond {
                         let _ = host
-                            .command(LeLongTermKeyRequestReply::new(handle, ltk.to_le_bytes()))
+                            .command(LeLongTermKeyRequestReply::new(handle, bond.ltk.to_le_bytes()))
                             .await?;
                     } else {
                         warn!("[host] Long term key request reply failed, no long term key");
@@ -738,10 +908,16 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                             },
                         );
                 if let Some((index, role, identity)) = connection_data {
-                    if let Some(ltk) = self.security_manager.get_peer_long_term_key(&identity) {
+                    if let Some(bond) = self.security_manager.get_peer_bond_information(&identity) {
                         if let Some(LeConnRole::Central) = role {
-                            host.async_command(LeEnableEncryption::new(handle, [0; 8], 0, ltk.to_le_bytes()))
-                                .await?;
+                            let id = bond.central_identification.unwrap_or_default();
+                            host.async_command(LeEnableEncryption::new(
+                                handle,
+                                id.rand,
+                                id.ediv,
+                                bond.ltk.to_le_bytes(),
+                            ))
+                            .await?;
                         }
                     } else {
                         warn!("[host] Enable encryption failed, no long term key")
@@ -755,6 +931,9 @@ impl<'d, P: PacketPool> ConnectionManager<'d, P> {
                 self.security_manager.cancel_timeout();
             }
             crate::security_manager::SecurityEventData::TimerChange => (),
+            crate::security_manager::SecurityEventData::ResolvingListChanged => {
+                host.update_resolving_list().await?;
+            }
         }
         Ok(())
     }
@@ -802,6 +981,9 @@ pub struct ConnectionStorage<P> {
     pub role: Option<LeConnRole>,
     pub peer_addr_kind: Option<AddrKind>,
     pub peer_identity: Option<Identity>,
+    // Address the peer connected with when the controller resolved it to the identity address
+    #[cfg(feature = "security")]
+    pub peer_resolvable_address: Option<BdAddr>,
     pub att_mtu: u16,
     pub link_credits: usize,
     pub link_credit_waker: WakerRegistration,
@@ -810,8 +992,18 @@ pub struct ConnectionStorage<P> {
     pub metrics: Metrics,
     #[cfg(feature = "security")]
     pub security_level: SecurityLevel,
+    // Size of the key encrypting the link in bytes, 0 when unencrypted
+    #[cfg(feature = "security")]
+    pub encryption_key_size: u8,
+    #[cfg(feature = "security")]
+    pub secure_connections: bool,
     #[cfg(feature = "security")]
     pub bondable: bool,
+    // Updated by the security manager, which only has shared access to the storage
+    #[cfg(feature = "security")]
+    pub security_changes: Cell<u8>,
+    #[cfg(feature = "security")]
+    pub security_waker: RefCell<WakerRegistration>,
     pub events: EventChannel,
     pub reassembly: PacketReassembly<P>,
     #[cfg(feature = "gatt")]
@@ -890,6 +1082,8 @@ impl<P> ConnectionStorage<P> {
             role: None,
             peer_addr_kind: None,
             peer_identity: None,
+            #[cfg(feature = "security")]
+            peer_resolvable_address: None,
             att_mtu: 23,
             link_credits: 0,
             link_credit_waker: WakerRegistration::new(),
@@ -898,6 +1092,10 @@ impl<P> ConnectionStorage<P> {
             metrics: Metrics::new(),
             #[cfg(feature = "security")]
             security_level: SecurityLevel::NoEncryption,
+            #[cfg(feature = "security")]
+            encryption_key_size: 0,
+            #[cfg(feature = "security")]
+            secure_connections: false,
             events: EventChannel::new(),
             #[cfg(feature = "gatt")]
             gatt: GattChannel::new(),
@@ -906,8 +1104,34 @@ impl<P> ConnectionStorage<P> {
             reassembly: PacketReassembly::new(),
             #[cfg(feature = "security")]
             bondable: false,
+            #[cfg(feature = "security")]
+            security_changes: Cell::new(0),
+            #[cfg(feature = "security")]
+            security_waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Address the peer used to establish the connection, as used by pairing.
+    #[cfg(feature = "security")]
+    pub(crate) fn peer_connection_address(&self) -> Option<Address> {
+        match self.peer_resolvable_address {
+            Some(addr) => Some(Address {
+                kind: AddrKind::RANDOM,
+                addr,
+            }),
+            None => Some(Address {
+                kind: self.peer_addr_kind?,
+                addr: self.peer_identity?.bd_addr,
+            }),
         }
     }
+
+    /// Record that encryption or pairing completed or failed on this connection.
+    #[cfg(feature = "security")]
+    pub(crate) fn security_changed(&self) {
+        self.security_changes.set(self.security_changes.get().wrapping_add(1));
+        self.security_waker.borrow_mut().wake();
+    }
 }
 
 impl<P> core::fmt::Debug for ConnectionStorage<P> {
@@ -1314,4 +1538,71 @@ pub(crate) mod tests {
 
         assert!(!mgr.is_handle_connected(ConnHandle::new(3)));
     }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn security_change_wakes_waiter() {
+        let mgr = setup();
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::RANDOM,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        let Poll::Ready(handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+
+        let changes = mgr.state.borrow().connections[0].security_changes.get();
+        assert!(mgr.poll_security_change(0, changes, &mut cx).is_pending());
+
+        unwrap!(mgr.with_connected_handle(ConnHandle::new(0), |storage| {
+            storage.security_changed();
+            Ok(())
+        }));
+        assert_eq!(
+            mgr.poll_security_change(0, changes, &mut cx),
+            Poll::Ready(Ok(SecurityLevel::NoEncryption))
+        );
+
+        // Waiters are released when the connection is lost.
+        let changes = mgr.state.borrow().connections[0].security_changes.get();
+        unwrap!(mgr.disconnected(ConnHandle::new(0), Status::UNSPECIFIED));
+        assert_eq!(
+            mgr.poll_security_change(0, changes, &mut cx),
+            Poll::Ready(Err(Error::Disconnected))
+        );
+        drop(handle);
+    }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn resolved_peer_reports_identity() {
+        let mgr = setup();
+        let rpa = BdAddr::new([1, 2, 3, 4, 5, 0x46]);
+        unwrap!(mgr.connect(
+            ConnHandle::new(0),
+            AddrKind::PUBLIC,
+            BdAddr::new(ADDR_1),
+            LeConnRole::Peripheral
+        ));
+        mgr.set_peer_resolvable_address(ConnHandle::new(0), rpa);
+
+        let Poll::Ready(handle) = mgr.poll_accept(LeConnRole::Peripheral, &[], None) else {
+            panic!("expected connection to be accepted");
+        };
+        assert_eq!(handle.peer_addr_kind(), AddrKind::PUBLIC);
+        assert_eq!(handle.peer_identity().bd_addr, BdAddr::new(ADDR_1));
+
+        // Pairing uses the address the connection was established with
+        assert_eq!(
+            mgr.state.borrow().connections[0].peer_connection_address(),
+            Some(Address {
+                kind: AddrKind::RANDOM,
+                addr: rpa
+            })
+        );
+        drop(handle);
+    }
 }
@@ -11,19 +11,29 @@ use embassy_sync::blocking_mutex::raw::RawMutex;
 use heapless::String;
 use static_cell::StaticCell;
 
+use crate::attribute_server::SERVER_ENHANCED_ATT;
 use crate::prelude::*;
 
 /// Advertising packet is limited to 31 bytes. 9 of these are used by other GAP data, leaving 22 bytes for the Device Name characteristic
 const DEVICE_NAME_MAX_LENGTH: usize = 22;
 
 /// The number of attributes added by the GAP and GATT services
-/// GAP_SERVICE:       1
-/// ├── DEVICE_NAME:   2
-/// └── APPEARANCE:    2
-/// GATT_SERVICE:    + 1
-///                  ---
-///                  = 6
-pub const GAP_SERVICE_ATTRIBUTE_COUNT: usize = 6;
+/// GAP_SERVICE:                   1
+/// ├── DEVICE_NAME:               2
+/// └── APPEARANCE:                2
+/// GATT_SERVICE:                + 1
+/// ├── SERVICE_CHANGED:           3
+/// ├── CLIENT_SUPPORTED_FEATURES: 2
+/// ├── DATABASE_HASH:             2 (with the `security` feature)
+/// └── SERVER_SUPPORTED_FEATURES: 2
+///                              ---
+///                              = 15
+pub const GAP_SERVICE_ATTRIBUTE_COUNT: usize = if cfg!(feature = "security") { 15 } else { 13 };
+
+/// The number of CCCDs added by the GAP and GATT services
+/// GATT_SERVICE:
+/// └── SERVICE_CHANGED: 1
+pub const GAP_SERVICE_CCCD_COUNT: usize = 1;
 
 /// Configuration for the GAP Service.
 pub enum GapConfig<'a> {
@@ -93,7 +103,7 @@ impl<'a> PeripheralConfig<'a> {
         gap_builder.add_characteristic_ro(characteristic::APPEARANCE, self.appearance);
         gap_builder.build();
 
-        table.add_service(Service::new(service::GATT));
+        build_gatt_service(table);
 
         Ok(())
     }
@@ -113,8 +123,41 @@ impl<'a> CentralConfig<'a> {
         gap_builder.add_characteristic_ro(characteristic::APPEARANCE, self.appearance);
         gap_builder.build();
 
-        table.add_service(Service::new(service::GATT));
+        build_gatt_service(table);
 
         Ok(())
     }
 }
+
+/// Add the GATT service to the attribute table.
+///
+/// The values of these characteristics are managed by the attribute server.
+fn build_gatt_service<M: RawMutex, const MAX: usize>(table: &mut AttributeTable<'_, M, MAX>) {
+    static SERVICE_CHANGED: StaticCell<[u8; 4]> = StaticCell::new();
+    static CLIENT_SUPPORTED_FEATURES: StaticCell<[u8; 1]> = StaticCell::new();
+    #[cfg(feature = "security")]
+    static DATABASE_HASH: StaticCell<[u8; 16]> = StaticCell::new();
+
+    let mut gatt_builder = table.add_service(Service::new(service::GATT));
+    gatt_builder.add_characteristic(
+        characteristic::SERVICE_CHANGED,
+        &[CharacteristicProp::Indicate],
+        [0u8; 4],
+        SERVICE_CHANGED.init([0; 4]),
+    );
+    gatt_builder.add_characteristic(
+        characteristic::CLIENT_SUPPORTED_FEATURES,
+        &[CharacteristicProp::Read, CharacteristicProp::Write],
+        0u8,
+        CLIENT_SUPPORTED_FEATURES.init([0; 1]),
+    );
+    #[cfg(feature = "security")]
+    gatt_builder.add_characteristic(
+        characteristic::DATABASE_HASH,
+        &[CharacteristicProp::Read],
+        [0u8; 16],
+        DATABASE_HASH.init([0; 16]),
+    );
+    gatt_builder.add_characteristic_ro(characteristic::SERVER_SUPPORTED_FEATURES, &SERVER_ENHANCED_ATT);
+    gatt_builder.build();
+}
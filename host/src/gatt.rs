@@ -1,37 +1,51 @@
 //! GATT server and client implementation.
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::future::Future;
 use core::marker::PhantomData;
 
 use bt_hci::controller::Controller;
-use bt_hci::param::{ConnHandle, PhyKind, Status};
-use bt_hci::uuid::declarations::{CHARACTERISTIC, PRIMARY_SERVICE};
+use bt_hci::param::{BdAddr, ConnHandle, PhyKind, Status};
+use bt_hci::uuid::characteristic::{
+    CLIENT_SUPPORTED_FEATURES, DATABASE_HASH, SERVER_SUPPORTED_FEATURES, SERVICE_CHANGED,
+};
+use bt_hci::uuid::declarations::{CHARACTERISTIC, INCLUDE, PRIMARY_SERVICE};
 use bt_hci::uuid::descriptors::CLIENT_CHARACTERISTIC_CONFIGURATION;
-use embassy_futures::select::{select, Either};
+use bt_hci::uuid::service;
+use embassy_futures::select::{select, select_array, Either};
 use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
 use embassy_sync::channel::Channel;
+use embassy_sync::mutex::{Mutex, MutexGuard};
 use embassy_sync::pubsub::{self, PubSubChannel, WaitResult};
-use embassy_time::Duration;
+use embassy_time::{with_deadline, Duration, Instant};
 use heapless::Vec;
 
-use crate::att::{self, Att, AttClient, AttCmd, AttErrorCode, AttReq, AttRsp, AttServer, AttUns, ATT_HANDLE_VALUE_NTF};
-use crate::attribute::{AttributeData, Characteristic, CharacteristicProp, Uuid};
-use crate::attribute_server::{AttributeServer, DynamicAttributeServer};
+use crate::att::{
+    self, Att, AttCfm, AttClient, AttCmd, AttErrorCode, AttReq, AttRsp, AttServer, AttUns, MultipleHandleValueIter,
+    ATT_HANDLE_VALUE_IND, ATT_HANDLE_VALUE_NTF, ATT_MULTIPLE_HANDLE_VALUE_NTF,
+};
+use crate::attribute::{AttributeData, Characteristic, CharacteristicProp, CharacteristicProps, Uuid};
+use crate::attribute_server::{
+    AttributeServer, DynamicAttributeServer, ENHANCED_ATT, MULTIPLE_NOTIFICATIONS, SERVER_ENHANCED_ATT,
+};
+use crate::channel_manager::ChannelIndex;
 use crate::connection::Connection;
 #[cfg(feature = "security")]
 use crate::connection::SecurityLevel;
 use crate::cursor::{ReadCursor, WriteCursor};
+use crate::l2cap::{L2capChannel, L2capChannelConfig, L2CAP_MAX_ENHANCED_CHANNELS};
 use crate::pdu::Pdu;
 use crate::prelude::ConnectionEvent;
 #[cfg(feature = "security")]
-use crate::security_manager::PassKey;
+use crate::security_manager::{KeypressNotification, PassKey};
 use crate::types::gatt_traits::{AsGatt, FromGatt, FromGattError};
-use crate::types::l2cap::L2capHeader;
+use crate::types::l2cap::{L2capHeader, L2CAP_PSM_EATT};
+use crate::{config, BleHostError, Error, Identity, PacketPool, Stack};
 #[cfg(feature = "security")]
-use crate::BondInformation;
-use crate::{config, BleHostError, Error, PacketPool, Stack};
+use crate::{BondInformation, IdentityResolvingKey};
 
 /// A GATT connection event.
+// GATT events carry the combined value of prepared writes, which can't be boxed without an allocator.
+#[allow(clippy::large_enum_variant)]
 pub enum GattConnectionEvent<'stack, 'server, P: PacketPool> {
     /// Connection disconnected.
     Disconnected {
@@ -92,6 +106,9 @@ pub enum GattConnectionEvent<'stack, 'server, P: PacketPool> {
     /// Input the pass key
     PassKeyInput,
     #[cfg(feature = "security")]
+    /// Progress of the pass key input on the peer
+    PassKeyKeypress(KeypressNotification),
+    #[cfg(feature = "security")]
     /// Pairing completed
     PairingComplete {
         /// Security level of this pairing
@@ -102,14 +119,24 @@ pub enum GattConnectionEvent<'stack, 'server, P: PacketPool> {
     #[cfg(feature = "security")]
     /// Pairing failed
     PairingFailed(Error),
+    #[cfg(feature = "security")]
+    /// A bond was evicted to store the bond created by pairing on this connection
+    BondEvicted {
+        /// Identity of the peer of the evicted bond
+        identity: Identity,
+    },
 }
 
 /// Used to manage a GATT connection with a client.
 pub struct GattConnection<'stack, 'server, P: PacketPool> {
     connection: Connection<'stack, P>,
     pub(crate) server: &'server dyn DynamicAttributeServer<P>,
+    executing: Cell<Option<Executing<P::Packet>>>,
 }
 
+/// Execute write request being reported, with the last attribute reported so far.
+type Executing<P> = (AttBearer, Pdu<P>, u16);
+
 impl<P: PacketPool> Drop for GattConnection<'_, '_, P> {
     fn drop(&mut self) {
         trace!("[gatt {}] disconnecting from server", self.connection.handle().raw());
@@ -126,7 +153,11 @@ impl<'stack, 'server, P: PacketPool> GattConnection<'stack, 'server, P> {
     ) -> Result<Self, Error> {
         trace!("[gatt {}] connecting to server", connection.handle().raw());
         server.connect(&connection)?;
-        Ok(Self { connection, server })
+        Ok(Self {
+            connection,
+            server,
+            executing: Cell::new(None),
+        })
     }
 
     /// Confirm that the displayed pass key matches the one displayed on the other party
@@ -144,10 +175,33 @@ impl<'stack, 'server, P: PacketPool> GattConnection<'stack, 'server, P> {
         self.connection.pass_key_input(pass_key)
     }
 
+    #[cfg(feature = "security")]
+    /// Notify the peer of the progress of the pass key input
+    pub fn send_keypress_notification(&self, notification: KeypressNotification) -> Result<(), Error> {
+        self.connection.send_keypress_notification(notification)
+    }
+
     /// Wait for the next GATT connection event.
     ///
     /// Uses the attribute server to handle the protocol.
     pub async fn next(&self) -> GattConnectionEvent<'stack, 'server, P> {
+        if let Some((bearer, pdu, after)) = self.executing.take() {
+            return self.gatt_event(GattData::new(pdu, bearer, self.connection.clone()), after);
+        }
+
+        #[cfg(feature = "security")]
+        if let Some(handle) = self.server.service_changed_indication(&self.connection) {
+            // The attribute table changed since the client last connected, so its whole cache is affected.
+            let uns = AttUns::Indicate {
+                handle,
+                data: &[0x01, 0x00, 0xff, 0xff],
+            };
+            match assemble(&self.connection, AttBearer::Unenhanced, AttServer::Unsolicited(uns)) {
+                Ok(pdu) => self.connection.send(pdu).await,
+                Err(e) => warn!("[gatt] error sending service changed indication: {:?}", e),
+            }
+        }
+
         match select(self.connection.next(), self.connection.next_gatt()).await {
             Either::First(event) => match event {
                 ConnectionEvent::Disconnected { reason } => GattConnectionEvent::Disconnected { reason },
@@ -193,6 +247,9 @@ impl<'stack, 'server, P: PacketPool> GattConnection<'stack, 'server, P> {
                 #[cfg(feature = "security")]
                 ConnectionEvent::PassKeyInput => GattConnectionEvent::PassKeyInput,
 
+                #[cfg(feature = "security")]
+                ConnectionEvent::PassKeyKeypress(notification) => GattConnectionEvent::PassKeyKeypress(notification),
+
                 #[cfg(feature = "security")]
                 ConnectionEvent::PairingComplete { security_level, bond } => {
                     GattConnectionEvent::PairingComplete { security_level, bond }
@@ -200,11 +257,83 @@ impl<'stack, 'server, P: PacketPool> GattConnection<'stack, 'server, P> {
 
                 #[cfg(feature = "security")]
                 ConnectionEvent::PairingFailed(err) => GattConnectionEvent::PairingFailed(err),
+
+                #[cfg(feature = "security")]
+                ConnectionEvent::BondEvicted { identity } => GattConnectionEvent::BondEvicted { identity },
             },
-            Either::Second(data) => GattConnectionEvent::Gatt {
-                event: GattEvent::new(GattData::new(data, self.connection.clone()), self.server),
-            },
+            Either::Second((bearer, data)) => self.gatt_event(GattData::new(data, bearer, self.connection.clone()), 0),
+        }
+    }
+
+    /// Report a GATT PDU, the writes executed by an execute write request being reported for each attribute
+    /// above `after` in turn.
+    fn gatt_event(&self, mut data: GattData<'stack, P>, after: u16) -> GattConnectionEvent<'stack, 'server, P> {
+        if let Some(handle) = data.combine_prepared_writes(self.server, after) {
+            if self.server.prepared_write_handle(&self.connection, handle).is_some() {
+                // Only the event of the last attribute carries the request, executing all writes once accepted.
+                if let Some(pdu) = data.pdu.take() {
+                    self.executing.set(Some((data.bearer, pdu, handle)));
+                }
+            }
+        }
+        GattConnectionEvent::Gatt {
+            event: GattEvent::new(data, self.server),
+        }
+    }
+
+    /// Notify the client of the values of several characteristics at once.
+    ///
+    /// The values are stored in the attribute server first, and characteristics the client hasn't subscribed to
+    /// are skipped. As many values as the ATT MTU allows are sent in each Multiple Handle Value Notification, or
+    /// each value is notified separately if the client doesn't support them.
+    pub async fn notify_multiple<const N: usize>(&self, values: &MultipleNotification<'_, N>) -> Result<(), Error> {
+        let mut notified: Vec<(u16, &[u8]), N> = Vec::new();
+        for (handle, cccd_handle, value) in values.values.iter() {
+            self.server.set(*handle, value)?;
+            if self.server.should_notify(&self.connection, *cccd_handle) {
+                unwrap!(notified.push((*handle, value)));
+            }
         }
+        let multiple = self.server.client_features(&self.connection) & MULTIPLE_NOTIFICATIONS != 0;
+
+        let mtu = self.connection.get_att_mtu() as usize;
+        let mut rest = &notified[..];
+        while let Some(&(handle, value)) = rest.first() {
+            // The opcode, then a handle and length before each value
+            let mut size = 1;
+            let count = rest
+                .iter()
+                .take_while(|(_, value)| {
+                    size += 4 + value.len();
+                    size <= mtu
+                })
+                .count();
+
+            // A multiple notification carries at least two values
+            if !multiple || count < 2 {
+                let uns = AttUns::Notify { handle, data: value };
+                GattData::send_unsolicited(&self.connection, uns).await?;
+                rest = &rest[1..];
+                continue;
+            }
+
+            let (group, remaining) = rest.split_at(count);
+            let mut tx = P::allocate().ok_or(Error::OutOfMemory)?;
+            let mut w = WriteCursor::new(tx.as_mut());
+            let (mut header, mut data) = w.split(4)?;
+            data.write(att::ATT_MULTIPLE_HANDLE_VALUE_NTF)?;
+            for (handle, value) in group {
+                data.write(*handle)?;
+                data.write(value.len() as u16)?;
+                data.append(value)?;
+            }
+            header.write(data.len() as u16)?;
+            header.write(4_u16)?;
+            let len = header.len() + data.len();
+            self.connection.send(Pdu::new(tx, len)).await;
+            rest = remaining;
+        }
+        Ok(())
     }
 
     /// Get a reference to the underlying BLE connection.
@@ -213,20 +342,90 @@ impl<'stack, 'server, P: PacketPool> GattConnection<'stack, 'server, P> {
     }
 }
 
+/// Characteristic values to notify at once with [`GattConnection::notify_multiple`].
+pub struct MultipleNotification<'a, const N: usize> {
+    /// Value handle, CCCD handle and value of each characteristic.
+    values: Vec<(u16, u16, &'a [u8]), N>,
+}
+
+impl<const N: usize> Default for MultipleNotification<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> MultipleNotification<'a, N> {
+    /// Create an empty set of values.
+    pub fn new() -> Self {
+        Self { values: Vec::new() }
+    }
+
+    /// Add the value of a characteristic, which must support notifications.
+    pub fn push<T: AsGatt>(&mut self, characteristic: &Characteristic<T>, value: &'a T) -> Result<(), Error> {
+        let cccd_handle = characteristic.cccd_handle.ok_or(Error::NotFound)?;
+        self.values
+            .push((characteristic.handle, cccd_handle, value.as_gatt()))
+            .map_err(|_| Error::InsufficientSpace)
+    }
+}
+
+/// The ATT bearer a PDU is exchanged on.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AttBearer {
+    /// The fixed ATT channel of the connection.
+    Unenhanced,
+    /// An Enhanced ATT bearer, using the MTU of its L2CAP channel as ATT MTU.
+    Enhanced { index: ChannelIndex, mtu: u16 },
+}
+
+impl AttBearer {
+    fn mtu<P: PacketPool>(&self, connection: &Connection<'_, P>) -> u16 {
+        match self {
+            Self::Unenhanced => connection.get_att_mtu(),
+            Self::Enhanced { mtu, .. } => *mtu,
+        }
+    }
+
+    /// Whether both bearers are the same, regardless of MTU changes.
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Unenhanced, Self::Unenhanced) => true,
+            (Self::Enhanced { index: a, .. }, Self::Enhanced { index: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
 /// A GATT payload ready for processing.
 pub struct GattData<'stack, P: PacketPool> {
     pdu: Option<Pdu<P::Packet>>,
+    bearer: AttBearer,
     connection: Connection<'stack, P>,
+    prepared: Option<PreparedWrite>,
+}
+
+/// Combined value of the writes prepared for an attribute, reported when executing them.
+struct PreparedWrite {
+    handle: u16,
+    value: Vec<u8, { config::GATT_PREPARE_WRITE_QUEUE_SIZE }>,
 }
 
 impl<'stack, P: PacketPool> GattData<'stack, P> {
-    pub(crate) const fn new(pdu: Pdu<P::Packet>, connection: Connection<'stack, P>) -> Self {
+    pub(crate) const fn new(pdu: Pdu<P::Packet>, bearer: AttBearer, connection: Connection<'stack, P>) -> Self {
         Self {
             pdu: Some(pdu),
+            bearer,
             connection,
+            prepared: None,
         }
     }
 
+    /// Whether the PDU was received on an Enhanced ATT bearer rather than the fixed ATT channel.
+    pub fn is_enhanced(&self) -> bool {
+        matches!(self.bearer, AttBearer::Enhanced { .. })
+    }
+
     /// Return the characteristic handle that this GATT request is related to, if applicable.
     ///
     /// Returns `None` if the request is not related to a characteristic handle (e.g. a service discovery request).
@@ -234,14 +433,38 @@ impl<'stack, P: PacketPool> GattData<'stack, P> {
         match self.incoming() {
             AttClient::Request(AttReq::Write { handle, .. }) => Some(handle),
             AttClient::Command(AttCmd::Write { handle, .. }) => Some(handle),
+            AttClient::Command(AttCmd::SignedWrite { handle, .. }) => Some(handle),
             AttClient::Request(AttReq::Read { handle }) => Some(handle),
             AttClient::Request(AttReq::ReadBlob { handle, .. }) => Some(handle),
+            AttClient::Request(AttReq::ExecuteWrite { .. }) => self.prepared.as_ref().map(|p| p.handle),
             _ => None,
         }
     }
 
+    /// Combine the writes prepared for the first attribute above `after` when the request executes them,
+    /// so that they can be handled like a regular write. Returns the handle of the attribute.
+    fn combine_prepared_writes(&mut self, server: &dyn DynamicAttributeServer<P>, after: u16) -> Option<u16> {
+        if !matches!(
+            self.incoming(),
+            AttClient::Request(AttReq::ExecuteWrite { flags: 0x01 })
+        ) {
+            return None;
+        }
+        let handle = server.prepared_write_handle(&self.connection, after)?;
+        let mut value = Vec::new();
+        let _ = value.resize_default(value.capacity());
+        let len = server.prepared_write(&self.connection, handle, &mut value)?;
+        value.truncate(len);
+        self.prepared = Some(PreparedWrite { handle, value });
+        Some(handle)
+    }
+
     /// Get the raw incoming ATT PDU.
     pub fn incoming(&self) -> AttClient<'_> {
+        if self.pdu.is_none() && self.prepared.is_some() {
+            // The writes prepared for an attribute other than the last one are reported without the request.
+            return AttClient::Request(AttReq::ExecuteWrite { flags: 0x01 });
+        }
         // We know that:
         // - The PDU is decodable, as it was already decoded once before adding it to the connection queue
         // - The PDU is of type `Att::Client` because only those types of PDUs are added to the connection queue
@@ -255,14 +478,14 @@ impl<'stack, P: PacketPool> GattData<'stack, P> {
 
     /// Respond directly to request.
     pub async fn reply(self, rsp: AttRsp<'_>) -> Result<(), Error> {
-        let pdu = assemble(&self.connection, AttServer::Response(rsp))?;
-        self.connection.send(pdu).await;
+        let pdu = assemble(&self.connection, self.bearer, AttServer::Response(rsp))?;
+        self.connection.send_att(self.bearer, pdu).await;
         Ok(())
     }
 
     /// Send an unsolicited ATT PDU without having a request (e.g. notification or indication)
     pub async fn send_unsolicited(connection: &Connection<'_, P>, uns: AttUns<'_>) -> Result<(), Error> {
-        let pdu = assemble(connection, AttServer::Unsolicited(uns))?;
+        let pdu = assemble(connection, AttBearer::Unenhanced, AttServer::Unsolicited(uns))?;
         connection.send(pdu).await;
         Ok(())
     }
@@ -280,10 +503,18 @@ pub enum GattEvent<'stack, 'server, P: PacketPool> {
 
 impl<'stack, 'server, P: PacketPool> GattEvent<'stack, 'server, P> {
     /// Create a new GATT event from the provided `GattData` and `DynamicAttributeServer`.
-    pub fn new(data: GattData<'stack, P>, server: &'server dyn DynamicAttributeServer<P>) -> Self {
+    ///
+    /// Executing prepared writes is reported as a write of the combined value of the first attribute written,
+    /// and accepting it executes all of them. [`GattConnection::next`] reports a write for each attribute
+    /// instead. Prepared writes that can't be executed are reported as an other event.
+    pub fn new(mut data: GattData<'stack, P>, server: &'server dyn DynamicAttributeServer<P>) -> Self {
+        let combined = data.prepared.is_some() || data.combine_prepared_writes(server, 0).is_some();
         let att = data.incoming();
         match att {
-            AttClient::Request(AttReq::Write { .. }) | AttClient::Command(AttCmd::Write { .. }) => {
+            AttClient::Request(AttReq::Write { .. })
+            | AttClient::Command(AttCmd::Write { .. })
+            | AttClient::Command(AttCmd::SignedWrite { .. }) => GattEvent::Write(WriteEvent { data, server }),
+            AttClient::Request(AttReq::ExecuteWrite { .. }) if combined => {
                 GattEvent::Write(WriteEvent { data, server })
             }
             AttClient::Request(AttReq::Read { .. }) | AttClient::Request(AttReq::ReadBlob { .. }) => {
@@ -375,7 +606,9 @@ impl<'stack, P: PacketPool> ReadEvent<'stack, '_, P> {
     pub fn into_payload(mut self) -> GattData<'stack, P> {
         GattData {
             pdu: self.data.pdu.take(),
+            bearer: self.data.bearer,
             connection: self.data.connection.clone(),
+            prepared: self.data.prepared.take(),
         }
     }
 }
@@ -402,8 +635,16 @@ impl<'stack, P: PacketPool> WriteEvent<'stack, '_, P> {
 
     /// Raw data to be written
     pub fn data(&self) -> &[u8] {
-        // Note: write event data is always at offset 3, right?
-        &self.data.pdu.as_ref().unwrap().as_ref()[3..]
+        if let Some(prepared) = &self.data.prepared {
+            return &prepared.value;
+        }
+        let pdu = self.data.pdu.as_ref().unwrap().as_ref();
+        match pdu[0] {
+            // The authentication signature follows the value
+            att::ATT_SIGNED_WRITE_CMD => &pdu[3..pdu.len() - 12],
+            // Note: write event data is always at offset 3, right?
+            _ => &pdu[3..],
+        }
     }
 
     /// Characteristic data to be written
@@ -437,7 +678,9 @@ impl<'stack, P: PacketPool> WriteEvent<'stack, '_, P> {
     pub fn into_payload(mut self) -> GattData<'stack, P> {
         GattData {
             pdu: self.data.pdu.take(),
+            bearer: self.data.bearer,
             connection: self.data.connection.clone(),
+            prepared: self.data.prepared.take(),
         }
     }
 }
@@ -481,7 +724,9 @@ impl<'stack, P: PacketPool> OtherEvent<'stack, '_, P> {
     pub fn into_payload(mut self) -> GattData<'stack, P> {
         GattData {
             pdu: self.data.pdu.take(),
+            bearer: self.data.bearer,
             connection: self.data.connection.clone(),
+            prepared: self.data.prepared.take(),
         }
     }
 }
@@ -500,19 +745,32 @@ fn process<'stack, P>(
 where
     P: PacketPool,
 {
+    let prepared = data.prepared.take();
     if let Some(pdu) = data.pdu.take() {
         let res = match result {
-            Ok(_) => process_accept(&pdu, &data.connection, server),
-            Err(code) => process_reject(&pdu, &data.connection, code),
+            Ok(_) => process_accept(&pdu, data.bearer, &data.connection, server),
+            Err(code) => {
+                let reply = process_reject(&pdu, data.bearer, &data.connection, prepared.map(|p| p.handle), code);
+                if pdu.as_ref()[0] == att::ATT_EXECUTE_WRITE_REQ {
+                    // Prepared writes are dropped when rejected.
+                    server.cancel_prepared_writes(&data.connection);
+                }
+                reply
+            }
         };
         res
     } else {
-        Ok(Reply::new(data.connection.clone(), None))
+        if let (Some(prepared), Err(code)) = (prepared, result) {
+            // All prepared writes are executed at once, so rejecting one of them drops them all.
+            server.reject_prepared_writes(&data.connection, prepared.handle, code);
+        }
+        Ok(Reply::new(data.connection.clone(), data.bearer, None))
     }
 }
 
 fn process_accept<'stack, P>(
     pdu: &Pdu<P::Packet>,
+    bearer: AttBearer,
     connection: &Connection<'stack, P>,
     server: &dyn DynamicAttributeServer<P>,
 ) -> Result<Reply<'stack, P>, Error>
@@ -529,22 +787,24 @@ where
     let mut w = WriteCursor::new(tx.as_mut());
     let (mut header, mut data) = w.split(4)?;
     if let Some(written) = server.process(connection, &att, data.write_buf())? {
-        let mtu = connection.get_att_mtu();
+        let mtu = bearer.mtu(connection);
         data.commit(written)?;
         data.truncate(mtu as usize);
         header.write(data.len() as u16)?;
         header.write(4_u16)?;
         let len = header.len() + data.len();
         let pdu = Pdu::new(tx, len);
-        Ok(Reply::new(connection.clone(), Some(pdu)))
+        Ok(Reply::new(connection.clone(), bearer, Some(pdu)))
     } else {
-        Ok(Reply::new(connection.clone(), None))
+        Ok(Reply::new(connection.clone(), bearer, None))
     }
 }
 
 fn process_reject<'stack, P: PacketPool>(
     pdu: &Pdu<P::Packet>,
+    bearer: AttBearer,
     connection: &Connection<'stack, P>,
+    prepared: Option<u16>,
     code: AttErrorCode,
 ) -> Result<Reply<'stack, P>, Error> {
     // - The PDU is decodable, as it was already decoded once before adding it to the connection queue
@@ -556,19 +816,22 @@ fn process_reject<'stack, P: PacketPool>(
     let handle = match att {
         AttClient::Request(AttReq::Write { handle, .. }) => handle,
         AttClient::Command(AttCmd::Write { handle, .. }) => handle,
+        AttClient::Command(AttCmd::SignedWrite { handle, .. }) => handle,
         AttClient::Request(AttReq::Read { handle }) => handle,
         AttClient::Request(AttReq::ReadBlob { handle, .. }) => handle,
+        AttClient::Request(AttReq::ExecuteWrite { .. }) => prepared.unwrap_or(0),
         _ => 0, // As per spec, if the incoming ATT does not have an ATT handle, we should report with handle 0
     };
     // We know it has been checked, therefore this cannot fail
     let request = pdu.as_ref()[0];
     let rsp = AttRsp::Error { request, handle, code };
-    let pdu = assemble(connection, AttServer::Response(rsp))?;
-    Ok(Reply::new(connection.clone(), Some(pdu)))
+    let pdu = assemble(connection, bearer, AttServer::Response(rsp))?;
+    Ok(Reply::new(connection.clone(), bearer, Some(pdu)))
 }
 
 pub(crate) fn assemble<'stack, P: PacketPool>(
     conn: &Connection<'stack, P>,
+    bearer: AttBearer,
     att: AttServer<'_>,
 ) -> Result<Pdu<P::Packet>, Error> {
     let mut tx = P::allocate().ok_or(Error::OutOfMemory)?;
@@ -576,7 +839,7 @@ pub(crate) fn assemble<'stack, P: PacketPool>(
     let (mut header, mut data) = w.split(4)?;
     data.write(Att::Server(att))?;
 
-    let mtu = conn.get_att_mtu();
+    let mtu = bearer.mtu(conn);
     data.truncate(mtu as usize);
     header.write(data.len() as u16)?;
     header.write(4_u16)?;
@@ -590,12 +853,17 @@ pub(crate) fn assemble<'stack, P: PacketPool>(
 /// in case of a full outbound queue, the async send() should be used rather than relying on the Drop implementation.
 pub struct Reply<'stack, P: PacketPool> {
     connection: Connection<'stack, P>,
+    bearer: AttBearer,
     pdu: Option<Pdu<P::Packet>>,
 }
 
 impl<'stack, P: PacketPool> Reply<'stack, P> {
-    fn new(connection: Connection<'stack, P>, pdu: Option<Pdu<P::Packet>>) -> Self {
-        Self { connection, pdu }
+    fn new(connection: Connection<'stack, P>, bearer: AttBearer, pdu: Option<Pdu<P::Packet>>) -> Self {
+        Self {
+            connection,
+            bearer,
+            pdu,
+        }
     }
 
     /// Send the reply.
@@ -603,7 +871,7 @@ impl<'stack, P: PacketPool> Reply<'stack, P> {
     /// May fail if the outbound queue is full.
     pub fn try_send(mut self) -> Result<(), Error> {
         if let Some(pdu) = self.pdu.take() {
-            self.connection.try_send(pdu)
+            self.connection.try_send_att(self.bearer, pdu)
         } else {
             Ok(())
         }
@@ -612,7 +880,7 @@ impl<'stack, P: PacketPool> Reply<'stack, P> {
     /// Send the reply.
     pub async fn send(mut self) {
         if let Some(pdu) = self.pdu.take() {
-            self.connection.send(pdu).await
+            self.connection.send_att(self.bearer, pdu).await
         }
     }
 }
@@ -620,7 +888,7 @@ impl<'stack, P: PacketPool> Reply<'stack, P> {
 impl<P: PacketPool> Drop for Reply<'_, P> {
     fn drop(&mut self) {
         if let Some(pdu) = self.pdu.take() {
-            if self.connection.try_send(pdu).is_err() {
+            if self.connection.try_send_att(self.bearer, pdu).is_err() {
                 warn!("[gatt] error sending reply (outbound buffer full)");
             }
         }
@@ -650,17 +918,72 @@ impl<'lst, const MTU: usize> NotificationListener<'lst, MTU> {
 const MAX_NOTIF: usize = config::GATT_CLIENT_NOTIFICATION_MAX_SUBSCRIBERS;
 const NOTIF_QSIZE: usize = config::GATT_CLIENT_NOTIFICATION_QUEUE_SIZE;
 
+/// Time the server has to respond to a request before the ATT bearer is considered failed.
+const ATT_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// A GATT client capable of using the GATT protocol.
+///
+/// Requests can be issued concurrently from several tasks. ATT only allows one outstanding request per bearer,
+/// so they are performed one at a time unless Enhanced ATT bearers are opened with
+/// [`GattClient::open_enhanced_bearers`]. If the server doesn't respond within 30 seconds, the bearer can't be
+/// used anymore. Once no bearer is usable, all further requests fail with [`Error::Timeout`] until the peer is
+/// reconnected.
 pub struct GattClient<'reference, T: Controller, P: PacketPool, const MAX_SERVICES: usize> {
-    known_services: RefCell<Vec<ServiceHandle, MAX_SERVICES>>,
+    cache: RefCell<GattCache<MAX_SERVICES>>,
     stack: &'reference Stack<'reference, T, P>,
     connection: Connection<'reference, P>,
-    response_channel: Channel<NoopRawMutex, (ConnHandle, Pdu<P::Packet>), 1>,
+    /// The fixed ATT channel, followed by the Enhanced ATT bearers.
+    bearers: [ClientBearer<P::Packet>; 1 + L2CAP_MAX_ENHANCED_CHANNELS],
+    enhanced: RefCell<Vec<L2capChannel<'reference, P>, L2CAP_MAX_ENHANCED_CHANNELS>>,
+    #[cfg(feature = "security")]
+    auto_security: Cell<bool>,
 
     // TODO: Wait for something like https://github.com/rust-lang/rust/issues/132980 (min_generic_const_args) to allow using P::MTU
     notifications: PubSubChannel<NoopRawMutex, Notification<512>, NOTIF_QSIZE, MAX_NOTIF, 1>,
 }
 
+type TransactionGuard<'a> = MutexGuard<'a, NoopRawMutex, Option<Instant>>;
+
+/// A bearer reserved by a procedure.
+///
+/// Procedures made of several requests, like long reads and prepared writes, hold the transaction lock until
+/// they are done, so their requests are neither interleaved with others nor spread over several bearers.
+struct Transaction<'a, P> {
+    bearer: &'a ClientBearer<P>,
+    outstanding: TransactionGuard<'a>,
+}
+
+/// An ATT bearer used by the client.
+struct ClientBearer<P> {
+    bearer: Cell<Option<AttBearer>>,
+    response: Channel<NoopRawMutex, Pdu<P>, 1>,
+    /// Transaction lock, holding the time the outstanding request was sent, if any.
+    transaction: Mutex<NoopRawMutex, Option<Instant>>,
+    timed_out: Cell<bool>,
+}
+
+impl<P> ClientBearer<P> {
+    const fn new(bearer: Option<AttBearer>) -> Self {
+        Self {
+            bearer: Cell::new(bearer),
+            response: Channel::new(),
+            transaction: Mutex::new(None),
+            timed_out: Cell::new(false),
+        }
+    }
+
+    fn is(&self, bearer: &AttBearer) -> bool {
+        self.bearer.get().is_some_and(|b| b.same(bearer))
+    }
+
+    /// The ATT MTU of the bearer.
+    fn mtu<Q: PacketPool>(&self, connection: &Connection<'_, Q>) -> u16 {
+        self.bearer
+            .get()
+            .map_or(connection.get_att_mtu(), |bearer| bearer.mtu(connection))
+    }
+}
+
 /// A notification payload.
 #[derive(Debug, PartialEq, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -668,6 +991,16 @@ pub struct Notification<const MTU: usize> {
     handle: u16,
     data: [u8; MTU],
     len: usize,
+    indication: bool,
+}
+
+impl<const MTU: usize> Notification<MTU> {
+    /// Whether the value was sent as an indication rather than a notification.
+    ///
+    /// Indications are confirmed by the client as soon as they are received.
+    pub fn is_indication(&self) -> bool {
+        self.indication
+    }
 }
 
 impl<const MTU: usize> AsRef<[u8]> for Notification<MTU> {
@@ -685,6 +1018,381 @@ pub struct ServiceHandle {
     uuid: Uuid,
 }
 
+impl ServiceHandle {
+    /// UUID of the service.
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// First and last attribute handle of the service.
+    pub fn handle_range(&self) -> (u16, u16) {
+        (self.start, self.end)
+    }
+
+    fn contains(&self, handle: u16) -> bool {
+        self.start < handle && handle <= self.end
+    }
+}
+
+/// A characteristic found when discovering the characteristics of a service.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+pub struct DiscoveredCharacteristic<T: AsGatt> {
+    /// UUID of the characteristic.
+    pub uuid: Uuid,
+    /// Properties of the characteristic.
+    pub props: CharacteristicProps,
+    /// Last attribute handle of the characteristic, including its descriptors.
+    pub end_handle: u16,
+    /// Handle used to access the characteristic value.
+    pub characteristic: Characteristic<T>,
+}
+
+/// Handle for a characteristic descriptor.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct DescriptorHandle {
+    handle: u16,
+    uuid: Uuid,
+}
+
+impl DescriptorHandle {
+    /// Attribute handle of the descriptor.
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    /// UUID of the descriptor.
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+}
+
+const CACHE_SIZE: usize = config::GATT_CLIENT_CACHE_SIZE;
+const CACHE_FORMAT_VERSION: u8 = 1;
+
+/// A service remembered by the [`GattCache`].
+#[derive(Debug, PartialEq, Clone)]
+struct CachedService {
+    service: ServiceHandle,
+    /// All characteristics of the service are cached.
+    complete: bool,
+}
+
+/// A characteristic remembered by the [`GattCache`].
+#[derive(Debug, PartialEq, Clone)]
+struct CachedCharacteristic {
+    uuid: Uuid,
+    props: CharacteristicProps,
+    handle: u16,
+    cccd_handle: Option<u16>,
+    end_handle: u16,
+}
+
+impl CachedCharacteristic {
+    fn to_discovered<T: AsGatt>(&self) -> DiscoveredCharacteristic<T> {
+        DiscoveredCharacteristic {
+            uuid: self.uuid.clone(),
+            props: self.props,
+            end_handle: self.end_handle,
+            characteristic: Characteristic {
+                handle: self.handle,
+                cccd_handle: self.cccd_handle,
+                phantom: PhantomData,
+            },
+        }
+    }
+}
+
+/// The attribute database of a peer, as discovered by the GATT client.
+///
+/// A cache exported with [`GattClient::export_cache`] can be stored next to the bond information of the peer
+/// and imported on a later connection with [`GattClient::import_cache`], so that discovery can be skipped.
+/// It is validated against the Database Hash of the peer when imported, and invalidated whenever
+/// the peer indicates a change of its services (see [`GattClient::subscribe_service_changed`]).
+#[derive(Debug, PartialEq, Clone)]
+pub struct GattCache<const MAX_SERVICES: usize> {
+    identity: Identity,
+    hash: Option<[u8; 16]>,
+    /// Value handle of the Service Changed characteristic, once subscribed to.
+    service_changed: Option<u16>,
+    /// All primary services are cached.
+    complete: bool,
+    services: Vec<CachedService, MAX_SERVICES>,
+    characteristics: Vec<CachedCharacteristic, CACHE_SIZE>,
+}
+
+impl<const MAX_SERVICES: usize> GattCache<MAX_SERVICES> {
+    /// Maximum number of bytes used by a serialized cache.
+    pub const SERIALIZED_SIZE: usize = 44 + 2 + MAX_SERVICES * 22 + 2 + CACHE_SIZE * 24;
+
+    fn new(identity: Identity) -> Self {
+        Self {
+            identity,
+            hash: None,
+            service_changed: None,
+            complete: false,
+            services: Vec::new(),
+            characteristics: Vec::new(),
+        }
+    }
+
+    /// Identity of the peer the cache belongs to.
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    /// Database Hash of the peer at the time the cache was exported, if the peer has one.
+    pub fn hash(&self) -> Option<&[u8; 16]> {
+        self.hash.as_ref()
+    }
+
+    /// Check whether nothing has been cached.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty() && self.characteristics.is_empty()
+    }
+
+    /// Serialize the cache into the provided buffer, returning the number of bytes written.
+    ///
+    /// At most [`Self::SERIALIZED_SIZE`] bytes are needed.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = WriteCursor::new(buf);
+        w.write(CACHE_FORMAT_VERSION)?;
+        w.append(self.identity.bd_addr.raw())?;
+        #[cfg(feature = "security")]
+        let irk = self.identity.irk.map(|irk| irk.to_le_bytes());
+        #[cfg(not(feature = "security"))]
+        let irk: Option<[u8; 16]> = None;
+        for field in [irk, self.hash] {
+            w.write(field.is_some() as u8)?;
+            w.append(&field.unwrap_or_default())?;
+        }
+        w.write(self.service_changed.unwrap_or(0))?;
+        w.write(self.complete as u8)?;
+
+        w.write(self.services.len() as u16)?;
+        for s in self.services.iter() {
+            w.write(s.service.start)?;
+            w.write(s.service.end)?;
+            w.write(s.complete as u8)?;
+            w.write(s.service.uuid.as_raw().len() as u8)?;
+            w.append(s.service.uuid.as_raw())?;
+        }
+
+        w.write(self.characteristics.len() as u16)?;
+        for c in self.characteristics.iter() {
+            w.write(c.handle)?;
+            w.write(c.cccd_handle.unwrap_or(0))?;
+            w.write(c.end_handle)?;
+            w.append(c.props.as_gatt())?;
+            w.write(c.uuid.as_raw().len() as u8)?;
+            w.append(c.uuid.as_raw())?;
+        }
+        Ok(w.len())
+    }
+
+    /// Deserialize a cache previously serialized with [`Self::to_bytes`].
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Error> {
+        let mut r = ReadCursor::new(buf);
+        let version: u8 = r.read()?;
+        if version != CACHE_FORMAT_VERSION {
+            return Err(Error::InvalidValue);
+        }
+        let mut bd_addr = [0; 6];
+        bd_addr.copy_from_slice(r.slice(6)?);
+        let mut fields = [None; 2];
+        for field in fields.iter_mut() {
+            let present: u8 = r.read()?;
+            let mut value = [0; 16];
+            value.copy_from_slice(r.slice(16)?);
+            *field = (present != 0).then_some(value);
+        }
+        let [_irk, hash] = fields;
+        let mut cache = Self::new(Identity {
+            bd_addr: BdAddr::new(bd_addr),
+            #[cfg(feature = "security")]
+            irk: _irk.map(IdentityResolvingKey::from_le_bytes),
+        });
+        cache.hash = hash;
+        let service_changed: u16 = r.read()?;
+        cache.service_changed = (service_changed != 0).then_some(service_changed);
+        cache.complete = r.read::<u8>()? != 0;
+
+        let services: u16 = r.read()?;
+        for _ in 0..services {
+            let start = r.read()?;
+            let end = r.read()?;
+            let complete = r.read::<u8>()? != 0;
+            let len: u8 = r.read()?;
+            let uuid = Uuid::try_from(r.slice(len as usize)?)?;
+            cache
+                .services
+                .push(CachedService {
+                    service: ServiceHandle { start, end, uuid },
+                    complete,
+                })
+                .map_err(|_| Error::InsufficientSpace)?;
+        }
+
+        let characteristics: u16 = r.read()?;
+        for _ in 0..characteristics {
+            let handle = r.read()?;
+            let cccd_handle: u16 = r.read()?;
+            let end_handle = r.read()?;
+            let props = CharacteristicProps::from_gatt(r.slice(1)?).map_err(|_| Error::InvalidValue)?;
+            let len: u8 = r.read()?;
+            let uuid = Uuid::try_from(r.slice(len as usize)?)?;
+            cache
+                .characteristics
+                .push(CachedCharacteristic {
+                    uuid,
+                    props,
+                    handle,
+                    cccd_handle: (cccd_handle != 0).then_some(cccd_handle),
+                    end_handle,
+                })
+                .map_err(|_| Error::InsufficientSpace)?;
+        }
+        Ok(cache)
+    }
+
+    /// Forget everything that was discovered.
+    ///
+    /// The Service Changed characteristic is kept, as its handle can't change while the peer is bonded.
+    fn clear(&mut self) {
+        self.hash = None;
+        self.complete = false;
+        self.services.clear();
+        self.characteristics.clear();
+    }
+
+    /// Primary services with the given UUID, or all of them, if they are known.
+    fn services(&self, uuid: Option<&Uuid>) -> Option<Vec<ServiceHandle, MAX_SERVICES>> {
+        let matches = |s: &&CachedService| uuid.is_none_or(|uuid| s.service.uuid == *uuid);
+        if !self.complete && (uuid.is_none() || !self.services.iter().any(|s| matches(&s))) {
+            return None;
+        }
+        Some(
+            self.services
+                .iter()
+                .filter(matches)
+                .map(|s| s.service.clone())
+                .collect(),
+        )
+    }
+
+    /// Remember all primary services of the peer.
+    fn set_services(&mut self, services: &[ServiceHandle]) {
+        let mut known = Vec::new();
+        for service in services {
+            let complete = self.services.iter().any(|s| s.service == *service && s.complete);
+            let _ = known.push(CachedService {
+                service: service.clone(),
+                complete,
+            });
+        }
+        self.services = known;
+        self.complete = true;
+    }
+
+    /// Remember all primary services with a given UUID.
+    ///
+    /// Services are cached per UUID, so they are either all remembered or none of them are.
+    fn insert_services(&mut self, uuid: &Uuid, services: &[ServiceHandle]) {
+        for service in services {
+            if self.services.iter().any(|s| s.service == *service) {
+                continue;
+            }
+            let cached = CachedService {
+                service: service.clone(),
+                complete: false,
+            };
+            if self.services.push(cached).is_err() {
+                self.services.retain(|s| s.service.uuid != *uuid);
+                return;
+            }
+        }
+    }
+
+    /// All characteristics of a service, if they are known.
+    fn characteristics<T: AsGatt, const N: usize>(
+        &self,
+        service: &ServiceHandle,
+    ) -> Result<Option<Vec<DiscoveredCharacteristic<T>, N>>, Error> {
+        if !self.services.iter().any(|s| s.service == *service && s.complete) {
+            return Ok(None);
+        }
+        let mut result = Vec::new();
+        for c in self.characteristics.iter().filter(|c| service.contains(c.handle)) {
+            result.push(c.to_discovered()).map_err(|_| Error::InsufficientSpace)?;
+        }
+        Ok(Some(result))
+    }
+
+    /// Remember all characteristics of a service.
+    fn set_characteristics<T: AsGatt>(
+        &mut self,
+        service: &ServiceHandle,
+        characteristics: &[DiscoveredCharacteristic<T>],
+    ) {
+        self.characteristics.retain(|c| !service.contains(c.handle));
+        for c in characteristics {
+            let cached = CachedCharacteristic {
+                uuid: c.uuid.clone(),
+                props: c.props,
+                handle: c.characteristic.handle,
+                cccd_handle: c.characteristic.cccd_handle,
+                end_handle: c.end_handle,
+            };
+            if self.characteristics.push(cached).is_err() {
+                // Without room for all of them, only characteristics looked up by UUID are cached.
+                self.characteristics.retain(|c| !service.contains(c.handle));
+                return;
+            }
+        }
+        if let Some(s) = self.services.iter_mut().find(|s| s.service == *service) {
+            s.complete = true;
+        }
+    }
+
+    /// A characteristic of a service with the given UUID, if it is known.
+    fn characteristic(&self, service: &ServiceHandle, uuid: &Uuid) -> Option<&CachedCharacteristic> {
+        self.characteristics
+            .iter()
+            .find(|c| service.contains(c.handle) && c.uuid == *uuid)
+    }
+
+    /// Remember a single characteristic.
+    fn insert_characteristic(&mut self, characteristic: CachedCharacteristic) {
+        if !self.characteristics.iter().any(|c| c.handle == characteristic.handle) {
+            let _ = self.characteristics.push(characteristic);
+        }
+    }
+
+    /// Check whether a handle is the value handle of the subscribed Service Changed characteristic.
+    fn is_service_changed(&self, handle: u16) -> bool {
+        self.service_changed == Some(handle)
+    }
+
+    /// Forget everything cached in the given handle range.
+    fn invalidate(&mut self, start: u16, end: u16) {
+        let overlaps = |first: u16, last: u16| first <= end && last >= start;
+        self.hash = None;
+        self.complete = false;
+        // Services are cached per UUID, see `insert_services`
+        while let Some(uuid) = self
+            .services
+            .iter()
+            .find(|s| overlaps(s.service.start, s.service.end))
+            .map(|s| s.service.uuid.clone())
+        {
+            self.services.retain(|s| s.service.uuid != uuid);
+        }
+        self.characteristics
+            .retain(|c| !overlaps(c.handle.saturating_sub(1), c.end_handle));
+    }
+}
+
 pub(crate) struct Response<P> {
     pdu: Pdu<P>,
     handle: ConnHandle,
@@ -701,27 +1409,153 @@ impl<'reference, T: Controller, P: PacketPool, const MAX_SERVICES: usize> Client
     for GattClient<'reference, T, P, MAX_SERVICES>
 {
     async fn request(&self, req: AttReq<'_>) -> Result<Response<P::Packet>, BleHostError<T::Error>> {
-        let data = Att::Client(AttClient::Request(req));
-
-        self.send_att_data(data).await?;
-
-        let (h, pdu) = self.response_channel.receive().await;
-
-        assert_eq!(h, self.connection.handle());
-        Ok(Response { handle: h, pdu })
+        let mut transaction = self.acquire_bearer().await?;
+        self.request_on(&mut transaction, req).await
     }
 
     async fn command(&self, cmd: AttCmd<'_>) -> Result<(), BleHostError<T::Error>> {
+        if self.bearers[0].timed_out.get() {
+            return Err(Error::Timeout.into());
+        }
         let data = Att::Client(AttClient::Command(cmd));
 
-        self.send_att_data(data).await?;
+        self.send_att_data(AttBearer::Unenhanced, data).await?;
 
         Ok(())
     }
 }
 
 impl<'reference, T: Controller, P: PacketPool, const MAX_SERVICES: usize> GattClient<'reference, T, P, MAX_SERVICES> {
-    async fn send_att_data(&self, data: Att<'_>) -> Result<(), BleHostError<T::Error>> {
+    /// Perform a request on a reserved bearer, escalating security and retrying it if enabled.
+    async fn request_on(
+        &self,
+        transaction: &mut Transaction<'_, P::Packet>,
+        req: AttReq<'_>,
+    ) -> Result<Response<P::Packet>, BleHostError<T::Error>> {
+        #[cfg(feature = "security")]
+        if self.auto_security.get() {
+            let response = self.transact(transaction, req.clone()).await?;
+            let Some(code) = Self::insufficient_security(&response) else {
+                return Ok(response);
+            };
+            // Release the response packet while pairing
+            drop(response);
+            if self.escalate_security().await? {
+                return self.transact(transaction, req).await;
+            }
+            return Err(Error::Att(code).into());
+        }
+        self.transact(transaction, req).await
+    }
+
+    async fn transact(
+        &self,
+        transaction: &mut Transaction<'_, P::Packet>,
+        req: AttReq<'_>,
+    ) -> Result<Response<P::Packet>, BleHostError<T::Error>> {
+        let bearer = transaction.bearer;
+        let outstanding = &mut transaction.outstanding;
+        let Some(att_bearer) = bearer.bearer.get().filter(|_| self.is_usable(bearer)) else {
+            return Err(Error::Timeout.into());
+        };
+
+        // The response to a request dropped while in flight still has to be received before sending another one.
+        if let Some(sent) = **outstanding {
+            self.receive_response(bearer, sent).await?;
+            **outstanding = None;
+        }
+
+        // Only marked outstanding once handed to the connection, as a request dropped while the
+        // outbound queue is full is never sent.
+        let data = Att::Client(AttClient::Request(req));
+        self.send_att_data(att_bearer, data).await?;
+        let sent = Instant::now();
+        **outstanding = Some(sent);
+
+        let pdu = self.receive_response(bearer, sent).await?;
+        **outstanding = None;
+
+        Ok(Response {
+            handle: self.connection.handle(),
+            pdu,
+        })
+    }
+
+    fn is_usable(&self, bearer: &ClientBearer<P::Packet>) -> bool {
+        match bearer.bearer.get() {
+            Some(AttBearer::Unenhanced) => !bearer.timed_out.get(),
+            Some(AttBearer::Enhanced { index, .. }) => {
+                !bearer.timed_out.get() && self.stack.host.channels.is_connected(index)
+            }
+            None => false,
+        }
+    }
+
+    /// Wait until one of the usable bearers has no outstanding request, preferring the fixed ATT channel.
+    async fn acquire_bearer(&self) -> Result<Transaction<'_, P::Packet>, BleHostError<T::Error>> {
+        if !self.bearers.iter().any(|bearer| self.is_usable(bearer)) {
+            return Err(Error::Timeout.into());
+        }
+        let transactions: [_; 1 + L2CAP_MAX_ENHANCED_CHANNELS] = core::array::from_fn(|i| {
+            let bearer = &self.bearers[i];
+            let usable = self.is_usable(bearer);
+            async move {
+                if !usable {
+                    core::future::pending::<()>().await;
+                }
+                bearer.transaction.lock().await
+            }
+        });
+        let (outstanding, i) = select_array(transactions).await;
+        Ok(Transaction {
+            bearer: &self.bearers[i],
+            outstanding,
+        })
+    }
+
+    /// Wait for the response to a request sent at `sent`.
+    async fn receive_response(
+        &self,
+        bearer: &ClientBearer<P::Packet>,
+        sent: Instant,
+    ) -> Result<Pdu<P::Packet>, BleHostError<T::Error>> {
+        match with_deadline(sent + ATT_TRANSACTION_TIMEOUT, bearer.response.receive()).await {
+            Ok(response) => Ok(response),
+            Err(_) => {
+                warn!("[gatt] ATT transaction timed out, the bearer can't be used anymore");
+                bearer.timed_out.set(true);
+                Err(Error::Timeout.into())
+            }
+        }
+    }
+
+    /// Return the error code of a response rejecting a request for lack of security.
+    #[cfg(feature = "security")]
+    fn insufficient_security(response: &Response<P::Packet>) -> Option<att::AttErrorCode> {
+        match Att::decode(response.pdu.as_ref()) {
+            Ok(Att::Server(AttServer::Response(AttRsp::Error { code, .. })))
+                if code == att::AttErrorCode::INSUFFICIENT_AUTHENTICATION
+                    || code == att::AttErrorCode::INSUFFICIENT_ENCRYPTION =>
+            {
+                Some(code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Request security and wait for it to be established. Returns whether the security level changed.
+    #[cfg(feature = "security")]
+    async fn escalate_security(&self) -> Result<bool, BleHostError<T::Error>> {
+        let level = self.connection.security_level()?;
+        match self.connection.escalate_security().await {
+            Ok(new_level) => Ok(new_level != level),
+            Err(Error::Disconnected) => Err(Error::Disconnected.into()),
+            // Security can't be requested, i.e. the link is already encrypted
+            Err(_) => Ok(false),
+        }
+    }
+
+    async fn send_att_data(&self, bearer: AttBearer, data: Att<'_>) -> Result<(), BleHostError<T::Error>> {
         let header = L2capHeader {
             channel: crate::types::l2cap::L2CAP_CID_ATT,
             length: data.size() as u16,
@@ -733,7 +1567,7 @@ impl<'reference, T: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         w.write(data)?;
         let len = w.len();
 
-        self.connection.send(Pdu::new(buf, len)).await;
+        self.connection.send_att(bearer, Pdu::new(buf, len)).await;
         Ok(())
     }
 }
@@ -755,21 +1589,91 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         let len = w.len();
         connection.send(Pdu::new(buf, len)).await;
         Ok(Self {
-            known_services: RefCell::new(heapless::Vec::new()),
+            cache: RefCell::new(GattCache::new(connection.peer_identity())),
             stack,
             connection: connection.clone(),
 
-            response_channel: Channel::new(),
+            bearers: core::array::from_fn(|i| ClientBearer::new((i == 0).then_some(AttBearer::Unenhanced))),
+            enhanced: RefCell::new(Vec::new()),
+            #[cfg(feature = "security")]
+            auto_security: Cell::new(false),
 
             notifications: PubSubChannel::new(),
         })
     }
 
-    /// Discover primary services associated with a UUID.
-    pub async fn services_by_uuid(
+    /// Enable or disable automatic security escalation.
+    ///
+    /// When enabled, a request rejected by the server with insufficient authentication or encryption
+    /// makes the client request security on the connection, wait for encryption to be enabled or pairing
+    /// to complete, and retry the request once. Disabled by default.
+    #[cfg(feature = "security")]
+    pub fn set_auto_security(&self, enabled: bool) {
+        self.auto_security.set(enabled);
+    }
+
+    /// Export the attribute database discovered so far.
+    ///
+    /// The Database Hash of the peer is read, if it has one, so the cache can be validated when imported.
+    pub async fn export_cache(&self) -> Result<GattCache<MAX_SERVICES>, BleHostError<C::Error>> {
+        let hash = self.database_hash().await?;
+        let mut cache = self.cache.borrow().clone();
+        cache.identity = self.connection.peer_identity();
+        cache.hash = hash;
+        Ok(cache)
+    }
+
+    /// Import an attribute database previously exported for the connected peer.
+    ///
+    /// The cache is only used if it belongs to the peer and its Database Hash is unchanged. A peer without a
+    /// Database Hash is trusted to indicate any change of its services. Returns whether the cache was used.
+    pub async fn import_cache(&self, cache: &GattCache<MAX_SERVICES>) -> Result<bool, BleHostError<C::Error>> {
+        if !self.connection.peer_identity().match_identity(&cache.identity) {
+            return Ok(false);
+        }
+        if self.database_hash().await? != cache.hash {
+            return Ok(false);
+        }
+        *self.cache.borrow_mut() = cache.clone();
+        Ok(true)
+    }
+
+    /// Read the Database Hash characteristic of the peer.
+    async fn database_hash(&self) -> Result<Option<[u8; 16]>, BleHostError<C::Error>> {
+        let data = att::AttReq::ReadByType {
+            start: 0x0001,
+            end: 0xffff,
+            attribute_type: DATABASE_HASH.into(),
+        };
+        let response = self.request(data).await?;
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::ReadByType { mut it } => match it.next() {
+                Some(res) => {
+                    let (_, value) = res?;
+                    let hash = value.try_into().map_err(|_| Error::InvalidValue)?;
+                    Ok(Some(hash))
+                }
+                None => Ok(None),
+            },
+            AttRsp::Error { request, handle, code } => {
+                if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                    return Ok(None);
+                }
+                Err(Error::Att(code).into())
+            }
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
+    /// Discover primary services associated with a UUID.
+    pub async fn services_by_uuid(
         &self,
         uuid: &Uuid,
     ) -> Result<Vec<ServiceHandle, MAX_SERVICES>, BleHostError<C::Error>> {
+        if let Some(services) = self.cache.borrow().services(Some(uuid)) {
+            return Ok(services);
+        }
+
         let mut start: u16 = 0x0001;
         let mut result = Vec::new();
 
@@ -800,11 +1704,7 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
                             end,
                             uuid: uuid.clone(),
                         };
-                        result.push(svc.clone()).map_err(|_| Error::InsufficientSpace)?;
-                        self.known_services
-                            .borrow_mut()
-                            .push(svc)
-                            .map_err(|_| Error::InsufficientSpace)?;
+                        result.push(svc).map_err(|_| Error::InsufficientSpace)?;
                     }
                     if end == 0xFFFF {
                         break;
@@ -818,6 +1718,249 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
             }
         }
 
+        self.cache.borrow_mut().insert_services(uuid, &result);
+        Ok(result)
+    }
+
+    /// Discover all primary services.
+    pub async fn services(&self) -> Result<Vec<ServiceHandle, MAX_SERVICES>, BleHostError<C::Error>> {
+        if let Some(services) = self.cache.borrow().services(None) {
+            return Ok(services);
+        }
+
+        let mut start: u16 = 0x0001;
+        let mut result = Vec::new();
+
+        loop {
+            let data = att::AttReq::ReadByGroupType {
+                start,
+                end: 0xffff,
+                group_type: PRIMARY_SERVICE.into(),
+            };
+
+            let response = self.request(data).await?;
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::Error { request, handle, code } => {
+                    if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                        break;
+                    }
+                    return Err(Error::Att(code).into());
+                }
+                AttRsp::ReadByGroupType { mut it } => {
+                    let mut end: u16 = 0;
+                    while let Some(res) = it.next() {
+                        let (handle, e, uuid) = res?;
+                        end = e;
+                        let svc = ServiceHandle {
+                            start: handle,
+                            end,
+                            uuid: Uuid::try_from(uuid)?,
+                        };
+                        result.push(svc).map_err(|_| Error::InsufficientSpace)?;
+                    }
+                    if end == 0 || end == 0xFFFF {
+                        break;
+                    }
+                    start = end + 1;
+                }
+                res => {
+                    trace!("[gatt client] response: {:?}", res);
+                    return Err(Error::UnexpectedGattResponse.into());
+                }
+            }
+        }
+
+        self.cache.borrow_mut().set_services(&result);
+        Ok(result)
+    }
+
+    /// Find the services included by a given service.
+    pub async fn included_services<const N: usize>(
+        &self,
+        service: &ServiceHandle,
+    ) -> Result<Vec<ServiceHandle, N>, BleHostError<C::Error>> {
+        // Placeholder for 128-bit UUIDs, which are not part of the include declaration.
+        const UNKNOWN: Uuid = Uuid::Uuid16([0, 0]);
+
+        let mut start: u16 = service.start;
+        let mut result: Vec<ServiceHandle, N> = Vec::new();
+
+        while start <= service.end {
+            let data = att::AttReq::ReadByType {
+                start,
+                end: service.end,
+                attribute_type: INCLUDE.into(),
+            };
+            let response = self.request(data).await?;
+
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::ReadByType { mut it } => {
+                    let mut last = start;
+                    while let Some(res) = it.next() {
+                        let (handle, item) = res?;
+                        let mut r = ReadCursor::new(item);
+                        let included = ServiceHandle {
+                            start: r.read()?,
+                            end: r.read()?,
+                            uuid: match r.remaining() {
+                                [] => UNKNOWN,
+                                uuid => Uuid::try_from(uuid)?,
+                            },
+                        };
+                        result.push(included).map_err(|_| Error::InsufficientSpace)?;
+                        last = handle;
+                    }
+                    if last == 0xFFFF {
+                        break;
+                    }
+                    start = last + 1;
+                }
+                AttRsp::Error { request, handle, code } => {
+                    if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                        break;
+                    }
+                    return Err(Error::Att(code).into());
+                }
+                _ => return Err(Error::UnexpectedGattResponse.into()),
+            }
+        }
+
+        // The UUID of services with 128-bit UUIDs is read from their service declaration.
+        for included in result.iter_mut().filter(|s| s.uuid == UNKNOWN) {
+            let response = self.request(att::AttReq::Read { handle: included.start }).await?;
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::Read { data } => included.uuid = Uuid::try_from(data)?,
+                AttRsp::Error { request, handle, code } => return Err(Error::Att(code).into()),
+                _ => return Err(Error::UnexpectedGattResponse.into()),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Discover all characteristics in a given service.
+    ///
+    /// The CCCD handle is looked up for characteristics supporting notifications or indications.
+    pub async fn characteristics<T: AsGatt, const N: usize>(
+        &self,
+        service: &ServiceHandle,
+    ) -> Result<Vec<DiscoveredCharacteristic<T>, N>, BleHostError<C::Error>> {
+        if let Some(characteristics) = self.cache.borrow().characteristics(service)? {
+            return Ok(characteristics);
+        }
+
+        let mut start: u16 = service.start;
+        let mut result: Vec<DiscoveredCharacteristic<T>, N> = Vec::new();
+
+        while start <= service.end {
+            let data = att::AttReq::ReadByType {
+                start,
+                end: service.end,
+                attribute_type: CHARACTERISTIC.into(),
+            };
+            let response = self.request(data).await?;
+
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::ReadByType { mut it } => {
+                    let mut last = start;
+                    while let Some(res) = it.next() {
+                        let (decl_handle, item) = res?;
+                        let AttributeData::Declaration { props, handle, uuid } =
+                            AttributeData::decode_declaration(item)?
+                        else {
+                            return Err(Error::InvalidCharacteristicDeclarationData.into());
+                        };
+                        // The previous characteristic ends right before this declaration
+                        if let Some(prev) = result.last_mut() {
+                            prev.end_handle = decl_handle - 1;
+                        }
+                        result
+                            .push(DiscoveredCharacteristic {
+                                uuid,
+                                props,
+                                end_handle: service.end,
+                                characteristic: Characteristic {
+                                    handle,
+                                    cccd_handle: None,
+                                    phantom: PhantomData,
+                                },
+                            })
+                            .map_err(|_| Error::InsufficientSpace)?;
+                        last = decl_handle;
+                    }
+                    if last == 0xFFFF {
+                        break;
+                    }
+                    start = last + 1;
+                }
+                AttRsp::Error { request, handle, code } => {
+                    if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                        break;
+                    }
+                    return Err(Error::Att(code).into());
+                }
+                _ => return Err(Error::UnexpectedGattResponse.into()),
+            }
+        }
+
+        for c in result.iter_mut() {
+            if c.props.any(&[CharacteristicProp::Indicate, CharacteristicProp::Notify]) {
+                c.characteristic.cccd_handle = match self
+                    .get_characteristic_cccd(c.characteristic.handle + 1, c.end_handle)
+                    .await
+                {
+                    Ok(handle) => Some(handle),
+                    Err(BleHostError::BleHost(Error::NotFound)) => None,
+                    Err(BleHostError::BleHost(Error::Att(code))) if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND => {
+                        None
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+        }
+
+        self.cache.borrow_mut().set_characteristics(service, &result);
+        Ok(result)
+    }
+
+    /// Discover all descriptors of a characteristic.
+    pub async fn descriptors<T: AsGatt, const N: usize>(
+        &self,
+        characteristic: &DiscoveredCharacteristic<T>,
+    ) -> Result<Vec<DescriptorHandle, N>, BleHostError<C::Error>> {
+        let mut start: u16 = characteristic.characteristic.handle + 1;
+        let end = characteristic.end_handle;
+        let mut result = Vec::new();
+
+        while start <= end && start != 0 {
+            let data = att::AttReq::FindInformation {
+                start_handle: start,
+                end_handle: end,
+            };
+            let response = self.request(data).await?;
+
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::FindInformation { mut it } => {
+                    let mut last = start;
+                    while let Some(res) = it.next() {
+                        let (handle, uuid) = res?;
+                        result
+                            .push(DescriptorHandle { handle, uuid })
+                            .map_err(|_| Error::InsufficientSpace)?;
+                        last = handle;
+                    }
+                    start = last.wrapping_add(1);
+                }
+                AttRsp::Error { request, handle, code } => {
+                    if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                        break;
+                    }
+                    return Err(Error::Att(code).into());
+                }
+                _ => return Err(Error::UnexpectedGattResponse.into()),
+            }
+        }
+
         Ok(result)
     }
 
@@ -827,6 +1970,27 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         service: &ServiceHandle,
         uuid: &Uuid,
     ) -> Result<Characteristic<T>, BleHostError<C::Error>> {
+        let cached = self.cache.borrow().characteristic(service, uuid).cloned();
+        let found = match cached {
+            Some(c) => c,
+            None => {
+                let c = self.discover_characteristic_by_uuid(service, uuid).await?;
+                self.cache.borrow_mut().insert_characteristic(c.clone());
+                c
+            }
+        };
+        Ok(Characteristic {
+            handle: found.handle,
+            cccd_handle: found.cccd_handle,
+            phantom: PhantomData,
+        })
+    }
+
+    async fn discover_characteristic_by_uuid(
+        &self,
+        service: &ServiceHandle,
+        uuid: &Uuid,
+    ) -> Result<CachedCharacteristic, BleHostError<C::Error>> {
         let mut start: u16 = service.start;
         let mut found_indicate_or_notify_uuid = Option::None;
 
@@ -857,11 +2021,13 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
                             uuid: decl_uuid,
                         } = AttributeData::decode_declaration(item)?
                         {
-                            if let Some(start_handle) = found_indicate_or_notify_uuid {
-                                return Ok(Characteristic {
+                            if let Some((start_handle, found_props)) = found_indicate_or_notify_uuid {
+                                return Ok(CachedCharacteristic {
+                                    uuid: uuid.clone(),
+                                    props: found_props,
                                     handle: start_handle,
                                     cccd_handle: Some(self.get_characteristic_cccd(start_handle, handle).await?),
-                                    phantom: PhantomData,
+                                    end_handle: handle - 2,
                                 });
                             }
 
@@ -869,13 +2035,15 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
                                 // If there are "notify" and "indicate" characteristic properties we need to find the
                                 // next characteristic so we can determine the search space for the CCCD
                                 if !props.any(&[CharacteristicProp::Indicate, CharacteristicProp::Notify]) {
-                                    return Ok(Characteristic {
+                                    return Ok(CachedCharacteristic {
+                                        uuid: uuid.clone(),
+                                        props,
                                         handle,
                                         cccd_handle: None,
-                                        phantom: PhantomData,
+                                        end_handle: service.end,
                                     });
                                 }
-                                found_indicate_or_notify_uuid = Some(handle);
+                                found_indicate_or_notify_uuid = Some((handle, props));
                             }
 
                             if handle == 0xFFFF {
@@ -889,11 +2057,13 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
                 }
                 AttRsp::Error { request, handle, code } => match code {
                     att::AttErrorCode::ATTRIBUTE_NOT_FOUND => match found_indicate_or_notify_uuid {
-                        Some(handle) => {
-                            return Ok(Characteristic {
+                        Some((handle, props)) => {
+                            return Ok(CachedCharacteristic {
+                                uuid: uuid.clone(),
+                                props,
                                 handle,
                                 cccd_handle: Some(self.get_characteristic_cccd(handle, service.end).await?),
-                                phantom: PhantomData,
+                                end_handle: service.end,
                             });
                         }
                         None => return Err(Error::NotFound.into()),
@@ -961,6 +2131,50 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         }
     }
 
+    /// Read a descriptor described by a handle.
+    ///
+    /// The number of bytes copied into the provided buffer is returned.
+    pub async fn read_descriptor(
+        &self,
+        descriptor: &DescriptorHandle,
+        dest: &mut [u8],
+    ) -> Result<usize, BleHostError<C::Error>> {
+        let response = self
+            .request(att::AttReq::Read {
+                handle: descriptor.handle,
+            })
+            .await?;
+
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::Read { data } => {
+                let to_copy = data.len().min(dest.len());
+                dest[..to_copy].copy_from_slice(&data[..to_copy]);
+                Ok(to_copy)
+            }
+            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
+    /// Write to a descriptor described by a handle.
+    pub async fn write_descriptor(
+        &self,
+        descriptor: &DescriptorHandle,
+        buf: &[u8],
+    ) -> Result<(), BleHostError<C::Error>> {
+        let data = att::AttReq::Write {
+            handle: descriptor.handle,
+            data: buf,
+        };
+
+        let response = self.request(data).await?;
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::Write => Ok(()),
+            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
     /// Read a long characteristic value using blob reads if necessary.
     ///
     /// This method automatically handles characteristics longer than ATT MTU
@@ -970,9 +2184,29 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         characteristic: &Characteristic<T>,
         dest: &mut [u8],
     ) -> Result<usize, BleHostError<C::Error>> {
+        // All parts are read on the same bearer
+        let mut transaction = self.acquire_bearer().await?;
+        let att_mtu = transaction.bearer.mtu(&self.connection) as usize;
+
         // first read, use regular read
-        let first_read_len = self.read_characteristic(characteristic, dest).await?;
-        let att_mtu = self.connection.att_mtu() as usize;
+        let response = self
+            .request_on(
+                &mut transaction,
+                att::AttReq::Read {
+                    handle: characteristic.handle,
+                },
+            )
+            .await?;
+        let first_read_len = match Self::response(response.pdu.as_ref())? {
+            AttRsp::Read { data } => {
+                let to_copy = data.len().min(dest.len());
+                dest[..to_copy].copy_from_slice(&data[..to_copy]);
+                to_copy
+            }
+            AttRsp::Error { request, handle, code } => return Err(Error::Att(code).into()),
+            _ => return Err(Error::UnexpectedGattResponse.into()),
+        };
+        drop(response);
 
         if first_read_len != att_mtu - 1 {
             // att_mtu-1 indicates there's more to read
@@ -983,10 +2217,13 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         let mut offset = first_read_len;
         loop {
             let response = self
-                .request(att::AttReq::ReadBlob {
-                    handle: characteristic.handle,
-                    offset: offset as u16,
-                })
+                .request_on(
+                    &mut transaction,
+                    att::AttReq::ReadBlob {
+                        handle: characteristic.handle,
+                        offset: offset as u16,
+                    },
+                )
                 .await?;
 
             match Self::response(response.pdu.as_ref())? {
@@ -1060,6 +2297,172 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         }
     }
 
+    /// Read several characteristics with a single Read Multiple Variable Length request.
+    ///
+    /// Each value is copied into the buffer at the same position as its handle, values not fitting in the ATT MTU
+    /// are truncated. The number of bytes copied for each handle is returned.
+    pub async fn read_multiple_variable<const N: usize>(
+        &self,
+        handles: &[u16; N],
+        dest: &mut [&mut [u8]; N],
+    ) -> Result<[usize; N], BleHostError<C::Error>> {
+        let mut buf = [0u8; 512];
+        let mut w = WriteCursor::new(&mut buf);
+        for handle in handles {
+            w.write(*handle)?;
+        }
+        let len = w.len();
+
+        let response = self
+            .request(att::AttReq::ReadMultipleVariable { handles: &buf[..len] })
+            .await?;
+
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::ReadMultipleVariable { mut it } => {
+                let mut lengths = [0; N];
+                for (dest, copied) in dest.iter_mut().zip(lengths.iter_mut()) {
+                    let Some(data) = it.next() else {
+                        break;
+                    };
+                    let data = data?;
+                    *copied = data.len().min(dest.len());
+                    dest[..*copied].copy_from_slice(&data[..*copied]);
+                }
+                Ok(lengths)
+            }
+            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
+    /// Open up to `count` Enhanced ATT bearers to the server.
+    ///
+    /// Requests are spread over the fixed ATT channel and the enhanced bearers, allowing several requests to be
+    /// outstanding at the same time. The client also announces support for Multiple Handle Value Notifications.
+    /// Returns the number of bearers opened, or [`Error::NotSupported`] if the server does not support Enhanced ATT.
+    /// The link has to be encrypted first, otherwise this fails with insufficient encryption without contacting
+    /// the server.
+    pub async fn open_enhanced_bearers(&self, count: usize) -> Result<usize, BleHostError<C::Error>> {
+        // Enhanced ATT bearers may only be opened on an encrypted link
+        if !self.connection.security_level()?.encrypted() {
+            return Err(Error::Att(att::AttErrorCode::INSUFFICIENT_ENCRYPTION).into());
+        }
+
+        let server_features = self.read_feature(SERVER_SUPPORTED_FEATURES.into()).await?;
+        if server_features.is_none_or(|(_, features)| features & SERVER_ENHANCED_ATT == 0) {
+            return Err(Error::NotSupported.into());
+        }
+
+        if let Some((handle, features)) = self.read_feature(CLIENT_SUPPORTED_FEATURES.into()).await? {
+            let data = att::AttReq::Write {
+                handle,
+                data: &[features | ENHANCED_ATT | MULTIPLE_NOTIFICATIONS],
+            };
+            let response = self.request(data).await?;
+            match Self::response(response.pdu.as_ref())? {
+                AttRsp::Write => {}
+                AttRsp::Error { request, handle, code } => return Err(Error::Att(code).into()),
+                _ => return Err(Error::UnexpectedGattResponse.into()),
+            }
+        }
+
+        let free = self.bearers.iter().filter(|bearer| !self.is_usable(bearer)).count();
+        let count = count.min(free);
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let channels = L2capChannel::create_group(
+            self.stack,
+            &self.connection,
+            L2CAP_PSM_EATT,
+            count,
+            &L2capChannelConfig::default(),
+        )
+        .await?;
+
+        let mut enhanced = self.enhanced.borrow_mut();
+        // Bearers of disconnected channels are replaced
+        enhanced.retain(|channel| self.stack.host.channels.is_connected(channel.index()));
+        let opened = channels.len();
+        for channel in channels {
+            let index = channel.index();
+            let bearer = AttBearer::Enhanced {
+                index,
+                mtu: self.stack.host.channels.mtu(index),
+            };
+            if let Some(slot) = self.bearers[1..].iter().find(|slot| !self.is_usable(slot)) {
+                slot.bearer.set(Some(bearer));
+                slot.timed_out.set(false);
+                // A response for the previous bearer can't arrive anymore
+                let _ = slot.response.try_receive();
+            }
+            let _ = enhanced.push(channel);
+        }
+        Ok(opened)
+    }
+
+    /// Discover the Service Changed characteristic of the peer and subscribe to its indications.
+    ///
+    /// Cached attributes are only invalidated on indications of this characteristic, so this should be
+    /// called once after connecting, unless an imported cache already subscribed to it. Returns `false` if
+    /// the peer doesn't have a Service Changed characteristic.
+    pub async fn subscribe_service_changed(&self) -> Result<bool, BleHostError<C::Error>> {
+        let services = self.services_by_uuid(&service::GATT.into()).await?;
+        let Some(service) = services.first() else {
+            return Ok(false);
+        };
+        let characteristic = match self
+            .discover_characteristic_by_uuid(service, &SERVICE_CHANGED.into())
+            .await
+        {
+            Ok(characteristic) => characteristic,
+            Err(BleHostError::BleHost(Error::NotFound)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let cccd_handle = characteristic.cccd_handle.ok_or(Error::NotSupported)?;
+
+        let data = att::AttReq::Write {
+            handle: cccd_handle,
+            data: &0x02u16.to_le_bytes(),
+        };
+        let response = self.request(data).await?;
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::Write => {
+                self.cache.borrow_mut().service_changed = Some(characteristic.handle);
+                Ok(true)
+            }
+            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
+    /// Read the first byte of a GATT service feature characteristic, returning its handle along with the value.
+    async fn read_feature(&self, uuid: Uuid) -> Result<Option<(u16, u8)>, BleHostError<C::Error>> {
+        let data = att::AttReq::ReadByType {
+            start: 0x0001,
+            end: 0xffff,
+            attribute_type: uuid,
+        };
+        let response = self.request(data).await?;
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::ReadByType { mut it } => match it.next() {
+                Some(res) => {
+                    let (handle, value) = res?;
+                    Ok(Some((handle, value.first().copied().unwrap_or(0))))
+                }
+                None => Ok(None),
+            },
+            AttRsp::Error { request, handle, code } => {
+                if code == att::AttErrorCode::ATTRIBUTE_NOT_FOUND {
+                    return Ok(None);
+                }
+                Err(Error::Att(code).into())
+            }
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
     /// Write to a characteristic described by a handle.
     pub async fn write_characteristic<T: FromGatt>(
         &self,
@@ -1079,6 +2482,102 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         }
     }
 
+    /// Write a value longer than the ATT MTU to a characteristic described by a handle.
+    ///
+    /// The value is sent in parts using Prepare Write requests, which the server queues
+    /// until they are all written with an Execute Write request.
+    pub async fn write_characteristic_long<T: FromGatt>(
+        &self,
+        handle: &Characteristic<T>,
+        buf: &[u8],
+    ) -> Result<(), BleHostError<C::Error>> {
+        let mut transaction = self.acquire_bearer().await?;
+        self.prepare_write(&mut transaction, handle.handle, buf, false).await?;
+        self.execute_write(&mut transaction, true).await
+    }
+
+    /// Write to a characteristic described by a handle using a reliable write.
+    ///
+    /// Like [`GattClient::write_characteristic_long`], but every part echoed by the server is checked
+    /// against what was sent. On a mismatch, the queued parts are cancelled and `Error::ReliableWriteMismatch`
+    /// is returned.
+    pub async fn write_characteristic_reliable<T: FromGatt>(
+        &self,
+        handle: &Characteristic<T>,
+        buf: &[u8],
+    ) -> Result<(), BleHostError<C::Error>> {
+        let mut transaction = self.acquire_bearer().await?;
+        self.prepare_write(&mut transaction, handle.handle, buf, true).await?;
+        self.execute_write(&mut transaction, true).await
+    }
+
+    /// Queue a value on the server in parts that fit within the ATT MTU of the reserved bearer.
+    ///
+    /// Anything already queued is cancelled if the server rejects a part, or if `verify` is set and
+    /// the echoed part doesn't match.
+    async fn prepare_write(
+        &self,
+        transaction: &mut Transaction<'_, P::Packet>,
+        handle: u16,
+        buf: &[u8],
+        verify: bool,
+    ) -> Result<(), BleHostError<C::Error>> {
+        // Opcode, handle and offset
+        let len = transaction.bearer.mtu(&self.connection) as usize - 5;
+        for (i, part) in buf.chunks(len).enumerate() {
+            let offset = (i * len) as u16;
+            let result = {
+                let response = self
+                    .request_on(
+                        transaction,
+                        att::AttReq::PrepareWrite {
+                            handle,
+                            offset,
+                            value: part,
+                        },
+                    )
+                    .await?;
+                match Self::response(response.pdu.as_ref())? {
+                    AttRsp::PrepareWrite {
+                        handle: h,
+                        offset: o,
+                        value,
+                    } => {
+                        if verify && (h != handle || o != offset || value != part) {
+                            Err(Error::ReliableWriteMismatch)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    AttRsp::Error { request, handle, code } => Err(Error::Att(code)),
+                    _ => Err(Error::UnexpectedGattResponse),
+                }
+            };
+
+            if let Err(e) = result {
+                self.execute_write(transaction, false).await?;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all queued values, or cancel them.
+    async fn execute_write(
+        &self,
+        transaction: &mut Transaction<'_, P::Packet>,
+        commit: bool,
+    ) -> Result<(), BleHostError<C::Error>> {
+        let response = self
+            .request_on(transaction, att::AttReq::ExecuteWrite { flags: commit as u8 })
+            .await?;
+        match Self::response(response.pdu.as_ref())? {
+            AttRsp::ExecuteWrite => Ok(()),
+            AttRsp::Error { request, handle, code } => Err(Error::Att(code).into()),
+            _ => Err(Error::UnexpectedGattResponse.into()),
+        }
+    }
+
     /// Write without waiting for a response to a characteristic described by a handle.
     pub async fn write_characteristic_without_response<T: FromGatt>(
         &self,
@@ -1095,6 +2594,41 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         Ok(())
     }
 
+    /// Write without waiting for a response to a characteristic described by a handle, using a signed write.
+    ///
+    /// The write is authenticated with the signing key distributed to the peer during bonding, so the
+    /// characteristic can be written by a bonded client without encrypting the link. If the link is already
+    /// encrypted, a plain write without response is sent instead.
+    #[cfg(feature = "security")]
+    pub async fn write_characteristic_signed<T: FromGatt>(
+        &self,
+        handle: &Characteristic<T>,
+        buf: &[u8],
+    ) -> Result<(), BleHostError<C::Error>> {
+        if self.connection.security_level()?.encrypted() {
+            return self.write_characteristic_without_response(handle, buf).await;
+        }
+
+        let signature = {
+            // The signature covers the opcode, handle and value
+            let mut message = [0u8; 512];
+            let message = message.get_mut(..3 + buf.len()).ok_or(Error::InsufficientSpace)?;
+            message[0] = att::ATT_SIGNED_WRITE_CMD;
+            message[1..3].copy_from_slice(&handle.handle.to_le_bytes());
+            message[3..].copy_from_slice(buf);
+            self.connection.sign(message)?
+        };
+        let data = att::AttCmd::SignedWrite {
+            handle: handle.handle,
+            data: buf,
+            signature,
+        };
+
+        self.command(data).await?;
+
+        Ok(())
+    }
+
     /// Subscribe to indication/notification of a given Characteristic
     ///
     /// A listener is returned, which has a `next()` method
@@ -1150,38 +2684,67 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         }
     }
 
-    /// Handle a notification that was received.
-    async fn handle_notification_packet(&self, data: &[u8]) -> Result<(), BleHostError<C::Error>> {
+    /// Handle a notification or indication that was received.
+    async fn handle_notification_packet(&self, data: &[u8], indication: bool) -> Result<(), BleHostError<C::Error>> {
         let mut r = ReadCursor::new(data);
         let value_handle: u16 = r.read()?;
         let value_attr = r.remaining();
 
         let handle = value_handle;
 
+        // A change of the peer's services makes the cached handles in the affected range unusable
+        if indication && self.cache.borrow().is_service_changed(handle) {
+            let mut range = ReadCursor::new(value_attr);
+            let start: u16 = range.read()?;
+            let end: u16 = range.read()?;
+            self.cache.borrow_mut().invalidate(start, end);
+        }
+
+        self.publish_notification(handle, value_attr, indication);
+        Ok(())
+    }
+
+    fn publish_notification(&self, handle: u16, value: &[u8], indication: bool) {
         // TODO
         let mut data = [0u8; 512];
-        let to_copy = data.len().min(value_attr.len());
-        data[..to_copy].copy_from_slice(&value_attr[..to_copy]);
+        let to_copy = data.len().min(value.len());
+        data[..to_copy].copy_from_slice(&value[..to_copy]);
         let n = Notification {
             handle,
             data,
             len: to_copy,
+            indication,
         };
         self.notifications.immediate_publisher().publish_immediate(n);
-        Ok(())
     }
 
     /// Task which handles GATT rx data (needed for notifications to work)
     pub async fn task(&self) -> Result<(), BleHostError<C::Error>> {
         loop {
-            let handle = self.connection.handle();
-            let pdu = self.connection.next_gatt_client().await;
+            let (bearer, pdu) = self.connection.next_gatt_client().await;
             let data = pdu.as_ref();
-            // handle notifications
-            if pdu.as_ref()[0] == ATT_HANDLE_VALUE_NTF {
-                self.handle_notification_packet(&pdu.as_ref()[1..]).await?;
-            } else {
-                self.response_channel.send((handle, pdu)).await;
+            // handle notifications and indications
+            match data[0] {
+                ATT_HANDLE_VALUE_NTF => {
+                    self.handle_notification_packet(&data[1..], false).await?;
+                }
+                ATT_HANDLE_VALUE_IND => {
+                    self.handle_notification_packet(&data[1..], true).await?;
+                    // The server can't send another indication on this bearer until this one is confirmed.
+                    self.send_att_data(bearer, Att::Client(AttClient::Confirmation(AttCfm::ConfirmIndication)))
+                        .await?;
+                }
+                ATT_MULTIPLE_HANDLE_VALUE_NTF => {
+                    let mut it = MultipleHandleValueIter::new(&data[1..]);
+                    while let Some(res) = it.next() {
+                        let (handle, value) = res?;
+                        self.publish_notification(handle, value, false);
+                    }
+                }
+                _ => match self.bearers.iter().find(|slot| slot.is(&bearer)) {
+                    Some(slot) => slot.response.send(pdu).await,
+                    None => warn!("[gatt] response received on unknown bearer"),
+                },
             }
         }
     }
@@ -1194,3 +2757,490 @@ impl<'reference, C: Controller, P: PacketPool, const MAX_SERVICES: usize> GattCl
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use bt_hci::cmd::info::ReadBdAddr;
+    use bt_hci::param::{AddrKind, LeConnRole};
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_time::{with_timeout, Timer};
+    #[cfg(feature = "security")]
+    use rand_chacha::{ChaCha12Core, ChaCha12Rng};
+    #[cfg(feature = "security")]
+    use rand_core::SeedableRng;
+
+    use super::*;
+    use crate::attribute::CharacteristicProp;
+    use crate::mock_controller::MockController;
+    use crate::prelude::DefaultPacketPool;
+    use crate::{Host, HostResources};
+
+    /// Handle of the connection to the mock server.
+    const CONN: u16 = 1;
+
+    type MockClient<'d> = GattClient<'d, MockController, DefaultPacketPool, 4>;
+
+    fn mock_stack(
+        resources: &mut HostResources<DefaultPacketPool, 1, 2>,
+    ) -> Stack<'_, MockController, DefaultPacketPool> {
+        let stack = crate::new(MockController::new(), resources);
+        #[cfg(feature = "security")]
+        let stack = {
+            let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+            stack.set_random_generator_seed(&mut rng)
+        };
+        stack
+    }
+
+    /// Connect to the mock server and create a client, agreeing on the minimum ATT MTU of 23 bytes.
+    async fn connect<'d>(
+        stack: &'d Stack<'d, MockController, DefaultPacketPool>,
+    ) -> (Connection<'d, DefaultPacketPool>, MockClient<'d>) {
+        let controller = &stack.host.controller;
+        controller.wait_command::<ReadBdAddr>().await;
+        controller.connection_complete(
+            ConnHandle::new(CONN),
+            LeConnRole::Central,
+            AddrKind::PUBLIC,
+            BdAddr::new([1; 6]),
+        );
+        let connection = stack.host.connections.accept(LeConnRole::Central, &[]).await;
+        let client = unwrap!(GattClient::new(stack, &connection).await);
+        expect(controller, &[0x02, 247, 0]).await;
+        respond(controller, &[0x03, 23, 0]);
+        core::future::poll_fn(|cx| {
+            if connection.att_mtu() == 23 {
+                core::task::Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                core::task::Poll::Pending
+            }
+        })
+        .await;
+        (connection, client)
+    }
+
+    /// Wait for the client to send `pdu` on the fixed ATT channel.
+    async fn expect(controller: &MockController, pdu: &[u8]) {
+        let (handle, data) = controller.next_acl().await;
+        assert_eq!(handle, ConnHandle::new(CONN));
+        assert_eq!(data[..4], [pdu.len() as u8, 0, 4, 0]);
+        assert_eq!(data[4..], *pdu);
+        controller.number_of_completed_packets(handle, 1);
+    }
+
+    /// Deliver `pdu` from the server on the fixed ATT channel.
+    fn respond(controller: &MockController, pdu: &[u8]) {
+        let mut data = std::vec![pdu.len() as u8, 0, 4, 0];
+        data.extend_from_slice(pdu);
+        controller.acl(ConnHandle::new(CONN), &data);
+    }
+
+    /// Run `operations` on a client connected to the mock server, while `server` plays the server side.
+    fn run_client<'d>(
+        stack: &'d Stack<'d, MockController, DefaultPacketPool>,
+        operations: impl AsyncFnOnce(&MockClient<'d>),
+        server: impl AsyncFnOnce(&MockController),
+    ) {
+        let Host { mut runner, .. } = stack.build();
+        let test = async {
+            let (_connection, client) = connect(stack).await;
+            match select(client.task(), join(operations(&client), server(&stack.host.controller))).await {
+                Either::First(result) => panic!("client stopped: {:?}", result),
+                Either::Second(_) => {}
+            }
+        };
+        match block_on(select(runner.run(), test)) {
+            Either::First(result) => panic!("runner stopped: {:?}", result),
+            Either::Second(()) => {}
+        }
+    }
+
+    fn mock_characteristic<T: AsGatt>(handle: u16, cccd_handle: Option<u16>) -> Characteristic<T> {
+        Characteristic {
+            handle,
+            cccd_handle,
+            phantom: PhantomData,
+        }
+    }
+
+    fn service(start: u16, end: u16, uuid: u16) -> ServiceHandle {
+        ServiceHandle {
+            start,
+            end,
+            uuid: Uuid::new_short(uuid),
+        }
+    }
+
+    fn characteristic(handle: u16, end: u16, uuid: Uuid) -> DiscoveredCharacteristic<[u8; 4]> {
+        DiscoveredCharacteristic {
+            uuid,
+            props: [CharacteristicProp::Read, CharacteristicProp::Indicate].into(),
+            end_handle: end,
+            characteristic: Characteristic {
+                handle,
+                cccd_handle: Some(handle + 1),
+                phantom: PhantomData,
+            },
+        }
+    }
+
+    fn populated_cache() -> GattCache<4> {
+        let gatt = service(1, 4, 0x1801);
+        let battery = service(5, 9, 0x180f);
+        let mut cache = GattCache::new(Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+            #[cfg(feature = "security")]
+            irk: None,
+        });
+        cache.set_services(&[gatt.clone(), battery.clone()]);
+        cache.set_characteristics(&gatt, &[characteristic(3, 4, SERVICE_CHANGED.into())]);
+        cache.set_characteristics(&battery, &[characteristic(7, 9, Uuid::new_long([7; 16]))]);
+        cache.hash = Some([0xaa; 16]);
+        cache.service_changed = Some(3);
+        cache
+    }
+
+    #[test]
+    fn test_gatt_cache_serialization() {
+        let cache = populated_cache();
+        let mut buf = [0; GattCache::<4>::SERIALIZED_SIZE];
+        let len = cache.to_bytes(&mut buf).unwrap();
+        assert_eq!(GattCache::<4>::from_bytes(&buf[..len]).unwrap(), cache);
+
+        assert!(cache.to_bytes(&mut buf[..len - 1]).is_err());
+        assert!(GattCache::<4>::from_bytes(&buf[..len - 1]).is_err());
+        assert!(GattCache::<1>::from_bytes(&buf[..len]).is_err());
+    }
+
+    #[test]
+    fn test_gatt_cache_service_changed() {
+        let mut cache = populated_cache();
+        let battery = service(5, 9, 0x180f);
+        let discovered: Vec<DiscoveredCharacteristic<[u8; 4]>, 4> = cache.characteristics(&battery).unwrap().unwrap();
+        assert_eq!(discovered.len(), 1);
+        assert!(cache.characteristic(&battery, &Uuid::new_long([7; 16])).is_some());
+
+        assert!(cache.is_service_changed(3));
+        assert!(!cache.is_service_changed(7));
+        cache.invalidate(6, 0xffff);
+
+        assert_eq!(cache.hash(), None);
+        assert!(cache.services(None).is_none());
+        assert!(cache.services(Some(&Uuid::new_short(0x180f))).is_none());
+        assert!(cache.characteristic(&battery, &Uuid::new_long([7; 16])).is_none());
+        assert!(cache.is_service_changed(3));
+        assert_eq!(cache.services(Some(&Uuid::new_short(0x1801))).unwrap().len(), 1);
+
+        // A discovered Service Changed characteristic isn't trusted until subscribed to.
+        cache.service_changed = None;
+        assert!(!cache.is_service_changed(3));
+    }
+
+    #[test]
+    fn test_multiple_handle_value_notification() {
+        let pdu = [
+            ATT_MULTIPLE_HANDLE_VALUE_NTF,
+            0x03,
+            0x00,
+            0x01,
+            0x00,
+            0xaa,
+            0x07,
+            0x00,
+            0x02,
+            0x00,
+            0xbb,
+            0xcc,
+        ];
+        let att = Att::decode(&pdu).unwrap();
+        let mut buf = [0; 12];
+        let mut w = WriteCursor::new(&mut buf);
+        w.write(att).unwrap();
+        assert_eq!(w.len(), pdu.len());
+        assert_eq!(buf, pdu);
+
+        let Ok(Att::Server(AttServer::Unsolicited(AttUns::MultipleNotify { mut it }))) = Att::decode(&pdu) else {
+            panic!("expected a multiple handle value notification");
+        };
+        assert_eq!(it.next().unwrap().unwrap(), (3, &[0xaa][..]));
+        assert_eq!(it.next().unwrap().unwrap(), (7, &[0xbb, 0xcc][..]));
+        assert!(it.next().is_none());
+
+        // A value longer than the remaining PDU is malformed
+        let mut it = MultipleHandleValueIter::new(&pdu[1..11]);
+        assert!(it.next().unwrap().is_ok());
+        assert!(it.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn long_and_reliable_writes() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+        let characteristic: Characteristic<[u8; 40]> = mock_characteristic(3, None);
+        let value: [u8; 40] = core::array::from_fn(|i| i as u8);
+
+        run_client(
+            &stack,
+            async |client| {
+                unwrap!(client.write_characteristic_long(&characteristic, &value).await);
+                let result = client.write_characteristic_reliable(&characteristic, &value).await;
+                assert!(matches!(
+                    result,
+                    Err(BleHostError::BleHost(Error::ReliableWriteMismatch))
+                ));
+            },
+            async |controller| {
+                // The value is queued 18 bytes at a time with a 23 byte ATT MTU, then written.
+                for offset in [0, 18, 36] {
+                    let part = &value[offset..value.len().min(offset + 18)];
+                    let mut pdu = [&[0x16, 3, 0, offset as u8, 0][..], part].concat();
+                    expect(controller, &pdu).await;
+                    pdu[0] = 0x17;
+                    respond(controller, &pdu);
+                }
+                expect(controller, &[0x18, 0x01]).await;
+                respond(controller, &[0x19]);
+
+                // The reliable write is cancelled once a part is echoed back wrong.
+                let mut pdu = [&[0x16, 3, 0, 0, 0][..], &value[..18]].concat();
+                expect(controller, &pdu).await;
+                pdu[0] = 0x17;
+                respond(controller, &pdu);
+                let mut pdu = [&[0x16, 3, 0, 18, 0][..], &value[18..36]].concat();
+                expect(controller, &pdu).await;
+                pdu[0] = 0x17;
+                pdu[5] ^= 0xff;
+                respond(controller, &pdu);
+                expect(controller, &[0x18, 0x00]).await;
+                respond(controller, &[0x19]);
+            },
+        );
+    }
+
+    #[test]
+    fn indications_are_confirmed() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+        let characteristic: Characteristic<[u8; 2]> = mock_characteristic(3, Some(4));
+        // The listener only queues one value, so the server waits for each to be received.
+        let subscribed = embassy_sync::signal::Signal::<NoopRawMutex, ()>::new();
+        let received = embassy_sync::signal::Signal::<NoopRawMutex, ()>::new();
+
+        run_client(
+            &stack,
+            async |client| {
+                let mut listener = unwrap!(client.subscribe(&characteristic, true).await);
+                subscribed.signal(());
+                let indication = listener.next().await;
+                assert!(indication.is_indication());
+                assert_eq!(indication.as_ref(), [0xaa, 0xbb]);
+                received.signal(());
+                let notification = listener.next().await;
+                assert!(!notification.is_indication());
+                assert_eq!(notification.as_ref(), [0xcc]);
+
+                let mut value = [0; 2];
+                unwrap!(client.read_characteristic(&characteristic, &mut value).await);
+            },
+            async |controller| {
+                expect(controller, &[0x12, 4, 0, 0x02, 0x00]).await;
+                respond(controller, &[0x13]);
+                subscribed.wait().await;
+
+                respond(controller, &[0x1d, 3, 0, 0xaa, 0xbb]);
+                expect(controller, &[0x1e]).await;
+                received.wait().await;
+
+                // Notifications are not confirmed, the next PDU is the read request.
+                respond(controller, &[0x1b, 3, 0, 0xcc]);
+                expect(controller, &[0x0a, 3, 0]).await;
+                respond(controller, &[0x0b, 0xaa, 0xbb]);
+            },
+        );
+    }
+
+    #[test]
+    fn service_characteristic_and_descriptor_discovery() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+
+        run_client(
+            &stack,
+            async |client| {
+                let services = unwrap!(client.services().await);
+                assert_eq!(services.len(), 2);
+                assert_eq!(services[0].handle_range(), (1, 5));
+                assert_eq!(services[0].uuid(), &Uuid::new_short(0x180f));
+                assert_eq!(services[1].handle_range(), (6, 8));
+
+                let included: Vec<ServiceHandle, 2> = unwrap!(client.included_services(&services[0]).await);
+                assert_eq!(included.len(), 1);
+                assert_eq!(included[0].handle_range(), (6, 8));
+                assert_eq!(included[0].uuid(), &Uuid::new_short(0x1234));
+
+                let characteristics: Vec<DiscoveredCharacteristic<u8>, 2> =
+                    unwrap!(client.characteristics(&services[0]).await);
+                assert_eq!(characteristics.len(), 1);
+                let battery = &characteristics[0];
+                assert_eq!(battery.uuid, Uuid::new_short(0x2a19));
+                assert!(battery.props.any(&[CharacteristicProp::Notify]));
+                assert_eq!(battery.end_handle, 5);
+                assert_eq!(battery.characteristic.handle, 4);
+                assert_eq!(battery.characteristic.cccd_handle, Some(5));
+
+                let descriptors: Vec<DescriptorHandle, 2> = unwrap!(client.descriptors(battery).await);
+                assert_eq!(descriptors.len(), 1);
+                assert_eq!(descriptors[0].handle(), 5);
+                assert_eq!(descriptors[0].uuid(), &CLIENT_CHARACTERISTIC_CONFIGURATION.into());
+            },
+            async |controller| {
+                // Primary services are read by group type until the server runs out of them.
+                expect(controller, &[0x10, 1, 0, 0xff, 0xff, 0x00, 0x28]).await;
+                respond(controller, &[0x11, 6, 1, 0, 5, 0, 0x0f, 0x18, 6, 0, 8, 0, 0x34, 0x12]);
+                expect(controller, &[0x10, 9, 0, 0xff, 0xff, 0x00, 0x28]).await;
+                respond(controller, &[0x01, 0x10, 9, 0, 0x0a]);
+
+                // Include declarations within the first service.
+                expect(controller, &[0x08, 1, 0, 5, 0, 0x02, 0x28]).await;
+                respond(controller, &[0x09, 8, 2, 0, 6, 0, 8, 0, 0x34, 0x12]);
+                expect(controller, &[0x08, 3, 0, 5, 0, 0x02, 0x28]).await;
+                respond(controller, &[0x01, 0x08, 3, 0, 0x0a]);
+
+                // Characteristic declarations, followed by the CCCD lookup of the notifying one.
+                expect(controller, &[0x08, 1, 0, 5, 0, 0x03, 0x28]).await;
+                respond(controller, &[0x09, 7, 3, 0, 0x12, 4, 0, 0x19, 0x2a]);
+                expect(controller, &[0x08, 4, 0, 5, 0, 0x03, 0x28]).await;
+                respond(controller, &[0x01, 0x08, 4, 0, 0x0a]);
+                expect(controller, &[0x04, 5, 0, 5, 0]).await;
+                respond(controller, &[0x05, 0x01, 5, 0, 0x02, 0x29]);
+
+                // Descriptors are found with Find Information up to the end of the characteristic.
+                expect(controller, &[0x04, 5, 0, 5, 0]).await;
+                respond(controller, &[0x05, 0x01, 5, 0, 0x02, 0x29]);
+            },
+        );
+    }
+
+    #[test]
+    fn long_write_is_not_interleaved() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+        let long: Characteristic<[u8; 30]> = mock_characteristic(3, None);
+        let short: Characteristic<[u8; 2]> = mock_characteristic(5, None);
+        let value: [u8; 30] = core::array::from_fn(|i| i as u8);
+
+        run_client(
+            &stack,
+            async |client| {
+                let mut read = [0; 2];
+                let (written, len) = join(client.write_characteristic_long(&long, &value), async {
+                    // Issued while the first part is queued on the server.
+                    client.read_characteristic(&short, &mut read).await
+                })
+                .await;
+                unwrap!(written);
+                assert_eq!(unwrap!(len), 2);
+                assert_eq!(read, [0xaa, 0xbb]);
+            },
+            async |controller| {
+                // The bearer stays with the long write until it is executed.
+                for offset in [0, 18] {
+                    let part = &value[offset..value.len().min(offset + 18)];
+                    let mut pdu = [&[0x16, 3, 0, offset as u8, 0][..], part].concat();
+                    expect(controller, &pdu).await;
+                    pdu[0] = 0x17;
+                    respond(controller, &pdu);
+                }
+                expect(controller, &[0x18, 0x01]).await;
+                respond(controller, &[0x19]);
+
+                expect(controller, &[0x0a, 5, 0]).await;
+                respond(controller, &[0x0b, 0xaa, 0xbb]);
+            },
+        );
+    }
+
+    #[test]
+    fn response_to_dropped_request_is_not_mistaken() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+        let first: Characteristic<[u8; 2]> = mock_characteristic(3, None);
+        let second: Characteristic<[u8; 2]> = mock_characteristic(5, None);
+        let in_flight = embassy_sync::signal::Signal::<NoopRawMutex, ()>::new();
+        let dropped = embassy_sync::signal::Signal::<NoopRawMutex, ()>::new();
+
+        run_client(
+            &stack,
+            async |client| {
+                let mut value = [0; 2];
+                let read = select(client.read_characteristic(&first, &mut value), in_flight.wait()).await;
+                assert!(matches!(read, Either::Second(())));
+                dropped.signal(());
+
+                let mut value = [0; 2];
+                unwrap!(client.read_characteristic(&second, &mut value).await);
+                assert_eq!(value, [0xaa, 0xbb]);
+            },
+            async |controller| {
+                expect(controller, &[0x0a, 3, 0]).await;
+                in_flight.signal(());
+                dropped.wait().await;
+                // The next request is only sent once the late response is received.
+                respond(controller, &[0x0b, 0x01, 0x02]);
+                expect(controller, &[0x0a, 5, 0]).await;
+                respond(controller, &[0x0b, 0xaa, 0xbb]);
+            },
+        );
+    }
+
+    #[test]
+    fn request_dropped_before_sending_is_not_outstanding() {
+        let mut resources = HostResources::new();
+        let stack = mock_stack(&mut resources);
+        let first: Characteristic<[u8; 2]> = mock_characteristic(3, None);
+        let second: Characteristic<[u8; 2]> = mock_characteristic(5, None);
+        let queue_full = embassy_sync::signal::Signal::<NoopRawMutex, ()>::new();
+
+        run_client(
+            &stack,
+            async |client| {
+                // Without completed packets from the controller, commands pile up until the outbound queue is full.
+                while let Either::First(written) = select(
+                    client.write_characteristic_without_response(&first, &[0, 0]),
+                    Timer::after_millis(10),
+                )
+                .await
+                {
+                    unwrap!(written);
+                }
+
+                let mut value = [0; 2];
+                let read = select(client.read_characteristic(&first, &mut value), Timer::after_millis(10)).await;
+                assert!(matches!(read, Either::Second(())));
+                queue_full.signal(());
+
+                let mut value = [0; 2];
+                let read = with_timeout(Duration::from_secs(1), client.read_characteristic(&second, &mut value)).await;
+                assert_eq!(unwrap!(unwrap!(read)), 2);
+                assert_eq!(value, [0xaa, 0xbb]);
+            },
+            async |controller| {
+                queue_full.wait().await;
+                // The dropped read never reaches the server, the next request is answered right away.
+                loop {
+                    let (handle, data) = controller.next_acl().await;
+                    controller.number_of_completed_packets(handle, 1);
+                    if data[4..] != [0x52, 3, 0, 0, 0] {
+                        assert_eq!(data[4..], [0x0a, 5, 0]);
+                        break;
+                    }
+                }
+                respond(controller, &[0x0b, 0xaa, 0xbb]);
+            },
+        );
+    }
+}
@@ -4,7 +4,7 @@
 use core::cell::RefCell;
 use core::future::poll_fn;
 use core::mem::MaybeUninit;
-use core::task::Poll;
+use core::task::{Context, Poll};
 
 use bt_hci::cmd::controller_baseband::{
     HostBufferSize, HostNumberOfCompletedPackets, Reset, SetControllerToHostFlowControl, SetEventMask,
@@ -12,9 +12,10 @@ use bt_hci::cmd::controller_baseband::{
 };
 use bt_hci::cmd::info::ReadBdAddr;
 use bt_hci::cmd::le::{
-    LeConnUpdate, LeCreateConnCancel, LeEnableEncryption, LeLongTermKeyRequestReply, LeReadBufferSize,
-    LeReadFilterAcceptListSize, LeSetAdvEnable, LeSetEventMask, LeSetExtAdvEnable, LeSetExtScanEnable, LeSetRandomAddr,
-    LeSetScanEnable,
+    LeAddDeviceToResolvingList, LeClearResolvingList, LeConnUpdate, LeCreateConnCancel, LeEnableEncryption,
+    LeLongTermKeyRequestReply, LeReadBufferSize, LeReadFilterAcceptListSize, LeSetAddrResolutionEnable, LeSetAdvEnable,
+    LeSetAdvSetRandomAddr, LeSetEventMask, LeSetExtAdvEnable, LeSetExtScanEnable, LeSetPrivacyMode, LeSetRandomAddr,
+    LeSetResolvablePrivateAddrTimeout, LeSetScanEnable,
 };
 use bt_hci::cmd::link_control::Disconnect;
 use bt_hci::cmd::{AsyncCmd, SyncCmd};
@@ -29,23 +30,35 @@ use bt_hci::event::le::{
     LeEnhancedConnectionComplete, LeEventKind, LeEventPacket, LePhyUpdateComplete, LeRemoteConnectionParameterRequest,
 };
 use bt_hci::event::{DisconnectionComplete, EventKind, NumberOfCompletedPackets, Vendor};
+#[cfg(feature = "security")]
+use bt_hci::param::PrivacyMode;
 use bt_hci::param::{
     AddrKind, AdvHandle, AdvSet, BdAddr, ConnHandle, DisconnectReason, EventMask, EventMaskPage2, FilterDuplicates,
     LeConnRole, LeEventMask, Status,
 };
 use bt_hci::{ControllerToHostPacket, FromHciBytes, WriteHci};
+#[cfg(any(feature = "gatt", feature = "security", test))]
+use embassy_futures::select::{select, Either};
 use embassy_futures::select::{select3, select4, Either3, Either4};
 use embassy_sync::once_lock::OnceLock;
 use embassy_sync::waitqueue::WakerRegistration;
 use embassy_time::Duration;
+#[cfg(feature = "security")]
+use embassy_time::{Instant, Timer};
 use futures::pin_mut;
 
 use crate::att::{AttClient, AttServer};
+#[cfg(feature = "security")]
+use crate::bt_hci_duration;
+#[cfg(feature = "gatt")]
+use crate::channel_manager::ChannelIndex;
 use crate::channel_manager::{ChannelManager, ChannelStorage};
 use crate::command::CommandState;
 use crate::connection::ConnectionEvent;
 use crate::connection_manager::{ConnectionManager, ConnectionStorage, PacketGrant};
 use crate::cursor::WriteCursor;
+#[cfg(feature = "gatt")]
+use crate::gatt::AttBearer;
 use crate::pdu::Pdu;
 #[cfg(feature = "security")]
 use crate::security_manager::SecurityEventData;
@@ -55,6 +68,10 @@ use crate::types::l2cap::{
 };
 use crate::{att, Address, BleHostError, Error, PacketPool, Stack};
 
+/// Delay before rotating the advertising address again if the controller refused it.
+#[cfg(feature = "security")]
+const RPA_ROTATION_RETRY: Duration = Duration::from_secs(1);
+
 /// A BLE Host.
 ///
 /// The BleHost holds the runtime state of the host, and is the entry point
@@ -84,13 +101,16 @@ pub(crate) struct InitialState {
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum AdvHandleState {
     None,
-    Advertising(AdvHandle),
+    Advertising(AdvSet),
     Terminated(AdvHandle),
 }
 
 pub(crate) struct AdvInnerState<'d> {
     handles: &'d mut [AdvHandleState],
+    /// Advertising was started with the extended advertising commands.
+    extended: bool,
     waker: WakerRegistration,
+    control_waker: WakerRegistration,
 }
 
 pub(crate) struct AdvState<'d> {
@@ -102,7 +122,9 @@ impl<'d> AdvState<'d> {
         Self {
             state: RefCell::new(AdvInnerState {
                 handles,
+                extended: false,
                 waker: WakerRegistration::new(),
+                control_waker: WakerRegistration::new(),
             }),
         }
     }
@@ -113,6 +135,7 @@ impl<'d> AdvState<'d> {
             *entry = AdvHandleState::None;
         }
         state.waker.wake();
+        state.control_waker.wake();
     }
 
     // Terminate handle
@@ -120,13 +143,23 @@ impl<'d> AdvState<'d> {
         let mut state = self.state.borrow_mut();
         for entry in state.handles.iter_mut() {
             match entry {
-                AdvHandleState::Advertising(h) if *h == handle => {
+                AdvHandleState::Advertising(set) if set.adv_handle == handle => {
                     *entry = AdvHandleState::Terminated(handle);
                 }
                 _ => {}
             }
         }
         state.waker.wake();
+        state.control_waker.wake();
+    }
+
+    /// Legacy advertising stops once a connection is established, extended advertising sets are
+    /// terminated by the controller instead.
+    pub(crate) fn connected(&self) {
+        let extended = self.state.borrow().extended;
+        if !extended {
+            self.reset();
+        }
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -134,7 +167,7 @@ impl<'d> AdvState<'d> {
         state.handles.len()
     }
 
-    pub(crate) fn start(&self, sets: &[AdvSet]) {
+    pub(crate) fn start(&self, sets: &[AdvSet], extended: bool) {
         let mut state = self.state.borrow_mut();
         assert!(sets.len() <= state.handles.len());
         for handle in state.handles.iter_mut() {
@@ -142,7 +175,32 @@ impl<'d> AdvState<'d> {
         }
 
         for (idx, entry) in sets.iter().enumerate() {
-            state.handles[idx] = AdvHandleState::Advertising(entry.adv_handle);
+            state.handles[idx] = AdvHandleState::Advertising(*entry);
+        }
+        state.extended = extended;
+        state.control_waker.wake();
+    }
+
+    /// Poll until any set is advertising, returning whether extended advertising is used.
+    pub(crate) fn poll_advertising(&self, cx: &mut Context<'_>) -> Poll<bool> {
+        let mut state = self.state.borrow_mut();
+        state.control_waker.register(cx.waker());
+        if state
+            .handles
+            .iter()
+            .any(|entry| matches!(entry, AdvHandleState::Advertising(_)))
+        {
+            Poll::Ready(state.extended)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// The set at the given index, if it is still advertising.
+    pub(crate) fn advertising_set(&self, index: usize) -> Option<AdvSet> {
+        match self.state.borrow().handles.get(index) {
+            Some(AdvHandleState::Advertising(set)) => Some(*set),
+            _ => None,
         }
     }
 
@@ -225,6 +283,191 @@ where
         Ok(ret)
     }
 
+    /// Address used for advertising, scanning and initiating connections.
+    pub(crate) fn own_address(&self) -> Option<Address> {
+        #[cfg(feature = "security")]
+        if let Some(address) = self.connections.security_manager.resolvable_private_address() {
+            return Some(address);
+        }
+        self.address
+    }
+
+    /// Set a new resolvable private address in the controller if privacy is enabled and the current one expired.
+    pub(crate) async fn update_random_address(&self) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetRandomAddr>,
+    {
+        #[cfg(feature = "security")]
+        if let Some(address) = self.connections.security_manager.next_resolvable_private_address() {
+            match self.command(LeSetRandomAddr::new(address.addr)).await {
+                Ok(_) => {
+                    info!("[host] using resolvable private address {}", address);
+                    self.connections
+                        .security_manager
+                        .set_resolvable_private_address(address);
+                }
+                // Refused while advertising, scanning or initiating in another role, retried when starting the next one.
+                Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::CMD_DISALLOWED))) => {
+                    warn!("[host] unable to rotate resolvable private address while the controller is busy");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until the resolvable private address used while advertising expires.
+    ///
+    /// Returns whether extended advertising is used.
+    #[cfg(feature = "security")]
+    pub(crate) async fn advertising_address_expired(&self) -> bool {
+        loop {
+            let extended = poll_fn(|cx| self.advertise_state.poll_advertising(cx)).await;
+            let Some(expires_at) = self.connections.security_manager.resolvable_private_address_expiry() else {
+                // Privacy is not enabled
+                return core::future::pending().await;
+            };
+            // An expired address means the controller refused the previous rotation, don't retry too often
+            let now = Instant::now();
+            let at = if expires_at > now {
+                expires_at
+            } else {
+                now + RPA_ROTATION_RETRY
+            };
+            match select(
+                Timer::at(at),
+                poll_fn(|cx| match self.advertise_state.poll_advertising(cx) {
+                    Poll::Ready(_) => Poll::Pending,
+                    Poll::Pending => Poll::Ready(()),
+                }),
+            )
+            .await
+            {
+                Either::First(_) => return extended,
+                // Advertising stopped, the address is rotated when it is started again
+                Either::Second(_) => {}
+            }
+        }
+    }
+
+    /// Rotate the resolvable private address of ongoing advertising.
+    ///
+    /// Legacy advertising is restarted, as the controller doesn't accept a new address while it is enabled.
+    /// Extended advertising sets are restarted one at a time with the new address.
+    #[cfg(feature = "security")]
+    pub(crate) async fn rotate_advertising_address(&self, extended: bool) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetRandomAddr>
+            + ControllerCmdSync<LeSetAdvEnable>
+            + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>,
+    {
+        if !extended {
+            self.command(LeSetAdvEnable::new(false)).await?;
+            self.update_random_address().await?;
+            return self.command(LeSetAdvEnable::new(true)).await.map(|_| ());
+        }
+
+        self.update_random_address().await?;
+        let Some(address) = self.own_address() else {
+            return Ok(());
+        };
+        for index in 0..self.advertise_state.len() {
+            if let Some(set) = self.advertise_state.advertising_set(index) {
+                let sets = [set];
+                self.command(LeSetExtAdvEnable::new(false, &sets)).await?;
+                self.command(LeSetAdvSetRandomAddr::new(set.adv_handle, address.addr))
+                    .await?;
+                self.command(LeSetExtAdvEnable::new(true, &sets)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the bonded identities to the controller resolving list if they changed.
+    ///
+    /// Address resolution in the controller is enabled as long as the list is not empty.
+    #[cfg(feature = "security")]
+    pub(crate) async fn update_resolving_list(&self) -> Result<(), BleHostError<T::Error>>
+    where
+        T: ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetPrivacyMode>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>,
+    {
+        let security_manager = &self.connections.security_manager;
+        let Some(list) = security_manager.take_resolving_list() else {
+            return Ok(());
+        };
+        let local_irk = list.local_irk.map(|irk| irk.to_le_bytes()).unwrap_or_default();
+        // Valid range is 1 second to 1 hour
+        let timeout = security_manager
+            .resolvable_private_address_timeout()
+            .clamp(Duration::from_secs(1), Duration::from_secs(3600));
+
+        let result = async {
+            self.command(LeSetAddrResolutionEnable::new(false)).await?;
+            self.command(LeClearResolvingList::new()).await?;
+            let mut added = 0;
+            for (identity, irk) in list.entries.iter() {
+                match self
+                    .command(LeAddDeviceToResolvingList::new(
+                        identity.kind,
+                        identity.addr,
+                        irk.to_le_bytes(),
+                        local_irk,
+                    ))
+                    .await
+                {
+                    Ok(_) => added += 1,
+                    Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::MEMORY_CAPACITY_EXCEEDED))) => {
+                        warn!(
+                            "[host] resolving list full, {} bonds not resolved",
+                            list.entries.len() - added
+                        );
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+                // Device privacy mode also accepts the identity address of peers that don't use privacy
+                match self
+                    .command(LeSetPrivacyMode::new(identity.kind, identity.addr, PrivacyMode::Device))
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::UNKNOWN_CMD))) => {
+                        warn!("[host] controller does not support privacy modes");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            self.command(LeSetResolvablePrivateAddrTimeout::new(bt_hci_duration(timeout)))
+                .await?;
+            self.command(LeSetAddrResolutionEnable::new(added > 0)).await?;
+            Ok(added)
+        }
+        .await;
+
+        match result {
+            Ok(added) => {
+                info!("[host] resolving list updated with {} entries", added);
+                Ok(())
+            }
+            // Refused while advertising, scanning or initiating, retried once they stop.
+            Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::CMD_DISALLOWED))) => {
+                warn!("[host] unable to update resolving list while the controller is busy");
+                security_manager.resolving_list_failed();
+                Ok(())
+            }
+            Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::UNKNOWN_CMD))) => {
+                warn!("[host] controller does not support address resolution");
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Run an async HCI command where the response will generate an event later.
     pub(crate) async fn async_command<C>(&self, cmd: C) -> Result<(), BleHostError<T::Error>>
     where
@@ -242,6 +485,7 @@ where
         handle: ConnHandle,
         peer_addr_kind: AddrKind,
         peer_addr: BdAddr,
+        peer_resolvable_address: Option<BdAddr>,
         role: LeConnRole,
     ) -> bool {
         match status.to_result() {
@@ -250,6 +494,11 @@ where
                     warn!("Error establishing connection: {:?}", err);
                     return false;
                 } else {
+                    #[cfg(feature = "security")]
+                    if let Some(address) = peer_resolvable_address {
+                        self.connections.set_peer_resolvable_address(handle, address);
+                    }
+
                     #[cfg(feature = "defmt")]
                     debug!(
                         "[host] connection with handle {:?} established to {:02x}",
@@ -261,6 +510,9 @@ where
                         "[host] connection with handle {:?} established to {:02x?}",
                         handle, peer_addr
                     );
+                    if role == LeConnRole::Peripheral {
+                        self.advertise_state.connected();
+                    }
                     let mut m = self.metrics.borrow_mut();
                     m.connect_events = m.connect_events.wrapping_add(1);
                 }
@@ -296,9 +548,9 @@ where
                     return Err(Error::NotSupported);
                 }
 
-                // Avoids using the packet buffer for signalling packets
-                if header.channel == L2CAP_CID_LE_U_SIGNAL {
-                    assert!(data.len() == header.length as usize);
+                // Avoids using the packet buffer for signalling packets. Fragmented signals exceed the
+                // signaling MTU, and are reassembled only to be rejected.
+                if header.channel == L2CAP_CID_LE_U_SIGNAL && data.len() == header.length as usize {
                     self.channels.signal(acl.handle(), data, &self.connections)?;
                     return Ok(());
                 }
@@ -460,11 +712,25 @@ where
                 } else {
                     #[cfg(feature = "gatt")]
                     match a {
+                        Ok(att::Att::Client(AttClient::Command(att::AttCmd::SignedWrite { .. }))) => {
+                            #[cfg(feature = "security")]
+                            match self.connections.verify_signed_write(acl.handle(), pdu.as_ref()) {
+                                Ok(()) => self.connections.post_gatt(acl.handle(), AttBearer::Unenhanced, pdu)?,
+                                Err(e) => warn!("[host] dropping signed write that failed verification: {:?}", e),
+                            }
+                            #[cfg(not(feature = "security"))]
+                            warn!(
+                                "[host] dropping signed write, signatures can only be verified with security enabled"
+                            );
+                        }
                         Ok(att::Att::Client(_)) => {
-                            self.connections.post_gatt(acl.handle(), pdu)?;
+                            self.connections.post_gatt(acl.handle(), AttBearer::Unenhanced, pdu)?;
                         }
                         Ok(att::Att::Server(_)) => {
-                            if let Err(e) = self.connections.post_gatt_client(acl.handle(), pdu) {
+                            if let Err(e) = self
+                                .connections
+                                .post_gatt_client(acl.handle(), AttBearer::Unenhanced, pdu)
+                            {
                                 return Err(Error::OutOfMemory);
                             }
                         }
@@ -507,14 +773,18 @@ where
                 }
             }
             L2CAP_CID_LE_U_SIGNAL => {
-                panic!("le signalling channel was fragmented, impossible!");
+                self.channels.signal(acl.handle(), pdu.as_ref(), &self.connections)?;
             }
             L2CAP_CID_LE_U_SECURITY_MANAGER => {
                 self.connections
                     .handle_security_channel(acl.handle(), pdu, event_handler)?;
             }
             other if other >= L2CAP_CID_DYN_START => match self.channels.dispatch(header.channel, pdu) {
-                Ok(_) => {}
+                Ok(None) => {}
+                #[cfg(feature = "gatt")]
+                Ok(Some((index, sdu))) => self.handle_att_bearer(acl.handle(), index, sdu)?,
+                #[cfg(not(feature = "gatt"))]
+                Ok(Some(_)) => {}
                 Err(e) => {
                     warn!("Error dispatching l2cap packet to channel: {:?}", e);
                     return Err(e);
@@ -532,6 +802,27 @@ where
         Ok(())
     }
 
+    // Route an ATT PDU received on an Enhanced ATT bearer like the ones of the fixed ATT channel.
+    #[cfg(feature = "gatt")]
+    fn handle_att_bearer(&self, handle: ConnHandle, index: ChannelIndex, pdu: Pdu<P::Packet>) -> Result<(), Error> {
+        self.channels.att_flow_control(index, &self.connections)?;
+        let bearer = AttBearer::Enhanced {
+            index,
+            mtu: self.channels.mtu(index),
+        };
+        match att::Att::decode(pdu.as_ref()) {
+            // The MTU of an enhanced bearer is the one of its channel, and it is always encrypted.
+            Ok(att::Att::Client(AttClient::Request(att::AttReq::ExchangeMtu { .. })))
+            | Ok(att::Att::Client(AttClient::Command(att::AttCmd::SignedWrite { .. }))) => {
+                warn!("[host] dropping ATT PDU not allowed on an enhanced bearer");
+            }
+            Ok(att::Att::Client(_)) => self.connections.post_gatt(handle, bearer, pdu)?,
+            Ok(att::Att::Server(_)) => self.connections.post_gatt_client(handle, bearer, pdu)?,
+            Err(e) => warn!("Error decoding attribute payload: {:?}", e),
+        }
+        Ok(())
+    }
+
     // Send l2cap signal payload
     pub(crate) async fn l2cap_signal<D: L2capSignal>(
         &self,
@@ -723,6 +1014,12 @@ impl<'d, C: Controller, P: PacketPool> Runner<'d, C, P> {
             + ControllerCmdSync<LeReadBufferSize>
             + ControllerCmdSync<LeLongTermKeyRequestReply>
             + ControllerCmdAsync<LeEnableEncryption>
+            + ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetPrivacyMode>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<ReadBdAddr>,
     {
         let dummy = DummyHandler;
@@ -751,6 +1048,12 @@ impl<'d, C: Controller, P: PacketPool> Runner<'d, C, P> {
             + ControllerCmdSync<LeReadBufferSize>
             + ControllerCmdSync<LeLongTermKeyRequestReply>
             + ControllerCmdAsync<LeEnableEncryption>
+            + ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetPrivacyMode>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<ReadBdAddr>,
     {
         let control_fut = self.control.run();
@@ -843,6 +1146,7 @@ impl<'d, C: Controller, P: PacketPool> RxRunner<'d, C, P> {
                                         e.handle,
                                         e.peer_addr_kind,
                                         e.peer_addr,
+                                        None,
                                         e.role,
                                     ) {
                                         let _ = host
@@ -856,11 +1160,21 @@ impl<'d, C: Controller, P: PacketPool> RxRunner<'d, C, P> {
                                 }
                                 LeEventKind::LeEnhancedConnectionComplete => {
                                     let e = unwrap!(LeEnhancedConnectionComplete::from_hci_bytes_complete(event.data));
+                                    // If the controller resolved the peer address, the peer address is its identity
+                                    let (peer_addr_kind, peer_resolvable_address) =
+                                        if e.peer_addr_kind == AddrKind::RESOLVABLE_PRIVATE_OR_PUBLIC {
+                                            (AddrKind::PUBLIC, Some(e.peer_resolvable_private_addr))
+                                        } else if e.peer_addr_kind == AddrKind::RESOLVABLE_PRIVATE_OR_RANDOM {
+                                            (AddrKind::RANDOM, Some(e.peer_resolvable_private_addr))
+                                        } else {
+                                            (e.peer_addr_kind, None)
+                                        };
                                     if !host.handle_connection(
                                         e.status,
                                         e.handle,
-                                        e.peer_addr_kind,
+                                        peer_addr_kind,
                                         e.peer_addr,
+                                        peer_resolvable_address,
                                         e.role,
                                     ) {
                                         let _ = host
@@ -1047,6 +1361,12 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
             + ControllerCmdSync<LeReadBufferSize>
             + ControllerCmdSync<LeLongTermKeyRequestReply>
             + ControllerCmdAsync<LeEnableEncryption>
+            + ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetPrivacyMode>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<ReadBdAddr>,
     {
         let host = &self.stack.host;
@@ -1142,10 +1462,25 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
             }
         }
 
+        #[cfg(feature = "security")]
+        host.update_resolving_list().await?;
+
         loop {
-            match select3(
+            match select4(
                 poll_fn(|cx| host.connections.poll_disconnecting(Some(cx))),
                 poll_fn(|cx| host.channels.poll_disconnecting(Some(cx))),
+                select3(
+                    poll_fn(|cx| host.channels.poll_reconfigure_response(Some(cx))),
+                    host.channels.signal_timeout(),
+                    #[cfg(feature = "security")]
+                    {
+                        host.advertising_address_expired()
+                    },
+                    #[cfg(not(feature = "security"))]
+                    {
+                        poll_fn(|cx| Poll::<bool>::Pending)
+                    },
+                ),
                 select4(
                     poll_fn(|cx| host.connect_command_state.poll_cancelled(cx)),
                     poll_fn(|cx| host.advertise_command_state.poll_cancelled(cx)),
@@ -1162,7 +1497,7 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
             )
             .await
             {
-                Either3::First(request) => {
+                Either4::First(request) => {
                     trace!("[host] poll disconnecting links");
                     match host.command(Disconnect::new(request.handle(), request.reason())).await {
                         Ok(_) => {}
@@ -1173,7 +1508,7 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
                     }
                     request.confirm();
                 }
-                Either3::Second(request) => {
+                Either4::Second(request) => {
                     trace!("[host] poll disconnecting channels");
                     match request.send(host).await {
                         Ok(_) => {}
@@ -1185,7 +1520,29 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
                     }
                     request.confirm();
                 }
-                Either3::Third(states) => match states {
+                Either4::Third(Either3::First(response)) => {
+                    trace!("[host] poll reconfigure responses");
+                    match response.send(host).await {
+                        Ok(_) => {}
+                        Err(BleHostError::BleHost(Error::Hci(bt_hci::param::Error::UNKNOWN_CONN_IDENTIFIER))) => {}
+                        Err(e) => {
+                            return Err(e);
+                        }
+                    }
+                }
+                Either4::Third(Either3::Second(handle)) => {
+                    warn!("[host] no response to l2cap signal from {:?}, disconnecting", handle);
+                    host.connections
+                        .request_handle_disconnect(handle, DisconnectReason::RemoteUserTerminatedConn);
+                }
+                Either4::Third(Either3::Third(extended)) => {
+                    #[cfg(feature = "security")]
+                    {
+                        trace!("[host] rotating advertising address");
+                        host.rotate_advertising_address(extended).await?;
+                    }
+                }
+                Either4::Fourth(states) => match states {
                     Either4::First(_) => {
                         trace!("[host] cancel connection create");
                         // trace!("[host] cancelling create connection");
@@ -1194,6 +1551,8 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
                         }
                         // Signal to ensure no one is stuck
                         host.connect_command_state.canceled();
+                        #[cfg(feature = "security")]
+                        host.update_resolving_list().await?;
                     }
                     Either4::Second(ext) => {
                         trace!("[host] disabling advertising");
@@ -1202,7 +1561,10 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
                         } else {
                             host.command(LeSetAdvEnable::new(false)).await?
                         }
+                        host.advertise_state.reset();
                         host.advertise_command_state.canceled();
+                        #[cfg(feature = "security")]
+                        host.update_resolving_list().await?;
                     }
                     Either4::Third(ext) => {
                         trace!("[host] disabling scanning");
@@ -1219,6 +1581,8 @@ impl<'d, C: Controller, P: PacketPool> ControlRunner<'d, C, P> {
                             host.command(LeSetScanEnable::new(false, false)).await?;
                         }
                         host.scan_command_state.canceled();
+                        #[cfg(feature = "security")]
+                        host.update_resolving_list().await?;
                     }
                     Either4::Fourth(request) => {
                         #[cfg(feature = "security")]
@@ -1238,6 +1602,16 @@ impl<'d, C: Controller, P: PacketPool> TxRunner<'d, C, P> {
     pub async fn run(&mut self) -> Result<(), BleHostError<C::Error>> {
         let host = &self.stack.host;
         let params = host.initialized.get().await;
+        // PDUs of the Enhanced ATT bearers may have to wait for credits, which must not hold up other traffic.
+        #[cfg(feature = "gatt")]
+        match select(Self::send_outbound(host), Self::send_att_outbound(host)).await {
+            Either::First(result) | Either::Second(result) => result,
+        }
+        #[cfg(not(feature = "gatt"))]
+        Self::send_outbound(host).await
+    }
+
+    async fn send_outbound(host: &BleHost<'d, C, P>) -> Result<(), BleHostError<C::Error>> {
         loop {
             let (conn, pdu) = host.connections.outbound().await;
             match host.l2cap(conn, pdu.len() as u16, 1).await {
@@ -1260,6 +1634,32 @@ impl<'d, C: Controller, P: PacketPool> TxRunner<'d, C, P> {
             }
         }
     }
+
+    #[cfg(feature = "gatt")]
+    async fn send_att_outbound(host: &BleHost<'d, C, P>) -> Result<(), BleHostError<C::Error>> {
+        loop {
+            let (index, pdu) = host.connections.att_outbound().await;
+            let Some(mut p_buf) = P::allocate() else {
+                warn!("[host] no memory for sending on an enhanced ATT bearer");
+                continue;
+            };
+            // The basic L2CAP header is replaced by the ones of the K-frames.
+            match host
+                .channels
+                .send(index, &pdu.as_ref()[4..], p_buf.as_mut(), host)
+                .await
+            {
+                Ok(()) => {}
+                Err(BleHostError::BleHost(e)) => {
+                    warn!("[host] unable to send on an enhanced ATT bearer (ignored): {:?}", e);
+                }
+                Err(e) => {
+                    warn!("[host] error sending on an enhanced ATT bearer");
+                    return Err(e);
+                }
+            }
+        }
+    }
 }
 
 pub struct L2capSender<'a, 'd, T: Controller, P> {
@@ -1342,3 +1742,490 @@ impl<F: FnOnce()> Drop for OnDrop<F> {
         unsafe { self.f.as_ptr().read()() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use bt_hci::cmd::Cmd;
+    use embassy_futures::block_on;
+    #[cfg(feature = "security")]
+    use rand_chacha::{ChaCha12Core, ChaCha12Rng};
+    #[cfg(feature = "security")]
+    use rand_core::SeedableRng;
+
+    use super::*;
+    use crate::connection::Connection;
+    use crate::mock_controller::MockController;
+    use crate::prelude::DefaultPacketPool;
+    use crate::HostResources;
+
+    #[test]
+    fn runner_with_mock_controller() {
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let stack = crate::new(MockController::new(), &mut resources);
+        #[cfg(feature = "security")]
+        let stack = {
+            let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+            stack.set_random_generator_seed(&mut rng)
+        };
+        let crate::Host { mut runner, .. } = stack.build();
+        let controller = &stack.host.controller;
+        let conn = ConnHandle::new(1);
+
+        let test = async {
+            controller.wait_command::<ReadBdAddr>().await;
+            assert_eq!(controller.commands()[0].opcode, Reset::OPCODE);
+            assert_eq!(controller.sent::<HostBufferSize>().unwrap(), [255, 0, 0, 1, 0, 0, 0]);
+
+            controller.connection_complete(conn, LeConnRole::Peripheral, AddrKind::PUBLIC, BdAddr::new([1; 6]));
+            let connection = stack.host.connections.accept(LeConnRole::Peripheral, &[]).await;
+            assert_eq!(connection.handle(), conn);
+
+            // MTU exchange is answered by the host itself.
+            controller.acl(conn, &[3, 0, 4, 0, 0x02, 0x00, 0x02]);
+            let (handle, data) = controller.next_acl().await;
+            assert_eq!(handle, conn);
+            assert_eq!(data[..5], [3, 0, 4, 0, 0x03]);
+            controller.number_of_completed_packets(conn, 1);
+
+            // So are echo requests on the signaling channel.
+            controller.acl(conn, &[6, 0, 5, 0, 0x08, 1, 2, 0, 0xAB, 0xCD]);
+            let (_, data) = controller.next_acl().await;
+            assert_eq!(data, [6, 0, 5, 0, 0x09, 1, 2, 0, 0xAB, 0xCD]);
+            controller.number_of_completed_packets(conn, 1);
+
+            controller.disconnection_complete(conn, DisconnectReason::RemoteUserTerminatedConn);
+            poll_fn(|cx| {
+                if stack.host.connections.is_handle_connected(conn) {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+            .await;
+        };
+
+        match block_on(select(runner.run(), test)) {
+            Either::First(result) => panic!("runner stopped: {:?}", result),
+            Either::Second(()) => {}
+        }
+    }
+
+    /// Public addresses the mock controllers of a central and a peripheral stack report.
+    const CENTRAL_ADDR: [u8; 6] = [0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
+    const PERIPHERAL_ADDR: [u8; 6] = [0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f];
+
+    /// Build a stack whose controller reports `addr` as its public address.
+    fn mock_stack<'d>(
+        addr: [u8; 6],
+        resources: &'d mut HostResources<DefaultPacketPool, 1, 1>,
+    ) -> Stack<'d, MockController, DefaultPacketPool> {
+        let controller = MockController::new();
+        controller.respond::<ReadBdAddr>(Status::SUCCESS, &addr);
+        let stack = crate::new(controller, resources);
+        #[cfg(feature = "security")]
+        let stack = {
+            let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(addr[0].into()).into();
+            stack.set_random_generator_seed(&mut rng)
+        };
+        stack
+    }
+
+    /// Connect a central and a peripheral stack on `handle`, returning the connection on each side.
+    async fn connect<'d>(
+        central: &'d Stack<'d, MockController, DefaultPacketPool>,
+        peripheral: &'d Stack<'d, MockController, DefaultPacketPool>,
+        handle: ConnHandle,
+    ) -> (Connection<'d, DefaultPacketPool>, Connection<'d, DefaultPacketPool>) {
+        // The hosts are initialized once they read their address.
+        central.host.controller.wait_command::<ReadBdAddr>().await;
+        peripheral.host.controller.wait_command::<ReadBdAddr>().await;
+        central.host.controller.connection_complete(
+            handle,
+            LeConnRole::Central,
+            AddrKind::PUBLIC,
+            BdAddr::new(PERIPHERAL_ADDR),
+        );
+        peripheral.host.controller.connection_complete(
+            handle,
+            LeConnRole::Peripheral,
+            AddrKind::PUBLIC,
+            BdAddr::new(CENTRAL_ADDR),
+        );
+        let central = central.host.connections.accept(LeConnRole::Central, &[]).await;
+        let peripheral = peripheral.host.connections.accept(LeConnRole::Peripheral, &[]).await;
+        (central, peripheral)
+    }
+
+    /// Deliver the ACL data written by each host to the other one, as if their controllers were linked.
+    async fn bridge(a: &MockController, b: &MockController) {
+        loop {
+            let (from, to, (handle, data)) = match select(a.next_acl(), b.next_acl()).await {
+                Either::First(acl) => (a, b, acl),
+                Either::Second(acl) => (b, a, acl),
+            };
+            from.number_of_completed_packets(handle, 1);
+            to.acl(handle, &data);
+        }
+    }
+
+    #[cfg(feature = "gatt")]
+    #[test]
+    fn gatt_read_write_with_mock_controllers() {
+        use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+        use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+        use crate::attribute_server::AttributeServer;
+        use crate::gatt::{GattClient, GattConnectionEvent, GattEvent};
+
+        let mut central_resources = HostResources::new();
+        let mut peripheral_resources = HostResources::new();
+        let central = mock_stack(CENTRAL_ADDR, &mut central_resources);
+        let peripheral = mock_stack(PERIPHERAL_ADDR, &mut peripheral_resources);
+        let crate::Host {
+            runner: mut central_runner,
+            ..
+        } = central.build();
+        let crate::Host {
+            runner: mut peripheral_runner,
+            ..
+        } = peripheral.build();
+
+        let mut short_store = [0; 4];
+        let mut long_store = [0; 400];
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        let mut service = table.add_service(Service::new(0x180fu16));
+        let short: Characteristic<[u8; 4]> = service
+            .add_characteristic(
+                0x2a19u16,
+                &[CharacteristicProp::Read, CharacteristicProp::Write],
+                [1, 2, 3, 4],
+                &mut short_store[..],
+            )
+            .build();
+        let long: Characteristic<[u8; 400]> = service
+            .add_characteristic(
+                0x2a1au16,
+                &[CharacteristicProp::Read, CharacteristicProp::Write],
+                [0; 400],
+                &mut long_store[..],
+            )
+            .build();
+        service.build();
+        let server = AttributeServer::<NoopRawMutex, DefaultPacketPool, 8, 1, 1>::new(table);
+        let written: [u8; 400] = core::array::from_fn(|i| i as u8);
+
+        let test = async {
+            let (central_conn, peripheral_conn) = connect(&central, &peripheral, ConnHandle::new(1)).await;
+            let gatt = unwrap!(peripheral_conn.with_attribute_server(&server));
+            let serve = async {
+                loop {
+                    if let GattConnectionEvent::Gatt { event } = gatt.next().await {
+                        // The prepared writes are reported as a single write of the full value.
+                        if let GattEvent::Write(write) = &event {
+                            if write.handle() == long.handle {
+                                assert_eq!(write.data(), written);
+                            }
+                        }
+                        unwrap!(event.accept()).send().await;
+                    }
+                }
+            };
+
+            let client = unwrap!(GattClient::<_, _, 4>::new(&central, &central_conn).await);
+            let operations = async {
+                let mut value = [0; 400];
+                let len = unwrap!(client.read_characteristic(&short, &mut value).await);
+                assert_eq!(value[..len], [1, 2, 3, 4]);
+
+                unwrap!(client.write_characteristic(&short, &[5, 6, 7, 8]).await);
+                let len = unwrap!(client.read_characteristic(&short, &mut value).await);
+                assert_eq!(value[..len], [5, 6, 7, 8]);
+                assert_eq!(unwrap!(short.get(&server)), [5, 6, 7, 8]);
+
+                // The value doesn't fit in a write request with the 247 byte ATT MTU, so it is written with
+                // prepared writes.
+                unwrap!(client.write_characteristic_long(&long, &written).await);
+                assert_eq!(unwrap!(long.get(&server)), written);
+                let len = unwrap!(client.read_characteristic_long(&long, &mut value).await);
+                assert_eq!(value[..len], written);
+            };
+
+            match select4(
+                bridge(&central.host.controller, &peripheral.host.controller),
+                serve,
+                client.task(),
+                operations,
+            )
+            .await
+            {
+                Either4::Third(result) => panic!("client stopped: {:?}", result),
+                Either4::Fourth(()) => {}
+                _ => unreachable!(),
+            }
+        };
+
+        match block_on(select3(central_runner.run(), peripheral_runner.run(), test)) {
+            Either3::First(result) | Either3::Second(result) => panic!("runner stopped: {:?}", result),
+            Either3::Third(()) => {}
+        }
+    }
+
+    #[cfg(feature = "gatt")]
+    #[test]
+    fn prepared_writes_to_several_characteristics() {
+        use core::cell::RefCell;
+
+        use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+        use crate::att::AttErrorCode;
+        use crate::attribute::{AttributeTable, Characteristic, CharacteristicProp, Service};
+        use crate::attribute_server::AttributeServer;
+        use crate::gatt::{GattConnectionEvent, GattEvent};
+
+        let handle = ConnHandle::new(1);
+        let mut resources = HostResources::new();
+        let stack = mock_stack(PERIPHERAL_ADDR, &mut resources);
+        let crate::Host { mut runner, .. } = stack.build();
+        let controller = &stack.host.controller;
+
+        let mut first_store = [0; 4];
+        let mut second_store = [0; 8];
+        let mut table: AttributeTable<'_, NoopRawMutex, 8> = AttributeTable::new();
+        let mut service = table.add_service(Service::new(0x180fu16));
+        let props = [CharacteristicProp::Read, CharacteristicProp::Write];
+        let first: Characteristic<[u8; 4]> = service
+            .add_characteristic(0x2a19u16, &props, [0; 4], &mut first_store[..])
+            .build();
+        let second: Characteristic<[u8; 8]> = service
+            .add_characteristic(0x2a1au16, &props, [0; 8], &mut second_store[..])
+            .build();
+        service.build();
+        let server = AttributeServer::<NoopRawMutex, DefaultPacketPool, 8, 1, 1>::new(table);
+
+        // Play the client by writing ATT PDUs to the peripheral, checking the responses.
+        let request = |pdu: &[u8]| {
+            let mut data = std::vec![pdu.len() as u8, 0, 4, 0];
+            data.extend_from_slice(pdu);
+            controller.acl(handle, &data);
+        };
+        let expect = async |pdu: &[u8]| {
+            let (_, data) = controller.next_acl().await;
+            controller.number_of_completed_packets(handle, 1);
+            assert_eq!(data[4..], *pdu);
+        };
+        let prepare = async |characteristic: u16, offset: u16, value: &[u8]| {
+            let mut pdu = std::vec![0x16];
+            pdu.extend_from_slice(&characteristic.to_le_bytes());
+            pdu.extend_from_slice(&offset.to_le_bytes());
+            pdu.extend_from_slice(value);
+            request(&pdu);
+            pdu[0] = 0x17;
+            expect(&pdu).await;
+        };
+
+        let writes = RefCell::new(std::vec::Vec::new());
+        let test = async {
+            controller.wait_command::<ReadBdAddr>().await;
+            controller.connection_complete(
+                handle,
+                LeConnRole::Peripheral,
+                AddrKind::PUBLIC,
+                BdAddr::new(CENTRAL_ADDR),
+            );
+            let connection = stack.host.connections.accept(LeConnRole::Peripheral, &[]).await;
+            let gatt = unwrap!(connection.with_attribute_server(&server));
+            let serve = async {
+                loop {
+                    if let GattConnectionEvent::Gatt { event } = gatt.next().await {
+                        let reply = match &event {
+                            GattEvent::Write(write) => {
+                                writes.borrow_mut().push((write.handle(), write.data().to_vec()));
+                                if write.data()[0] == 0xff {
+                                    event.reject(AttErrorCode::VALUE_NOT_ALLOWED)
+                                } else {
+                                    event.accept()
+                                }
+                            }
+                            _ => event.accept(),
+                        };
+                        unwrap!(reply).send().await;
+                    }
+                }
+            };
+
+            let client = async {
+                // Each characteristic is reported with its combined value, and both are written once executed.
+                prepare(first.handle, 0, &[1, 2]).await;
+                prepare(second.handle, 0, &[3; 8]).await;
+                prepare(first.handle, 2, &[4, 5]).await;
+                request(&[0x18, 0x01]);
+                expect(&[0x19]).await;
+                assert_eq!(
+                    writes.take(),
+                    [(first.handle, std::vec![1, 2, 4, 5]), (second.handle, std::vec![3; 8])]
+                );
+                assert_eq!(unwrap!(first.get(&server)), [1, 2, 4, 5]);
+                assert_eq!(unwrap!(second.get(&server)), [3; 8]);
+
+                // Rejecting one of them fails the execution, and nothing is written.
+                prepare(first.handle, 0, &[0xff; 4]).await;
+                prepare(second.handle, 0, &[6; 8]).await;
+                request(&[0x18, 0x01]);
+                let [lo, hi] = first.handle.to_le_bytes();
+                expect(&[0x01, 0x18, lo, hi, 0x13]).await;
+                assert_eq!(writes.take(), [(first.handle, std::vec![0xff; 4])]);
+                assert_eq!(unwrap!(first.get(&server)), [1, 2, 4, 5]);
+                assert_eq!(unwrap!(second.get(&server)), [3; 8]);
+            };
+
+            select(serve, client).await;
+        };
+
+        match block_on(select(runner.run(), test)) {
+            Either::First(result) => panic!("runner stopped: {:?}", result),
+            Either::Second(_) => {}
+        }
+    }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn pairing_with_mock_controllers() {
+        use embassy_futures::join::join;
+
+        use crate::connection::SecurityLevel;
+
+        let mut central_resources = HostResources::new();
+        let mut peripheral_resources = HostResources::new();
+        let central = mock_stack(CENTRAL_ADDR, &mut central_resources);
+        let peripheral = mock_stack(PERIPHERAL_ADDR, &mut peripheral_resources);
+        let crate::Host {
+            runner: mut central_runner,
+            ..
+        } = central.build();
+        let crate::Host {
+            runner: mut peripheral_runner,
+            ..
+        } = peripheral.build();
+        let central_controller = &central.host.controller;
+        let peripheral_controller = &peripheral.host.controller;
+        let handle = ConnHandle::new(1);
+
+        async fn pairing_complete(connection: &Connection<'_, DefaultPacketPool>) -> SecurityLevel {
+            loop {
+                match connection.next().await {
+                    ConnectionEvent::PairingComplete { security_level, .. } => break security_level,
+                    ConnectionEvent::PairingFailed(reason) => panic!("pairing failed: {:?}", reason),
+                    _ => {}
+                }
+            }
+        }
+
+        let test = async {
+            let (central_conn, peripheral_conn) = connect(&central, &peripheral, handle).await;
+            unwrap!(central_conn.request_security());
+            // Once the keys are agreed, the central starts encryption with the LTK the peripheral hands to its
+            // controller.
+            let encrypt = async {
+                let params = central_controller.wait_command::<LeEnableEncryption>().await;
+                let ltk = &params[12..28];
+                peripheral_controller.le_event(0x05, &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+                let reply = peripheral_controller.wait_command::<LeLongTermKeyRequestReply>().await;
+                assert_eq!(reply[..2], [1, 0]);
+                assert_eq!(&reply[2..], ltk);
+                central_controller.encryption_change(handle, true);
+                peripheral_controller.encryption_change(handle, true);
+            };
+            let pairing = async {
+                let (_, (central_level, peripheral_level)) = join(
+                    encrypt,
+                    join(pairing_complete(&central_conn), pairing_complete(&peripheral_conn)),
+                )
+                .await;
+                assert_eq!(central_level, SecurityLevel::Encrypted);
+                assert_eq!(peripheral_level, SecurityLevel::Encrypted);
+                assert_eq!(unwrap!(central_conn.security_level()), SecurityLevel::Encrypted);
+                assert_eq!(unwrap!(peripheral_conn.encryption_key_size()), 16);
+            };
+            select(bridge(central_controller, peripheral_controller), pairing).await;
+        };
+
+        match block_on(select3(central_runner.run(), peripheral_runner.run(), test)) {
+            Either3::First(result) | Either3::Second(result) => panic!("runner stopped: {:?}", result),
+            Either3::Third(()) => {}
+        }
+    }
+
+    #[cfg(feature = "security")]
+    #[test]
+    fn rotate_address_while_advertising() {
+        use crate::advertise::{Advertisement, AdvertisementParameters};
+        use crate::security_manager::IdentityResolvingKey;
+
+        let mut resources: HostResources<DefaultPacketPool, 2, 2> = HostResources::new();
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        let stack = crate::new(MockController::new(), &mut resources)
+            .set_random_generator_seed(&mut rng)
+            .set_local_identity(
+                Address::random([1, 2, 3, 4, 5, 0xc6]),
+                IdentityResolvingKey::new(0x1234),
+            )
+            .set_resolvable_private_address_timeout(Duration::from_millis(50));
+        let crate::Host {
+            mut runner,
+            mut peripheral,
+            ..
+        } = stack.build();
+        let controller = &stack.host.controller;
+        let count = |opcode| {
+            controller
+                .commands()
+                .iter()
+                .filter(|command| command.opcode == opcode)
+                .count()
+        };
+
+        let test = async {
+            let advertiser = unwrap!(
+                peripheral
+                    .advertise(
+                        &AdvertisementParameters::default(),
+                        Advertisement::ConnectableScannableUndirected {
+                            adv_data: &[],
+                            scan_data: &[],
+                        },
+                    )
+                    .await
+            );
+            let first = unwrap!(controller.sent::<LeSetRandomAddr>());
+
+            // Advertising is restarted with a new address once it expires.
+            while count(LeSetRandomAddr::OPCODE) < 2 {
+                Timer::after_millis(10).await;
+            }
+            let commands = controller.commands();
+            let rotation = &commands[commands.len() - 3..];
+            assert_eq!(rotation[0].opcode, LeSetAdvEnable::OPCODE);
+            assert_eq!(rotation[0].params, [0]);
+            assert_eq!(rotation[1].opcode, LeSetRandomAddr::OPCODE);
+            assert_ne!(rotation[1].params, first);
+            assert_eq!(rotation[2].opcode, LeSetAdvEnable::OPCODE);
+            assert_eq!(rotation[2].params, [1]);
+
+            // Not once advertising stopped.
+            drop(advertiser);
+            while count(LeSetAdvEnable::OPCODE) < 4 {
+                Timer::after_millis(10).await;
+            }
+            Timer::after_millis(100).await;
+            assert_eq!(count(LeSetRandomAddr::OPCODE), 2);
+        };
+
+        match block_on(select(runner.run(), test)) {
+            Either::First(result) => panic!("runner stopped: {:?}", result),
+            Either::Second(()) => {}
+        }
+    }
+}
@@ -1,16 +1,21 @@
 //! L2CAP channels.
 use bt_hci::controller::{blocking, Controller};
+use bt_hci::param::ConnHandle;
+use heapless::Vec;
 
 pub use crate::channel_manager::CreditFlowPolicy;
 #[cfg(feature = "channel-metrics")]
 pub use crate::channel_manager::Metrics as ChannelMetrics;
 use crate::channel_manager::{ChannelIndex, ChannelManager};
-use crate::connection::Connection;
+use crate::connection::{Connection, SecurityLevel};
 use crate::pdu::Sdu;
-use crate::{BleHostError, Error, PacketPool, Stack};
+use crate::{BleHostError, Error, Identity, PacketPool, Stack};
 
 pub(crate) mod sar;
 
+/// Maximum number of channels opened by a single enhanced credit based connection request.
+pub const L2CAP_MAX_ENHANCED_CHANNELS: usize = 5;
+
 /// Handle representing an L2CAP channel.
 pub struct L2capChannel<'d, P: PacketPool> {
     index: ChannelIndex,
@@ -85,7 +90,7 @@ impl<P: PacketPool> Drop for L2capChannelReader<'_, P> {
 
 /// Configuration for an L2CAP channel.
 #[derive(Default)]
-pub struct L2capChannelConfig {
+pub struct L2capChannelConfig<'a> {
     /// Size of Service Data Unit. Defaults to packet allocator MTU-6.
     pub mtu: Option<u16>,
     /// Frame size (1 frame == 1 credit). Defaults to packet allocator MTU-4.
@@ -94,6 +99,87 @@ pub struct L2capChannelConfig {
     pub flow_policy: CreditFlowPolicy,
     /// Initial credits for connection oriented channels.
     pub initial_credits: Option<u16>,
+    /// Requirements incoming connection requests have to meet for each PSM, only used when accepting channels.
+    /// Requests for PSMs not listed only have to meet the default requirements. Defaults to no PSMs.
+    pub security: &'a [(u16, L2capSecurity)],
+}
+
+impl L2capChannelConfig<'_> {
+    /// Requirements of connection requests for `psm`.
+    pub(crate) fn security(&self, psm: u16) -> L2capSecurity {
+        self.security
+            .iter()
+            .find(|(p, _)| *p == psm)
+            .map(|(_, security)| *security)
+            .unwrap_or_default()
+    }
+}
+
+/// Security requirements of a PSM channels are accepted on.
+///
+/// Connection requests not meeting them are refused with the matching result code.
+#[derive(Clone, Copy, Debug)]
+pub struct L2capSecurity {
+    /// Minimum security level of the connection. Requests are refused with insufficient authentication below it,
+    /// or with insufficient encryption if the connection is not encrypted yet but the peer is bonded.
+    /// Defaults to [`SecurityLevel::NoEncryption`].
+    pub level: SecurityLevel,
+    /// Smallest encryption key size in bytes, checked when `level` requires encryption. Defaults to 7.
+    pub min_key_size: u8,
+    /// Decides whether the peer is authorized to open the channel, the request is refused with insufficient
+    /// authorization when this returns `false`. Defaults to `None`, authorizing every request meeting the
+    /// requirements.
+    pub authorize: Option<fn(&L2capConnectRequest) -> bool>,
+}
+
+impl Default for L2capSecurity {
+    fn default() -> Self {
+        Self {
+            level: SecurityLevel::NoEncryption,
+            min_key_size: 7,
+            authorize: None,
+        }
+    }
+}
+
+/// Incoming connection request, passed to [`L2capSecurity::authorize`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct L2capConnectRequest {
+    /// Connection the request was received on
+    pub handle: ConnHandle,
+    /// Peer identity
+    pub identity: Identity,
+    /// PSM the peer connects to
+    pub psm: u16,
+    /// Security level of the connection
+    pub security_level: SecurityLevel,
+}
+
+/// Reason a connection request was refused by the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum L2capConnectError {
+    /// No channel can be accepted on the PSM.
+    PsmNotSupported,
+    /// The peer has no resources left for the channel.
+    NoResources,
+    /// The connection has to be authenticated, pair with the peer first.
+    InsufficientAuthentication,
+    /// The peer did not authorize the channel.
+    InsufficientAuthorization,
+    /// The key encrypting the connection is too short.
+    EncryptionKeyTooShort,
+    /// The connection has to be encrypted.
+    InsufficientEncryption,
+    /// The source channel id is invalid.
+    InvalidSourceId,
+    /// The source channel id is already in use.
+    SourceIdAlreadyAllocated,
+    /// The peer does not accept the channel parameters.
+    UnacceptableParameters,
+    /// The request had invalid parameters.
+    InvalidParameters,
 }
 
 impl<'d, P: PacketPool> L2capChannel<'d, P> {
@@ -101,6 +187,10 @@ impl<'d, P: PacketPool> L2capChannel<'d, P> {
         Self { index, manager }
     }
 
+    pub(crate) fn index(&self) -> ChannelIndex {
+        self.index
+    }
+
     /// Disconnect this channel.
     pub fn disconnect(&mut self) {
         self.manager.disconnect(self.index);
@@ -176,22 +266,27 @@ impl<'d, P: PacketPool> L2capChannel<'d, P> {
     }
 
     /// Await an incoming connection request matching the list of PSM.
+    ///
+    /// Requests not meeting the [`L2capSecurity`] requirements the config has for their PSM are refused, and
+    /// the next request is awaited.
     pub async fn accept<T: Controller>(
         stack: &'d Stack<'d, T, P>,
         connection: &Connection<'_, P>,
         psm: &[u16],
-        config: &L2capChannelConfig,
+        config: &L2capChannelConfig<'_>,
     ) -> Result<Self, BleHostError<T::Error>> {
         let handle = connection.handle();
         stack.host.channels.accept(handle, psm, config, &stack.host).await
     }
 
     /// Create a new connection request with the provided PSM.
+    ///
+    /// Fails with [`Error::L2capConnectionRefused`] if the peer refuses the request.
     pub async fn create<T: Controller>(
         stack: &'d Stack<'d, T, P>,
         connection: &Connection<'_, P>,
         psm: u16,
-        config: &L2capChannelConfig,
+        config: &L2capChannelConfig<'_>,
     ) -> Result<Self, BleHostError<T::Error>> {
         stack
             .host
@@ -200,6 +295,55 @@ impl<'d, P: PacketPool> L2capChannel<'d, P> {
             .await
     }
 
+    /// Await an incoming enhanced credit based connection request matching the list of PSM.
+    ///
+    /// All channels of the request are accepted and returned in the order the peer requested them, or all of them
+    /// are refused if the request does not meet the [`L2capSecurity`] requirements the config has for its PSM.
+    pub async fn accept_group<T: Controller>(
+        stack: &'d Stack<'d, T, P>,
+        connection: &Connection<'_, P>,
+        psm: &[u16],
+        config: &L2capChannelConfig<'_>,
+    ) -> Result<Vec<Self, L2CAP_MAX_ENHANCED_CHANNELS>, BleHostError<T::Error>> {
+        let handle = connection.handle();
+        stack.host.channels.accept_group(handle, psm, config, &stack.host).await
+    }
+
+    /// Create `count` channels with the provided PSM using a single enhanced credit based connection request.
+    ///
+    /// The peer may refuse some of the channels, in which case only the accepted ones are returned.
+    /// The MTU and MPS must be at least 64 bytes.
+    pub async fn create_group<T: Controller>(
+        stack: &'d Stack<'d, T, P>,
+        connection: &Connection<'_, P>,
+        psm: u16,
+        count: usize,
+        config: &L2capChannelConfig<'_>,
+    ) -> Result<Vec<Self, L2CAP_MAX_ENHANCED_CHANNELS>, BleHostError<T::Error>> {
+        stack
+            .host
+            .channels
+            .create_group(connection.handle(), psm, count, config, &stack.host)
+            .await
+    }
+
+    /// Announce a new receive MTU and MPS for channels created or accepted as a group.
+    ///
+    /// The MTU can never be reduced, and the MPS can only be reduced when reconfiguring a single channel.
+    /// All channels must belong to the same connection.
+    pub async fn reconfigure<T: Controller>(
+        stack: &Stack<'_, T, P>,
+        channels: &[Self],
+        mtu: u16,
+        mps: u16,
+    ) -> Result<(), BleHostError<T::Error>> {
+        let mut indices: Vec<ChannelIndex, L2CAP_MAX_ENHANCED_CHANNELS> = Vec::new();
+        for channel in channels {
+            indices.push(channel.index).map_err(|_| Error::InvalidValue)?;
+        }
+        stack.host.channels.reconfigure(&indices, mtu, mps, &stack.host).await
+    }
+
     /// Split the channel into a writer and reader for concurrently
     /// writing to/reading from the channel.
     pub fn split(self) -> (L2capChannelWriter<'d, P>, L2capChannelReader<'d, P>) {
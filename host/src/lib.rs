@@ -12,16 +12,26 @@ use bt_hci::cmd::status::ReadRssi;
 use bt_hci::cmd::{AsyncCmd, SyncCmd};
 use bt_hci::param::{AddrKind, BdAddr};
 use bt_hci::FromHciBytesError;
+#[cfg(feature = "security")]
+use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_time::Duration;
 #[cfg(feature = "security")]
 use heapless::Vec;
 use rand_core::{CryptoRng, RngCore};
 
 use crate::att::AttErrorCode;
+#[cfg(feature = "security")]
+use crate::attribute_server::AttributeServer;
+#[cfg(feature = "security")]
+use crate::bond_store::{BondStore, StoredBond};
 use crate::channel_manager::ChannelStorage;
 use crate::connection_manager::ConnectionStorage;
 #[cfg(feature = "security")]
-pub use crate::security_manager::{BondInformation, IdentityResolvingKey, LongTermKey};
+pub use crate::security_manager::{
+    BondEvictionPolicy, BondInformation, BondUsage, CentralIdentification, ConnectionSignatureResolvingKey,
+    IdentityResolvingKey, KeypressNotification, LinkKey, LongTermKey, OobData, PairingRequest, SecurityPolicy,
+    SigningKey,
+};
 pub use crate::types::capabilities::IoCapabilities;
 
 /// Number of bonding information stored
@@ -33,6 +43,8 @@ mod fmt;
 compile_error!("Must enable at least one of the `central` or `peripheral` features");
 
 pub mod att;
+#[cfg(feature = "security")]
+pub mod bond_store;
 #[cfg(feature = "central")]
 pub mod central;
 mod channel_manager;
@@ -88,6 +100,8 @@ pub mod prelude {
     pub use crate::attribute::*;
     #[cfg(feature = "gatt")]
     pub use crate::attribute_server::*;
+    #[cfg(feature = "security")]
+    pub use crate::bond_store::*;
     #[cfg(feature = "central")]
     pub use crate::central::*;
     pub use crate::connection::*;
@@ -105,7 +119,11 @@ pub mod prelude {
     #[cfg(feature = "scan")]
     pub use crate::scan::*;
     #[cfg(feature = "security")]
-    pub use crate::security_manager::{BondInformation, IdentityResolvingKey, LongTermKey};
+    pub use crate::security_manager::{
+        BondEvictionPolicy, BondInformation, BondUsage, CentralIdentification, ConnectionSignatureResolvingKey,
+        IdentityResolvingKey, KeypressNotification, LinkKey, LongTermKey, OobData, PairingRequest, SecurityPolicy,
+        SigningKey,
+    };
     pub use crate::types::capabilities::IoCapabilities;
     #[cfg(feature = "gatt")]
     pub use crate::types::gatt_traits::{AsGatt, FixedGattValue, FromGatt};
@@ -293,6 +311,9 @@ pub enum Error {
     /// Unexpected GATT response.
     UnexpectedGattResponse,
 
+    /// The value echoed by the server during a reliable write did not match the value sent.
+    ReliableWriteMismatch,
+
     /// Received characteristic declaration data shorter than the minimum required length (5 bytes).
     MalformedCharacteristicDeclaration {
         /// Expected length.
@@ -337,6 +358,8 @@ pub enum Error {
     NotSupported,
     /// L2cap channel closed.
     ChannelClosed,
+    /// L2cap connection request refused by the peer.
+    L2capConnectionRefused(crate::l2cap::L2capConnectError),
     /// Operation timed out.
     Timeout,
     /// Controller is busy.
@@ -351,6 +374,9 @@ pub enum Error {
     ///
     /// The limit can be modified using the `gatt-client-notification-max-subscribers-N` features.
     GattSubscriberLimitReached,
+    #[cfg(feature = "security")]
+    /// Error accessing the bond store.
+    Storage,
     /// Other error.
     Other,
 }
@@ -446,6 +472,12 @@ pub trait Controller:
     + for<'t> ControllerCmdSync<LeSetScanResponseData>
     + ControllerCmdSync<LeLongTermKeyRequestReply>
     + ControllerCmdAsync<LeEnableEncryption>
+    + ControllerCmdSync<LeSetAddrResolutionEnable>
+    + ControllerCmdSync<LeClearResolvingList>
+    + ControllerCmdSync<LeAddDeviceToResolvingList>
+    + ControllerCmdSync<LeSetPrivacyMode>
+    + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+    + ControllerCmdSync<LeSetAdvSetRandomAddr>
     + ControllerCmdSync<ReadBdAddr>
 {
 }
@@ -481,6 +513,12 @@ impl<
             + for<'t> ControllerCmdSync<LeSetScanResponseData>
             + ControllerCmdSync<LeLongTermKeyRequestReply>
             + ControllerCmdAsync<LeEnableEncryption>
+            + ControllerCmdSync<LeSetAddrResolutionEnable>
+            + ControllerCmdSync<LeClearResolvingList>
+            + ControllerCmdSync<LeAddDeviceToResolvingList>
+            + ControllerCmdSync<LeSetPrivacyMode>
+            + ControllerCmdSync<LeSetResolvablePrivateAddrTimeout>
+            + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<ReadBdAddr>,
     > Controller for C
 {
@@ -634,6 +672,55 @@ impl<'stack, C: Controller, P: PacketPool> Stack<'stack, C, P> {
         self
     }
 
+    #[cfg(feature = "security")]
+    /// Set the requirements pairings have to meet.
+    ///
+    /// By default only LE Secure Connections pairing with 128-bit keys is accepted, without requiring
+    /// MITM protection. Disable [`SecurityPolicy::secure_connections_only`] to fall back to legacy
+    /// Just Works or Passkey Entry pairing with peers that do not support LE Secure Connections.
+    ///
+    /// Fails with [`Error::InvalidValue`] if [`SecurityPolicy::min_encryption_key_size`] is not
+    /// between 7 and 16 bytes.
+    pub fn set_security_policy(self, policy: SecurityPolicy) -> Result<Self, Error> {
+        self.host.connections.security_manager.set_policy(policy)?;
+        Ok(self)
+    }
+
+    #[cfg(feature = "security")]
+    /// Set the local identity address and identity resolving key.
+    ///
+    /// The identity is distributed to bonded peers during pairing, and a resolvable private address
+    /// generated from the IRK is used for advertising, scanning and initiating connections.
+    pub fn set_local_identity(self, address: Address, irk: IdentityResolvingKey) -> Self {
+        self.host.connections.security_manager.set_local_identity(address, irk);
+        self
+    }
+
+    #[cfg(feature = "security")]
+    /// Set which bond is evicted to make room for a new bond when the bond table is full.
+    ///
+    /// Defaults to [`BondEvictionPolicy::Refuse`], the new bond is then not stored.
+    pub fn set_bond_eviction_policy(self, policy: BondEvictionPolicy) -> Self {
+        self.host.connections.security_manager.set_eviction_policy(policy);
+        self
+    }
+
+    /// Set how long a resolvable private address is used before a new one is generated.
+    ///
+    /// Defaults to 15 minutes.
+    ///
+    /// Only relevant if the feature `security` is enabled.
+    pub fn set_resolvable_private_address_timeout(self, _timeout: Duration) -> Self {
+        #[cfg(feature = "security")]
+        {
+            self.host
+                .connections
+                .security_manager
+                .set_resolvable_private_address_timeout(_timeout);
+        }
+        self
+    }
+
     /// Build the stack.
     pub fn build(&'stack self) -> Host<'stack, C, P> {
         #[cfg(all(feature = "security", not(feature = "dev-disable-csprng-seed-requirement")))]
@@ -682,14 +769,25 @@ impl<'stack, C: Controller, P: PacketPool> Stack<'stack, C, P> {
     }
 
     #[cfg(feature = "security")]
-    /// Get bonded devices
-    pub fn add_bond_information(&self, bond_information: BondInformation) -> Result<(), Error> {
+    /// Add a bonded device
+    ///
+    /// Bonds with an identity resolving key are added to the controller resolving list, so the
+    /// controller resolves the private addresses of the peer and reports its identity address.
+    ///
+    /// If the bond table is full, a bond is evicted according to the [`BondEvictionPolicy`] and returned.
+    pub fn add_bond_information(&self, bond_information: BondInformation) -> Result<Option<BondInformation>, Error> {
         self.host
             .connections
             .security_manager
             .add_bond_information(bond_information)
     }
 
+    #[cfg(feature = "security")]
+    /// Pin a bonded device, so its bond is never evicted to make room for a new bond
+    pub fn set_bond_pinned(&self, identity: &Identity, pinned: bool) -> Result<(), Error> {
+        self.host.connections.security_manager.set_bond_pinned(identity, pinned)
+    }
+
     #[cfg(feature = "security")]
     /// Remove a bonded device
     pub fn remove_bond_information(&self, identity: Identity) -> Result<(), Error> {
@@ -701,6 +799,108 @@ impl<'stack, C: Controller, P: PacketPool> Stack<'stack, C, P> {
     pub fn get_bond_information(&self) -> Vec<BondInformation, BI_COUNT> {
         self.host.connections.security_manager.get_bond_information()
     }
+
+    #[cfg(feature = "security")]
+    /// Keep a bond store up to date with the bonds of the stack.
+    ///
+    /// The stored bonds are added to the stack first. Bonds are then saved to the store when pairing
+    /// completes or a bonded peer disconnects, and removed from it when removed from the stack. When a
+    /// bonded client reconnects, its stored CCCD values are restored in `server`.
+    ///
+    /// Runs forever. Errors accessing the store are logged, and the bond concerned is not saved,
+    /// removed or restored.
+    pub async fn run_bond_store<S, M, const ATT_MAX: usize, const CCCD_MAX: usize, const CONN_MAX: usize>(
+        &self,
+        store: &mut S,
+        server: &AttributeServer<'_, M, P, ATT_MAX, CCCD_MAX, CONN_MAX>,
+    ) where
+        S: BondStore<CCCD_MAX>,
+        M: RawMutex,
+    {
+        use core::future::poll_fn;
+
+        use crate::security_manager::BondStoreEvent;
+
+        let security_manager = &self.host.connections.security_manager;
+        let loaded = store
+            .iterate(|stored| {
+                if let Err(e) = security_manager.restore_bond(stored.bond, stored.usage) {
+                    warn!("[bond store] Unable to add stored bond: {:?}", e);
+                }
+            })
+            .await;
+        if let Err(e) = loaded {
+            warn!("[bond store] Unable to load stored bonds: {:?}", e);
+        }
+        security_manager.attach_bond_store();
+
+        loop {
+            match poll_fn(|cx| security_manager.poll_bond_store_event(cx)).await {
+                BondStoreEvent::Save(identity) => {
+                    let (Some(bond), Some(usage)) = (
+                        security_manager.get_peer_bond_information(&identity),
+                        security_manager.get_bond_usage(&identity),
+                    ) else {
+                        continue;
+                    };
+                    let cccd_table = match server.cccd_table(&identity) {
+                        Some(table) => table,
+                        None => match store.load(&identity).await {
+                            Ok(stored) => stored.map(|stored| stored.cccd_table).unwrap_or_default(),
+                            Err(e) => {
+                                warn!("[bond store] Unable to load bond {:?}: {:?}", identity, e);
+                                continue;
+                            }
+                        },
+                    };
+                    trace!("[bond store] Save bond {:?}", identity);
+                    if let Err(e) = store
+                        .save(&StoredBond {
+                            bond,
+                            usage,
+                            cccd_table,
+                        })
+                        .await
+                    {
+                        warn!("[bond store] Unable to save bond {:?}: {:?}", identity, e);
+                    }
+                }
+                BondStoreEvent::Remove(identity) => {
+                    trace!("[bond store] Remove bond {:?}", identity);
+                    if let Err(e) = store.remove(&identity).await {
+                        warn!("[bond store] Unable to remove bond {:?}: {:?}", identity, e);
+                    }
+                }
+                BondStoreEvent::Restore(identity) => match store.load(&identity).await {
+                    Ok(Some(stored)) => {
+                        trace!("[bond store] Restore CCCD values of {:?}", identity);
+                        if let Err(e) = server.restore_cccd_table(&identity, &stored.cccd_table) {
+                            warn!("[bond store] Unable to restore CCCD values: {:?}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("[bond store] Unable to load bond {:?}: {:?}", identity, e),
+                },
+            }
+        }
+    }
+
+    #[cfg(feature = "security")]
+    /// Generate the local LE Secure Connections OOB data, to be handed to a peer out of band (e.g. over NFC).
+    ///
+    /// Replaces previously generated data, a peer that received it pairs using the OOB method.
+    pub fn generate_oob_data(&self) -> OobData {
+        self.host.connections.security_manager.generate_oob_data()
+    }
+
+    #[cfg(feature = "security")]
+    /// Set the LE Secure Connections OOB data received out of band from the peer with the given address.
+    pub fn set_peer_oob_data(&self, address: Address, oob_data: OobData) {
+        self.host
+            .connections
+            .security_manager
+            .set_peer_oob_data(address, oob_data)
+    }
 }
 
 pub(crate) fn bt_hci_duration<const US: u32>(d: Duration) -> bt_hci::param::Duration<US> {
@@ -1,14 +1,225 @@
+//! In-process controller for host unit tests.
+//!
+//! The mock records every HCI command and ACL packet written by the host, answers commands with
+//! scripted (or default) return parameters, and delivers events and ACL data injected by the test
+//! to the host runner.
+extern crate std;
+
+use core::cell::RefCell;
 use core::convert::Infallible;
-use core::future::Future;
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+use std::collections::VecDeque;
+use std::vec::Vec;
 
-use bt_hci::cmd::{self, AsyncCmd, SyncCmd};
+use bt_hci::cmd::info::ReadBdAddr;
+use bt_hci::cmd::le::LeReadBufferSize;
+use bt_hci::cmd::{self, AsyncCmd, Cmd, CmdReturnBuf, Opcode, SyncCmd};
+use bt_hci::controller::blocking::TryError;
 use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
+use bt_hci::data::{AclPacket, AclPacketBoundary, IsoPacket, SyncPacket};
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, DisconnectReason, LeConnRole, Status};
+use bt_hci::{ControllerToHostPacket, FromHciBytes, PacketKind, WriteHci};
+use embassy_sync::waitqueue::WakerRegistration;
+
+/// LE ACL buffers reported to the host: 251 byte packets, 8 of them.
+const LE_BUFFER_SIZE: [u8; 3] = [251, 0, 8];
+
+/// Public address reported to the host.
+const BD_ADDR: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
 
-pub struct MockController {}
+/// A command written by the host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentCommand {
+    pub opcode: Opcode,
+    pub params: Vec<u8>,
+}
+
+struct Response {
+    opcode: Opcode,
+    status: Status,
+    params: Vec<u8>,
+}
+
+struct State {
+    commands: Vec<SentCommand>,
+    acl: VecDeque<(ConnHandle, Vec<u8>)>,
+    responses: Vec<Response>,
+    inbound: VecDeque<(PacketKind, Vec<u8>)>,
+    inbound_waker: WakerRegistration,
+    outbound_waker: WakerRegistration,
+}
+
+pub struct MockController {
+    state: RefCell<State>,
+}
 
 impl MockController {
     pub fn new() -> Self {
-        Self {}
+        let controller = Self {
+            state: RefCell::new(State {
+                commands: Vec::new(),
+                acl: VecDeque::new(),
+                responses: Vec::new(),
+                inbound: VecDeque::new(),
+                inbound_waker: WakerRegistration::new(),
+                outbound_waker: WakerRegistration::new(),
+            }),
+        };
+        controller.respond::<LeReadBufferSize>(Status::SUCCESS, &LE_BUFFER_SIZE);
+        controller.respond::<ReadBdAddr>(Status::SUCCESS, &BD_ADDR);
+        controller
+    }
+
+    /// Answer every following `C` command with `status` and the encoded return parameters.
+    ///
+    /// Commands without a scripted response succeed, returning zeroed parameters.
+    pub fn respond<C: Cmd>(&self, status: Status, params: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.responses.retain(|response| response.opcode != C::OPCODE);
+        state.responses.push(Response {
+            opcode: C::OPCODE,
+            status,
+            params: params.to_vec(),
+        });
+    }
+
+    /// Commands written by the host so far, oldest first.
+    pub fn commands(&self) -> Vec<SentCommand> {
+        self.state.borrow().commands.clone()
+    }
+
+    /// Parameters of the most recent `C` command written by the host.
+    pub fn sent<C: Cmd>(&self) -> Option<Vec<u8>> {
+        let state = self.state.borrow();
+        let command = state
+            .commands
+            .iter()
+            .rev()
+            .find(|command| command.opcode == C::OPCODE)?;
+        Some(command.params.clone())
+    }
+
+    /// Wait until the host has written a `C` command, returning its parameters.
+    pub async fn wait_command<C: Cmd>(&self) -> Vec<u8> {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            state.outbound_waker.register(cx.waker());
+            match state.commands.iter().find(|command| command.opcode == C::OPCODE) {
+                Some(command) => Poll::Ready(command.params.clone()),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait for the next ACL packet written by the host, returning its connection and payload.
+    pub async fn next_acl(&self) -> (ConnHandle, Vec<u8>) {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            state.outbound_waker.register(cx.waker());
+            match state.acl.pop_front() {
+                Some(acl) => Poll::Ready(acl),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Deliver an event with the given code and parameters to the host.
+    pub fn event(&self, code: u8, params: &[u8]) {
+        let mut packet = std::vec![code, params.len() as u8];
+        packet.extend_from_slice(params);
+        self.inject(PacketKind::Event, packet);
+    }
+
+    /// Deliver an LE meta event with the given subevent code and parameters to the host.
+    pub fn le_event(&self, subevent: u8, params: &[u8]) {
+        let mut packet = std::vec![subevent];
+        packet.extend_from_slice(params);
+        self.event(0x3E, &packet);
+    }
+
+    /// Complete a connection to `peer` in the given role.
+    pub fn connection_complete(&self, handle: ConnHandle, role: LeConnRole, peer_kind: AddrKind, peer: BdAddr) {
+        let mut params = std::vec![Status::SUCCESS.into_inner()];
+        params.extend_from_slice(&handle.raw().to_le_bytes());
+        params.push(role as u8);
+        params.push(peer_kind.into_inner());
+        params.extend_from_slice(peer.raw());
+        // 30 ms interval, no latency, 4 s supervision timeout, 500 ppm clock accuracy.
+        params.extend_from_slice(&[24, 0, 0, 0, 0x90, 0x01, 0]);
+        self.le_event(0x01, &params);
+    }
+
+    pub fn disconnection_complete(&self, handle: ConnHandle, reason: DisconnectReason) {
+        let mut params = std::vec![Status::SUCCESS.into_inner()];
+        params.extend_from_slice(&handle.raw().to_le_bytes());
+        params.push(reason as u8);
+        self.event(0x05, &params);
+    }
+
+    pub fn encryption_change(&self, handle: ConnHandle, enabled: bool) {
+        let mut params = std::vec![Status::SUCCESS.into_inner()];
+        params.extend_from_slice(&handle.raw().to_le_bytes());
+        params.push(enabled as u8);
+        self.event(0x08, &params);
+    }
+
+    pub fn number_of_completed_packets(&self, handle: ConnHandle, completed: u16) {
+        let mut params = std::vec![1];
+        params.extend_from_slice(&handle.raw().to_le_bytes());
+        params.extend_from_slice(&completed.to_le_bytes());
+        self.event(0x13, &params);
+    }
+
+    /// Deliver a complete L2CAP frame (basic header included) received on `handle` to the host.
+    pub fn acl(&self, handle: ConnHandle, data: &[u8]) {
+        let mut packet = Vec::new();
+        let header = handle.raw() | ((AclPacketBoundary::FirstFlushable as u16) << 12);
+        packet.extend_from_slice(&header.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        self.inject(PacketKind::AclData, packet);
+    }
+
+    fn inject(&self, kind: PacketKind, packet: Vec<u8>) {
+        let mut state = self.state.borrow_mut();
+        state.inbound.push_back((kind, packet));
+        state.inbound_waker.wake();
+    }
+
+    fn record_command<C: Cmd>(&self, cmd: &C) -> (Status, Vec<u8>) {
+        let mut params = [0; 255];
+        let len = cmd.params().size();
+        unwrap!(cmd.params().write_hci(&mut params[..]));
+
+        let mut state = self.state.borrow_mut();
+        state.commands.push(SentCommand {
+            opcode: C::OPCODE,
+            params: params[..len].to_vec(),
+        });
+        state.outbound_waker.wake();
+        match state.responses.iter().find(|response| response.opcode == C::OPCODE) {
+            Some(response) => (response.status, response.params.clone()),
+            None => (Status::SUCCESS, Vec::new()),
+        }
+    }
+
+    fn record_acl(&self, packet: &AclPacket) {
+        let mut state = self.state.borrow_mut();
+        state.acl.push_back((packet.handle(), packet.data().to_vec()));
+        state.outbound_waker.wake();
+    }
+
+    fn try_read_packet<'a>(&self, buf: &'a mut [u8]) -> Option<ControllerToHostPacket<'a>> {
+        let (kind, packet) = self.state.borrow_mut().inbound.pop_front()?;
+        buf[..packet.len()].copy_from_slice(&packet);
+        let (packet, _) = unwrap!(ControllerToHostPacket::from_hci_bytes_with_kind(
+            kind,
+            &buf[..packet.len()]
+        ));
+        Some(packet)
     }
 }
 
@@ -17,80 +228,87 @@ impl embedded_io::ErrorType for MockController {
 }
 
 impl bt_hci::controller::blocking::Controller for MockController {
-    fn write_acl_data(&self, packet: &bt_hci::data::AclPacket) -> Result<(), Self::Error> {
-        todo!()
+    fn write_acl_data(&self, packet: &AclPacket) -> Result<(), Self::Error> {
+        self.record_acl(packet);
+        Ok(())
     }
 
-    fn write_sync_data(&self, packet: &bt_hci::data::SyncPacket) -> Result<(), Self::Error> {
-        todo!()
+    fn write_sync_data(&self, packet: &SyncPacket) -> Result<(), Self::Error> {
+        Ok(())
     }
 
-    fn write_iso_data(&self, packet: &bt_hci::data::IsoPacket) -> Result<(), Self::Error> {
-        todo!()
+    fn write_iso_data(&self, packet: &IsoPacket) -> Result<(), Self::Error> {
+        Ok(())
     }
 
-    fn try_write_acl_data(
-        &self,
-        packet: &bt_hci::data::AclPacket,
-    ) -> Result<(), bt_hci::controller::blocking::TryError<Self::Error>> {
-        todo!()
+    fn try_write_acl_data(&self, packet: &AclPacket) -> Result<(), TryError<Self::Error>> {
+        self.record_acl(packet);
+        Ok(())
     }
 
-    fn try_write_sync_data(
-        &self,
-        packet: &bt_hci::data::SyncPacket,
-    ) -> Result<(), bt_hci::controller::blocking::TryError<Self::Error>> {
-        todo!()
+    fn try_write_sync_data(&self, packet: &SyncPacket) -> Result<(), TryError<Self::Error>> {
+        Ok(())
     }
 
-    fn try_write_iso_data(
-        &self,
-        packet: &bt_hci::data::IsoPacket,
-    ) -> Result<(), bt_hci::controller::blocking::TryError<Self::Error>> {
-        todo!()
+    fn try_write_iso_data(&self, packet: &IsoPacket) -> Result<(), TryError<Self::Error>> {
+        Ok(())
     }
 
-    fn read<'a>(&self, buf: &'a mut [u8]) -> Result<bt_hci::ControllerToHostPacket<'a>, Self::Error> {
-        todo!()
+    fn read<'a>(&self, buf: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        // There is no one else to inject packets while blocking.
+        Ok(unwrap!(self.try_read_packet(buf), "no packets injected"))
     }
 
-    fn try_read<'a>(
-        &self,
-        buf: &'a mut [u8],
-    ) -> Result<bt_hci::ControllerToHostPacket<'a>, bt_hci::controller::blocking::TryError<Self::Error>> {
-        todo!()
+    fn try_read<'a>(&self, buf: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, TryError<Self::Error>> {
+        self.try_read_packet(buf).ok_or(TryError::Busy)
     }
 }
 
 impl bt_hci::controller::Controller for MockController {
-    fn write_acl_data(&self, packet: &bt_hci::data::AclPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_acl_data(&self, packet: &AclPacket<'_>) -> Result<(), Self::Error> {
+        self.record_acl(packet);
+        Ok(())
     }
 
-    fn write_sync_data(&self, packet: &bt_hci::data::SyncPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_sync_data(&self, packet: &SyncPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
     }
 
-    fn write_iso_data(&self, packet: &bt_hci::data::IsoPacket) -> impl Future<Output = Result<(), Self::Error>> {
-        async { todo!() }
+    async fn write_iso_data(&self, packet: &IsoPacket<'_>) -> Result<(), Self::Error> {
+        Ok(())
     }
 
-    fn read<'a>(
-        &self,
-        buf: &'a mut [u8],
-    ) -> impl Future<Output = Result<bt_hci::ControllerToHostPacket<'a>, Self::Error>> {
-        async { todo!() }
+    async fn read<'a>(&self, buf: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            state.inbound_waker.register(cx.waker());
+            if state.inbound.is_empty() {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await;
+        Ok(unwrap!(self.try_read_packet(buf)))
     }
 }
 
 impl<C: SyncCmd> ControllerCmdSync<C> for MockController {
     fn exec(&self, cmd: &C) -> impl Future<Output = Result<C::Return, cmd::Error<Self::Error>>> {
-        async { todo!() }
+        let (status, mut params) = self.record_command(cmd);
+        async move {
+            status.to_result().map_err(cmd::Error::Hci)?;
+            params.resize(params.len().max(C::ReturnBuf::LEN), 0);
+            let (ret, _) = C::Return::from_hci_bytes(&params)
+                .map_err(|_| cmd::Error::Hci(bt_hci::param::Error::INVALID_HCI_PARAMETERS))?;
+            Ok(ret)
+        }
     }
 }
 
 impl<C: AsyncCmd> ControllerCmdAsync<C> for MockController {
     fn exec(&self, cmd: &C) -> impl Future<Output = Result<(), cmd::Error<Self::Error>>> {
-        async { todo!() }
+        let (status, _) = self.record_command(cmd);
+        async move { status.to_result().map_err(cmd::Error::Hci) }
     }
 }
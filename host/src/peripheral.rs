@@ -4,7 +4,7 @@ use core::task::Poll;
 use bt_hci::cmd::le::{
     LeClearAdvSets, LeReadNumberOfSupportedAdvSets, LeSetAdvData, LeSetAdvEnable, LeSetAdvParams,
     LeSetAdvSetRandomAddr, LeSetExtAdvData, LeSetExtAdvEnable, LeSetExtAdvParams, LeSetExtScanResponseData,
-    LeSetScanResponseData,
+    LeSetRandomAddr, LeSetScanResponseData,
 };
 use bt_hci::controller::{Controller, ControllerCmdSync};
 use bt_hci::param::{AddrKind, AdvChannelMap, AdvHandle, AdvKind, AdvSet, BdAddr, LeConnRole, Operation};
@@ -34,7 +34,8 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
         C: for<'t> ControllerCmdSync<LeSetAdvData>
             + ControllerCmdSync<LeSetAdvParams>
             + for<'t> ControllerCmdSync<LeSetAdvEnable>
-            + for<'t> ControllerCmdSync<LeSetScanResponseData>,
+            + for<'t> ControllerCmdSync<LeSetScanResponseData>
+            + ControllerCmdSync<LeSetRandomAddr>,
     {
         let host = &self.stack.host;
 
@@ -43,6 +44,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
             host.advertise_command_state.cancel(false);
         });
         host.advertise_command_state.request().await;
+        host.update_random_address().await?;
 
         // Clear current advertising terminations
         host.advertise_state.reset();
@@ -72,7 +74,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
             bt_hci_duration(params.interval_min),
             bt_hci_duration(params.interval_max),
             kind,
-            host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_address().map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
             peer.kind,
             peer.addr,
             params.channel_map.unwrap_or(AdvChannelMap::ALL),
@@ -101,7 +103,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
         }];
 
         trace!("[host] enabling advertising");
-        host.advertise_state.start(&advset[..]);
+        host.advertise_state.start(&advset[..], false);
         host.command(LeSetAdvEnable::new(true)).await?;
         drop.defuse();
         Ok(Advertiser {
@@ -161,7 +163,8 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
             + ControllerCmdSync<LeSetAdvSetRandomAddr>
             + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
             + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
-            + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>,
+            + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>
+            + ControllerCmdSync<LeSetRandomAddr>,
     {
         assert_eq!(sets.len(), handles.len());
         let host = &self.stack.host;
@@ -178,6 +181,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
             host.advertise_command_state.cancel(true);
         });
         host.advertise_command_state.request().await;
+        host.update_random_address().await?;
 
         // Clear current advertising terminations
         host.advertise_state.reset();
@@ -196,7 +200,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
                 bt_hci_ext_duration(params.interval_min),
                 bt_hci_ext_duration(params.interval_max),
                 params.channel_map.unwrap_or(AdvChannelMap::ALL),
-                host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+                host.own_address().map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
                 peer.kind,
                 peer.addr,
                 params.filter_policy,
@@ -209,7 +213,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
             ))
             .await?;
 
-            if let Some(address) = host.address.as_ref() {
+            if let Some(address) = host.own_address() {
                 host.command(LeSetAdvSetRandomAddr::new(handle, address.addr)).await?;
             }
 
@@ -238,7 +242,7 @@ impl<'d, C: Controller, P: PacketPool> Peripheral<'d, C, P> {
         }
 
         trace!("[host] enabling extended advertising");
-        host.advertise_state.start(handles);
+        host.advertise_state.start(handles, true);
         host.command(LeSetExtAdvEnable::new(true, handles)).await?;
         drop.defuse();
         Ok(Advertiser {
@@ -1,7 +1,7 @@
 //! Scan config.
 use bt_hci::cmd::le::{
-    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeSetExtScanEnable, LeSetExtScanParams, LeSetScanEnable,
-    LeSetScanParams,
+    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeSetExtScanEnable, LeSetExtScanParams, LeSetRandomAddr,
+    LeSetScanEnable, LeSetScanParams,
 };
 use bt_hci::controller::{Controller, ControllerCmdSync};
 use bt_hci::param::{AddrKind, FilterDuplicates, ScanningPhy};
@@ -40,13 +40,15 @@ impl<'d, C: Controller, P: PacketPool> Scanner<'d, C, P> {
         C: ControllerCmdSync<LeSetExtScanEnable>
             + ControllerCmdSync<LeSetExtScanParams>
             + ControllerCmdSync<LeClearFilterAcceptList>
-            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>,
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetRandomAddr>,
     {
         let host = &self.central.stack.host;
         let drop = crate::host::OnDrop::new(|| {
             host.scan_command_state.cancel(true);
         });
         host.scan_command_state.request().await;
+        host.update_random_address().await?;
         self.central.set_accept_filter(config.filter_accept_list).await?;
 
         let scanning = ScanningPhy {
@@ -57,7 +59,7 @@ impl<'d, C: Controller, P: PacketPool> Scanner<'d, C, P> {
         let phy_params = crate::central::create_phy_params(scanning, config.phys);
         let host = &self.central.stack.host;
         host.command(LeSetExtScanParams::new(
-            host.address.map(|s| s.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_address().map(|s| s.kind).unwrap_or(AddrKind::PUBLIC),
             if config.filter_accept_list.is_empty() {
                 bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
             } else {
@@ -94,13 +96,15 @@ impl<'d, C: Controller, P: PacketPool> Scanner<'d, C, P> {
         C: ControllerCmdSync<LeSetScanParams>
             + ControllerCmdSync<LeSetScanEnable>
             + ControllerCmdSync<LeClearFilterAcceptList>
-            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>,
+            + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+            + ControllerCmdSync<LeSetRandomAddr>,
     {
         let host = &self.central.stack.host;
         let drop = crate::host::OnDrop::new(|| {
             host.scan_command_state.cancel(false);
         });
         host.scan_command_state.request().await;
+        host.update_random_address().await?;
 
         self.central.set_accept_filter(config.filter_accept_list).await?;
 
@@ -112,7 +116,7 @@ impl<'d, C: Controller, P: PacketPool> Scanner<'d, C, P> {
             },
             bt_hci_duration(config.interval),
             bt_hci_duration(config.window),
-            host.address.map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
+            host.own_address().map(|a| a.kind).unwrap_or(AddrKind::PUBLIC),
             if config.filter_accept_list.is_empty() {
                 bt_hci::param::ScanningFilterPolicy::BasicUnfiltered
             } else {
@@ -2,6 +2,8 @@ use embassy_time::Duration;
 
 /// 128-bit encryption key size
 pub(crate) const ENCRYPTION_KEY_SIZE_128_BITS: u8 = 128 / 8;
+/// 56-bit encryption key size, the smallest allowed
+pub(crate) const ENCRYPTION_KEY_SIZE_56_BITS: u8 = 56 / 8;
 
 /// Long duration, to disable the timer
 pub(crate) const TIMEOUT_DISABLE: Duration = Duration::from_secs(3600 * 24 * 365 * 10); // ~10 years
@@ -9,3 +11,5 @@ pub(crate) const TIMEOUT_DISABLE: Duration = Duration::from_secs(3600 * 24 * 365
 const TIMEOUT_SECS: u64 = 30;
 /// Pairing time-out
 pub(crate) const TIMEOUT: Duration = Duration::from_secs(TIMEOUT_SECS);
+/// Default time after which a new resolvable private address is generated ([Vol 3] Part C, Appendix A)
+pub(crate) const RPA_TIMEOUT: Duration = Duration::from_secs(15 * 60);
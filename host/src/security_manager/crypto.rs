@@ -10,7 +10,7 @@ use rand_core::{CryptoRng, RngCore};
 
 use crate::Address;
 
-/// LE Secure Connections Long Term Key.
+/// Long Term Key.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[must_use]
 #[repr(transparent)]
@@ -32,6 +32,27 @@ impl LongTermKey {
     pub const fn to_le_bytes(self) -> [u8; 16] {
         self.0.to_le_bytes()
     }
+
+    /// Shortens the key to `key_size` bytes by zeroing its most significant bytes
+    /// ([Vol 3] Part H, Section 2.3.4).
+    pub fn shorten(self, key_size: u8) -> Self {
+        let key_size = u32::from(key_size.clamp(7, 16));
+        Self(self.0 & (u128::MAX >> (8 * (16 - key_size))))
+    }
+
+    /// Derives the BR/EDR link key from an LE Secure Connections LTK
+    /// ([Vol 3] Part H, Section 2.4.2.4).
+    ///
+    /// `ct2` selects the h7 function for the intermediate key, it is set when both devices
+    /// support it.
+    pub fn link_key(&self, ct2: bool) -> LinkKey {
+        let ilk = if ct2 {
+            h7(SALT_TMP1, self.0)
+        } else {
+            h6(self.0, *b"tmp1")
+        };
+        LinkKey(h6(ilk, *b"lebr"))
+    }
 }
 
 impl From<&LongTermKey> for u128 {
@@ -54,6 +75,50 @@ impl defmt::Format for LongTermKey {
     }
 }
 
+/// BR/EDR Link Key derived from an LE Secure Connections LTK.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[must_use]
+#[repr(transparent)]
+pub struct LinkKey(pub u128);
+
+impl LinkKey {
+    /// Creates a Link Key from a `u128` value.
+    #[inline(always)]
+    pub const fn new(k: u128) -> Self {
+        Self(k)
+    }
+    /// Creates a Link Key from a `[u8; 16]` value in little endian.
+    #[inline(always)]
+    pub const fn from_le_bytes(k: [u8; 16]) -> Self {
+        Self(u128::from_le_bytes(k))
+    }
+    /// Converts the Link Key to a `[u8; 16]` value in little endian.
+    #[inline(always)]
+    pub const fn to_le_bytes(self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl From<&LinkKey> for u128 {
+    #[inline(always)]
+    fn from(k: &LinkKey) -> Self {
+        k.0
+    }
+}
+
+impl core::fmt::Display for LinkKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for LinkKey {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{:016x}", self.0)
+    }
+}
+
 /// Identity Resolving Key.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 #[must_use]
@@ -162,6 +227,72 @@ impl defmt::Format for IdentityResolvingKey {
     }
 }
 
+/// Connection Signature Resolving Key.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[must_use]
+#[repr(transparent)]
+pub struct ConnectionSignatureResolvingKey(pub u128);
+
+impl ConnectionSignatureResolvingKey {
+    /// Creates a Connection Signature Resolving Key from a `u128` value.
+    #[inline(always)]
+    pub const fn new(k: u128) -> Self {
+        Self(k)
+    }
+
+    /// Creates a Connection Signature Resolving Key from a `[u8; 16]` value in little endian.
+    #[inline(always)]
+    pub const fn from_le_bytes(k: [u8; 16]) -> Self {
+        Self(u128::from_le_bytes(k))
+    }
+
+    /// Returns the Connection Signature Resolving Key as `[u8; 16]` value in little endian.
+    #[inline(always)]
+    pub const fn to_le_bytes(self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+
+    /// Generates the authentication signature of a signed data PDU
+    /// ([Vol 3] Part H, Section 2.4.5).
+    ///
+    /// The signature is the little endian sign counter followed by the 64 most
+    /// significant bits of the MAC over `message || sign_counter`.
+    pub fn sign(&self, message: &[u8], sign_counter: u32) -> [u8; 12] {
+        let mut m = AesCmac::new(&Key::new(self.0));
+        // The MAC is computed over the big endian representation of the little endian PDU
+        m.update(sign_counter.to_be_bytes());
+        for b in message.iter().rev() {
+            m.update([*b]);
+        }
+        let mac = m.finalize();
+
+        let mut signature = [0u8; 12];
+        signature[..4].copy_from_slice(&sign_counter.to_le_bytes());
+        signature[4..].copy_from_slice(&((mac >> 64) as u64).to_le_bytes());
+        signature
+    }
+}
+
+impl From<&ConnectionSignatureResolvingKey> for u128 {
+    #[inline(always)]
+    fn from(k: &ConnectionSignatureResolvingKey) -> Self {
+        k.0
+    }
+}
+
+impl core::fmt::Display for ConnectionSignatureResolvingKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ConnectionSignatureResolvingKey {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{:016x}", self.0)
+    }
+}
+
 /// RFC-4493 AES-CMAC ([Vol 3] Part H, Section 2.2.5).
 #[derive(Debug)]
 #[repr(transparent)]
@@ -325,6 +456,7 @@ pub struct Confirm(pub u128);
 pub struct NumCompare(pub u32);
 
 /// P-256 elliptic curve secret key.
+#[derive(Clone)]
 #[must_use]
 #[repr(transparent)]
 pub struct SecretKey(p256::NonZeroScalar);
@@ -478,6 +610,70 @@ impl DHKey {
     }
 }
 
+/// LE legacy pairing Temporary Key (TK) ([Vol 3] Part H, Section 2.3.5).
+///
+/// Zero for Just Works, the 6-digit passkey for Passkey Entry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[must_use]
+#[repr(transparent)]
+pub struct TemporaryKey(pub u128);
+
+impl TemporaryKey {
+    /// Generates LE legacy pairing confirm value
+    /// ([Vol 3] Part H, Section 2.2.3).
+    ///
+    /// `preq` and `pres` are the Pairing Request and Pairing Response commands,
+    /// including the command code, `ia` and `ra` the initiator and responder addresses.
+    #[inline]
+    pub fn c1(&self, r: Nonce, preq: &[u8; 7], pres: &[u8; 7], ia: Address, ra: Address) -> Confirm {
+        let p1 = (le_value(pres) << 72)
+            | (le_value(preq) << 16)
+            | (u128::from(ra.kind.into_inner()) << 8)
+            | u128::from(ia.kind.into_inner());
+        let p2 = (le_value(ia.addr.raw()) << 48) | le_value(ra.addr.raw());
+        Confirm(e(self.0, e(self.0, r.0 ^ p1) ^ p2))
+    }
+
+    /// Generates LE legacy pairing Short Term Key (STK)
+    /// ([Vol 3] Part H, Section 2.2.4).
+    #[inline]
+    pub fn s1(&self, r1: Nonce, r2: Nonce) -> LongTermKey {
+        let r = (r1.0 << 64) | (r2.0 & u128::from(u64::MAX));
+        LongTermKey(e(self.0, r))
+    }
+}
+
+/// Security function `e`, AES-128 encryption of a single block
+/// ([Vol 3] Part H, Section 2.2.1).
+fn e(key: u128, plaintext: u128) -> u128 {
+    let cipher = Aes128::new(&Key::new(key).0);
+    let mut block = plaintext.to_be_bytes();
+    cipher.encrypt_block((&mut block).into());
+    u128::from_be_bytes(block)
+}
+
+/// SALT of the intermediate key when converting an LTK to a link key with h7 ("tmp1").
+const SALT_TMP1: u128 = 0x0000_0000_0000_0000_0000_0000_746D_7031;
+
+/// Link key conversion function h6 ([Vol 3] Part H, Section 2.2.10).
+fn h6(w: u128, key_id: [u8; 4]) -> u128 {
+    let mut m = AesCmac::new(&Key::new(w));
+    m.update(key_id);
+    m.finalize()
+}
+
+/// Link key conversion function h7 ([Vol 3] Part H, Section 2.2.11).
+fn h7(salt: u128, w: u128) -> u128 {
+    let mut m = AesCmac::new(&Key::new(salt));
+    m.update(w.to_be_bytes());
+    m.finalize()
+}
+
+/// Interprets little-endian bytes as an unsigned value.
+fn le_value(bytes: &[u8]) -> u128 {
+    bytes.iter().rev().fold(0, |acc, b| (acc << 8) | u128::from(*b))
+}
+
 /// Combines `hi` and `lo` values into a big-endian byte array.
 #[allow(clippy::redundant_pub_crate)]
 #[cfg(test)]
@@ -658,10 +854,10 @@ mod tests {
 
     #[test]
     fn testtest() {
-        let skb = SecretKey::new(&mut OsRng::default());
+        let skb = SecretKey::new(&mut OsRng);
         let _pkb = skb.public_key();
 
-        let ska = SecretKey::new(&mut OsRng::default());
+        let ska = SecretKey::new(&mut OsRng);
         let pka = ska.public_key();
 
         let _dh_key = skb.dh_key(pka).unwrap();
@@ -676,7 +872,7 @@ mod tests {
             0x71, 0xe4, 0x95, 0x17, 0x71, 0x98, 0x82, 0x8f, 0xf8, 0x79, 0x94,
         ];
 
-        let skb = SecretKey::new(&mut OsRng::default());
+        let skb = SecretKey::new(&mut OsRng);
         let _pkb = skb.public_key();
 
         let pka = PublicKey::from_bytes(&bytes);
@@ -687,7 +883,7 @@ mod tests {
     #[test]
     fn nonce() {
         // No fair dice rolls for us!
-        assert_ne!(Nonce::new(&mut OsRng::default()), Nonce::new(&mut OsRng::default()));
+        assert_ne!(Nonce::new(&mut OsRng), Nonce::new(&mut OsRng));
     }
 
     /// Confirm value generation function ([Vol 3] Part H, Section D.2).
@@ -783,6 +979,54 @@ mod tests {
         assert_eq!(x.g2(&pkax, &pkbx, &y).0, 991180);
     }
 
+    /// Legacy confirm value generation function ([Vol 3] Part H, Section 2.2.3).
+    #[test]
+    fn temporary_key_c1() {
+        let k = TemporaryKey(0);
+        let r = Nonce(0x5783d521_56ad6f0e_6388274e_c6702ee0);
+        let preq = [0x01, 0x01, 0x00, 0x00, 0x10, 0x07, 0x07];
+        let pres = [0x02, 0x03, 0x00, 0x00, 0x08, 0x00, 0x05];
+        let ia = Address {
+            kind: AddrKind::RANDOM,
+            addr: BdAddr::new([0xa6, 0xa5, 0xa4, 0xa3, 0xa2, 0xa1]),
+        };
+        let ra = Address {
+            kind: AddrKind::PUBLIC,
+            addr: BdAddr::new([0xb6, 0xb5, 0xb4, 0xb3, 0xb2, 0xb1]),
+        };
+        assert_eq!(k.c1(r, &preq, &pres, ia, ra).0, 0x1e1e3fef_878988ea_d2a74dc5_bef13b86);
+    }
+
+    /// Legacy key generation function ([Vol 3] Part H, Section 2.2.4).
+    #[test]
+    fn temporary_key_s1() {
+        let k = TemporaryKey(0);
+        let r1 = Nonce(0x000f0e0d_0c0b0a09_11223344_55667788);
+        let r2 = Nonce(0x01020304_05060708_99aabbcc_ddeeff00);
+        assert_eq!(k.s1(r1, r2).0, 0x9a1fe1f0_e8b0f49b_5b4216ae_796da062);
+    }
+
+    #[test]
+    fn ltk_shorten() {
+        let ltk = LongTermKey::new(0x69867911_69d7cd23_980522b5_94750a38);
+        assert_eq!(ltk.shorten(16), ltk);
+        assert_eq!(ltk.shorten(7).0, 0x00000000_00000000_000522b5_94750a38);
+    }
+
+    /// Link key conversion function h6 ([Vol 3] Part H, Section D.8).
+    #[test]
+    fn link_key_h6() {
+        let w = 0xec0234a3_57c8ad05_341010a6_0a397d9b;
+        assert_eq!(h6(w, *b"lebr"), 0x2d9ae102_e76dc91c_e8d3a9e2_80b16399);
+    }
+
+    /// Link key conversion function h7 ([Vol 3] Part H, Section D.9).
+    #[test]
+    fn link_key_h7() {
+        let w = 0xec0234a3_57c8ad05_341010a6_0a397d9b;
+        assert_eq!(h7(SALT_TMP1, w), 0xfb173597_c6a3c0ec_d2998c2a_75a57011);
+    }
+
     #[test]
     pub fn irk_test() {
         let irk = IdentityResolvingKey::new(0xec0234a3_57c8ad05_341010a6_0a397d9b);
@@ -799,4 +1043,14 @@ mod tests {
         let re = irk.resolve_address(&address);
         assert_eq!(re, true);
     }
+
+    /// Data signing algorithm ([Vol 3] Part H, Section 2.4.5) using the RFC-4493 example 2 vector.
+    #[test]
+    fn csrk_sign() {
+        let csrk = ConnectionSignatureResolvingKey::new(0x2b7e1516_28aed2a6_abf71588_09cf4f3c);
+        let message = [0x2a, 0x17, 0x93, 0x73, 0x11, 0x7e, 0x3d, 0xe9, 0x96, 0x9f, 0x40, 0x2e];
+        let signature = csrk.sign(&message, 0x6bc1bee2);
+        assert_eq!(signature[..4], 0x6bc1bee2u32.to_le_bytes());
+        assert_eq!(signature[4..], 0x070a16b4_6b4d4144u64.to_le_bytes());
+    }
 }
@@ -6,43 +6,77 @@ mod constants;
 mod crypto;
 mod pairing;
 mod types;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::future::{poll_fn, Future};
 use core::ops::DerefMut;
+use core::task::{Context, Poll};
 
 use bt_hci::event::le::{LeEventKind, LeEventPacket, LeLongTermKeyRequest};
 use bt_hci::event::{EncryptionChangeV1, EventKind, EventPacket};
-use bt_hci::param::{ConnHandle, EncryptionEnabledLevel, LeConnRole};
+use bt_hci::param::{AddrKind, BdAddr, ConnHandle, EncryptionEnabledLevel, LeConnRole};
 use bt_hci::FromHciBytes;
-pub use crypto::{IdentityResolvingKey, LongTermKey};
+pub(crate) use crypto::AesCmac;
+pub use crypto::{ConnectionSignatureResolvingKey, IdentityResolvingKey, LinkKey, LongTermKey};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
-use embassy_time::{Instant, TimeoutError, WithTimeout};
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant, TimeoutError, WithTimeout};
 use heapless::Vec;
 use rand_chacha::ChaCha12Rng;
 use rand_core::SeedableRng;
 use types::Command;
-pub use types::{PassKey, Reason};
+pub use types::{KeypressNotification, PassKey, Reason};
 
 use crate::connection::SecurityLevel;
 use crate::connection_manager::{ConnectionManager, ConnectionStorage};
 use crate::pdu::Pdu;
 use crate::prelude::ConnectionEvent;
-use crate::security_manager::pairing::{Pairing, PairingOps};
-use crate::security_manager::types::BondingFlag;
+use crate::security_manager::crypto::{Nonce, SecretKey};
+use crate::security_manager::pairing::{make_oob_data, Pairing, PairingOps};
+use crate::security_manager::types::{BondingFlag, PairingFeatures};
 use crate::types::l2cap::L2CAP_CID_LE_U_SECURITY_MANAGER;
 use crate::{Address, Error, Identity, IoCapabilities, PacketPool};
 
 /// Events of interest to the security manager
 pub(crate) enum SecurityEventData {
-    /// A long term key request has been issued
-    SendLongTermKey(ConnHandle),
+    /// A long term key request has been issued for the key with the given EDIV and Rand
+    SendLongTermKey(ConnHandle, CentralIdentification),
     /// Enable encryption on channel
     EnableEncryption(ConnHandle, BondInformation),
     /// Pairing timeout
     Timeout,
     /// Pairing timer changed
     TimerChange,
+    /// Bonds or the local identity changed, the controller resolving list needs updating
+    ResolvingListChanged,
+}
+
+/// Bond changes to apply to a bond store
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum BondStoreEvent {
+    /// Save the bond with the peer, pairing completed or the peer disconnected
+    Save(Identity),
+    /// Remove the bond with the peer
+    Remove(Identity),
+    /// Restore the stored state of a bonded peer that connected
+    Restore(Identity),
+}
+
+impl BondStoreEvent {
+    fn identity(&self) -> &Identity {
+        match self {
+            Self::Save(identity) | Self::Remove(identity) | Self::Restore(identity) => identity,
+        }
+    }
+}
+
+/// Entries of the controller resolving list
+pub(crate) struct ResolvingList<const N: usize> {
+    /// Local IRK, if a local identity is set
+    pub local_irk: Option<IdentityResolvingKey>,
+    /// Identity address and IRK of every bonded peer that distributed one
+    pub entries: Vec<(Address, IdentityResolvingKey), N>,
 }
 
 /// Bond Information
@@ -50,24 +84,79 @@ pub(crate) enum SecurityEventData {
 pub struct BondInformation {
     /// Long Term Key (LTK)
     pub ltk: LongTermKey,
+    /// Size of the long term key in bytes, from 7 to 16.
+    pub encryption_key_size: u8,
     /// Peer identity
     pub identity: Identity,
+    /// Type of the peer identity address, public or static random.
+    pub identity_kind: AddrKind,
     /// True if this bond information is from a bonded pairing
     pub is_bonded: bool,
     /// Security level of this long term key.
     pub security_level: SecurityLevel,
+    /// EDIV and Rand of a key from LE legacy pairing, `None` for LE Secure Connections keys.
+    pub central_identification: Option<CentralIdentification>,
+    /// Key used to sign data sent to the peer, distributed by this device during bonding.
+    pub local_signing_key: Option<SigningKey>,
+    /// Key used to verify data signed by the peer, distributed by the peer during bonding.
+    pub peer_signing_key: Option<SigningKey>,
+    /// BR/EDR link key derived from the LTK, when both devices asked for it during bonding.
+    pub link_key: Option<LinkKey>,
 }
 
 impl BondInformation {
-    /// Create a BondInformation
+    /// Create a BondInformation for a key from LE Secure Connections pairing
+    ///
+    /// The identity address is assumed to be public.
     pub fn new(identity: Identity, ltk: LongTermKey, security_level: SecurityLevel, is_bonded: bool) -> Self {
         Self {
             ltk,
+            encryption_key_size: constants::ENCRYPTION_KEY_SIZE_128_BITS,
             identity,
+            identity_kind: AddrKind::PUBLIC,
             is_bonded,
             security_level,
+            central_identification: None,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         }
     }
+
+    /// True if the long term key was generated by LE Secure Connections pairing
+    pub fn secure_connections(&self) -> bool {
+        self.central_identification.is_none()
+    }
+}
+
+/// Connection Signature Resolving Key (CSRK) and its sign counter ([Vol 3] Part H, Section 2.4.5).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SigningKey {
+    /// Connection Signature Resolving Key
+    pub csrk: ConnectionSignatureResolvingKey,
+    /// Sign counter of the next signed data PDU
+    pub counter: u32,
+}
+
+impl SigningKey {
+    /// Create a signing key with a zero sign counter
+    pub fn new(csrk: ConnectionSignatureResolvingKey) -> Self {
+        Self { csrk, counter: 0 }
+    }
+}
+
+/// Encrypted Diversifier (EDIV) and Random Number (Rand) identifying a long term key
+/// distributed by LE legacy pairing ([Vol 3] Part H, Section 3.6.3).
+///
+/// Both are zero while a link is encrypted with the Short Term Key during pairing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CentralIdentification {
+    /// Encrypted Diversifier (EDIV)
+    pub ediv: u16,
+    /// Random Number (Rand)
+    pub rand: [u8; 8],
 }
 
 impl core::fmt::Display for BondInformation {
@@ -83,14 +172,134 @@ impl defmt::Format for BondInformation {
     }
 }
 
+/// LE Secure Connections out-of-band data ([Vol 3] Part H, Section 2.3.5.6.4).
+///
+/// Exchanged with the peer over another channel, e.g. NFC, before pairing. Pairing with it provides MITM protection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OobData {
+    /// Random value r
+    pub random: u128,
+    /// Confirmation value C = f4(PKx, PKx, r, 0)
+    pub confirm: u128,
+}
+
+/// Which bond is removed to make room for a new bond when the bond table is full
+///
+/// Pinned bonds, and keys of pairings without bonding, are never evicted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BondEvictionPolicy {
+    /// Keep the existing bonds, the new bond is not stored
+    #[default]
+    Refuse,
+    /// Evict the bond with the peer that connected the longest time ago
+    LeastRecentlyUsed,
+    /// Evict the bond created first
+    Oldest,
+}
+
+/// Pairing Request received from a peer, passed to [`SecurityPolicy::accept_pairing`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PairingRequest {
+    /// Peer identity, only the connection address until the peer distributes its identity
+    pub identity: Identity,
+    /// IO capabilities of the peer
+    pub io_capabilities: IoCapabilities,
+    /// The peer requests bonding
+    pub bonding: bool,
+    /// The peer requests MITM protection
+    pub mitm: bool,
+    /// The peer supports LE Secure Connections
+    pub secure_connections: bool,
+    /// The peer has out-of-band data of this device
+    pub oob_data: bool,
+    /// Largest encryption key size supported by the peer, in bytes
+    pub maximum_encryption_key_size: u8,
+}
+
+/// Requirements a pairing has to meet, pairing fails when they are not met.
+#[derive(Clone, Copy, Debug)]
+pub struct SecurityPolicy {
+    /// Smallest encryption key size accepted, from 7 to 16 bytes, pairing fails with
+    /// `Reason::EncryptionKeySize` below it. Defaults to 16.
+    pub min_encryption_key_size: u8,
+    /// Refuse pairing without MITM protection, i.e. Just Works, with `Reason::AuthenticationRequirements`.
+    /// Defaults to `false`.
+    pub mitm_required: bool,
+    /// Refuse peers that only support LE legacy pairing with `Reason::AuthenticationRequirements`.
+    /// Defaults to `true`.
+    pub secure_connections_only: bool,
+    /// Whether new connections are bondable, see [`Connection::set_bondable`](crate::connection::Connection::set_bondable).
+    /// Defaults to `false`.
+    pub bondable: bool,
+    /// Derive a BR/EDR link key from the LTK when bonding with LE Secure Connections, for dual-mode
+    /// devices, see [`BondInformation::link_key`]. Defaults to `false`.
+    pub cross_transport_key_derivation: bool,
+    /// Decides whether a Pairing Request from a peer is accepted, it is rejected with
+    /// `Reason::PairingNotSupported` when this returns `false`. Defaults to `None`, accepting all requests
+    /// that meet the policy.
+    pub accept_pairing: Option<fn(&PairingRequest) -> bool>,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        Self {
+            min_encryption_key_size: constants::ENCRYPTION_KEY_SIZE_128_BITS,
+            mitm_required: false,
+            secure_connections_only: true,
+            bondable: false,
+            cross_transport_key_derivation: false,
+            accept_pairing: None,
+        }
+    }
+}
+
+/// What the eviction policy needs to know about a bond, kept along with it in a bond store.
+///
+/// Bonds are ordered by a counter incremented each time a bond is created or used, rather than by
+/// time, so the order survives a reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BondUsage {
+    /// Never evicted
+    pub pinned: bool,
+    /// When the bond was created
+    pub created: u64,
+    /// When the bond was created, updated or the peer last connected
+    pub last_used: u64,
+}
+
+/// Bond information along with what the eviction policy needs
+#[derive(Clone, Debug)]
+struct Bond {
+    info: BondInformation,
+    usage: BondUsage,
+}
+
 /// Security manager data
 struct SecurityManagerData<const BOND_COUNT: usize> {
     /// Local device address
     local_address: Option<Address>,
     /// Current bonds with other devices
-    bond: Vec<BondInformation, BOND_COUNT>,
+    bond: Vec<Bond, BOND_COUNT>,
+    /// Bond evicted when the bond table is full
+    eviction_policy: BondEvictionPolicy,
+    /// Last value of the bond usage counter
+    bond_usage_counter: u64,
     /// Random generator seeded
     random_generator_seeded: bool,
+    /// Key pair and random value of the local OOB data
+    local_oob: Option<(SecretKey, Nonce)>,
+    /// OOB data received from a peer
+    peer_oob: Option<(Address, OobData)>,
+    /// Local identity address and IRK distributed during pairing
+    local_identity: Option<(Address, IdentityResolvingKey)>,
+    /// Resolvable private address in use and when it expires
+    resolvable_private_address: Option<(Address, Instant)>,
+    /// Time after which a new resolvable private address is generated
+    rpa_timeout: Duration,
 }
 
 impl<const BOND_COUNT: usize> SecurityManagerData<BOND_COUNT> {
@@ -99,9 +308,22 @@ impl<const BOND_COUNT: usize> SecurityManagerData<BOND_COUNT> {
         Self {
             local_address: None,
             bond: Vec::new(),
+            eviction_policy: BondEvictionPolicy::default(),
+            bond_usage_counter: 0,
             random_generator_seeded: false,
+            local_oob: None,
+            peer_oob: None,
+            local_identity: None,
+            resolvable_private_address: None,
+            rpa_timeout: constants::RPA_TIMEOUT,
         }
     }
+
+    /// Next value of the bond usage counter
+    fn next_bond_usage(&mut self) -> u64 {
+        self.bond_usage_counter += 1;
+        self.bond_usage_counter
+    }
 }
 
 /// Packet structure for sending security manager protocol (SMP) commands
@@ -162,8 +384,6 @@ enum PairingMethod {
     LeSecureConnectionOob,
 }
 
-// TODO: IRK exchange, HCI_LE_­Add_­Device_­To_­Resolving_­List
-
 /// Security manager that handles SM packet
 pub struct SecurityManager<const BOND_COUNT: usize> {
     /// Random generator
@@ -176,6 +396,20 @@ pub struct SecurityManager<const BOND_COUNT: usize> {
     events: Channel<NoopRawMutex, SecurityEventData, 2>,
     /// Io capabilities
     io_capabilities: RefCell<IoCapabilities>,
+    /// Requirements of pairings
+    policy: Cell<SecurityPolicy>,
+    /// The controller resolving list does not match the bonds
+    resolving_list_outdated: Cell<bool>,
+    /// The host has not been notified about the outdated resolving list yet
+    resolving_list_notify: Cell<bool>,
+    /// Waker of the task applying resolving list changes
+    resolving_list_waker: RefCell<WakerRegistration>,
+    /// A bond store follows the bonds
+    bond_store_attached: Cell<bool>,
+    /// Bond changes not applied to the bond store yet
+    bond_store_events: RefCell<Vec<BondStoreEvent, BOND_COUNT>>,
+    /// Waker of the task applying bond store changes
+    bond_store_waker: RefCell<WakerRegistration>,
 }
 
 impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
@@ -188,6 +422,13 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
             events: Channel::new(),
             pairing_sm: RefCell::new(None),
             io_capabilities: RefCell::new(IoCapabilities::NoInputNoOutput),
+            policy: Cell::new(SecurityPolicy::default()),
+            resolving_list_outdated: Cell::new(false),
+            resolving_list_notify: Cell::new(false),
+            resolving_list_waker: RefCell::new(WakerRegistration::new()),
+            bond_store_attached: Cell::new(false),
+            bond_store_events: RefCell::new(Vec::new()),
+            bond_store_waker: RefCell::new(WakerRegistration::new()),
         }
     }
 
@@ -196,6 +437,22 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         self.io_capabilities.replace(io_capabilities);
     }
 
+    /// Set the requirements of pairings
+    pub(crate) fn set_policy(&self, policy: SecurityPolicy) -> Result<(), Error> {
+        if !(constants::ENCRYPTION_KEY_SIZE_56_BITS..=constants::ENCRYPTION_KEY_SIZE_128_BITS)
+            .contains(&policy.min_encryption_key_size)
+        {
+            return Err(Error::InvalidValue);
+        }
+        self.policy.set(policy);
+        Ok(())
+    }
+
+    /// Whether new connections are bondable
+    pub(crate) fn default_bondable(&self) -> bool {
+        self.policy.get().bondable
+    }
+
     /// Set the current local address
     pub(crate) fn set_random_generator_seed(&self, random_seed: [u8; 32]) {
         self.rng.replace(ChaCha12Rng::from_seed(random_seed));
@@ -207,56 +464,208 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         self.state.borrow_mut().local_address = Some(address);
     }
 
-    fn get_peer_bond_information(&self, identity: &Identity) -> Option<BondInformation> {
+    /// Get the bond information for peer
+    pub(crate) fn get_peer_bond_information(&self, identity: &Identity) -> Option<BondInformation> {
         trace!("[security manager] Find long term key for {:?}", identity);
         self.state.borrow().bond.iter().find_map(|bond| {
-            if bond.identity.match_identity(identity) {
-                Some(bond.clone())
+            if bond.info.identity.match_identity(identity) {
+                Some(bond.info.clone())
             } else {
                 None
             }
         })
     }
 
-    /// Get the long term key for peer
-    pub(crate) fn get_peer_long_term_key(&self, identity: &Identity) -> Option<LongTermKey> {
-        trace!("[security manager] Find long term key for {:?}", identity);
-        self.state.borrow().bond.iter().find_map(|bond| {
-            if bond.identity.match_identity(identity) {
-                Some(bond.ltk)
-            } else {
-                None
+    /// Set the local identity, resolvable private addresses are used once it is set
+    pub(crate) fn set_local_identity(&self, address: Address, irk: IdentityResolvingKey) {
+        self.state.borrow_mut().local_identity = Some((address, irk));
+        self.resolving_list_changed();
+    }
+
+    /// Set the time after which a new resolvable private address is generated
+    pub(crate) fn set_resolvable_private_address_timeout(&self, timeout: Duration) {
+        self.state.borrow_mut().rpa_timeout = timeout;
+        self.resolving_list_changed();
+    }
+
+    /// Get the time after which a new resolvable private address is generated
+    pub(crate) fn resolvable_private_address_timeout(&self) -> Duration {
+        self.state.borrow().rpa_timeout
+    }
+
+    /// Get the resolvable private address in use, `None` if no local identity is set
+    pub(crate) fn resolvable_private_address(&self) -> Option<Address> {
+        let state = self.state.borrow();
+        state
+            .local_identity
+            .and(state.resolvable_private_address)
+            .map(|(address, _)| address)
+    }
+
+    /// Get the time at which the resolvable private address in use expires, `None` if there is none
+    pub(crate) fn resolvable_private_address_expiry(&self) -> Option<Instant> {
+        let state = self.state.borrow();
+        state
+            .local_identity
+            .and(state.resolvable_private_address)
+            .map(|(_, expires_at)| expires_at)
+    }
+
+    /// Generate a new resolvable private address if there is none yet or the current one expired
+    ///
+    /// The address is only used after [`Self::set_resolvable_private_address`], once the controller accepted it.
+    pub(crate) fn next_resolvable_private_address(&self) -> Option<Address> {
+        let (_, irk) = self.state.borrow().local_identity?;
+        if let Some((_, expires_at)) = self.state.borrow().resolvable_private_address {
+            if Instant::now() < expires_at {
+                return None;
             }
+        }
+        let addr = irk.generate_resolvable_address(self.rng.borrow_mut().deref_mut());
+        Some(Address {
+            kind: AddrKind::RANDOM,
+            addr: BdAddr::new(addr),
         })
     }
 
+    /// Start using a resolvable private address as local address
+    pub(crate) fn set_resolvable_private_address(&self, address: Address) {
+        let mut state = self.state.borrow_mut();
+        state.resolvable_private_address = Some((address, Instant::now() + state.rpa_timeout));
+        state.local_address = Some(address);
+    }
+
+    /// Generate the local OOB data, replacing any previously generated
+    pub(crate) fn generate_oob_data(&self) -> OobData {
+        let mut rng = self.rng.borrow_mut();
+        let secret_key = SecretKey::new(rng.deref_mut());
+        let random = Nonce::new(rng.deref_mut());
+        let oob_data = make_oob_data(&secret_key, random);
+        self.state.borrow_mut().local_oob = Some((secret_key, random));
+        oob_data
+    }
+
+    /// Set the OOB data received from a peer
+    pub(crate) fn set_peer_oob_data(&self, address: Address, oob_data: OobData) {
+        self.state.borrow_mut().peer_oob = Some((address, oob_data));
+    }
+
     /// Has the random generator been seeded?
     pub(crate) fn get_random_generator_seeded(&self) -> bool {
         self.state.borrow().random_generator_seeded
     }
 
+    /// Set which bond is evicted when the bond table is full
+    pub(crate) fn set_eviction_policy(&self, policy: BondEvictionPolicy) {
+        self.state.borrow_mut().eviction_policy = policy;
+    }
+
     /// Add a bonded device
-    pub(crate) fn add_bond_information(&self, bond_information: BondInformation) -> Result<(), Error> {
+    ///
+    /// If the bond table is full, a bond is evicted according to the eviction policy and returned.
+    pub(crate) fn add_bond_information(
+        &self,
+        bond_information: BondInformation,
+    ) -> Result<Option<BondInformation>, Error> {
         trace!("[security manager] Add bond for {:?}", bond_information.identity);
-        let index = self
-            .state
+        let mut evicted = None;
+        {
+            let mut state = self.state.borrow_mut();
+            let now = state.next_bond_usage();
+            let policy = state.eviction_policy;
+            match state
+                .bond
+                .iter_mut()
+                .find(|bond| bond_information.identity.match_identity(&bond.info.identity))
+            {
+                Some(bond) => {
+                    // Replace existing bond if it exists
+                    bond.info = bond_information;
+                    bond.usage.last_used = now;
+                }
+                None => {
+                    if state.bond.is_full() {
+                        let index = Self::eviction_candidate(&state.bond, policy).ok_or(Error::OutOfMemory)?;
+                        evicted = Some(state.bond.remove(index).info);
+                    }
+                    state
+                        .bond
+                        .push(Bond {
+                            info: bond_information,
+                            usage: BondUsage {
+                                pinned: false,
+                                created: now,
+                                last_used: now,
+                            },
+                        })
+                        .map_err(|_| Error::OutOfMemory)?;
+                }
+            }
+        }
+        self.resolving_list_changed();
+        if let Some(bond) = &evicted {
+            info!("[security manager] Bond table full, evicted bond {:?}", bond.identity);
+            self.bond_store_event(BondStoreEvent::Remove(bond.identity));
+        }
+        Ok(evicted)
+    }
+
+    /// Find the bond to evict according to the policy
+    fn eviction_candidate(bonds: &[Bond], policy: BondEvictionPolicy) -> Option<usize> {
+        let candidates = bonds
+            .iter()
+            .enumerate()
+            .filter(|(_, bond)| bond.info.is_bonded && !bond.usage.pinned);
+        match policy {
+            BondEvictionPolicy::Refuse => None,
+            BondEvictionPolicy::LeastRecentlyUsed => candidates.min_by_key(|(_, bond)| bond.usage.last_used),
+            BondEvictionPolicy::Oldest => candidates.min_by_key(|(_, bond)| bond.usage.created),
+        }
+        .map(|(index, _)| index)
+    }
+
+    /// Pin or unpin a bond, pinned bonds are never evicted
+    pub(crate) fn set_bond_pinned(&self, identity: &Identity, pinned: bool) -> Result<(), Error> {
+        let bonded = {
+            let mut state = self.state.borrow_mut();
+            let bond = state
+                .bond
+                .iter_mut()
+                .find(|bond| bond.info.identity.match_identity(identity))
+                .ok_or(Error::NotFound)?;
+            bond.usage.pinned = pinned;
+            bond.info.is_bonded
+        };
+        if bonded {
+            self.bond_store_event(BondStoreEvent::Save(*identity));
+        }
+        Ok(())
+    }
+
+    /// Usage of the bond with the peer, for the bond store
+    pub(crate) fn get_bond_usage(&self, identity: &Identity) -> Option<BondUsage> {
+        self.state
             .borrow()
             .bond
             .iter()
-            .position(|bond| bond_information.identity.match_identity(&bond.identity));
-        match index {
-            Some(index) => {
-                // Replace existing bond if it exists
-                self.state.borrow_mut().bond[index] = bond_information;
-                Ok(())
-            }
-            None => self
-                .state
-                .borrow_mut()
-                .bond
-                .push(bond_information)
-                .map_err(|_| Error::OutOfMemory),
+            .find(|bond| bond.info.identity.match_identity(identity))
+            .map(|bond| bond.usage)
+    }
+
+    /// Add a bond loaded from a bond store, along with its usage
+    pub(crate) fn restore_bond(&self, bond_information: BondInformation, usage: BondUsage) -> Result<(), Error> {
+        let identity = bond_information.identity;
+        self.add_bond_information(bond_information)?;
+        let mut state = self.state.borrow_mut();
+        state.bond_usage_counter = state.bond_usage_counter.max(usage.created).max(usage.last_used);
+        if let Some(bond) = state
+            .bond
+            .iter_mut()
+            .find(|bond| bond.info.identity.match_identity(&identity))
+        {
+            bond.usage = usage;
         }
+        Ok(())
     }
 
     /// Remove a bonded device
@@ -267,10 +676,12 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
             .borrow_mut()
             .bond
             .iter()
-            .position(|bond| bond.identity.match_identity(&identity));
+            .position(|bond| bond.info.identity.match_identity(&identity));
         match index {
             Some(index) => {
                 self.state.borrow_mut().bond.remove(index);
+                self.resolving_list_changed();
+                self.bond_store_event(BondStoreEvent::Remove(identity));
                 Ok(())
             }
             None => Err(Error::NotFound),
@@ -279,7 +690,145 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
 
     /// Get bonded devices
     pub(crate) fn get_bond_information(&self) -> Vec<BondInformation, BOND_COUNT> {
-        Vec::from_slice(self.state.borrow().bond.as_slice()).unwrap()
+        self.state.borrow().bond.iter().map(|bond| bond.info.clone()).collect()
+    }
+
+    /// Sign a data PDU for a bonded peer using the local signing key of the bond ([Vol 3] Part H, Section 2.4.5)
+    pub(crate) fn sign(&self, identity: &Identity, message: &[u8]) -> Result<[u8; 12], Error> {
+        let mut state = self.state.borrow_mut();
+        let key = state
+            .bond
+            .iter_mut()
+            .find(|bond| bond.info.identity.match_identity(identity))
+            .and_then(|bond| bond.info.local_signing_key.as_mut())
+            .ok_or(Error::NotFound)?;
+        // The sign counter shall not wrap around
+        let next_counter = key.counter.checked_add(1).ok_or(Error::InvalidState)?;
+        let signature = key.csrk.sign(message, key.counter);
+        key.counter = next_counter;
+        Ok(signature)
+    }
+
+    /// Verify a data PDU signed by a bonded peer, rejecting sign counters that were already used
+    pub(crate) fn verify_signature(
+        &self,
+        identity: &Identity,
+        message: &[u8],
+        signature: &[u8; 12],
+    ) -> Result<(), Error> {
+        let mut state = self.state.borrow_mut();
+        let key = state
+            .bond
+            .iter_mut()
+            .find(|bond| bond.info.identity.match_identity(identity))
+            .and_then(|bond| bond.info.peer_signing_key.as_mut())
+            .ok_or(Error::NotFound)?;
+        let counter = u32::from_le_bytes([signature[0], signature[1], signature[2], signature[3]]);
+        if counter < key.counter {
+            warn!(
+                "[security manager] Replayed sign counter {} from {:?}",
+                counter, identity
+            );
+            return Err(Error::InvalidValue);
+        }
+        if key.csrk.sign(message, counter) != *signature {
+            return Err(Error::InvalidValue);
+        }
+        key.counter = counter.checked_add(1).ok_or(Error::InvalidState)?;
+        Ok(())
+    }
+
+    /// Mark the controller resolving list as outdated and notify the host
+    fn resolving_list_changed(&self) {
+        self.resolving_list_outdated.set(true);
+        self.resolving_list_notify.set(true);
+        self.resolving_list_waker.borrow_mut().wake();
+    }
+
+    /// Get the resolving list if the controller resolving list is outdated
+    ///
+    /// The list is considered up to date until the next change, call [`Self::resolving_list_failed`] if
+    /// the controller did not accept it.
+    pub(crate) fn take_resolving_list(&self) -> Option<ResolvingList<BOND_COUNT>> {
+        if !self.resolving_list_outdated.replace(false) {
+            return None;
+        }
+        let state = self.state.borrow();
+        let local_irk = state.local_identity.map(|(_, irk)| irk);
+        let entries = state
+            .bond
+            .iter()
+            .filter_map(|bond| {
+                bond.info.identity.irk.map(|irk| {
+                    let address = Address {
+                        kind: bond.info.identity_kind,
+                        addr: bond.info.identity.bd_addr,
+                    };
+                    (address, irk)
+                })
+            })
+            .collect();
+        Some(ResolvingList { local_irk, entries })
+    }
+
+    /// The controller refused the resolving list, retry on the next attempt
+    pub(crate) fn resolving_list_failed(&self) {
+        self.resolving_list_outdated.set(true);
+    }
+
+    /// Start recording bond changes for a bond store
+    pub(crate) fn attach_bond_store(&self) {
+        self.bond_store_attached.set(true);
+    }
+
+    /// Record a bond change for the bond store, replacing older changes for the same peer
+    fn bond_store_event(&self, event: BondStoreEvent) {
+        if !self.bond_store_attached.get() {
+            return;
+        }
+        let mut events = self.bond_store_events.borrow_mut();
+        // A pending restore has to happen before the bond is saved again, unless the bond is gone
+        let removed = matches!(event, BondStoreEvent::Remove(_));
+        events.retain(|e| {
+            !e.identity().match_identity(event.identity()) || (!removed && matches!(e, BondStoreEvent::Restore(_)))
+        });
+        if events.push(event).is_err() {
+            warn!("[security manager] Bond store changes full, dropping {:?}", event);
+        }
+        self.bond_store_waker.borrow_mut().wake();
+    }
+
+    /// A peer connected, its bond is marked as used and its stored state restored if it is bonded
+    pub(crate) fn peer_connected(&self, identity: &Identity) {
+        let bonded = {
+            let mut state = self.state.borrow_mut();
+            let now = state.next_bond_usage();
+            match state
+                .bond
+                .iter_mut()
+                .find(|bond| bond.info.identity.match_identity(identity))
+            {
+                Some(bond) if bond.info.is_bonded => {
+                    bond.usage.last_used = now;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if bonded {
+            self.bond_store_event(BondStoreEvent::Restore(*identity));
+        }
+    }
+
+    /// Poll for the next bond change to apply to the bond store
+    pub(crate) fn poll_bond_store_event(&self, cx: &mut Context<'_>) -> Poll<BondStoreEvent> {
+        let mut events = self.bond_store_events.borrow_mut();
+        if events.is_empty() {
+            self.bond_store_waker.borrow_mut().register(cx.waker());
+            Poll::Pending
+        } else {
+            Poll::Ready(events.remove(0))
+        }
     }
 
     fn handle_peripheral<P: PacketPool>(
@@ -289,12 +838,8 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         storage: &ConnectionStorage<P::Packet>,
     ) -> Result<(), Error> {
         let handle = storage.handle.ok_or(Error::InvalidValue)?;
-        let peer_address_kind = storage.peer_addr_kind.ok_or(Error::InvalidValue)?;
         let peer_identity = storage.peer_identity.ok_or(Error::InvalidValue)?;
-        let peer_address = Address {
-            kind: peer_address_kind,
-            addr: peer_identity.bd_addr,
-        };
+        let peer_address = storage.peer_connection_address().ok_or(Error::InvalidValue)?;
         let mut buffer = [0u8; 72];
         let size = {
             let size = pdu.len().min(buffer.len());
@@ -363,12 +908,8 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         storage: &ConnectionStorage<P::Packet>,
     ) -> Result<(), Error> {
         let handle = storage.handle.ok_or(Error::InvalidValue)?;
-        let peer_address_kind = storage.peer_addr_kind.ok_or(Error::InvalidValue)?;
         let peer_identity = storage.peer_identity.ok_or(Error::InvalidValue)?;
-        let peer_address = Address {
-            kind: peer_address_kind,
-            addr: peer_identity.bd_addr,
-        };
+        let peer_address = storage.peer_connection_address().ok_or(Error::InvalidValue)?;
         let mut buffer = [0u8; 72];
         let size = {
             let size = pdu.len().min(buffer.len());
@@ -506,12 +1047,8 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         if pairing_sm.is_none() {
             let handle = storage.handle.ok_or(Error::InvalidValue)?;
             let local_address = self.state.borrow().local_address.ok_or(Error::InvalidValue)?;
-            let peer_address_kind = storage.peer_addr_kind.ok_or(Error::InvalidValue)?;
             let peer_identity = storage.peer_identity.ok_or(Error::InvalidValue)?;
-            let peer_address = Address {
-                kind: peer_address_kind,
-                addr: peer_identity.bd_addr,
-            };
+            let peer_address = storage.peer_connection_address().ok_or(Error::InvalidValue)?;
             let mut ops = PairingOpsImpl {
                 security_manager: self,
                 conn_handle: handle,
@@ -555,7 +1092,11 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
             self.state
                 .borrow_mut()
                 .bond
-                .retain(|x| x.is_bonded || x.identity != identity);
+                .retain(|x| x.info.is_bonded || x.info.identity != identity);
+            // Sign counters and the state of the peer changed while connected
+            if self.get_peer_bond_information(&identity).is_some() {
+                self.bond_store_event(BondStoreEvent::Save(identity));
+            }
         }
 
         Ok(())
@@ -571,7 +1112,13 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         match event.kind {
             LeEventKind::LeLongTermKeyRequest => {
                 let event_data = LeLongTermKeyRequest::from_hci_bytes_complete(event.data)?;
-                self.try_send_event(SecurityEventData::SendLongTermKey(event_data.handle))?;
+                self.try_send_event(SecurityEventData::SendLongTermKey(
+                    event_data.handle,
+                    CentralIdentification {
+                        ediv: event_data.encrypted_diversifier,
+                        rand: event_data.random_number,
+                    },
+                ))?;
             }
             _ => (),
         }
@@ -592,6 +1139,7 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
                     Ok(()) => {
                         trace!("[smp] Encryption Changed event {:?}", event_data.enabled);
                         connections.with_connected_handle(event_data.handle, |storage| {
+                            storage.security_changed();
                             let sm = self.pairing_sm.borrow();
                             if let Some(sm) = &*sm {
                                 let mut rng = self.rng.borrow_mut();
@@ -612,6 +1160,8 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
                                 match res {
                                     Ok(_) => {
                                         storage.security_level = sm.security_level();
+                                        storage.encryption_key_size = sm.encryption_key_size();
+                                        storage.secure_connections = sm.secure_connections();
                                         Ok(())
                                     }
                                     x => x,
@@ -621,13 +1171,17 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
                                     Some(bond) if event_data.enabled != EncryptionEnabledLevel::Off => {
                                         info!("[smp] Encryption changed to true using bond {:?}", bond.identity);
                                         storage.security_level = bond.security_level;
+                                        storage.encryption_key_size = bond.encryption_key_size;
+                                        storage.secure_connections = bond.secure_connections();
                                     }
                                     _ => {
                                         warn!(
                                             "[smp] Either encryption failed to enable or bond not found for {:?}",
                                             identity
                                         );
-                                        storage.security_level = SecurityLevel::NoEncryption
+                                        storage.security_level = SecurityLevel::NoEncryption;
+                                        storage.encryption_key_size = 0;
+                                        storage.secure_connections = false;
                                     }
                                 }
                             }
@@ -636,6 +1190,10 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
                     }
                     Err(error) => {
                         error!("[security manager] Encryption Changed Handle Error {:?}", error);
+                        let _ = connections.with_connected_handle(event_data.handle, |storage| {
+                            storage.security_changed();
+                            Ok(())
+                        });
                     }
                 }
             }
@@ -694,6 +1252,29 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
         self.handle_event(pairing_event, connections, storage)
     }
 
+    /// Send a keypress notification to the peer while the user inputs the pass key
+    ///
+    /// Unlike pairing events, a notification that cannot be sent does not fail the pairing.
+    pub(crate) fn send_keypress_notification<P: PacketPool>(
+        &self,
+        notification: KeypressNotification,
+        connections: &ConnectionManager<'_, P>,
+        storage: &ConnectionStorage<P::Packet>,
+    ) -> Result<(), Error> {
+        let sm = self.pairing_sm.borrow();
+        let sm = sm.as_ref().ok_or(Error::InvalidState)?;
+        let mut ops = PairingOpsImpl {
+            peer_identity: storage.peer_identity.ok_or(Error::InvalidValue)?,
+            security_manager: self,
+            conn_handle: storage.handle.ok_or(Error::InvalidValue)?,
+            connections,
+            storage,
+        };
+        sm.send_keypress_notification(notification, &mut ops)?;
+        sm.reset_timeout();
+        Ok(())
+    }
+
     /// Prepare a packet for sending
     fn prepare_packet<P: PacketPool>(
         &self,
@@ -732,7 +1313,17 @@ impl<const BOND_COUNT: usize> SecurityManager<BOND_COUNT> {
             .map(|x| x.timeout_at())
             .unwrap_or(Instant::now() + constants::TIMEOUT_DISABLE);
         // try to pop an event from the channel
-        poll_fn(|cx| self.events.poll_receive(cx)).with_deadline(deadline)
+        poll_fn(|cx| {
+            if let Poll::Ready(event) = self.events.poll_receive(cx) {
+                return Poll::Ready(event);
+            }
+            if self.resolving_list_notify.replace(false) {
+                return Poll::Ready(SecurityEventData::ResolvingListChanged);
+            }
+            self.resolving_list_waker.borrow_mut().register(cx.waker());
+            Poll::Pending
+        })
+        .with_deadline(deadline)
     }
 }
 
@@ -753,21 +1344,38 @@ impl<'sm, 'cm, 'cm2, 'cs, const B: usize, P: PacketPool> PairingOps<P> for Pairi
     }
 
     fn try_update_bond_information(&mut self, bond: &BondInformation) -> Result<(), Error> {
-        self.security_manager.add_bond_information(bond.clone())
+        if let Some(evicted) = self.security_manager.add_bond_information(bond.clone())? {
+            // The bond is already stored, only the notification is lost
+            if let Err(e) = self.try_send_connection_event(ConnectionEvent::BondEvicted {
+                identity: evicted.identity,
+            }) {
+                warn!("[security manager] Unable to report evicted bond: {:?}", e);
+            }
+        }
+        Ok(())
     }
 
     fn try_enable_encryption(
         &mut self,
         ltk: &LongTermKey,
+        encryption_key_size: u8,
         security_level: SecurityLevel,
         is_bonded: bool,
+        central_identification: Option<CentralIdentification>,
     ) -> Result<BondInformation, Error> {
         info!("Enabling encryption for {:?}", self.peer_identity);
         let bond_info = BondInformation {
             ltk: *ltk,
+            encryption_key_size,
             identity: self.peer_identity,
+            // Identity addresses resolved by the controller are reported with types 0x02 and 0x03
+            identity_kind: AddrKind::new(self.storage.peer_addr_kind.unwrap_or_default().as_raw() & 0x01),
             is_bonded,
             security_level,
+            central_identification,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         };
         self.try_update_bond_information(&bond_info)?;
         self.security_manager
@@ -782,11 +1390,11 @@ impl<'sm, 'cm, 'cm2, 'cs, const B: usize, P: PacketPool> PairingOps<P> for Pairi
             .borrow()
             .bond
             .iter()
-            .find(|x| x.identity.match_identity(&self.peer_identity))
+            .find(|x| x.info.identity.match_identity(&self.peer_identity))
         {
             self.security_manager
-                .try_send_event(SecurityEventData::EnableEncryption(self.conn_handle, bond.clone()))?;
-            Ok(Some(bond.clone()))
+                .try_send_event(SecurityEventData::EnableEncryption(self.conn_handle, bond.info.clone()))?;
+            Ok(Some(bond.info.clone()))
         } else {
             Ok(None)
         }
@@ -800,6 +1408,54 @@ impl<'sm, 'cm, 'cm2, 'cs, const B: usize, P: PacketPool> PairingOps<P> for Pairi
         }
     }
 
+    fn allow_legacy_pairing(&self) -> bool {
+        !self.security_manager.policy.get().secure_connections_only
+    }
+
+    fn min_encryption_key_size(&self) -> u8 {
+        self.security_manager.policy.get().min_encryption_key_size
+    }
+
+    fn mitm_required(&self) -> bool {
+        self.security_manager.policy.get().mitm_required
+    }
+
+    fn cross_transport_key_derivation(&self) -> bool {
+        self.security_manager.policy.get().cross_transport_key_derivation
+    }
+
+    fn accept_pairing_request(&self, features: &PairingFeatures) -> bool {
+        let Some(accept) = self.security_manager.policy.get().accept_pairing else {
+            return true;
+        };
+        accept(&PairingRequest {
+            identity: self.peer_identity,
+            io_capabilities: features.io_capabilities,
+            bonding: matches!(features.security_properties.bond(), BondingFlag::Bonding),
+            mitm: features.security_properties.man_in_the_middle(),
+            secure_connections: features.security_properties.secure_connection(),
+            oob_data: features.use_oob.into(),
+            maximum_encryption_key_size: features.maximum_encryption_key_size,
+        })
+    }
+
+    fn local_oob_data(&self) -> Option<(SecretKey, Nonce)> {
+        self.security_manager.state.borrow().local_oob.clone()
+    }
+
+    fn local_identity(&self) -> Option<(Address, IdentityResolvingKey)> {
+        self.security_manager.state.borrow().local_identity
+    }
+
+    fn peer_oob_data(&self) -> Option<OobData> {
+        self.security_manager
+            .state
+            .borrow()
+            .peer_oob
+            .filter(|(address, _)| address.addr == self.peer_identity.bd_addr)
+            .map(|(_, oob_data)| oob_data)
+    }
+
     fn connection_handle(&mut self) -> ConnHandle {
         self.conn_handle
     }
@@ -809,10 +1465,220 @@ impl<'sm, 'cm, 'cm2, 'cs, const B: usize, P: PacketPool> PairingOps<P> for Pairi
             event,
             ConnectionEvent::PairingComplete { .. } | ConnectionEvent::PairingFailed(_)
         );
+        if let ConnectionEvent::PairingComplete { bond: Some(bond), .. } = &event {
+            self.security_manager
+                .bond_store_event(BondStoreEvent::Save(bond.identity));
+        }
         self.storage.events.try_send(event).map_err(|_| Error::OutOfMemory)?;
         if timer_changed {
+            self.storage.security_changed();
             let _ = self.security_manager.events.try_send(SecurityEventData::TimerChange);
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolving_list_follows_bonds() {
+        let sm: SecurityManager<4> = SecurityManager::new();
+        assert!(sm.take_resolving_list().is_none());
+
+        let local_irk = IdentityResolvingKey::new(1);
+        sm.set_local_identity(Address::random([1, 2, 3, 4, 5, 0xc6]), local_irk);
+        // The address type comes from the bond, not from the address bits
+        let public = BdAddr::new([1, 2, 3, 4, 5, 0xc6]);
+        let static_random = BdAddr::new([1, 2, 3, 4, 6, 0xc6]);
+        for (bd_addr, kind, irk) in [
+            (public, AddrKind::PUBLIC, Some(IdentityResolvingKey::new(2))),
+            (static_random, AddrKind::RANDOM, Some(IdentityResolvingKey::new(3))),
+            (BdAddr::new([7, 8, 9, 10, 11, 12]), AddrKind::PUBLIC, None),
+        ] {
+            let identity = Identity { bd_addr, irk };
+            let mut bond = BondInformation::new(identity, LongTermKey::new(4), SecurityLevel::Encrypted, true);
+            bond.identity_kind = kind;
+            unwrap!(sm.add_bond_information(bond));
+        }
+
+        // Only bonds with an IRK are resolved
+        let list = sm.take_resolving_list().unwrap();
+        assert_eq!(list.local_irk, Some(local_irk));
+        assert_eq!(
+            list.entries.as_slice(),
+            &[
+                (
+                    Address {
+                        kind: AddrKind::PUBLIC,
+                        addr: public
+                    },
+                    IdentityResolvingKey::new(2)
+                ),
+                (
+                    Address {
+                        kind: AddrKind::RANDOM,
+                        addr: static_random
+                    },
+                    IdentityResolvingKey::new(3)
+                ),
+            ]
+        );
+        assert!(sm.take_resolving_list().is_none());
+
+        // Retried if the controller refused it
+        sm.resolving_list_failed();
+        assert!(sm.take_resolving_list().is_some());
+
+        unwrap!(sm.remove_bond_information(Identity {
+            bd_addr: public,
+            irk: None
+        }));
+        assert_eq!(sm.take_resolving_list().unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn signed_data_rejects_replay() {
+        let local: SecurityManager<4> = SecurityManager::new();
+        let peer: SecurityManager<4> = SecurityManager::new();
+        let local_identity = Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+            irk: None,
+        };
+        let peer_identity = Identity {
+            bd_addr: BdAddr::new([7, 8, 9, 10, 11, 12]),
+            irk: None,
+        };
+        let csrk = ConnectionSignatureResolvingKey::new(0x1234);
+        let mut bond = BondInformation::new(peer_identity, LongTermKey::new(1), SecurityLevel::Encrypted, true);
+        bond.local_signing_key = Some(SigningKey::new(csrk));
+        unwrap!(local.add_bond_information(bond));
+        let mut bond = BondInformation::new(local_identity, LongTermKey::new(1), SecurityLevel::Encrypted, true);
+        bond.peer_signing_key = Some(SigningKey::new(csrk));
+        unwrap!(peer.add_bond_information(bond));
+
+        let message = [0xd2, 0x03, 0x00, 0x01];
+        let first = unwrap!(local.sign(&peer_identity, &message));
+        let second = unwrap!(local.sign(&peer_identity, &message));
+        assert_ne!(first, second);
+
+        assert!(peer.verify_signature(&local_identity, &message, &second).is_ok());
+        // The first signature has a lower sign counter than the last one accepted
+        assert!(peer.verify_signature(&local_identity, &message, &first).is_err());
+        assert!(peer.verify_signature(&local_identity, &message, &second).is_err());
+
+        let third = unwrap!(local.sign(&peer_identity, &message));
+        assert!(peer
+            .verify_signature(&local_identity, &[0xd2, 0x03, 0x00, 0x02], &third)
+            .is_err());
+        assert!(peer.verify_signature(&local_identity, &message, &third).is_ok());
+    }
+
+    #[test]
+    fn bond_store_events() {
+        let sm: SecurityManager<4> = SecurityManager::new();
+        let identity = Identity {
+            bd_addr: BdAddr::new([1, 2, 3, 4, 5, 6]),
+            irk: None,
+        };
+        let bond = BondInformation::new(identity, LongTermKey::new(1), SecurityLevel::Encrypted, true);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // Bonds loaded before a store is attached are not saved back
+        unwrap!(sm.add_bond_information(bond));
+        sm.peer_connected(&identity);
+        assert!(sm.poll_bond_store_event(&mut cx).is_pending());
+
+        sm.attach_bond_store();
+        sm.peer_connected(&identity);
+        unwrap!(sm.disconnect(ConnHandle::new(0), Some(identity)));
+        unwrap!(sm.disconnect(ConnHandle::new(0), Some(identity)));
+        assert_eq!(
+            sm.poll_bond_store_event(&mut cx),
+            Poll::Ready(BondStoreEvent::Restore(identity))
+        );
+        assert_eq!(
+            sm.poll_bond_store_event(&mut cx),
+            Poll::Ready(BondStoreEvent::Save(identity))
+        );
+        assert!(sm.poll_bond_store_event(&mut cx).is_pending());
+
+        // Removing the bond replaces pending changes of the peer
+        sm.peer_connected(&identity);
+        unwrap!(sm.remove_bond_information(identity));
+        assert_eq!(
+            sm.poll_bond_store_event(&mut cx),
+            Poll::Ready(BondStoreEvent::Remove(identity))
+        );
+        assert!(sm.poll_bond_store_event(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn bond_eviction() {
+        let sm: SecurityManager<2> = SecurityManager::new();
+        let identity = |n| Identity {
+            bd_addr: BdAddr::new([n, 2, 3, 4, 5, 6]),
+            irk: None,
+        };
+        let bond = |n| BondInformation::new(identity(n), LongTermKey::new(n as u128), SecurityLevel::Encrypted, true);
+        unwrap!(sm.add_bond_information(bond(1)));
+        unwrap!(sm.add_bond_information(bond(2)));
+        assert!(sm.add_bond_information(bond(3)).is_err());
+
+        sm.set_eviction_policy(BondEvictionPolicy::Oldest);
+        assert_eq!(unwrap!(sm.add_bond_information(bond(3))), Some(bond(1)));
+
+        // Bond 2 is the oldest, but bond 3 was used the longest time ago
+        sm.set_eviction_policy(BondEvictionPolicy::LeastRecentlyUsed);
+        sm.peer_connected(&identity(2));
+        assert_eq!(unwrap!(sm.add_bond_information(bond(1))), Some(bond(3)));
+
+        // Replacing a bond does not evict
+        assert_eq!(unwrap!(sm.add_bond_information(bond(1))), None);
+
+        unwrap!(sm.set_bond_pinned(&identity(2), true));
+        assert_eq!(unwrap!(sm.add_bond_information(bond(4))), Some(bond(1)));
+        unwrap!(sm.set_bond_pinned(&identity(4), true));
+        assert!(sm.add_bond_information(bond(5)).is_err());
+        assert!(sm.set_bond_pinned(&identity(5), true).is_err());
+    }
+
+    #[test]
+    fn policy_key_size_range() {
+        let sm: SecurityManager<2> = SecurityManager::new();
+        let policy = |min_encryption_key_size| SecurityPolicy {
+            min_encryption_key_size,
+            ..Default::default()
+        };
+        assert_eq!(sm.set_policy(policy(6)), Err(Error::InvalidValue));
+        assert_eq!(sm.set_policy(policy(17)), Err(Error::InvalidValue));
+        assert_eq!(sm.policy.get().min_encryption_key_size, 16);
+        unwrap!(sm.set_policy(policy(7)));
+        assert_eq!(sm.policy.get().min_encryption_key_size, 7);
+    }
+
+    #[test]
+    fn restored_bond_usage() {
+        let sm: SecurityManager<2> = SecurityManager::new();
+        let identity = |n| Identity {
+            bd_addr: BdAddr::new([n, 2, 3, 4, 5, 6]),
+            irk: None,
+        };
+        let bond = |n| BondInformation::new(identity(n), LongTermKey::new(n as u128), SecurityLevel::Encrypted, true);
+        let usage = |pinned, created, last_used| BondUsage {
+            pinned,
+            created,
+            last_used,
+        };
+        unwrap!(sm.restore_bond(bond(1), usage(false, 10, 40)));
+        unwrap!(sm.restore_bond(bond(2), usage(true, 20, 30)));
+        assert_eq!(sm.get_bond_usage(&identity(2)), Some(usage(true, 20, 30)));
+
+        // New bonds are more recent than the restored ones, and pinning survives
+        sm.set_eviction_policy(BondEvictionPolicy::LeastRecentlyUsed);
+        assert_eq!(unwrap!(sm.add_bond_information(bond(3))), Some(bond(1)));
+        assert!(sm.get_bond_usage(&identity(3)).unwrap().created > 40);
+    }
+}
@@ -7,16 +7,23 @@ use rand_core::{CryptoRng, RngCore};
 
 use crate::codec::{Decode, Encode};
 use crate::connection::{ConnectionEvent, SecurityLevel};
-use crate::security_manager::constants::ENCRYPTION_KEY_SIZE_128_BITS;
-use crate::security_manager::crypto::{Confirm, DHKey, MacKey, Nonce, PublicKey, SecretKey};
+use crate::security_manager::crypto::{
+    Confirm, ConnectionSignatureResolvingKey, DHKey, MacKey, Nonce, PublicKey, SecretKey, TemporaryKey,
+};
 use crate::security_manager::pairing::util::{
-    choose_pairing_method, make_confirm_packet, make_dhkey_check_packet, make_pairing_random, make_public_key_packet,
-    prepare_packet, CommandAndPayload, PairingMethod, PassKeyEntryAction,
+    check_security_policy, choose_legacy_pairing_method, choose_pairing_method, derive_link_key,
+    handle_keypress_notification, make_confirm_packet, make_dhkey_check_packet,
+    make_identity_address_information_packet, make_identity_information_packet, make_keypress_notification_packet,
+    make_legacy_confirm, make_pairing_random, make_public_key_packet, make_secret_key, make_signing_information_packet,
+    parse_central_identification, parse_identity_address_information, prepare_packet, verify_oob_data,
+    CommandAndPayload, PairingMethod, PassKeyEntryAction,
 };
 use crate::security_manager::pairing::{Event, PairingOps};
-use crate::security_manager::types::{AuthReq, BondingFlag, Command, PairingFeatures};
-use crate::security_manager::{PassKey, Reason};
-use crate::{Address, BondInformation, Error, IoCapabilities, LongTermKey, PacketPool};
+use crate::security_manager::types::{
+    AuthReq, BondingFlag, Command, KeypressNotification, PairingFeatures, UseOutOfBand,
+};
+use crate::security_manager::{CentralIdentification, PassKey, Reason, SigningKey};
+use crate::{Address, BondInformation, Error, IdentityResolvingKey, IoCapabilities, LongTermKey, PacketPool};
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -32,10 +39,20 @@ enum Step {
     WaitingPassKeyInput,
     WaitingPassKeyEntryConfirm(PassKeyEntryConfirmSentTag),
     WaitingPassKeyEntryRandom(i32),
-    // TODO add OOB
+    // Out of band
+    WaitingOobRandom,
     WaitingDHKeyEb(DHKeyEaSentTag),
+    // LE legacy pairing
+    WaitingLegacyPassKeyInput,
+    WaitingLegacyConfirm,
+    WaitingLegacyRandom,
     WaitingLinkEncrypted,
     WaitingBondedLinkEncryption,
+    WaitingEncryptionInformation,
+    WaitingCentralIdentification,
+    WaitingIdentityInformation,
+    WaitingIdentityAddressInformation,
+    WaitingSigningInformation,
     ReceivingKeys(i32),
     SendingKeys(i32),
     Success,
@@ -123,7 +140,7 @@ impl DHKeyEaSentTag {
         let check = make_dhkey_check_packet(&ea)?;
         ops.try_send_packet(check)?;
         pairing_data.mac_key = Some(mac);
-        pairing_data.ltk = Some(ltk);
+        pairing_data.ltk = Some(ltk.shorten(pairing_data.encryption_key_size()));
         Ok(DHKeyEaSentTag {})
     }
 }
@@ -147,6 +164,7 @@ struct PairingData {
     ltk: Option<LongTermKey>,
     timeout_at: Instant,
     bond_information: Option<BondInformation>,
+    legacy: bool,
 }
 
 impl PairingData {
@@ -154,6 +172,51 @@ impl PairingData {
         matches!(self.local_features.security_properties.bond(), BondingFlag::Bonding)
             && matches!(self.peer_features.security_properties.bond(), BondingFlag::Bonding)
     }
+
+    fn encryption_key_size(&self) -> u8 {
+        self.local_features
+            .maximum_encryption_key_size
+            .min(self.peer_features.maximum_encryption_key_size)
+    }
+
+    fn set_local_features<P: PacketPool, OPS: PairingOps<P>>(&mut self, ops: &OPS) {
+        let bonding = ops.bonding_flag();
+        self.local_features.security_properties = AuthReq::new(bonding);
+        if ops.cross_transport_key_derivation() {
+            self.local_features.security_properties.set_ct2();
+        }
+        // Legacy pairing peers distribute their LTK after encryption, Secure Connections peers ignore this
+        if ops.allow_legacy_pairing() && matches!(bonding, BondingFlag::Bonding) {
+            self.local_features.responder_key_distribution.set_encryption_key();
+        }
+        if matches!(bonding, BondingFlag::Bonding) {
+            self.local_features.responder_key_distribution.set_identity_key();
+            self.local_features.responder_key_distribution.set_signing_key();
+            self.local_features.initiator_key_distribution.set_signing_key();
+            // Derived from the LTK for BR/EDR, ignored by legacy pairing peers
+            if ops.cross_transport_key_derivation() {
+                self.local_features.responder_key_distribution.set_link_key();
+                self.local_features.initiator_key_distribution.set_link_key();
+            }
+        }
+        if ops.local_identity().is_some() {
+            self.local_features.initiator_key_distribution.set_identity_key();
+        }
+        if ops.peer_oob_data().is_some() {
+            self.local_features.use_oob = UseOutOfBand::Present;
+        }
+    }
+
+    fn legacy_confirm(&self, nonce: &Nonce) -> Result<Confirm, Error> {
+        make_legacy_confirm(
+            &TemporaryKey(self.local_secret_ra),
+            nonce,
+            &self.local_features,
+            &self.peer_features,
+            self.local_address,
+            self.peer_address,
+        )
+    }
 }
 
 pub struct Pairing {
@@ -207,6 +270,7 @@ impl Pairing {
             private_key: None,
             timeout_at: Instant::now() + crate::security_manager::constants::TIMEOUT_DISABLE,
             bond_information: None,
+            legacy: false,
         };
         Self {
             pairing_data: RefCell::new(pairing_data),
@@ -223,7 +287,7 @@ impl Pairing {
         let ret = Self::new_idle(local_address, peer_address, local_io);
         {
             let mut pairing_data = ret.pairing_data.borrow_mut();
-            pairing_data.local_features.security_properties = AuthReq::new(ops.bonding_flag());
+            pairing_data.set_local_features(ops);
             let next_step = if let Some(bond) = ops.try_enable_bonded_encryption()? {
                 pairing_data.bond_information = Some(bond);
                 Step::WaitingBondedLinkEncryption
@@ -243,7 +307,14 @@ impl Pairing {
     pub fn security_level(&self) -> SecurityLevel {
         let step = self.current_step.borrow();
         match step.deref() {
-            Step::SendingKeys(_) | Step::ReceivingKeys(_) | Step::Success => self
+            Step::WaitingEncryptionInformation
+            | Step::WaitingCentralIdentification
+            | Step::WaitingIdentityInformation
+            | Step::WaitingIdentityAddressInformation
+            | Step::WaitingSigningInformation
+            | Step::SendingKeys(_)
+            | Step::ReceivingKeys(_)
+            | Step::Success => self
                 .pairing_data
                 .borrow()
                 .bond_information
@@ -254,6 +325,43 @@ impl Pairing {
         }
     }
 
+    /// Send a keypress notification while the pass key is input, if both devices support them
+    pub fn send_keypress_notification<P: PacketPool, OPS: PairingOps<P>>(
+        &self,
+        notification: KeypressNotification,
+        ops: &mut OPS,
+    ) -> Result<(), Error> {
+        if !matches!(
+            self.current_step.borrow().deref(),
+            Step::WaitingPassKeyInput | Step::WaitingLegacyPassKeyInput
+        ) {
+            return Err(Error::InvalidState);
+        }
+        let pairing_data = self.pairing_data.borrow();
+        if !pairing_data.local_features.security_properties.key_press_notification()
+            || !pairing_data.peer_features.security_properties.key_press_notification()
+        {
+            return Err(Error::NotSupported);
+        }
+        ops.try_send_packet(make_keypress_notification_packet(notification)?)
+    }
+
+    pub fn secure_connections(&self) -> bool {
+        self.pairing_data
+            .borrow()
+            .bond_information
+            .as_ref()
+            .is_some_and(|bond| bond.secure_connections())
+    }
+
+    pub fn encryption_key_size(&self) -> u8 {
+        self.pairing_data
+            .borrow()
+            .bond_information
+            .as_ref()
+            .map_or(0, |bond| bond.encryption_key_size)
+    }
+
     pub fn handle_l2cap_command<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
         &self,
         command: Command,
@@ -282,8 +390,14 @@ impl Pairing {
             (Step::WaitingLinkEncrypted, Event::LinkEncryptedResult(res)) => {
                 if res {
                     info!("Link encrypted!");
-                    // TODO wait for keys
-                    Step::Success
+                    Self::key_distribution(
+                        self.pairing_data.borrow_mut().deref_mut(),
+                        ops,
+                        rng,
+                        false,
+                        false,
+                        false,
+                    )?
                 } else {
                     error!("Link encryption failed!");
                     Step::Error(Error::Security(Reason::KeyRejected))
@@ -304,6 +418,11 @@ impl Pairing {
             (Step::WaitingNumericComparisonResult, Event::PassKeyCancel) => {
                 Step::Error(Error::Security(Reason::NumericComparisonFailed))
             }
+            (Step::WaitingLegacyPassKeyInput, Event::PassKeyInput(input)) => {
+                let mut pairing_data = self.pairing_data.borrow_mut();
+                pairing_data.local_secret_ra = input as u128;
+                Self::send_legacy_confirm(pairing_data.deref_mut(), ops, rng)?
+            }
             (Step::WaitingPassKeyInput, Event::PassKeyInput(input)) => {
                 let mut pairing_data = self.pairing_data.borrow_mut();
                 pairing_data.local_secret_ra = input as u128;
@@ -362,7 +481,7 @@ impl Pairing {
             trace!("Handling {:?}, step {:?}", command.command, current_step);
             match (current_step, command.command) {
                 (Step::Idle, Command::SecurityRequest) => {
-                    pairing_data.local_features.security_properties = AuthReq::new(ops.bonding_flag());
+                    pairing_data.set_local_features(ops);
                     if let Some(bond) = ops.try_enable_bonded_encryption()? {
                         pairing_data.bond_information = Some(bond);
                         Step::WaitingBondedLinkEncryption
@@ -376,14 +495,67 @@ impl Pairing {
                 }
                 (Step::WaitingPairingResponse(_), Command::PairingResponse) => {
                     Self::handle_pairing_response(command.payload, ops, pairing_data)?;
-                    Self::generate_private_public_key_pair(pairing_data, rng)?;
-                    Self::send_public_key(ops, pairing_data.local_public_key.as_ref().unwrap())?;
-                    Step::WaitingPublicKey
+                    if pairing_data.legacy {
+                        Self::start_legacy_pairing(pairing_data, ops, rng)?
+                    } else {
+                        Self::generate_private_public_key_pair(pairing_data, ops, rng)?;
+                        Self::send_public_key(ops, pairing_data.local_public_key.as_ref().unwrap())?;
+                        Step::WaitingPublicKey
+                    }
+                }
+                (Step::WaitingLegacyConfirm, Command::PairingConfirm) => {
+                    Self::handle_pass_key_confirm(command.payload, pairing_data)?;
+                    Self::send_nonce(ops, &pairing_data.local_nonce)?;
+                    Step::WaitingLegacyRandom
+                }
+                (Step::WaitingLegacyRandom, Command::PairingRandom) => {
+                    Self::handle_legacy_random(command.payload, ops, pairing_data)?;
+                    Step::WaitingLinkEncrypted
+                }
+                (Step::WaitingEncryptionInformation, Command::EncryptionInformation) => {
+                    pairing_data.ltk = Some(LongTermKey::from_le_bytes(
+                        command.payload.try_into().map_err(|_| Error::InvalidValue)?,
+                    ));
+                    Step::WaitingCentralIdentification
+                }
+                (Step::WaitingCentralIdentification, Command::CentralIdentification) => {
+                    Self::handle_central_identification(command.payload, ops, pairing_data)?;
+                    Self::key_distribution(pairing_data, ops, rng, true, false, false)?
+                }
+                (Step::WaitingIdentityInformation, Command::IdentityInformation) => {
+                    let irk = IdentityResolvingKey::from_le_bytes(
+                        command.payload.try_into().map_err(|_| Error::InvalidValue)?,
+                    );
+                    if let Some(bond) = pairing_data.bond_information.as_mut() {
+                        bond.identity.irk = Some(irk);
+                    }
+                    Step::WaitingIdentityAddressInformation
+                }
+                (Step::WaitingIdentityAddressInformation, Command::IdentityAddressInformation) => {
+                    Self::handle_identity_address_information(command.payload, ops, pairing_data)?;
+                    Self::key_distribution(pairing_data, ops, rng, true, true, false)?
+                }
+                (Step::WaitingSigningInformation, Command::SigningInformation) => {
+                    let csrk = ConnectionSignatureResolvingKey::from_le_bytes(
+                        command.payload.try_into().map_err(|_| Error::InvalidValue)?,
+                    );
+                    if let Some(bond) = pairing_data.bond_information.as_mut() {
+                        bond.peer_signing_key = Some(SigningKey::new(csrk));
+                        if bond.is_bonded {
+                            ops.try_update_bond_information(bond)?;
+                        }
+                    }
+                    Self::key_distribution(pairing_data, ops, rng, true, true, true)?
                 }
                 (Step::WaitingPublicKey, Command::PairingPublicKey) => {
                     Self::handle_public_key(command.payload, pairing_data)?;
                     match pairing_data.pairing_method {
-                        PairingMethod::OutOfBand => todo!("OOB not implemented"),
+                        PairingMethod::OutOfBand => {
+                            Self::handle_peer_oob_data(ops, pairing_data)?;
+                            pairing_data.local_nonce = Nonce::new(rng);
+                            Self::send_nonce(ops, &pairing_data.local_nonce)?;
+                            Step::WaitingOobRandom
+                        }
                         PairingMethod::PassKeyEntry { central, .. } => {
                             if central == PassKeyEntryAction::Display {
                                 pairing_data.local_secret_ra =
@@ -406,6 +578,12 @@ impl Pairing {
                         _ => Step::WaitingNumericComparisonConfirm,
                     }
                 }
+                (Step::WaitingOobRandom, Command::PairingRandom) => {
+                    pairing_data.peer_nonce = Nonce(u128::from_le_bytes(
+                        command.payload.try_into().map_err(|_| Error::InvalidValue)?,
+                    ));
+                    Step::WaitingDHKeyEb(DHKeyEaSentTag::new(pairing_data, ops)?)
+                }
                 (Step::WaitingNumericComparisonConfirm, Command::PairingConfirm) => {
                     Self::handle_numeric_compare_confirm(command.payload, pairing_data, rng)?;
                     Self::send_nonce(ops, &pairing_data.local_nonce)?;
@@ -437,13 +615,30 @@ impl Pairing {
                     Self::handle_dhkey_eb(command.payload, ops, pairing_data)?;
                     Step::WaitingLinkEncrypted
                 }
-                (x, Command::KeypressNotification) => x,
+                (x, Command::KeypressNotification) => {
+                    handle_keypress_notification(command.payload, ops);
+                    x
+                }
 
                 _ => return Err(Error::InvalidState),
             }
         };
 
+        let is_success = matches!(next_step, Step::Success);
         self.current_step.replace(next_step);
+        if is_success {
+            if let Some(bond) = pairing_data.bond_information.as_ref() {
+                let pairing_bond = if pairing_data.want_bonding() {
+                    Some(bond.clone())
+                } else {
+                    None
+                };
+                ops.try_send_connection_event(ConnectionEvent::PairingComplete {
+                    security_level: bond.security_level,
+                    bond: pairing_bond,
+                })?;
+            }
+        }
 
         Ok(())
     }
@@ -454,25 +649,182 @@ impl Pairing {
         pairing_data: &mut PairingData,
     ) -> Result<(), Error> {
         let peer_features = PairingFeatures::decode(payload).map_err(|_| Error::Security(Reason::InvalidParameters))?;
-        if peer_features.maximum_encryption_key_size < ENCRYPTION_KEY_SIZE_128_BITS {
-            return Err(Error::Security(Reason::EncryptionKeySize));
-        }
-        if !peer_features.security_properties.secure_connection() {
-            return Err(Error::Security(Reason::UnspecifiedReason));
+        pairing_data.legacy = !peer_features.security_properties.secure_connection();
+        if pairing_data.legacy && !ops.allow_legacy_pairing() {
+            warn!("[smp] Peer does not support LE Secure Connections");
+            return Err(Error::Security(Reason::AuthenticationRequirements));
         }
 
         pairing_data.peer_features = peer_features;
-        pairing_data.pairing_method = choose_pairing_method(pairing_data.local_features, pairing_data.peer_features);
-        info!("[smp] Pairing method {:?}", pairing_data.pairing_method);
+        pairing_data.pairing_method = if pairing_data.legacy {
+            choose_legacy_pairing_method(pairing_data.local_features, pairing_data.peer_features)
+        } else {
+            choose_pairing_method(pairing_data.local_features, pairing_data.peer_features)
+        };
+        check_security_policy(
+            ops,
+            &pairing_data.local_features,
+            &pairing_data.peer_features,
+            pairing_data.pairing_method,
+        )?;
+        info!(
+            "[smp] Pairing method {:?}, legacy {}",
+            pairing_data.pairing_method, pairing_data.legacy
+        );
+
+        Ok(())
+    }
+
+    fn start_legacy_pairing<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        pairing_data: &mut PairingData,
+        ops: &mut OPS,
+        rng: &mut RNG,
+    ) -> Result<Step, Error> {
+        match pairing_data.pairing_method {
+            PairingMethod::OutOfBand => Err(Error::Security(Reason::OobNotAvailable)),
+            PairingMethod::PassKeyEntry {
+                central: PassKeyEntryAction::Input,
+                ..
+            } => {
+                ops.try_send_connection_event(ConnectionEvent::PassKeyInput)?;
+                Ok(Step::WaitingLegacyPassKeyInput)
+            }
+            PairingMethod::PassKeyEntry { .. } => {
+                pairing_data.local_secret_ra = rng.sample(rand::distributions::Uniform::new_inclusive(0, 999999));
+                ops.try_send_connection_event(ConnectionEvent::PassKeyDisplay(PassKey(
+                    pairing_data.local_secret_ra as u32,
+                )))?;
+                Self::send_legacy_confirm(pairing_data, ops, rng)
+            }
+            _ => {
+                // Just Works uses a zero temporary key
+                pairing_data.local_secret_ra = 0;
+                Self::send_legacy_confirm(pairing_data, ops, rng)
+            }
+        }
+    }
+
+    fn send_legacy_confirm<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        pairing_data: &mut PairingData,
+        ops: &mut OPS,
+        rng: &mut RNG,
+    ) -> Result<Step, Error> {
+        pairing_data.local_nonce = Nonce::new(rng);
+        let confirm = pairing_data.legacy_confirm(&pairing_data.local_nonce)?;
+        ops.try_send_packet(make_confirm_packet(&confirm)?)?;
+        Ok(Step::WaitingLegacyConfirm)
+    }
+
+    fn handle_legacy_random<P: PacketPool, OPS: PairingOps<P>>(
+        payload: &[u8],
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+    ) -> Result<(), Error> {
+        let peer_nonce = Nonce(u128::from_le_bytes(
+            payload.try_into().map_err(|_| Error::InvalidValue)?,
+        ));
+        if pairing_data.legacy_confirm(&peer_nonce)? != pairing_data.confirm {
+            return Err(Error::Security(Reason::ConfirmValueFailed));
+        }
+        pairing_data.peer_nonce = peer_nonce;
 
+        let stk = TemporaryKey(pairing_data.local_secret_ra)
+            .s1(pairing_data.peer_nonce, pairing_data.local_nonce)
+            .shorten(pairing_data.encryption_key_size());
+        let bond = ops.try_enable_encryption(
+            &stk,
+            pairing_data.encryption_key_size(),
+            pairing_data.pairing_method.security_level(),
+            false,
+            Some(CentralIdentification::default()),
+        )?;
+        pairing_data.bond_information = Some(bond);
         Ok(())
     }
 
-    fn generate_private_public_key_pair<RNG: CryptoRng + RngCore>(
+    /// Next step of the key distribution, the peripheral distributes its keys first ([Vol 3] Part H, Section 3.6.1)
+    fn key_distribution<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
         pairing_data: &mut PairingData,
+        ops: &mut OPS,
         rng: &mut RNG,
+        encryption_key_received: bool,
+        identity_received: bool,
+        signing_received: bool,
+    ) -> Result<Step, Error> {
+        let responder_keys = pairing_data.peer_features.responder_key_distribution;
+        if pairing_data.legacy && responder_keys.encryption_key() && !encryption_key_received {
+            // Peripheral distributes its LTK now that the link is encrypted with the STK
+            return Ok(Step::WaitingEncryptionInformation);
+        }
+        if responder_keys.identity_key() && !identity_received {
+            return Ok(Step::WaitingIdentityInformation);
+        }
+        if responder_keys.signing_key() && !signing_received {
+            return Ok(Step::WaitingSigningInformation);
+        }
+        let initiator_keys = pairing_data.peer_features.initiator_key_distribution;
+        if initiator_keys.identity_key() {
+            let (address, irk) = ops.local_identity().ok_or(Error::InvalidValue)?;
+            ops.try_send_packet(make_identity_information_packet(&irk)?)?;
+            ops.try_send_packet(make_identity_address_information_packet(&address)?)?;
+        }
+        if initiator_keys.signing_key() {
+            let csrk = ConnectionSignatureResolvingKey::new(rng.gen());
+            ops.try_send_packet(make_signing_information_packet(&csrk)?)?;
+            if let Some(bond) = pairing_data.bond_information.as_mut() {
+                bond.local_signing_key = Some(SigningKey::new(csrk));
+                if bond.is_bonded {
+                    ops.try_update_bond_information(bond)?;
+                }
+            }
+        }
+        Ok(Step::Success)
+    }
+
+    fn handle_identity_address_information<P: PacketPool, OPS: PairingOps<P>>(
+        payload: &[u8],
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
     ) -> Result<(), Error> {
-        let secret_key = SecretKey::new(rng);
+        let address = parse_identity_address_information(payload)?;
+        trace!("Identity address information: {:?}", address);
+        pairing_data.peer_address = address;
+        if let Some(bond) = pairing_data.bond_information.as_mut() {
+            bond.identity.bd_addr = address.addr;
+            bond.identity_kind = address.kind;
+            if bond.is_bonded {
+                ops.try_update_bond_information(bond)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_central_identification<P: PacketPool, OPS: PairingOps<P>>(
+        payload: &[u8],
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+    ) -> Result<(), Error> {
+        let central_identification = parse_central_identification(payload)?;
+        let ltk = pairing_data.ltk.ok_or(Error::InvalidValue)?;
+        let is_bonded = pairing_data.want_bonding();
+        let bond = pairing_data.bond_information.as_mut().ok_or(Error::InvalidValue)?;
+        bond.ltk = ltk;
+        bond.central_identification = Some(central_identification);
+        bond.is_bonded = is_bonded;
+        if bond.is_bonded {
+            ops.try_update_bond_information(bond)?;
+        }
+        Ok(())
+    }
+
+    fn generate_private_public_key_pair<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        pairing_data: &mut PairingData,
+        ops: &OPS,
+        rng: &mut RNG,
+    ) -> Result<(), Error> {
+        let (secret_key, local_oob_random) =
+            make_secret_key(pairing_data.pairing_method, &pairing_data.peer_features, ops, rng)?;
+        pairing_data.local_secret_ra = local_oob_random;
         let public_key = secret_key.public_key();
         pairing_data.local_public_key = Some(public_key);
         pairing_data.private_key = Some(secret_key);
@@ -508,6 +860,19 @@ impl Pairing {
         Ok(())
     }
 
+    fn handle_peer_oob_data<P: PacketPool, OPS: PairingOps<P>>(
+        ops: &OPS,
+        pairing_data: &mut PairingData,
+    ) -> Result<(), Error> {
+        // rb is zero if the peripheral OOB data was not received
+        if matches!(pairing_data.local_features.use_oob, UseOutOfBand::Present) {
+            let oob_data = ops.peer_oob_data().ok_or(Error::Security(Reason::OobNotAvailable))?;
+            let peer_public_key = pairing_data.peer_public_key.as_ref().ok_or(Error::InvalidValue)?;
+            pairing_data.peer_secret_rb = verify_oob_data(&oob_data, peer_public_key)?;
+        }
+        Ok(())
+    }
+
     fn handle_numeric_compare_confirm<RNG: CryptoRng + RngCore>(
         payload: &[u8],
         pairing_data: &mut PairingData,
@@ -590,11 +955,18 @@ impl Pairing {
             return Err(Error::Security(Reason::DHKeyCheckFailed));
         }
 
-        let bond = ops.try_enable_encryption(
-            &pairing_data.ltk.ok_or(Error::InvalidValue)?,
+        let ltk = pairing_data.ltk.ok_or(Error::InvalidValue)?;
+        let mut bond = ops.try_enable_encryption(
+            &ltk,
+            pairing_data.encryption_key_size(),
             pairing_data.pairing_method.security_level(),
             pairing_data.want_bonding(),
+            None,
         )?;
+        bond.link_key = derive_link_key(&pairing_data.local_features, &pairing_data.peer_features, &ltk);
+        if bond.is_bonded && bond.link_key.is_some() {
+            ops.try_update_bond_information(&bond)?;
+        }
         pairing_data.bond_information = Some(bond);
         Ok(())
     }
@@ -629,3 +1001,148 @@ impl Pairing {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use core::ops::Deref;
+
+    use rand_chacha::{ChaCha12Core, ChaCha12Rng};
+    use rand_core::SeedableRng;
+
+    use super::{Pairing, Step};
+    use crate::prelude::{ConnectionEvent, SecurityLevel};
+    use crate::security_manager::crypto::{Nonce, TemporaryKey};
+    use crate::security_manager::pairing::tests::{HeaplessPool, TestOps};
+    use crate::security_manager::pairing::Event;
+    use crate::security_manager::types::Command;
+    use crate::security_manager::CentralIdentification;
+    use crate::{Address, IoCapabilities, LongTermKey};
+
+    #[test]
+    fn legacy_pass_key_entry_with_ltk_distribution() {
+        let mut pairing_ops: TestOps<10> = TestOps {
+            bondable: true,
+            allow_legacy: true,
+            ..Default::default()
+        };
+        let local = Address::random([1, 2, 3, 4, 5, 6]);
+        let peer = Address::random([7, 8, 9, 10, 11, 12]);
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        let pairing =
+            Pairing::initiate::<HeaplessPool, _>(local, peer, &mut pairing_ops, IoCapabilities::KeyboardOnly).unwrap();
+
+        // Request asks for the peripheral LTK so a legacy peripheral can distribute it, its identity and signing keys
+        assert_eq!(pairing_ops.sent_packets[0].command, Command::PairingRequest);
+        let pairing_request: [u8; 6] = pairing_ops.sent_packets[0].payload().try_into().unwrap();
+        assert_eq!(pairing_request, [0x02, 0x00, 0x1d, 16, 0x04, 0x07]);
+
+        // Legacy peripheral with a display, only distributing its LTK
+        let pairing_response = [0x00, 0x00, 0x05, 16, 0x00, 0x01];
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingResponse,
+                &pairing_response,
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert!(matches!(
+            pairing_ops.connection_events[0],
+            ConnectionEvent::PassKeyInput
+        ));
+        pairing
+            .handle_event(Event::PassKeyInput(123456), &mut pairing_ops, &mut rng)
+            .unwrap();
+        assert_eq!(pairing_ops.sent_packets[1].command, Command::PairingConfirm);
+
+        let preq = [&[0x01][..], &pairing_request[..]].concat();
+        let pres = [&[0x02][..], &pairing_response[..]].concat();
+        let tk = TemporaryKey(123456);
+        let confirm = |nonce: Nonce| {
+            tk.c1(
+                nonce,
+                &preq.clone().try_into().unwrap(),
+                &pres.clone().try_into().unwrap(),
+                local,
+                peer,
+            )
+            .0
+            .to_le_bytes()
+        };
+
+        // Peripheral sends Sconfirm, expects Mrand matching Mconfirm
+        let srand = Nonce(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10);
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingConfirm,
+                &confirm(srand),
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert_eq!(pairing_ops.sent_packets[2].command, Command::PairingRandom);
+        let mrand = Nonce(u128::from_le_bytes(
+            pairing_ops.sent_packets[2].payload().try_into().unwrap(),
+        ));
+        assert_eq!(pairing_ops.sent_packets[1].payload(), &confirm(mrand));
+
+        // Peripheral sends Srand, the link is encrypted with the STK
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingRandom,
+                &srand.0.to_le_bytes(),
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert_eq!(pairing_ops.encryptions[0], tk.s1(srand, mrand));
+        pairing
+            .handle_event(Event::LinkEncryptedResult(true), &mut pairing_ops, &mut rng)
+            .unwrap();
+        assert!(matches!(
+            pairing.current_step.borrow().deref(),
+            Step::WaitingEncryptionInformation
+        ));
+
+        // Peripheral distributes its LTK
+        let ltk = LongTermKey(0x0f0e_0d0c_0b0a_0908_0706_0504_0302_0100);
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::EncryptionInformation,
+                &ltk.to_le_bytes(),
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::CentralIdentification,
+                &[0x34, 0x12, 1, 2, 3, 4, 5, 6, 7, 8],
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert!(matches!(pairing.current_step.borrow().deref(), Step::Success));
+        assert_eq!(pairing_ops.sent_packets.len(), 3);
+
+        match &pairing_ops.connection_events[1] {
+            ConnectionEvent::PairingComplete {
+                security_level,
+                bond: Some(bond),
+            } => {
+                assert_eq!(*security_level, SecurityLevel::EncryptedAuthenticated);
+                assert!(bond.is_bonded);
+                assert_eq!(bond.ltk, ltk);
+                assert_eq!(
+                    bond.central_identification,
+                    Some(CentralIdentification {
+                        ediv: 0x1234,
+                        rand: [1, 2, 3, 4, 5, 6, 7, 8],
+                    })
+                );
+            }
+            _ => panic!("Unexpected connection event"),
+        }
+        assert!(!pairing.secure_connections());
+    }
+}
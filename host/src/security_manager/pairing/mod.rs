@@ -3,28 +3,41 @@ use embassy_time::Instant;
 use rand_core::{CryptoRng, RngCore};
 
 use crate::connection::{ConnectionEvent, SecurityLevel};
-use crate::security_manager::types::{BondingFlag, Command};
-use crate::security_manager::TxPacket;
-use crate::{Address, BondInformation, Error, IoCapabilities, LongTermKey, PacketPool};
+use crate::security_manager::crypto::{Nonce, SecretKey};
+use crate::security_manager::types::{BondingFlag, Command, KeypressNotification, PairingFeatures};
+use crate::security_manager::{CentralIdentification, OobData, TxPacket};
+use crate::{Address, BondInformation, Error, IdentityResolvingKey, IoCapabilities, LongTermKey, PacketPool};
 
 pub mod central;
 pub mod peripheral;
 // pub mod central;
 mod util;
 
+pub(crate) use util::make_oob_data;
+
 pub trait PairingOps<P: PacketPool> {
     fn try_send_packet(&mut self, packet: TxPacket<P>) -> Result<(), Error>;
     fn try_enable_bonded_encryption(&mut self) -> Result<Option<BondInformation>, Error>;
     fn try_enable_encryption(
         &mut self,
         ltk: &LongTermKey,
+        encryption_key_size: u8,
         security_level: SecurityLevel,
         is_bonded: bool,
+        central_identification: Option<CentralIdentification>,
     ) -> Result<BondInformation, Error>;
     fn try_update_bond_information(&mut self, bond: &BondInformation) -> Result<(), Error>;
     fn connection_handle(&mut self) -> ConnHandle;
     fn try_send_connection_event(&mut self, event: ConnectionEvent) -> Result<(), Error>;
     fn bonding_flag(&self) -> BondingFlag;
+    fn allow_legacy_pairing(&self) -> bool;
+    fn min_encryption_key_size(&self) -> u8;
+    fn mitm_required(&self) -> bool;
+    fn cross_transport_key_derivation(&self) -> bool;
+    fn accept_pairing_request(&self, features: &PairingFeatures) -> bool;
+    fn local_oob_data(&self) -> Option<(SecretKey, Nonce)>;
+    fn local_identity(&self) -> Option<(Address, IdentityResolvingKey)>;
+    fn peer_oob_data(&self) -> Option<OobData>;
 }
 
 pub enum Pairing {
@@ -61,12 +74,36 @@ impl Pairing {
         }
     }
 
+    pub(crate) fn send_keypress_notification<P: PacketPool, OPS: PairingOps<P>>(
+        &self,
+        notification: KeypressNotification,
+        ops: &mut OPS,
+    ) -> Result<(), Error> {
+        match self {
+            Pairing::Central(central) => central.send_keypress_notification(notification, ops),
+            Pairing::Peripheral(peripheral) => peripheral.send_keypress_notification(notification, ops),
+        }
+    }
+
     pub(crate) fn security_level(&self) -> SecurityLevel {
         match self {
             Pairing::Central(c) => c.security_level(),
             Pairing::Peripheral(p) => p.security_level(),
         }
     }
+    pub(crate) fn secure_connections(&self) -> bool {
+        match self {
+            Pairing::Central(c) => c.secure_connections(),
+            Pairing::Peripheral(p) => p.secure_connections(),
+        }
+    }
+    pub(crate) fn encryption_key_size(&self) -> u8 {
+        match self {
+            Pairing::Central(c) => c.encryption_key_size(),
+            Pairing::Peripheral(p) => p.encryption_key_size(),
+        }
+    }
+
     pub(crate) fn new_central(local_address: Address, peer_address: Address, local_io: IoCapabilities) -> Pairing {
         Pairing::Central(central::Pairing::new_idle(local_address, peer_address, local_io))
     }
@@ -141,10 +178,13 @@ pub enum Event {
 
 #[cfg(test)]
 mod tests {
+    use bt_hci::param::AddrKind;
     use rand_chacha::{ChaCha12Core, ChaCha12Rng};
     use rand_core::SeedableRng;
 
     use super::*;
+    use crate::security_manager::constants::ENCRYPTION_KEY_SIZE_128_BITS;
+    use crate::security_manager::Reason;
     use crate::{Identity, Packet};
 
     #[derive(Debug)]
@@ -189,6 +229,14 @@ mod tests {
         pub(crate) connection_events: heapless::Vec<ConnectionEvent, 10>,
         pub(crate) bond_information: Option<BondInformation>,
         pub(crate) bondable: bool,
+        pub(crate) allow_legacy: bool,
+        pub(crate) min_encryption_key_size: Option<u8>,
+        pub(crate) mitm_required: bool,
+        pub(crate) cross_transport_key_derivation: bool,
+        pub(crate) reject_pairing: bool,
+        pub(crate) local_oob: Option<(SecretKey, Nonce)>,
+        pub(crate) peer_oob: Option<OobData>,
+        pub(crate) local_identity: Option<(Address, IdentityResolvingKey)>,
     }
 
     impl<const N: usize> PairingOps<HeaplessPool> for TestOps<N> {
@@ -199,21 +247,29 @@ mod tests {
         fn try_enable_encryption(
             &mut self,
             ltk: &LongTermKey,
+            encryption_key_size: u8,
             security_level: SecurityLevel,
             is_bonded: bool,
+            central_identification: Option<CentralIdentification>,
         ) -> Result<BondInformation, Error> {
-            self.encryptions.push(ltk.clone()).unwrap();
+            self.encryptions.push(*ltk).unwrap();
             Ok(BondInformation {
+                encryption_key_size,
                 security_level,
                 identity: Identity::default(),
-                ltk: ltk.clone(),
+                identity_kind: AddrKind::PUBLIC,
+                ltk: *ltk,
                 is_bonded,
+                central_identification,
+                local_signing_key: None,
+                peer_signing_key: None,
+                link_key: None,
             })
         }
 
         fn try_enable_bonded_encryption(&mut self) -> Result<Option<BondInformation>, Error> {
             if let Some(bond) = &self.bond_information {
-                self.encryptions.push(bond.ltk.clone()).unwrap();
+                self.encryptions.push(bond.ltk).unwrap();
                 Ok(Some(bond.clone()))
             } else {
                 Ok(None)
@@ -229,8 +285,7 @@ mod tests {
         }
 
         fn try_send_connection_event(&mut self, event: ConnectionEvent) -> Result<(), Error> {
-            self.connection_events.push(event).unwrap();
-            Ok(())
+            self.connection_events.push(event).map_err(|_| Error::OutOfMemory)
         }
 
         fn bonding_flag(&self) -> BondingFlag {
@@ -240,6 +295,38 @@ mod tests {
                 BondingFlag::NoBonding
             }
         }
+
+        fn allow_legacy_pairing(&self) -> bool {
+            self.allow_legacy
+        }
+
+        fn min_encryption_key_size(&self) -> u8 {
+            self.min_encryption_key_size.unwrap_or(ENCRYPTION_KEY_SIZE_128_BITS)
+        }
+
+        fn mitm_required(&self) -> bool {
+            self.mitm_required
+        }
+
+        fn cross_transport_key_derivation(&self) -> bool {
+            self.cross_transport_key_derivation
+        }
+
+        fn accept_pairing_request(&self, _features: &PairingFeatures) -> bool {
+            !self.reject_pairing
+        }
+
+        fn local_oob_data(&self) -> Option<(SecretKey, Nonce)> {
+            self.local_oob.clone()
+        }
+
+        fn local_identity(&self) -> Option<(Address, IdentityResolvingKey)> {
+            self.local_identity
+        }
+
+        fn peer_oob_data(&self) -> Option<OobData> {
+            self.peer_oob
+        }
     }
 
     #[test]
@@ -537,6 +624,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pass_key_entry_keypress_notifications() {
+        let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
+        let central = Address::random([0xff, 2, 2, 3, 4, 5]);
+
+        let mut peripheral_ops = TestOps::<80>::default();
+        let mut central_ops = TestOps::<80>::default();
+
+        let peripheral_pairing = peripheral::Pairing::new(peripheral, central, IoCapabilities::DisplayOnly);
+        let central_pairing =
+            central::Pairing::initiate(central, peripheral, &mut central_ops, IoCapabilities::KeyboardOnly).unwrap();
+
+        let mut num_central_data_sent = 0;
+        let mut num_peripheral_data_sent = 0;
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        transmit_packets(
+            &mut peripheral_ops,
+            &mut central_ops,
+            &mut rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
+
+        let pass_key = match &peripheral_ops.connection_events[0] {
+            ConnectionEvent::PassKeyDisplay(pk) => *pk,
+            _ => panic!("Unexpected connection event"),
+        };
+
+        // Only the device inputting the pass key notifies keypresses
+        assert_eq!(
+            peripheral_pairing.send_keypress_notification(KeypressNotification::EntryStarted, &mut peripheral_ops),
+            Err(Error::InvalidState)
+        );
+        for notification in [
+            KeypressNotification::EntryStarted,
+            KeypressNotification::DigitEntered,
+            KeypressNotification::DigitErased,
+            KeypressNotification::EntryCompleted,
+        ] {
+            central_pairing
+                .send_keypress_notification(notification, &mut central_ops)
+                .unwrap();
+        }
+        central_pairing
+            .handle_event(Event::PassKeyInput(pass_key.value()), &mut central_ops, &mut rng)
+            .unwrap();
+        assert_eq!(
+            central_pairing.send_keypress_notification(KeypressNotification::Cleared, &mut central_ops),
+            Err(Error::InvalidState)
+        );
+
+        transmit_packets(
+            &mut peripheral_ops,
+            &mut central_ops,
+            &mut rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
+
+        assert!(matches!(
+            peripheral_ops.connection_events[1..5],
+            [
+                ConnectionEvent::PassKeyKeypress(KeypressNotification::EntryStarted),
+                ConnectionEvent::PassKeyKeypress(KeypressNotification::DigitEntered),
+                ConnectionEvent::PassKeyKeypress(KeypressNotification::DigitErased),
+                ConnectionEvent::PassKeyKeypress(KeypressNotification::EntryCompleted),
+            ]
+        ));
+        assert_eq!(central_ops.encryptions[0], peripheral_ops.encryptions[0]);
+
+        // Keypress notifications the application has no room for are dropped
+        while peripheral_ops.connection_events.len() < peripheral_ops.connection_events.capacity() {
+            peripheral_ops
+                .connection_events
+                .push(ConnectionEvent::PassKeyKeypress(KeypressNotification::Cleared))
+                .unwrap();
+        }
+        util::handle_keypress_notification(&[KeypressNotification::DigitEntered.into()], &mut peripheral_ops);
+        assert!(matches!(
+            peripheral_ops.connection_events.last(),
+            Some(ConnectionEvent::PassKeyKeypress(KeypressNotification::Cleared))
+        ));
+    }
+
     #[test]
     fn pass_key_entry_central_display() {
         let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
@@ -624,6 +799,8 @@ mod tests {
         let mut central_ops = TestOps::<80>::default();
         peripheral_ops.bondable = true;
         central_ops.bondable = true;
+        peripheral_ops.cross_transport_key_derivation = true;
+        central_ops.cross_transport_key_derivation = true;
 
         let peripheral_pairing = peripheral::Pairing::new(peripheral, central, IoCapabilities::NoInputNoOutput);
         let central_pairing =
@@ -649,6 +826,16 @@ mod tests {
         peripheral_pairing
             .handle_event(Event::LinkEncryptedResult(true), &mut peripheral_ops, &mut rng)
             .unwrap();
+        // Both sides distribute a signing key once the link is encrypted
+        transmit_packets(
+            &mut peripheral_ops,
+            &mut central_ops,
+            &mut rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
 
         assert!(matches!(
             central_ops.connection_events[0],
@@ -672,35 +859,140 @@ mod tests {
                 })
             }
         ));
+        match (&central_ops.connection_events[0], &peripheral_ops.connection_events[0]) {
+            (
+                ConnectionEvent::PairingComplete {
+                    bond: Some(central_bond),
+                    ..
+                },
+                ConnectionEvent::PairingComplete {
+                    bond: Some(peripheral_bond),
+                    ..
+                },
+            ) => {
+                assert!(central_bond.local_signing_key.is_some());
+                assert!(peripheral_bond.local_signing_key.is_some());
+                assert_eq!(central_bond.local_signing_key, peripheral_bond.peer_signing_key);
+                assert_eq!(central_bond.peer_signing_key, peripheral_bond.local_signing_key);
+                // Both sides derive the same BR/EDR link key from the LTK, using h7 as both support CT2
+                assert_eq!(central_bond.link_key, Some(central_bond.ltk.link_key(true)));
+                assert_eq!(central_bond.link_key, peripheral_bond.link_key);
+            }
+            _ => panic!("Unexpected connection events"),
+        }
         assert_eq!(central_pairing.security_level(), SecurityLevel::Encrypted);
         assert_eq!(peripheral_pairing.security_level(), SecurityLevel::Encrypted);
     }
 
+    #[test]
+    fn bondable_just_works_with_identity_distribution() {
+        let peripheral = Address::random([0xff, 1, 2, 3, 4, 0x45]);
+        let central = Address::random([0xff, 2, 2, 3, 4, 0x45]);
+        let peripheral_identity = (Address::random([1, 1, 2, 3, 4, 0xc5]), IdentityResolvingKey::new(1));
+        let central_identity = (Address::random([2, 1, 2, 3, 4, 0xc5]), IdentityResolvingKey::new(2));
+
+        let mut peripheral_ops = TestOps::<80>::default();
+        let mut central_ops = TestOps::<80>::default();
+        peripheral_ops.bondable = true;
+        central_ops.bondable = true;
+        peripheral_ops.local_identity = Some(peripheral_identity);
+        central_ops.local_identity = Some(central_identity);
+
+        let peripheral_pairing = peripheral::Pairing::new(peripheral, central, IoCapabilities::NoInputNoOutput);
+        let central_pairing =
+            central::Pairing::initiate(central, peripheral, &mut central_ops, IoCapabilities::NoInputNoOutput).unwrap();
+
+        let mut num_central_data_sent = 0;
+        let mut num_peripheral_data_sent = 0;
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        transmit_packets(
+            &mut peripheral_ops,
+            &mut central_ops,
+            &mut rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
+
+        // The peripheral distributes its identity first, then the central
+        central_pairing
+            .handle_event(Event::LinkEncryptedResult(true), &mut central_ops, &mut rng)
+            .unwrap();
+        peripheral_pairing
+            .handle_event(Event::LinkEncryptedResult(true), &mut peripheral_ops, &mut rng)
+            .unwrap();
+        transmit_packets(
+            &mut peripheral_ops,
+            &mut central_ops,
+            &mut rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
+
+        let (peripheral_identity_address, peripheral_irk) = peripheral_identity;
+        let (central_identity_address, central_irk) = central_identity;
+        match &central_ops.connection_events[0] {
+            ConnectionEvent::PairingComplete { bond: Some(bond), .. } => {
+                assert!(bond.is_bonded);
+                assert_eq!(bond.identity.irk, Some(peripheral_irk));
+                assert_eq!(bond.identity.bd_addr, peripheral_identity_address.addr);
+                // Cross-transport key derivation is opt-in
+                assert_eq!(bond.link_key, None);
+            }
+            _ => panic!("Unexpected connection event"),
+        }
+        match &peripheral_ops.connection_events[0] {
+            ConnectionEvent::PairingComplete { bond: Some(bond), .. } => {
+                assert!(bond.is_bonded);
+                assert_eq!(bond.identity.irk, Some(central_irk));
+                assert_eq!(bond.identity.bd_addr, central_identity_address.addr);
+            }
+            _ => panic!("Unexpected connection event"),
+        }
+        assert_eq!(central_ops.connection_events.len(), 1);
+        assert_eq!(peripheral_ops.connection_events.len(), 1);
+    }
+
     #[test]
     fn bonded_central_initiates() {
         let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
         let central = Address::random([0xff, 2, 2, 3, 4, 5]);
 
-        let mut peripheral_ops = TestOps::<80>::default();
         let mut central_ops = TestOps::<80>::default();
+        let mut peripheral_ops = TestOps::<80>::default();
         central_ops.bond_information = Some(BondInformation {
             security_level: SecurityLevel::EncryptedAuthenticated,
             is_bonded: true,
             ltk: LongTermKey(1),
+            encryption_key_size: 16,
             identity: Identity {
                 irk: None,
                 bd_addr: peripheral.addr,
             },
+            identity_kind: peripheral.kind,
+            central_identification: None,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         });
 
         peripheral_ops.bond_information = Some(BondInformation {
             security_level: SecurityLevel::EncryptedAuthenticated,
             is_bonded: true,
             ltk: LongTermKey(1),
+            encryption_key_size: 16,
             identity: Identity {
                 irk: None,
                 bd_addr: central.addr,
             },
+            identity_kind: central.kind,
+            central_identification: None,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         });
 
         let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
@@ -748,26 +1040,38 @@ mod tests {
         let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
         let central = Address::random([0xff, 2, 2, 3, 4, 5]);
 
-        let mut peripheral_ops = TestOps::<80>::default();
         let mut central_ops = TestOps::<80>::default();
+        let mut peripheral_ops = TestOps::<80>::default();
         central_ops.bond_information = Some(BondInformation {
             security_level: SecurityLevel::EncryptedAuthenticated,
             is_bonded: true,
             ltk: LongTermKey(1),
+            encryption_key_size: 16,
             identity: Identity {
                 irk: None,
                 bd_addr: peripheral.addr,
             },
+            identity_kind: peripheral.kind,
+            central_identification: None,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         });
 
         peripheral_ops.bond_information = Some(BondInformation {
             security_level: SecurityLevel::EncryptedAuthenticated,
             is_bonded: true,
             ltk: LongTermKey(1),
+            encryption_key_size: 16,
             identity: Identity {
                 irk: None,
                 bd_addr: central.addr,
             },
+            identity_kind: central.kind,
+            central_identification: None,
+            local_signing_key: None,
+            peer_signing_key: None,
+            link_key: None,
         });
 
         let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
@@ -828,6 +1132,156 @@ mod tests {
         );
     }
 
+    fn oob_pairing<const N: usize>(
+        peripheral_ops: &mut TestOps<N>,
+        central_ops: &mut TestOps<N>,
+        rng: &mut ChaCha12Rng,
+    ) -> (peripheral::Pairing, central::Pairing) {
+        let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
+        let central = Address::random([0xff, 2, 2, 3, 4, 5]);
+
+        let peripheral_pairing = peripheral::Pairing::new(peripheral, central, IoCapabilities::NoInputNoOutput);
+        let central_pairing =
+            central::Pairing::initiate(central, peripheral, central_ops, IoCapabilities::NoInputNoOutput).unwrap();
+
+        let mut num_central_data_sent = 0;
+        let mut num_peripheral_data_sent = 0;
+        transmit_packets(
+            peripheral_ops,
+            central_ops,
+            rng,
+            &peripheral_pairing,
+            &central_pairing,
+            &mut num_central_data_sent,
+            &mut num_peripheral_data_sent,
+        );
+
+        assert_eq!(central_ops.encryptions[0], peripheral_ops.encryptions[0]);
+        central_pairing
+            .handle_event(Event::LinkEncryptedResult(true), central_ops, rng)
+            .unwrap();
+        peripheral_pairing
+            .handle_event(Event::LinkEncryptedResult(true), peripheral_ops, rng)
+            .unwrap();
+
+        for events in [&central_ops.connection_events, &peripheral_ops.connection_events] {
+            assert!(matches!(
+                events[0],
+                ConnectionEvent::PairingComplete {
+                    security_level: SecurityLevel::EncryptedAuthenticated,
+                    bond: None
+                }
+            ));
+        }
+        (peripheral_pairing, central_pairing)
+    }
+
+    #[test]
+    fn out_of_band_both_directions() {
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        let central_secret_key = SecretKey::new(&mut rng);
+        let central_random = Nonce::new(&mut rng);
+        let peripheral_secret_key = SecretKey::new(&mut rng);
+        let peripheral_random = Nonce::new(&mut rng);
+
+        let mut peripheral_ops = TestOps::<10> {
+            peer_oob: Some(make_oob_data(&central_secret_key, central_random)),
+            local_oob: Some((peripheral_secret_key.clone(), peripheral_random)),
+            ..Default::default()
+        };
+        let mut central_ops = TestOps::<10> {
+            peer_oob: Some(make_oob_data(&peripheral_secret_key, peripheral_random)),
+            local_oob: Some((central_secret_key.clone(), central_random)),
+            ..Default::default()
+        };
+
+        let (peripheral_pairing, central_pairing) = oob_pairing(&mut peripheral_ops, &mut central_ops, &mut rng);
+        assert_eq!(central_pairing.security_level(), SecurityLevel::EncryptedAuthenticated);
+        assert_eq!(
+            peripheral_pairing.security_level(),
+            SecurityLevel::EncryptedAuthenticated
+        );
+    }
+
+    #[test]
+    fn out_of_band_one_direction() {
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        let peripheral_secret_key = SecretKey::new(&mut rng);
+        let peripheral_random = Nonce::new(&mut rng);
+
+        // Only the central has received OOB data, e.g. by reading the NFC tag of the peripheral
+        let mut peripheral_ops = TestOps::<10> {
+            local_oob: Some((peripheral_secret_key.clone(), peripheral_random)),
+            ..Default::default()
+        };
+        let mut central_ops = TestOps::<10> {
+            peer_oob: Some(make_oob_data(&peripheral_secret_key, peripheral_random)),
+            ..Default::default()
+        };
+
+        oob_pairing(&mut peripheral_ops, &mut central_ops, &mut rng);
+    }
+
+    #[test]
+    fn out_of_band_confirm_mismatch() {
+        let peripheral = Address::random([0xff, 1, 2, 3, 4, 5]);
+        let central = Address::random([0xff, 2, 2, 3, 4, 5]);
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+        let peripheral_secret_key = SecretKey::new(&mut rng);
+        let peripheral_random = Nonce::new(&mut rng);
+
+        // OOB data of another key pair than the one the peripheral pairs with
+        let mut peripheral_ops = TestOps::<10> {
+            local_oob: Some((SecretKey::new(&mut rng), peripheral_random)),
+            ..Default::default()
+        };
+        let mut central_ops = TestOps::<10> {
+            peer_oob: Some(make_oob_data(&peripheral_secret_key, peripheral_random)),
+            ..Default::default()
+        };
+
+        let peripheral_pairing = peripheral::Pairing::new(peripheral, central, IoCapabilities::NoInputNoOutput);
+        let central_pairing =
+            central::Pairing::initiate(central, peripheral, &mut central_ops, IoCapabilities::NoInputNoOutput).unwrap();
+
+        // Pairing request, pairing response and central public key
+        peripheral_pairing
+            .handle_l2cap_command(
+                central_ops.sent_packets[0].command,
+                central_ops.sent_packets[0].payload(),
+                &mut peripheral_ops,
+                &mut rng,
+            )
+            .unwrap();
+        central_pairing
+            .handle_l2cap_command(
+                peripheral_ops.sent_packets[0].command,
+                peripheral_ops.sent_packets[0].payload(),
+                &mut central_ops,
+                &mut rng,
+            )
+            .unwrap();
+        peripheral_pairing
+            .handle_l2cap_command(
+                central_ops.sent_packets[1].command,
+                central_ops.sent_packets[1].payload(),
+                &mut peripheral_ops,
+                &mut rng,
+            )
+            .unwrap();
+
+        // The peripheral public key does not match its OOB data
+        let result = central_pairing.handle_l2cap_command(
+            peripheral_ops.sent_packets[1].command,
+            peripheral_ops.sent_packets[1].payload(),
+            &mut central_ops,
+            &mut rng,
+        );
+        assert_eq!(result, Err(Error::Security(Reason::ConfirmValueFailed)));
+        assert!(central_ops.encryptions.is_empty());
+        assert_eq!(central_ops.sent_packets.len(), 2);
+    }
+
     fn transmit_packets<const N: usize>(
         peripheral_ops: &mut TestOps<N>,
         central_ops: &mut TestOps<N>,
@@ -1,7 +1,6 @@
 use core::cell::RefCell;
 use core::ops::{Deref, DerefMut};
 
-use bt_hci::param::{AddrKind, BdAddr};
 use embassy_time::Instant;
 use rand::Rng;
 use rand_core::{CryptoRng, RngCore};
@@ -9,15 +8,22 @@ use rand_core::{CryptoRng, RngCore};
 use crate::codec::{Decode, Encode};
 use crate::connection::SecurityLevel;
 use crate::prelude::ConnectionEvent;
-use crate::security_manager::constants::ENCRYPTION_KEY_SIZE_128_BITS;
-use crate::security_manager::crypto::{Confirm, DHKey, MacKey, Nonce, PublicKey, SecretKey};
+use crate::security_manager::crypto::{
+    Confirm, ConnectionSignatureResolvingKey, DHKey, MacKey, Nonce, PublicKey, SecretKey, TemporaryKey,
+};
 use crate::security_manager::pairing::util::{
-    choose_pairing_method, make_confirm_packet, make_dhkey_check_packet, make_pairing_random, make_public_key_packet,
-    prepare_packet, CommandAndPayload, PairingMethod, PassKeyEntryAction,
+    check_security_policy, choose_legacy_pairing_method, choose_pairing_method, derive_link_key,
+    handle_keypress_notification, make_central_identification_packet, make_confirm_packet, make_dhkey_check_packet,
+    make_encryption_information_packet, make_identity_address_information_packet, make_identity_information_packet,
+    make_keypress_notification_packet, make_legacy_confirm, make_pairing_random, make_public_key_packet,
+    make_secret_key, make_signing_information_packet, parse_identity_address_information, prepare_packet,
+    verify_oob_data, CommandAndPayload, PairingMethod, PassKeyEntryAction,
 };
 use crate::security_manager::pairing::{Event, PairingOps};
-use crate::security_manager::types::{AuthReq, BondingFlag, Command, PairingFeatures, PassKey};
-use crate::security_manager::Reason;
+use crate::security_manager::types::{
+    AuthReq, BondingFlag, Command, KeypressNotification, PairingFeatures, PassKey, UseOutOfBand,
+};
+use crate::security_manager::{CentralIdentification, Reason, SigningKey};
 use crate::{Address, BondInformation, Error, IdentityResolvingKey, IoCapabilities, LongTermKey, PacketPool};
 
 #[derive(Debug, Clone)]
@@ -32,13 +38,19 @@ enum Step {
     WaitingPassKeyInput(Option<[u8; size_of::<u128>()]>),
     WaitingPassKeyEntryConfirm(i32),
     WaitingPassKeyEntryRandom(i32),
-    // TODO add OOB
+    // Out of band
+    WaitingOobRandom,
     WaitingDHKeyEa,
+    // LE legacy pairing, associated data is the central confirm received before the passkey was entered.
+    WaitingLegacyPassKeyInput(Option<[u8; size_of::<u128>()]>),
+    WaitingLegacyConfirm,
+    WaitingLegacyRandom,
     WaitingLinkEncrypted,
     // TODO: WaitingIdentitity is actually a subset of `ReceivingKeys(i32)`,
     // they can be removed after implementing the full receiving keys procedure.
     WaitingIdentitityInformation,
     WaitingIdentitityAddressInformation,
+    WaitingSigningInformation,
     SendingKeys(i32),
     ReceivingKeys(i32),
     Success,
@@ -101,6 +113,7 @@ struct PairingData {
     long_term_key: LongTermKey,
     timeout_at: Instant,
     bond_information: Option<BondInformation>,
+    legacy: bool,
 }
 
 impl PairingData {
@@ -108,6 +121,23 @@ impl PairingData {
         matches!(self.local_features.security_properties.bond(), BondingFlag::Bonding)
             && matches!(self.peer_features.security_properties.bond(), BondingFlag::Bonding)
     }
+
+    fn encryption_key_size(&self) -> u8 {
+        self.local_features
+            .maximum_encryption_key_size
+            .min(self.peer_features.maximum_encryption_key_size)
+    }
+
+    fn legacy_confirm(&self, nonce: &Nonce) -> Result<Confirm, Error> {
+        make_legacy_confirm(
+            &TemporaryKey(self.local_secret_rb),
+            nonce,
+            &self.peer_features,
+            &self.local_features,
+            self.peer_address,
+            self.local_address,
+        )
+    }
 }
 
 impl Pairing {
@@ -160,6 +190,7 @@ impl Pairing {
                 long_term_key: LongTermKey(0),
                 timeout_at: Instant::now() + crate::security_manager::constants::TIMEOUT,
                 bond_information: None,
+                legacy: false,
             }),
         }
     }
@@ -174,7 +205,11 @@ impl Pairing {
         {
             let mut security_request = prepare_packet(Command::SecurityRequest)?;
             let payload = security_request.payload_mut();
-            payload[0] = AuthReq::new(ops.bonding_flag()).into();
+            let mut auth_req = AuthReq::new(ops.bonding_flag());
+            if ops.cross_transport_key_derivation() {
+                auth_req.set_ct2();
+            }
+            payload[0] = auth_req.into();
             ops.try_send_packet(security_request)?;
         }
         Ok(ret)
@@ -209,20 +244,28 @@ impl Pairing {
                 if res {
                     info!("Link encrypted!");
                     if matches!(x.0, Step::WaitingLinkEncrypted) {
-                        // TODO send key data
+                        let mut pairing_data = self.pairing_data.borrow_mut();
+                        if pairing_data.legacy
+                            && pairing_data.local_features.responder_key_distribution.encryption_key()
+                        {
+                            Self::distribute_legacy_ltk(ops, pairing_data.deref_mut(), rng)?;
+                        }
+                        if pairing_data.local_features.responder_key_distribution.identity_key() {
+                            Self::distribute_identity(ops)?;
+                        }
+                        if pairing_data.local_features.responder_key_distribution.signing_key() {
+                            Self::distribute_signing_key(ops, pairing_data.deref_mut(), rng)?;
+                        }
                     } else {
                         self.pairing_data.borrow_mut().bond_information = ops.try_enable_bonded_encryption()?;
                     }
 
-                    if self
-                        .pairing_data
-                        .borrow()
-                        .peer_features
-                        .initiator_key_distribution
-                        .identity_key()
-                    {
+                    let pairing_data = self.pairing_data.borrow();
+                    if pairing_data.peer_features.initiator_key_distribution.identity_key() {
                         // Remote will share identity key
                         Step::WaitingIdentitityInformation
+                    } else if pairing_data.local_features.initiator_key_distribution.signing_key() {
+                        Step::WaitingSigningInformation
                     } else {
                         Step::Success
                     }
@@ -242,6 +285,14 @@ impl Pairing {
             (Step::WaitingNumericComparisonResult(_), Event::PassKeyCancel) => {
                 Step::Error(Error::Security(Reason::NumericComparisonFailed))
             }
+            (Step::WaitingLegacyPassKeyInput(confirm), Event::PassKeyInput(input)) => {
+                let mut pairing_data = self.pairing_data.borrow_mut();
+                pairing_data.local_secret_rb = input as u128;
+                match confirm {
+                    Some(payload) => Self::handle_legacy_confirm(&payload, ops, pairing_data.deref_mut(), rng)?,
+                    None => Step::WaitingLegacyConfirm,
+                }
+            }
             (Step::WaitingPassKeyInput(confirm), Event::PassKeyInput(input)) => {
                 let mut pairing_data = self.pairing_data.borrow_mut();
                 pairing_data.local_secret_rb = input as u128;
@@ -285,11 +336,49 @@ impl Pairing {
         }
     }
 
+    /// Send a keypress notification while the pass key is input, if both devices support them
+    pub fn send_keypress_notification<P: PacketPool, OPS: PairingOps<P>>(
+        &self,
+        notification: KeypressNotification,
+        ops: &mut OPS,
+    ) -> Result<(), Error> {
+        if !matches!(
+            self.current_step.borrow().deref(),
+            Step::WaitingPassKeyInput(_) | Step::WaitingLegacyPassKeyInput(_)
+        ) {
+            return Err(Error::InvalidState);
+        }
+        let pairing_data = self.pairing_data.borrow();
+        if !pairing_data.local_features.security_properties.key_press_notification()
+            || !pairing_data.peer_features.security_properties.key_press_notification()
+        {
+            return Err(Error::NotSupported);
+        }
+        ops.try_send_packet(make_keypress_notification_packet(notification)?)
+    }
+
+    pub fn secure_connections(&self) -> bool {
+        self.pairing_data
+            .borrow()
+            .bond_information
+            .as_ref()
+            .is_some_and(|bond| bond.secure_connections())
+    }
+
+    pub fn encryption_key_size(&self) -> u8 {
+        self.pairing_data
+            .borrow()
+            .bond_information
+            .as_ref()
+            .map_or(0, |bond| bond.encryption_key_size)
+    }
+
     pub fn security_level(&self) -> SecurityLevel {
         let step = self.current_step.borrow();
         match step.deref() {
             Step::WaitingIdentitityInformation
             | Step::WaitingIdentitityAddressInformation
+            | Step::WaitingSigningInformation
             | Step::SendingKeys(_)
             | Step::ReceivingKeys(_)
             | Step::Success => self
@@ -318,14 +407,32 @@ impl Pairing {
                 (Step::WaitingPairingRequest, Command::PairingRequest) => {
                     Self::handle_pairing_request(command.payload, ops, pairing_data)?;
                     Self::send_pairing_response(ops, pairing_data)?;
-                    Step::WaitingPublicKey
+                    if pairing_data.legacy {
+                        Self::start_legacy_pairing(ops, pairing_data, rng)?
+                    } else {
+                        Step::WaitingPublicKey
+                    }
+                }
+                (Step::WaitingLegacyPassKeyInput(_), Command::PairingConfirm) => {
+                    let confirm: [u8; size_of::<u128>()] =
+                        command.payload.try_into().map_err(|_| Error::InvalidValue)?;
+                    Step::WaitingLegacyPassKeyInput(Some(confirm))
+                }
+                (Step::WaitingLegacyConfirm, Command::PairingConfirm) => {
+                    Self::handle_legacy_confirm(command.payload, ops, pairing_data, rng)?
+                }
+                (Step::WaitingLegacyRandom, Command::PairingRandom) => {
+                    Self::handle_legacy_random(command.payload, ops, pairing_data)?
                 }
                 (Step::WaitingPublicKey, Command::PairingPublicKey) => {
                     Self::handle_public_key(command.payload, pairing_data);
-                    Self::generate_private_public_key_pair(pairing_data, rng)?;
+                    Self::generate_private_public_key_pair(pairing_data, ops, rng)?;
                     Self::send_public_key(ops, pairing_data.local_public_key.as_ref().unwrap())?;
                     match pairing_data.pairing_method {
-                        PairingMethod::OutOfBand => todo!("OOB not implemented"),
+                        PairingMethod::OutOfBand => {
+                            Self::handle_peer_oob_data(ops, pairing_data)?;
+                            Step::WaitingOobRandom
+                        }
                         PairingMethod::PassKeyEntry { peripheral, .. } => {
                             if peripheral == PassKeyEntryAction::Display {
                                 pairing_data.local_secret_rb =
@@ -347,6 +454,12 @@ impl Pairing {
                         )?),
                     }
                 }
+                (Step::WaitingOobRandom, Command::PairingRandom) => {
+                    Self::handle_numeric_compare_random(command.payload, pairing_data)?;
+                    pairing_data.local_nonce = Nonce::new(rng);
+                    Self::send_nonce(ops, &pairing_data.local_nonce)?;
+                    Step::WaitingDHKeyEa
+                }
                 (Step::WaitingNumericComparisonRandom(_), Command::PairingRandom) => {
                     Self::handle_numeric_compare_random(command.payload, pairing_data)?;
                     Self::send_nonce(ops, &pairing_data.local_nonce)?;
@@ -374,7 +487,10 @@ impl Pairing {
                     Self::handle_dhkey_ea(command.payload, ops, pairing_data)?
                 }
 
-                (x, Command::KeypressNotification) => x,
+                (x, Command::KeypressNotification) => {
+                    handle_keypress_notification(command.payload, ops);
+                    x
+                }
 
                 (Step::WaitingIdentitityInformation, Command::IdentityInformation) => {
                     Self::handle_identity_information(command.payload, pairing_data)?
@@ -384,6 +500,10 @@ impl Pairing {
                     Self::handle_identity_address_information(command.payload, pairing_data)?
                 }
 
+                (Step::WaitingSigningInformation, Command::SigningInformation) => {
+                    Self::handle_signing_information(command.payload, pairing_data)?
+                }
+
                 _ => return Err(Error::InvalidState),
             }
         };
@@ -425,11 +545,10 @@ impl Pairing {
         pairing_data: &mut PairingData,
     ) -> Result<(), Error> {
         let peer_features = PairingFeatures::decode(payload).map_err(|_| Error::Security(Reason::InvalidParameters))?;
-        if peer_features.maximum_encryption_key_size < ENCRYPTION_KEY_SIZE_128_BITS {
-            return Err(Error::Security(Reason::EncryptionKeySize));
-        }
-        if !peer_features.security_properties.secure_connection() {
-            return Err(Error::Security(Reason::UnspecifiedReason));
+        pairing_data.legacy = !peer_features.security_properties.secure_connection();
+        if pairing_data.legacy && !ops.allow_legacy_pairing() {
+            warn!("[smp] Peer does not support LE Secure Connections");
+            return Err(Error::Security(Reason::AuthenticationRequirements));
         }
 
         if peer_features.initiator_key_distribution.identity_key() {
@@ -439,10 +558,188 @@ impl Pairing {
                 .set_identity_key();
         }
 
+        if peer_features.responder_key_distribution.identity_key() && ops.local_identity().is_some() {
+            pairing_data
+                .local_features
+                .responder_key_distribution
+                .set_identity_key();
+        }
+
         pairing_data.peer_features = peer_features;
         pairing_data.local_features.security_properties = AuthReq::new(ops.bonding_flag());
-        pairing_data.pairing_method = choose_pairing_method(pairing_data.peer_features, pairing_data.local_features);
-        info!("[smp] Pairing method {:?}", pairing_data.pairing_method);
+        if ops.cross_transport_key_derivation() {
+            pairing_data.local_features.security_properties.set_ct2();
+        }
+        // Signing keys are kept with the bond, they are useless without one
+        if pairing_data.want_bonding() {
+            if peer_features.initiator_key_distribution.signing_key() {
+                pairing_data.local_features.initiator_key_distribution.set_signing_key();
+            }
+            if peer_features.responder_key_distribution.signing_key() {
+                pairing_data.local_features.responder_key_distribution.set_signing_key();
+            }
+            // Cross-transport key derivation is only defined for LE Secure Connections
+            if !pairing_data.legacy && ops.cross_transport_key_derivation() {
+                if peer_features.initiator_key_distribution.link_key() {
+                    pairing_data.local_features.initiator_key_distribution.set_link_key();
+                }
+                if peer_features.responder_key_distribution.link_key() {
+                    pairing_data.local_features.responder_key_distribution.set_link_key();
+                }
+            }
+        }
+        if ops.peer_oob_data().is_some() {
+            pairing_data.local_features.use_oob = UseOutOfBand::Present;
+        }
+        pairing_data.pairing_method = if pairing_data.legacy {
+            // The LTK is only distributed by legacy pairing, LE Secure Connections derive it on both sides
+            if pairing_data.want_bonding() && peer_features.responder_key_distribution.encryption_key() {
+                pairing_data
+                    .local_features
+                    .responder_key_distribution
+                    .set_encryption_key();
+            }
+            choose_legacy_pairing_method(pairing_data.peer_features, pairing_data.local_features)
+        } else {
+            choose_pairing_method(pairing_data.peer_features, pairing_data.local_features)
+        };
+        check_security_policy(
+            ops,
+            &pairing_data.local_features,
+            &pairing_data.peer_features,
+            pairing_data.pairing_method,
+        )?;
+        if !ops.accept_pairing_request(&peer_features) {
+            warn!("[smp] Pairing request refused by the application");
+            return Err(Error::Security(Reason::PairingNotSupported));
+        }
+        info!(
+            "[smp] Pairing method {:?}, legacy {}",
+            pairing_data.pairing_method, pairing_data.legacy
+        );
+        Ok(())
+    }
+
+    fn start_legacy_pairing<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+        rng: &mut RNG,
+    ) -> Result<Step, Error> {
+        match pairing_data.pairing_method {
+            PairingMethod::OutOfBand => Err(Error::Security(Reason::OobNotAvailable)),
+            PairingMethod::PassKeyEntry {
+                peripheral: PassKeyEntryAction::Input,
+                ..
+            } => {
+                ops.try_send_connection_event(ConnectionEvent::PassKeyInput)?;
+                Ok(Step::WaitingLegacyPassKeyInput(None))
+            }
+            PairingMethod::PassKeyEntry { .. } => {
+                pairing_data.local_secret_rb = rng.sample(rand::distributions::Uniform::new_inclusive(0, 999999));
+                ops.try_send_connection_event(ConnectionEvent::PassKeyDisplay(PassKey(
+                    pairing_data.local_secret_rb as u32,
+                )))?;
+                Ok(Step::WaitingLegacyConfirm)
+            }
+            _ => {
+                // Just Works uses a zero temporary key
+                pairing_data.local_secret_rb = 0;
+                Ok(Step::WaitingLegacyConfirm)
+            }
+        }
+    }
+
+    fn handle_legacy_confirm<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        payload: &[u8],
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+        rng: &mut RNG,
+    ) -> Result<Step, Error> {
+        pairing_data.confirm = Confirm(u128::from_le_bytes(
+            payload
+                .try_into()
+                .map_err(|_| Error::Security(Reason::InvalidParameters))?,
+        ));
+        pairing_data.local_nonce = Nonce::new(rng);
+        let confirm = pairing_data.legacy_confirm(&pairing_data.local_nonce)?;
+        ops.try_send_packet(make_confirm_packet(&confirm)?)?;
+        Ok(Step::WaitingLegacyRandom)
+    }
+
+    fn handle_legacy_random<P: PacketPool, OPS: PairingOps<P>>(
+        payload: &[u8],
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+    ) -> Result<Step, Error> {
+        pairing_data.peer_nonce = Nonce(u128::from_le_bytes(
+            payload
+                .try_into()
+                .map_err(|_| Error::Security(Reason::InvalidParameters))?,
+        ));
+        if pairing_data.legacy_confirm(&pairing_data.peer_nonce)? != pairing_data.confirm {
+            error!("[smp] Legacy confirm mismatch");
+            return Err(Error::Security(Reason::ConfirmValueFailed));
+        }
+        Self::send_nonce(ops, &pairing_data.local_nonce)?;
+
+        let stk = TemporaryKey(pairing_data.local_secret_rb)
+            .s1(pairing_data.local_nonce, pairing_data.peer_nonce)
+            .shorten(pairing_data.encryption_key_size());
+        let bond = ops.try_enable_encryption(
+            &stk,
+            pairing_data.encryption_key_size(),
+            pairing_data.pairing_method.security_level(),
+            false,
+            Some(CentralIdentification::default()),
+        )?;
+        pairing_data.bond_information = Some(bond);
+        Ok(Step::WaitingLinkEncrypted)
+    }
+
+    fn distribute_legacy_ltk<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+        rng: &mut RNG,
+    ) -> Result<(), Error> {
+        let ltk = LongTermKey(Nonce::new(rng).0).shorten(pairing_data.encryption_key_size());
+        let mut central_identification = CentralIdentification {
+            ediv: rng.gen(),
+            rand: [0; 8],
+        };
+        rng.fill_bytes(&mut central_identification.rand);
+        ops.try_send_packet(make_encryption_information_packet(&ltk)?)?;
+        ops.try_send_packet(make_central_identification_packet(&central_identification)?)?;
+
+        let is_bonded = pairing_data.want_bonding();
+        let bond = pairing_data.bond_information.as_mut().ok_or(Error::InvalidValue)?;
+        bond.ltk = ltk;
+        bond.central_identification = Some(central_identification);
+        bond.is_bonded = is_bonded;
+        ops.try_update_bond_information(bond)?;
+        pairing_data.long_term_key = ltk;
+        Ok(())
+    }
+
+    fn distribute_identity<P: PacketPool, OPS: PairingOps<P>>(ops: &mut OPS) -> Result<(), Error> {
+        let (address, irk) = ops.local_identity().ok_or(Error::InvalidValue)?;
+        ops.try_send_packet(make_identity_information_packet(&irk)?)?;
+        ops.try_send_packet(make_identity_address_information_packet(&address)?)?;
+        Ok(())
+    }
+
+    fn distribute_signing_key<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+        ops: &mut OPS,
+        pairing_data: &mut PairingData,
+        rng: &mut RNG,
+    ) -> Result<(), Error> {
+        let csrk = ConnectionSignatureResolvingKey::new(rng.gen());
+        ops.try_send_packet(make_signing_information_packet(&csrk)?)?;
+
+        let bond = pairing_data.bond_information.as_mut().ok_or(Error::InvalidValue)?;
+        bond.local_signing_key = Some(SigningKey::new(csrk));
+        if bond.is_bonded {
+            ops.try_update_bond_information(bond)?;
+        }
         Ok(())
     }
 
@@ -482,28 +779,29 @@ impl Pairing {
     }
 
     fn handle_identity_address_information(payload: &[u8], pairing_data: &mut PairingData) -> Result<Step, Error> {
-        let addr_type = payload[0];
-        let kind = if addr_type == 0 {
-            AddrKind::PUBLIC
-        } else if addr_type == 1 {
-            AddrKind::RANDOM
+        let address = parse_identity_address_information(payload)?;
+        pairing_data.peer_address = address;
+
+        if let Some(ref mut bond) = &mut pairing_data.bond_information {
+            bond.identity.bd_addr = address.addr;
+            bond.identity_kind = address.kind;
+        }
+
+        trace!("Identity address information: {:?}", address);
+        if pairing_data.local_features.initiator_key_distribution.signing_key() {
+            Ok(Step::WaitingSigningInformation)
         } else {
-            // Impossible
-            error!("[security manager] Invalid address type: {:?}", addr_type);
-            return Err(Error::InvalidValue);
-        };
-        let addr = BdAddr::new(payload[1..7].try_into().map_err(|_| Error::InvalidValue)?);
-        pairing_data.peer_address = Address { kind, addr };
+            Ok(Step::Success)
+        }
+    }
 
+    fn handle_signing_information(payload: &[u8], pairing_data: &mut PairingData) -> Result<Step, Error> {
+        let csrk = ConnectionSignatureResolvingKey::from_le_bytes(payload.try_into().map_err(|_| Error::InvalidValue)?);
         if let Some(ref mut bond) = &mut pairing_data.bond_information {
-            bond.identity.bd_addr = addr;
+            bond.peer_signing_key = Some(SigningKey::new(csrk));
         }
 
-        trace!(
-            "Identity address information: addr_type: {:?}, addr: {:?}",
-            addr_type,
-            addr
-        );
+        trace!("Signing information: CSRK: {:?}", csrk);
         Ok(Step::Success)
     }
 
@@ -512,11 +810,14 @@ impl Pairing {
         pairing_data.peer_public_key = Some(peer_public_key);
     }
 
-    fn generate_private_public_key_pair<RNG: CryptoRng + RngCore>(
+    fn generate_private_public_key_pair<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
         pairing_data: &mut PairingData,
+        ops: &OPS,
         rng: &mut RNG,
     ) -> Result<(), Error> {
-        let secret_key = SecretKey::new(rng);
+        let (secret_key, local_oob_random) =
+            make_secret_key(pairing_data.pairing_method, &pairing_data.peer_features, ops, rng)?;
+        pairing_data.local_secret_rb = local_oob_random;
         let public_key = secret_key.public_key();
         let peer_public_key = pairing_data
             .peer_public_key
@@ -532,6 +833,19 @@ impl Pairing {
         Ok(())
     }
 
+    fn handle_peer_oob_data<P: PacketPool, OPS: PairingOps<P>>(
+        ops: &OPS,
+        pairing_data: &mut PairingData,
+    ) -> Result<(), Error> {
+        // ra is zero if the central OOB data was not received
+        if matches!(pairing_data.local_features.use_oob, UseOutOfBand::Present) {
+            let oob_data = ops.peer_oob_data().ok_or(Error::Security(Reason::OobNotAvailable))?;
+            let peer_public_key = pairing_data.peer_public_key.as_ref().ok_or(Error::InvalidValue)?;
+            pairing_data.peer_secret_ra = verify_oob_data(&oob_data, peer_public_key)?;
+        }
+        Ok(())
+    }
+
     fn send_public_key<P: PacketPool, OPS: PairingOps<P>>(ops: &mut OPS, public_key: &PublicKey) -> Result<(), Error> {
         let packet = make_public_key_packet::<P>(public_key).map_err(|_| Error::Security(Reason::InvalidParameters))?;
 
@@ -579,7 +893,7 @@ impl Pairing {
         );
 
         pairing_data.mac_key = Some(mac);
-        pairing_data.long_term_key = ltk;
+        pairing_data.long_term_key = ltk.shorten(pairing_data.encryption_key_size());
         Ok(())
     }
 
@@ -608,11 +922,21 @@ impl Pairing {
             Err(Error::Security(Reason::DHKeyCheckFailed))
         } else {
             Self::send_dhkey_eb(ops, pairing_data)?;
-            let bond = ops.try_enable_encryption(
+            let mut bond = ops.try_enable_encryption(
                 &pairing_data.long_term_key,
+                pairing_data.encryption_key_size(),
                 pairing_data.pairing_method.security_level(),
                 pairing_data.want_bonding(),
+                None,
             )?;
+            bond.link_key = derive_link_key(
+                &pairing_data.local_features,
+                &pairing_data.peer_features,
+                &pairing_data.long_term_key,
+            );
+            if bond.is_bonded && bond.link_key.is_some() {
+                ops.try_update_bond_information(&bond)?;
+            }
             pairing_data.bond_information = Some(bond);
             Ok(Step::WaitingLinkEncrypted)
         }
@@ -727,12 +1051,13 @@ mod tests {
 
     use super::{Pairing, Step};
     use crate::prelude::{ConnectionEvent, SecurityLevel};
-    use crate::security_manager::crypto::{Nonce, PublicKey, SecretKey};
+    use crate::security_manager::crypto::{Nonce, PublicKey, SecretKey, TemporaryKey};
     use crate::security_manager::pairing::tests::{HeaplessPool, TestOps};
     use crate::security_manager::pairing::util::make_public_key_packet;
     use crate::security_manager::pairing::Event;
     use crate::security_manager::types::{Command, PairingFeatures};
-    use crate::{Address, IoCapabilities, LongTermKey};
+    use crate::security_manager::{CentralIdentification, Reason};
+    use crate::{Address, Error, IoCapabilities, LongTermKey};
 
     #[test]
     fn just_works() {
@@ -766,12 +1091,12 @@ mod tests {
             assert_eq!(sent_packets.len(), 1);
             let pairing_response = &sent_packets[0];
             assert_eq!(pairing_response.command, Command::PairingResponse);
-            assert_eq!(pairing_response.payload(), &[0x03, 0, 12, 16, 0, 0]);
+            assert_eq!(pairing_response.payload(), &[0x03, 0, 28, 16, 0, 0]);
             assert_eq!(
                 pairing_data.local_features,
                 PairingFeatures {
                     io_capabilities: IoCapabilities::NoInputNoOutput,
-                    security_properties: 12.into(),
+                    security_properties: 28.into(),
                     ..Default::default()
                 }
             );
@@ -865,7 +1190,7 @@ mod tests {
             assert_eq!(sent_packets[4].command, Command::PairingDhKeyCheck);
             assert_eq!(
                 sent_packets[4].payload(),
-                [22, 123, 0, 74, 239, 81, 163, 188, 71, 111, 251, 117, 54, 186, 205, 3]
+                [69, 253, 6, 175, 21, 143, 64, 198, 251, 199, 225, 219, 150, 51, 207, 150]
             );
             assert_eq!(pairing_ops.encryptions.len(), 1);
             assert!(matches!(pairing_ops.encryptions[0], LongTermKey(_)));
@@ -1023,4 +1348,161 @@ mod tests {
             _ => panic!("Unexpected connection event"),
         }
     }
+
+    #[test]
+    fn legacy_just_works_with_ltk_distribution() {
+        let mut pairing_ops: TestOps<10> = TestOps {
+            bondable: true,
+            allow_legacy: true,
+            ..Default::default()
+        };
+        let local = Address::random([1, 2, 3, 4, 5, 6]);
+        let peer = Address::random([7, 8, 9, 10, 11, 12]);
+        let pairing = Pairing::new(local, peer, IoCapabilities::NoInputNoOutput);
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+
+        // Legacy central requests bonding and the peripheral LTK
+        let pairing_request = [0x03, 0x00, 0x01, 16, 0x00, 0x01];
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingRequest,
+                &pairing_request,
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        let pairing_response: [u8; 6] = pairing_ops.sent_packets[0].payload().try_into().unwrap();
+        assert_eq!(pairing_ops.sent_packets[0].command, Command::PairingResponse);
+        assert_eq!(pairing_response, [0x03, 0x00, 0x1d, 16, 0x00, 0x01]);
+
+        let preq = [&[0x01][..], &pairing_request[..]].concat();
+        let pres = [&[0x02][..], &pairing_response[..]].concat();
+        let confirm = |nonce: Nonce| {
+            TemporaryKey(0)
+                .c1(
+                    nonce,
+                    &preq.clone().try_into().unwrap(),
+                    &pres.clone().try_into().unwrap(),
+                    peer,
+                    local,
+                )
+                .0
+                .to_le_bytes()
+        };
+
+        // Central sends Mconfirm, expects Sconfirm
+        let mrand = Nonce(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10);
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingConfirm,
+                &confirm(mrand),
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert_eq!(pairing_ops.sent_packets[1].command, Command::PairingConfirm);
+
+        // Central sends Mrand, expects Srand matching Sconfirm and encryption with the STK
+        pairing
+            .handle_l2cap_command::<HeaplessPool, _, _>(
+                Command::PairingRandom,
+                &mrand.0.to_le_bytes(),
+                &mut pairing_ops,
+                &mut rng,
+            )
+            .unwrap();
+        assert_eq!(pairing_ops.sent_packets[2].command, Command::PairingRandom);
+        let srand = Nonce(u128::from_le_bytes(
+            pairing_ops.sent_packets[2].payload().try_into().unwrap(),
+        ));
+        assert_eq!(pairing_ops.sent_packets[1].payload(), &confirm(srand));
+        assert_eq!(pairing_ops.encryptions[0], TemporaryKey(0).s1(srand, mrand));
+
+        // The LTK is distributed once the link is encrypted
+        pairing
+            .handle_event(Event::LinkEncryptedResult(true), &mut pairing_ops, &mut rng)
+            .unwrap();
+        assert!(matches!(pairing.current_step.borrow().deref(), Step::Success));
+        assert_eq!(pairing_ops.sent_packets.len(), 5);
+        assert_eq!(pairing_ops.sent_packets[3].command, Command::EncryptionInformation);
+        assert_eq!(pairing_ops.sent_packets[4].command, Command::CentralIdentification);
+        let ltk = LongTermKey::from_le_bytes(pairing_ops.sent_packets[3].payload().try_into().unwrap());
+        let central_identification = pairing_ops.sent_packets[4].payload();
+
+        match &pairing_ops.connection_events[0] {
+            ConnectionEvent::PairingComplete {
+                security_level,
+                bond: Some(bond),
+            } => {
+                assert_eq!(*security_level, SecurityLevel::Encrypted);
+                assert!(bond.is_bonded);
+                assert!(!bond.secure_connections());
+                assert_eq!(bond.ltk, ltk);
+                let CentralIdentification { ediv, rand } = bond.central_identification.unwrap();
+                assert_eq!(&ediv.to_le_bytes(), &central_identification[..2]);
+                assert_eq!(&rand, &central_identification[2..]);
+            }
+            _ => panic!("Unexpected connection event"),
+        }
+        assert!(!pairing.secure_connections());
+    }
+
+    #[test]
+    fn secure_connections_only_rejects_legacy() {
+        let mut pairing_ops: TestOps<10> = TestOps::default();
+        let pairing = Pairing::new(
+            Address::random([1, 2, 3, 4, 5, 6]),
+            Address::random([7, 8, 9, 10, 11, 12]),
+            IoCapabilities::NoInputNoOutput,
+        );
+        let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+
+        let result = pairing.handle_l2cap_command::<HeaplessPool, _, _>(
+            Command::PairingRequest,
+            &[0x03, 0x00, 0x01, 16, 0x00, 0x01],
+            &mut pairing_ops,
+            &mut rng,
+        );
+        assert_eq!(result, Err(Error::Security(Reason::AuthenticationRequirements)));
+        assert!(pairing_ops.sent_packets.is_empty());
+    }
+
+    #[test]
+    fn security_policy_rejects_pairing_request() {
+        let pairing_request = |pairing_ops: &mut TestOps<10>, payload: &[u8]| {
+            let pairing = Pairing::new(
+                Address::random([1, 2, 3, 4, 5, 6]),
+                Address::random([7, 8, 9, 10, 11, 12]),
+                IoCapabilities::NoInputNoOutput,
+            );
+            let mut rng: ChaCha12Rng = ChaCha12Core::seed_from_u64(1).into();
+            pairing.handle_l2cap_command::<HeaplessPool, _, _>(Command::PairingRequest, payload, pairing_ops, &mut rng)
+        };
+
+        // 56-bit keys are only accepted when the policy allows them
+        let mut pairing_ops: TestOps<10> = TestOps::default();
+        let result = pairing_request(&mut pairing_ops, &[0x03, 0x00, 0x08, 7, 0x00, 0x00]);
+        assert_eq!(result, Err(Error::Security(Reason::EncryptionKeySize)));
+        let mut pairing_ops: TestOps<10> = TestOps {
+            min_encryption_key_size: Some(7),
+            ..Default::default()
+        };
+        assert!(pairing_request(&mut pairing_ops, &[0x03, 0x00, 0x08, 7, 0x00, 0x00]).is_ok());
+
+        // Just Works does not provide MITM protection
+        let mut pairing_ops: TestOps<10> = TestOps {
+            mitm_required: true,
+            ..Default::default()
+        };
+        let result = pairing_request(&mut pairing_ops, &[0x03, 0x00, 0x0c, 16, 0x00, 0x00]);
+        assert_eq!(result, Err(Error::Security(Reason::AuthenticationRequirements)));
+
+        let mut pairing_ops: TestOps<10> = TestOps {
+            reject_pairing: true,
+            ..Default::default()
+        };
+        let result = pairing_request(&mut pairing_ops, &[0x03, 0x00, 0x08, 16, 0x00, 0x00]);
+        assert_eq!(result, Err(Error::Security(Reason::PairingNotSupported)));
+        assert!(pairing_ops.sent_packets.is_empty());
+    }
 }
@@ -1,9 +1,18 @@
+use bt_hci::param::{AddrKind, BdAddr};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::codec::Encode;
+use crate::connection::ConnectionEvent;
 use crate::pdu::Pdu;
 use crate::prelude::SecurityLevel;
-use crate::security_manager::crypto::{Check, Confirm, DHKey, MacKey, Nonce, PublicKey};
-use crate::security_manager::types::{Command, PairingFeatures, UseOutOfBand};
-use crate::security_manager::{Reason, TxPacket};
-use crate::{Address, Error, IoCapabilities, LongTermKey, PacketPool};
+use crate::security_manager::constants::ENCRYPTION_KEY_SIZE_128_BITS;
+use crate::security_manager::crypto::{
+    Check, Confirm, ConnectionSignatureResolvingKey, DHKey, MacKey, Nonce, PublicKey, SecretKey, TemporaryKey,
+};
+use crate::security_manager::pairing::PairingOps;
+use crate::security_manager::types::{Command, KeypressNotification, PairingFeatures, UseOutOfBand};
+use crate::security_manager::{CentralIdentification, OobData, Reason, TxPacket};
+use crate::{Address, Error, IdentityResolvingKey, IoCapabilities, LinkKey, LongTermKey, PacketPool};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -34,10 +43,10 @@ impl PairingMethod {
 }
 
 pub fn choose_pairing_method(central: PairingFeatures, peripheral: PairingFeatures) -> PairingMethod {
-    if !central.security_properties.man_in_the_middle() && !peripheral.security_properties.man_in_the_middle() {
-        PairingMethod::JustWorks
-    } else if matches!(central.use_oob, UseOutOfBand::Present) || matches!(peripheral.use_oob, UseOutOfBand::Present) {
+    if matches!(central.use_oob, UseOutOfBand::Present) || matches!(peripheral.use_oob, UseOutOfBand::Present) {
         PairingMethod::OutOfBand
+    } else if !central.security_properties.man_in_the_middle() && !peripheral.security_properties.man_in_the_middle() {
+        PairingMethod::JustWorks
     } else if peripheral.io_capabilities == IoCapabilities::DisplayOnly {
         match central.io_capabilities {
             IoCapabilities::KeyboardOnly | IoCapabilities::KeyboardDisplay => PairingMethod::PassKeyEntry {
@@ -86,6 +95,75 @@ pub fn choose_pairing_method(central: PairingFeatures, peripheral: PairingFeatur
     }
 }
 
+/// LE legacy pairing has no numeric comparison and only uses out of band data
+/// if both devices have it ([Vol 3] Part H, Section 2.3.5.1).
+pub fn choose_legacy_pairing_method(central: PairingFeatures, peripheral: PairingFeatures) -> PairingMethod {
+    if matches!(central.use_oob, UseOutOfBand::Present) && matches!(peripheral.use_oob, UseOutOfBand::Present) {
+        return PairingMethod::OutOfBand;
+    }
+    let without_oob = |features: PairingFeatures| PairingFeatures {
+        use_oob: UseOutOfBand::NotPresent,
+        ..features
+    };
+    match choose_pairing_method(without_oob(central), without_oob(peripheral)) {
+        PairingMethod::NumericComparison => match (central.io_capabilities, peripheral.io_capabilities) {
+            (IoCapabilities::KeyboardOnly | IoCapabilities::KeyboardDisplay, _) => PairingMethod::PassKeyEntry {
+                central: PassKeyEntryAction::Input,
+                peripheral: PassKeyEntryAction::Display,
+            },
+            (_, IoCapabilities::KeyboardDisplay) => PairingMethod::PassKeyEntry {
+                central: PassKeyEntryAction::Display,
+                peripheral: PassKeyEntryAction::Input,
+            },
+            _ => PairingMethod::JustWorks,
+        },
+        method => method,
+    }
+}
+
+/// Check the negotiated encryption key size and the pairing method against the security policy.
+///
+/// Fails with `Reason::EncryptionKeySize` if the smaller of the two maximum key sizes is below the
+/// policy minimum, and with `Reason::AuthenticationRequirements` if the policy requires MITM
+/// protection and the pairing method is Just Works.
+pub fn check_security_policy<P: PacketPool, OPS: PairingOps<P>>(
+    ops: &OPS,
+    local: &PairingFeatures,
+    peer: &PairingFeatures,
+    pairing_method: PairingMethod,
+) -> Result<(), Error> {
+    let key_size = local.maximum_encryption_key_size.min(peer.maximum_encryption_key_size);
+    if key_size < ops.min_encryption_key_size() {
+        warn!("[smp] Encryption key size {} too short", key_size);
+        return Err(Error::Security(Reason::EncryptionKeySize));
+    }
+    if ops.mitm_required() && pairing_method == PairingMethod::JustWorks {
+        warn!("[smp] Pairing without MITM protection refused");
+        return Err(Error::Security(Reason::AuthenticationRequirements));
+    }
+    Ok(())
+}
+
+/// Compute the LE legacy pairing confirm value for the nonce of either device.
+pub fn make_legacy_confirm(
+    tk: &TemporaryKey,
+    nonce: &Nonce,
+    central_features: &PairingFeatures,
+    peripheral_features: &PairingFeatures,
+    central_address: Address,
+    peripheral_address: Address,
+) -> Result<Confirm, Error> {
+    let command = |command: Command, features: &PairingFeatures| -> Result<[u8; 7], Error> {
+        let mut bytes = [0u8; 7];
+        bytes[0] = command.into();
+        features.encode(&mut bytes[1..]).map_err(|_| Error::InvalidValue)?;
+        Ok(bytes)
+    };
+    let preq = command(Command::PairingRequest, central_features)?;
+    let pres = command(Command::PairingResponse, peripheral_features)?;
+    Ok(tk.c1(*nonce, &preq, &pres, central_address, peripheral_address))
+}
+
 pub fn prepare_packet<P: PacketPool>(command: Command) -> Result<TxPacket<P>, Error> {
     let packet = P::allocate().ok_or(Error::OutOfMemory)?;
     TxPacket::new(packet, command)
@@ -139,6 +217,148 @@ pub fn make_confirm_packet<P: PacketPool>(confirm: &Confirm) -> Result<TxPacket<
     Ok(packet)
 }
 
+pub fn make_encryption_information_packet<P: PacketPool>(ltk: &LongTermKey) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::EncryptionInformation)?;
+    let response = packet.payload_mut();
+    response.copy_from_slice(&ltk.to_le_bytes());
+    Ok(packet)
+}
+
+pub fn make_central_identification_packet<P: PacketPool>(
+    central_identification: &CentralIdentification,
+) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::CentralIdentification)?;
+    let response = packet.payload_mut();
+    response[..2].copy_from_slice(&central_identification.ediv.to_le_bytes());
+    response[2..].copy_from_slice(&central_identification.rand);
+    Ok(packet)
+}
+
+pub fn parse_central_identification(payload: &[u8]) -> Result<CentralIdentification, Error> {
+    if payload.len() != usize::from(Command::CentralIdentification.payload_size()) {
+        return Err(Error::Security(Reason::InvalidParameters));
+    }
+    Ok(CentralIdentification {
+        ediv: u16::from_le_bytes([payload[0], payload[1]]),
+        rand: payload[2..].try_into().map_err(|_| Error::InvalidValue)?,
+    })
+}
+
+pub fn make_identity_information_packet<P: PacketPool>(irk: &IdentityResolvingKey) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::IdentityInformation)?;
+    let response = packet.payload_mut();
+    response.copy_from_slice(&irk.to_le_bytes());
+    Ok(packet)
+}
+
+pub fn make_identity_address_information_packet<P: PacketPool>(address: &Address) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::IdentityAddressInformation)?;
+    let response = packet.payload_mut();
+    response[0] = address.kind.as_raw();
+    response[1..].copy_from_slice(address.addr.raw());
+    Ok(packet)
+}
+
+pub fn parse_identity_address_information(payload: &[u8]) -> Result<Address, Error> {
+    if payload.len() != usize::from(Command::IdentityAddressInformation.payload_size()) {
+        return Err(Error::Security(Reason::InvalidParameters));
+    }
+    let kind = match payload[0] {
+        0 => AddrKind::PUBLIC,
+        1 => AddrKind::RANDOM,
+        addr_type => {
+            error!("[security manager] Invalid address type: {:?}", addr_type);
+            return Err(Error::InvalidValue);
+        }
+    };
+    let addr = BdAddr::new(payload[1..7].try_into().map_err(|_| Error::InvalidValue)?);
+    Ok(Address { kind, addr })
+}
+
+pub fn make_signing_information_packet<P: PacketPool>(
+    csrk: &ConnectionSignatureResolvingKey,
+) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::SigningInformation)?;
+    let response = packet.payload_mut();
+    response.copy_from_slice(&csrk.to_le_bytes());
+    Ok(packet)
+}
+
+pub fn make_keypress_notification_packet<P: PacketPool>(
+    notification: KeypressNotification,
+) -> Result<TxPacket<P>, Error> {
+    let mut packet = prepare_packet::<P>(Command::KeypressNotification)?;
+    let response = packet.payload_mut();
+    response[0] = notification.into();
+    Ok(packet)
+}
+
+/// Pass a keypress notification of the peer on to the application, invalid notifications are ignored
+///
+/// Notifications are only informative, one the application has no room for is dropped rather than
+/// failing the pairing.
+pub fn handle_keypress_notification<P: PacketPool, OPS: PairingOps<P>>(payload: &[u8], ops: &mut OPS) {
+    match payload.first().map(|value| KeypressNotification::try_from(*value)) {
+        Some(Ok(notification)) => {
+            if let Err(e) = ops.try_send_connection_event(ConnectionEvent::PassKeyKeypress(notification)) {
+                warn!("[smp] Dropping keypress notification {:?}: {:?}", notification, e);
+            }
+        }
+        _ => warn!("[smp] Ignoring invalid keypress notification {:?}", payload),
+    }
+}
+
+/// Derives the BR/EDR link key from an LE Secure Connections LTK if both devices set the LinkKey
+/// flag of a key distribution field ([Vol 3] Part H, Section 3.6.1).
+///
+/// Shortened LTKs are not converted, BR/EDR link keys are always 128 bits.
+pub fn derive_link_key(local: &PairingFeatures, peer: &PairingFeatures, ltk: &LongTermKey) -> Option<LinkKey> {
+    if local.maximum_encryption_key_size.min(peer.maximum_encryption_key_size) < ENCRYPTION_KEY_SIZE_128_BITS {
+        return None;
+    }
+    let initiator = local.initiator_key_distribution.link_key() && peer.initiator_key_distribution.link_key();
+    let responder = local.responder_key_distribution.link_key() && peer.responder_key_distribution.link_key();
+    let ct2 = local.security_properties.ct2() && peer.security_properties.ct2();
+    (initiator || responder).then(|| ltk.link_key(ct2))
+}
+
+/// OOB data committing to the public key of `secret_key`, Ca = f4(PKax, PKax, ra, 0).
+pub fn make_oob_data(secret_key: &SecretKey, random: Nonce) -> OobData {
+    let public_key = secret_key.public_key();
+    OobData {
+        random: random.0,
+        confirm: random.f4(public_key.x(), public_key.x(), 0).0,
+    }
+}
+
+/// Check the OOB data received from the peer against its public key, returns the peer random value.
+pub fn verify_oob_data(oob_data: &OobData, public_key: &PublicKey) -> Result<u128, Error> {
+    let confirm = Nonce(oob_data.random).f4(public_key.x(), public_key.x(), 0);
+    if confirm.0 != oob_data.confirm {
+        error!("[smp] OOB confirm mismatch");
+        return Err(Error::Security(Reason::ConfirmValueFailed));
+    }
+    Ok(oob_data.random)
+}
+
+/// Secret key for LE Secure Connections pairing and the local OOB random value.
+///
+/// If the peer has received the local OOB data, the key pair it commits to has to be used,
+/// otherwise a fresh key pair is generated and the random value is zero.
+pub fn make_secret_key<P: PacketPool, OPS: PairingOps<P>, RNG: CryptoRng + RngCore>(
+    pairing_method: PairingMethod,
+    peer_features: &PairingFeatures,
+    ops: &OPS,
+    rng: &mut RNG,
+) -> Result<(SecretKey, u128), Error> {
+    if pairing_method == PairingMethod::OutOfBand && matches!(peer_features.use_oob, UseOutOfBand::Present) {
+        let (secret_key, random) = ops.local_oob_data().ok_or(Error::Security(Reason::OobNotAvailable))?;
+        Ok((secret_key, random.0))
+    } else {
+        Ok((SecretKey::new(rng), 0))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CommandAndPayload<'a> {
     pub command: Command,
@@ -179,10 +399,53 @@ mod tests {
     use super::*;
     use crate::security_manager::types::{AuthReq, BondingFlag};
 
+    #[test]
+    fn legacy_pairing_method() {
+        let features = |io: IoCapabilities| PairingFeatures {
+            io_capabilities: io,
+            security_properties: AuthReq::new(BondingFlag::NoBonding),
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_legacy_pairing_method(
+                features(IoCapabilities::DisplayYesNo),
+                features(IoCapabilities::DisplayYesNo)
+            ),
+            PairingMethod::JustWorks
+        );
+        assert_eq!(
+            choose_legacy_pairing_method(
+                features(IoCapabilities::KeyboardDisplay),
+                features(IoCapabilities::DisplayYesNo)
+            ),
+            PairingMethod::PassKeyEntry {
+                central: PassKeyEntryAction::Input,
+                peripheral: PassKeyEntryAction::Display,
+            }
+        );
+        assert_eq!(
+            choose_legacy_pairing_method(
+                features(IoCapabilities::DisplayYesNo),
+                features(IoCapabilities::KeyboardDisplay)
+            ),
+            PairingMethod::PassKeyEntry {
+                central: PassKeyEntryAction::Display,
+                peripheral: PassKeyEntryAction::Input,
+            }
+        );
+        for c in 0u8..5 {
+            for p in 0u8..5 {
+                let method =
+                    choose_legacy_pairing_method(features(c.try_into().unwrap()), features(p.try_into().unwrap()));
+                assert_ne!(method, PairingMethod::NumericComparison);
+            }
+        }
+    }
+
     #[test]
     fn oob_used() {
-        for p_oob in 0..1 {
-            for c_oob in 0..1 {
+        for p_oob in 0..2 {
+            for c_oob in 0..2 {
                 let p_oob = if p_oob == 1 {
                     UseOutOfBand::Present
                 } else {
@@ -203,7 +466,7 @@ mod tests {
                             responder_key_distribution: 0.into(),
                             maximum_encryption_key_size: 16,
                         };
-                        let mut central = peripheral.clone();
+                        let mut central = peripheral;
                         central.use_oob = c_oob;
                         central.io_capabilities = c.try_into().unwrap();
                         if p_oob == UseOutOfBand::NotPresent && c_oob == UseOutOfBand::NotPresent {
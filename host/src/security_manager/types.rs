@@ -306,6 +306,50 @@ impl defmt::Format for PassKey {
     }
 }
 
+/// Keypress notification sent by a device that inputs the pass key
+// ([Vol 3] Part H, Section 3.5.8).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeypressNotification {
+    /// Pass key entry started
+    EntryStarted,
+    /// Pass key digit entered
+    DigitEntered,
+    /// Pass key digit erased
+    DigitErased,
+    /// Pass key cleared
+    Cleared,
+    /// Pass key entry completed
+    EntryCompleted,
+}
+
+impl From<KeypressNotification> for u8 {
+    fn from(value: KeypressNotification) -> u8 {
+        match value {
+            KeypressNotification::EntryStarted => 0,
+            KeypressNotification::DigitEntered => 1,
+            KeypressNotification::DigitErased => 2,
+            KeypressNotification::Cleared => 3,
+            KeypressNotification::EntryCompleted => 4,
+        }
+    }
+}
+
+impl TryFrom<u8> for KeypressNotification {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<KeypressNotification, Error> {
+        Ok(match value {
+            0 => KeypressNotification::EntryStarted,
+            1 => KeypressNotification::DigitEntered,
+            2 => KeypressNotification::DigitErased,
+            3 => KeypressNotification::Cleared,
+            4 => KeypressNotification::EntryCompleted,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
 pub enum AppEvent {
     PassKeyConfirm,
     PassKeyCancel,
@@ -420,7 +464,7 @@ const AUTH_REQ_CT2: u8 = 0b0010_0000;
 impl AuthReq {
     /// Build a AuthReq octet
     pub fn new(bonding: BondingFlag) -> Self {
-        AuthReq((bonding as u8) | AUTH_REQ_MITM | AUTH_REQ_SECURE_CONNECTION)
+        AuthReq((bonding as u8) | AUTH_REQ_MITM | AUTH_REQ_SECURE_CONNECTION | AUTH_REQ_KEY_PRESS)
     }
     /// Bond requested
     pub fn bond(&self) -> BondingFlag {
@@ -446,6 +490,10 @@ impl AuthReq {
     pub fn ct2(&self) -> bool {
         (self.0 & AUTH_REQ_CT2) == AUTH_REQ_CT2
     }
+    /// Announce support for the h7 function
+    pub(crate) fn set_ct2(&mut self) {
+        self.0 |= AUTH_REQ_CT2;
+    }
 }
 
 impl From<u8> for AuthReq {
@@ -805,6 +853,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn keypress_notification_variants() {
+        assert!(u8::from(KeypressNotification::EntryStarted) == 0);
+        assert!(u8::from(KeypressNotification::DigitEntered) == 1);
+        assert!(u8::from(KeypressNotification::DigitErased) == 2);
+        assert!(u8::from(KeypressNotification::Cleared) == 3);
+        assert!(u8::from(KeypressNotification::EntryCompleted) == 4);
+
+        assert!(KeypressNotification::EntryStarted == 0u8.try_into().unwrap());
+        assert!(KeypressNotification::DigitEntered == 1u8.try_into().unwrap());
+        assert!(KeypressNotification::DigitErased == 2u8.try_into().unwrap());
+        assert!(KeypressNotification::Cleared == 3u8.try_into().unwrap());
+        assert!(KeypressNotification::EntryCompleted == 4u8.try_into().unwrap());
+
+        for n in (u8::from(KeypressNotification::EntryCompleted) + 1)..u8::MAX {
+            assert!(KeypressNotification::try_from(n) == Err(Error::InvalidValue));
+        }
+    }
+
     #[test]
     fn command_variants() {
         assert!(u8::from(Command::PairingRequest) == 0x01);
@@ -1,12 +1,23 @@
-use bt_hci::{FixedSizeValue, WriteHci};
+use bt_hci::{FixedSizeValue, FromHciBytes, FromHciBytesError, WriteHci};
+use heapless::Vec;
 
 use crate::codec::Error;
+use crate::l2cap::{L2capConnectError, L2CAP_MAX_ENHANCED_CHANNELS};
 
 pub(crate) const L2CAP_CID_ATT: u16 = 0x0004;
 pub(crate) const L2CAP_CID_LE_U_SIGNAL: u16 = 0x0005;
 pub(crate) const L2CAP_CID_LE_U_SECURITY_MANAGER: u16 = 0x0006;
 pub(crate) const L2CAP_CID_DYN_START: u16 = 0x0040;
 
+/// PSM of the Enhanced ATT bearers.
+pub(crate) const L2CAP_PSM_EATT: u16 = 0x0027;
+
+/// Minimum MTU and MPS of an enhanced credit based channel.
+pub(crate) const L2CAP_ECFC_MIN_MTU: u16 = 64;
+
+/// Largest signaling packet (MTUsig) accepted on the LE signaling channel.
+pub(crate) const L2CAP_LE_SIGNAL_MTU: u16 = 23;
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -32,12 +43,12 @@ pub struct L2capSignalHeader {
 
 unsafe impl FixedSizeValue for L2capSignalHeader {
     fn is_valid(data: &[u8]) -> bool {
-        true
+        L2capSignalCode::try_from(data[0]).is_ok()
     }
 }
 
 #[cfg(not(feature = "defmt"))]
-pub trait L2capSignal: WriteHci + FixedSizeValue + core::fmt::Debug {
+pub trait L2capSignal: WriteHci + core::fmt::Debug {
     fn channel() -> u16 {
         L2CAP_CID_LE_U_SIGNAL
     }
@@ -45,7 +56,7 @@ pub trait L2capSignal: WriteHci + FixedSizeValue + core::fmt::Debug {
 }
 
 #[cfg(feature = "defmt")]
-pub trait L2capSignal: WriteHci + FixedSizeValue + defmt::Format {
+pub trait L2capSignal: WriteHci + defmt::Format {
     fn channel() -> u16 {
         L2CAP_CID_LE_U_SIGNAL
     }
@@ -106,6 +117,25 @@ impl TryFrom<u8> for L2capSignalCode {
     }
 }
 
+impl L2capSignalCode {
+    /// Whether this signal answers a request, and so carries the identifier of that request.
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            Self::CommandRejectRes
+                | Self::ConnectionRes
+                | Self::ConfigurationRes
+                | Self::DisconnectionRes
+                | Self::EchoRes
+                | Self::InformationRes
+                | Self::ConnParamUpdateRes
+                | Self::LeCreditConnRes
+                | Self::CreditConnRes
+                | Self::CreditConnReconfigRes
+        )
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -130,7 +160,7 @@ impl L2capSignal for LeCreditConnReq {
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum LeCreditConnResultCode {
     Success = 0x0000,
@@ -143,6 +173,47 @@ pub enum LeCreditConnResultCode {
     InvalidSourceId = 0x0009,
     ScidAlreadyAllocated = 0x000A,
     UnacceptableParameters = 0x000B,
+    /// Only used in enhanced credit based connection responses.
+    InvalidParameters = 0x000C,
+}
+
+impl LeCreditConnResultCode {
+    /// Reason of a refused request, `None` if the request succeeded.
+    pub(crate) fn refusal(self) -> Option<L2capConnectError> {
+        Some(match self {
+            Self::Success => return None,
+            Self::SpsmNotSupported => L2capConnectError::PsmNotSupported,
+            Self::NoResources => L2capConnectError::NoResources,
+            Self::InsufficientAuthentication => L2capConnectError::InsufficientAuthentication,
+            Self::InsufficientAuthorization => L2capConnectError::InsufficientAuthorization,
+            Self::EncryptionKeyTooShort => L2capConnectError::EncryptionKeyTooShort,
+            Self::InsufficientEncryption => L2capConnectError::InsufficientEncryption,
+            Self::InvalidSourceId => L2capConnectError::InvalidSourceId,
+            Self::ScidAlreadyAllocated => L2capConnectError::SourceIdAlreadyAllocated,
+            Self::UnacceptableParameters => L2capConnectError::UnacceptableParameters,
+            Self::InvalidParameters => L2capConnectError::InvalidParameters,
+        })
+    }
+}
+
+impl TryFrom<u16> for LeCreditConnResultCode {
+    type Error = Error;
+    fn try_from(val: u16) -> Result<Self, Error> {
+        Ok(match val {
+            0x0000 => Self::Success,
+            0x0002 => Self::SpsmNotSupported,
+            0x0004 => Self::NoResources,
+            0x0005 => Self::InsufficientAuthentication,
+            0x0006 => Self::InsufficientAuthorization,
+            0x0007 => Self::EncryptionKeyTooShort,
+            0x0008 => Self::InsufficientEncryption,
+            0x0009 => Self::InvalidSourceId,
+            0x000A => Self::ScidAlreadyAllocated,
+            0x000B => Self::UnacceptableParameters,
+            0x000C => Self::InvalidParameters,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -188,20 +259,359 @@ impl L2capSignal for LeCreditFlowInd {
     }
 }
 
+// Enhanced credit based signals and command rejects carry a variable list of values, so they
+// are encoded by hand rather than through `FixedSizeValue`.
+const ECFC_SIGNAL_MAX_SIZE: usize = 8 + 2 * L2CAP_MAX_ENHANCED_CHANNELS;
+
+fn encode_ecfc(fields: &[u16], cids: &[u16], buf: &mut [u8; ECFC_SIGNAL_MAX_SIZE]) -> usize {
+    let mut len = 0;
+    for value in fields.iter().chain(cids.iter()) {
+        buf[len..len + 2].copy_from_slice(&value.to_le_bytes());
+        len += 2;
+    }
+    len
+}
+
+fn decode_cids(data: &[u8]) -> Result<Vec<u16, L2CAP_MAX_ENHANCED_CHANNELS>, FromHciBytesError> {
+    if data.is_empty() || !data.len().is_multiple_of(2) {
+        return Err(FromHciBytesError::InvalidSize);
+    }
+    let mut cids = Vec::new();
+    for cid in data.chunks_exact(2) {
+        cids.push(u16::from_le_bytes([cid[0], cid[1]]))
+            .map_err(|_| FromHciBytesError::InvalidSize)?;
+    }
+    Ok(cids)
+}
+
+macro_rules! ecfc_signal {
+    ($ty:ident, $s:ident => [$($field:expr),*], $cids:ident) => {
+        impl WriteHci for $ty {
+            fn size(&self) -> usize {
+                let $s = self;
+                2 * ([$($field),*].len() + self.$cids.len())
+            }
+
+            fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+                let $s = self;
+                let mut buf = [0; ECFC_SIGNAL_MAX_SIZE];
+                let len = encode_ecfc(&[$($field),*], &self.$cids, &mut buf);
+                writer.write_all(&buf[..len])
+            }
+
+            async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+                let $s = self;
+                let mut buf = [0; ECFC_SIGNAL_MAX_SIZE];
+                let len = encode_ecfc(&[$($field),*], &self.$cids, &mut buf);
+                writer.write_all(&buf[..len]).await
+            }
+        }
+
+        impl L2capSignal for $ty {
+            fn code() -> L2capSignalCode {
+                L2capSignalCode::$ty
+            }
+        }
+    };
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+pub struct CreditConnReq {
+    pub spsm: u16,
+    pub mtu: u16,
+    pub mps: u16,
+    pub credits: u16,
+    pub scids: Vec<u16, L2CAP_MAX_ENHANCED_CHANNELS>,
+}
+
+ecfc_signal!(CreditConnReq, s => [s.spsm, s.mtu, s.mps, s.credits], scids);
+
+impl<'de> FromHciBytes<'de> for CreditConnReq {
+    fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+        let (spsm, data) = u16::from_hci_bytes(data)?;
+        let (mtu, data) = u16::from_hci_bytes(data)?;
+        let (mps, data) = u16::from_hci_bytes(data)?;
+        let (credits, data) = u16::from_hci_bytes(data)?;
+        let scids = decode_cids(data)?;
+        Ok((
+            Self {
+                spsm,
+                mtu,
+                mps,
+                credits,
+                scids,
+            },
+            &[],
+        ))
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+pub struct CreditConnRes {
+    pub mtu: u16,
+    pub mps: u16,
+    pub credits: u16,
+    pub result: LeCreditConnResultCode,
+    /// Destination channel ids in request order, 0 for each refused channel.
+    pub dcids: Vec<u16, L2CAP_MAX_ENHANCED_CHANNELS>,
+}
+
+ecfc_signal!(CreditConnRes, s => [s.mtu, s.mps, s.credits, s.result as u16], dcids);
+
+impl<'de> FromHciBytes<'de> for CreditConnRes {
+    fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+        let (mtu, data) = u16::from_hci_bytes(data)?;
+        let (mps, data) = u16::from_hci_bytes(data)?;
+        let (credits, data) = u16::from_hci_bytes(data)?;
+        let (result, data) = u16::from_hci_bytes(data)?;
+        let result = LeCreditConnResultCode::try_from(result).map_err(|_| FromHciBytesError::InvalidValue)?;
+        let dcids = decode_cids(data)?;
+        Ok((
+            Self {
+                mtu,
+                mps,
+                credits,
+                result,
+                dcids,
+            },
+            &[],
+        ))
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+pub struct CreditConnReconfigReq {
+    pub mtu: u16,
+    pub mps: u16,
+    /// Channel ids of the endpoints on the device sending the request.
+    pub dcids: Vec<u16, L2CAP_MAX_ENHANCED_CHANNELS>,
+}
+
+ecfc_signal!(CreditConnReconfigReq, s => [s.mtu, s.mps], dcids);
+
+impl<'de> FromHciBytes<'de> for CreditConnReconfigReq {
+    fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+        let (mtu, data) = u16::from_hci_bytes(data)?;
+        let (mps, data) = u16::from_hci_bytes(data)?;
+        let dcids = decode_cids(data)?;
+        Ok((Self { mtu, mps, dcids }, &[]))
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum CreditConnReconfigResultCode {
+    Success = 0x0000,
+    MtuReductionNotAllowed = 0x0001,
+    MpsReductionNotAllowed = 0x0002,
+    InvalidDestinationCid = 0x0003,
+    UnacceptableParameters = 0x0004,
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
+pub struct CreditConnReconfigRes {
+    pub result: u16,
+}
+
+unsafe impl FixedSizeValue for CreditConnReconfigRes {
+    fn is_valid(data: &[u8]) -> bool {
+        true
+    }
+}
+
+impl L2capSignal for CreditConnReconfigRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::CreditConnReconfigRes
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum CommandRejectReason {
+    CommandNotUnderstood = 0x0000,
+    SignalingMtuExceeded = 0x0001,
+    InvalidCid = 0x0002,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
 pub struct CommandRejectRes {
     pub reason: u16,
-    // TODO: Optional fields pub data: u16,
+    /// Reason specific data: the signaling MTU, or the local and remote channel ids.
+    pub data: Vec<u16, 2>,
+}
+
+ecfc_signal!(CommandRejectRes, s => [s.reason], data);
+
+impl CommandRejectRes {
+    pub fn new(reason: CommandRejectReason, data: &[u16]) -> Self {
+        Self {
+            reason: reason as u16,
+            data: unwrap!(Vec::from_slice(data)),
+        }
+    }
+}
+
+impl<'de> FromHciBytes<'de> for CommandRejectRes {
+    fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+        let (reason, mut rest) = u16::from_hci_bytes(data)?;
+        let mut values = Vec::new();
+        while !rest.is_empty() {
+            let (value, next) = u16::from_hci_bytes(rest)?;
+            values.push(value).map_err(|_| FromHciBytesError::InvalidSize)?;
+            rest = next;
+        }
+        Ok((Self { reason, data: values }, &[]))
+    }
+}
+
+/// Largest payload of an echo request that fits the signaling MTU.
+pub(crate) const L2CAP_ECHO_MAX_SIZE: usize = L2CAP_LE_SIGNAL_MTU as usize - 4;
+
+macro_rules! echo_signal {
+    ($ty:ident) => {
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        #[derive(Debug, Clone)]
+        pub struct $ty {
+            pub data: Vec<u8, L2CAP_ECHO_MAX_SIZE>,
+        }
+
+        impl WriteHci for $ty {
+            fn size(&self) -> usize {
+                self.data.len()
+            }
+
+            fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+                writer.write_all(&self.data)
+            }
+
+            async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+                writer.write_all(&self.data).await
+            }
+        }
+
+        impl<'de> FromHciBytes<'de> for $ty {
+            fn from_hci_bytes(data: &'de [u8]) -> Result<(Self, &'de [u8]), FromHciBytesError> {
+                let data = Vec::from_slice(data).map_err(|_| FromHciBytesError::InvalidSize)?;
+                Ok((Self { data }, &[]))
+            }
+        }
+
+        impl L2capSignal for $ty {
+            fn code() -> L2capSignalCode {
+                L2capSignalCode::$ty
+            }
+        }
+    };
+}
+
+echo_signal!(EchoReq);
+echo_signal!(EchoRes);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum InformationType {
+    ConnectionlessMtu = 0x0001,
+    ExtendedFeatures = 0x0002,
+    FixedChannels = 0x0003,
 }
 
-unsafe impl FixedSizeValue for CommandRejectRes {
+impl TryFrom<u16> for InformationType {
+    type Error = Error;
+    fn try_from(val: u16) -> Result<Self, Error> {
+        Ok(match val {
+            0x0001 => Self::ConnectionlessMtu,
+            0x0002 => Self::ExtendedFeatures,
+            0x0003 => Self::FixedChannels,
+            _ => return Err(Error::InvalidValue),
+        })
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InformationReq {
+    pub info_type: u16,
+}
+
+unsafe impl FixedSizeValue for InformationReq {
     fn is_valid(data: &[u8]) -> bool {
         true
     }
 }
 
+impl L2capSignal for InformationReq {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::InformationReq
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+pub struct InformationRes {
+    pub info_type: u16,
+    /// 0x0000 on success, 0x0001 if the information type is not supported.
+    pub result: u16,
+    pub data: Vec<u8, 8>,
+}
+
+impl InformationRes {
+    /// Answer an information request with what this host supports.
+    pub fn new(info_type: u16) -> Self {
+        let data: &[u8] = match InformationType::try_from(info_type) {
+            // Fixed channels supported and enhanced credit based flow control mode.
+            Ok(InformationType::ExtendedFeatures) => &0x0000_0280u32.to_le_bytes(),
+            // The ATT, LE signaling and security manager channels.
+            Ok(InformationType::FixedChannels) => &0x0000_0000_0000_0070u64.to_le_bytes(),
+            Ok(InformationType::ConnectionlessMtu) | Err(_) => {
+                return Self {
+                    info_type,
+                    result: 0x0001,
+                    data: Vec::new(),
+                }
+            }
+        };
+        Self {
+            info_type,
+            result: 0x0000,
+            data: unwrap!(Vec::from_slice(data)),
+        }
+    }
+}
+
+impl WriteHci for InformationRes {
+    fn size(&self) -> usize {
+        4 + self.data.len()
+    }
+
+    fn write_hci<W: embedded_io::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.info_type.to_le_bytes())?;
+        writer.write_all(&self.result.to_le_bytes())?;
+        writer.write_all(&self.data)
+    }
+
+    async fn write_hci_async<W: embedded_io_async::Write>(&self, mut writer: W) -> Result<(), W::Error> {
+        writer.write_all(&self.info_type.to_le_bytes()).await?;
+        writer.write_all(&self.result.to_le_bytes()).await?;
+        writer.write_all(&self.data).await
+    }
+}
+
+impl L2capSignal for InformationRes {
+    fn code() -> L2capSignalCode {
+        L2capSignalCode::InformationRes
+    }
+}
+
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
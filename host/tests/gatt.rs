@@ -221,3 +221,216 @@ async fn gatt_client_server() {
         }
     }
 }
+
+#[tokio::test]
+async fn gatt_enhanced_bearers() {
+    let _ = env_logger::try_init();
+    let adapters = common::find_controllers();
+    let peripheral = adapters[0].clone();
+    let central = adapters[1].clone();
+
+    let peripheral_address: Address = Address::random([0xff, 0x9f, 0x1a, 0x05, 0xe4, 0xfe]);
+
+    let local = tokio::task::LocalSet::new();
+
+    // Spawn peripheral
+    let peripheral = local.spawn_local(async move {
+        let controller_peripheral = common::create_controller(&peripheral).await;
+
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+        let stack = trouble_host::new(controller_peripheral, &mut resources).set_random_address(peripheral_address);
+        let Host {
+            mut peripheral,
+            mut runner,
+            ..
+        } = stack.build();
+
+        let mut first: [u8; 1] = [0; 1];
+        let mut second: [u8; 2] = [0; 2];
+
+        // The GAP and GATT services announce Enhanced ATT support to the client
+        let mut table: AttributeTable<'_, NoopRawMutex, 24> = AttributeTable::new();
+        GapConfig::default("trouble-eatt").build(&mut table).unwrap();
+
+        let mut svc = table.add_service(Service::new(SERVICE_UUID.clone()));
+        let _: Characteristic<u8> = svc
+            .add_characteristic(
+                VALUE_UUID.clone(),
+                &[CharacteristicProp::Read, CharacteristicProp::Write],
+                0x12,
+                &mut first[..],
+            )
+            .build();
+        let _: Characteristic<[u8; 2]> = svc
+            .add_characteristic(0x2a19u16, &[CharacteristicProp::Read], [0x34, 0x56], &mut second[..])
+            .build();
+        svc.build();
+
+        let server =
+            AttributeServer::<NoopRawMutex, DefaultPacketPool, 24, { GAP_SERVICE_CCCD_COUNT }, CONNECTIONS_MAX>::new(
+                table,
+            );
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let mut adv_data = [0; 31];
+                let adv_data_len = AdStructure::encode_slice(
+                    &[AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)],
+                    &mut adv_data[..],
+                ).unwrap();
+
+                println!("[peripheral] advertising");
+                let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
+                    adv_data: &adv_data[..adv_data_len],
+                    scan_data: &[],
+                }).await?;
+                let conn = acceptor.accept().await?.with_attribute_server(&server)?;
+                println!("[peripheral] connected");
+                loop {
+                    match conn.next().await {
+                        GattConnectionEvent::Disconnected { reason } => {
+                            println!("Disconnected: {:?}", reason);
+                            break;
+                        }
+                        GattConnectionEvent::Gatt { event: GattEvent::Write(event) } => {
+                            let enhanced = event.payload().is_enhanced();
+                            event.accept().unwrap().send().await;
+                            if enhanced {
+                                println!("[peripheral] write received over an enhanced bearer, test pass");
+                                // NOTE: Ensure that adapter gets polled again
+                                tokio::time::sleep(Duration::from_secs(2)).await;
+                                break;
+                            }
+                        }
+                        GattConnectionEvent::Gatt { event } => {
+                            event.accept().unwrap().send().await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
+
+    // Spawn central
+    let central = local.spawn_local(async move {
+        let controller_central = common::create_controller(&central).await;
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+        let stack = trouble_host::new(controller_central, &mut resources);
+        let Host {
+            mut central,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let config = ConnectConfig {
+                    connect_params: Default::default(),
+                    scan_config: ScanConfig {
+                        active: true,
+                        filter_accept_list: &[(peripheral_address.kind, &peripheral_address.addr)],
+                        ..Default::default()
+                    },
+                };
+
+                println!("[central] connecting");
+                let conn = central.connect(&config).await.unwrap();
+                println!("[central] connected");
+                tokio::time::sleep(Duration::from_secs(5)).await;
+
+                let client = GattClient::<common::Controller, DefaultPacketPool, 10>::new(&stack, &conn).await.unwrap();
+
+                select! {
+                    r = async {
+                        client.task().await
+                    } => {
+                        r
+                    }
+                    r = async {
+                        let opened = client.open_enhanced_bearers(2).await.unwrap();
+                        println!("[central] opened {} enhanced bearers", opened);
+                        assert_eq!(opened, 2);
+
+                        let services = client.services_by_uuid(&SERVICE_UUID).await.unwrap();
+                        let service = services.first().unwrap().clone();
+                        let first: Characteristic<u8> = client.characteristic_by_uuid(&service, &VALUE_UUID).await.unwrap();
+                        let second: Characteristic<[u8; 2]> =
+                            client.characteristic_by_uuid(&service, &Uuid::new_short(0x2a19)).await.unwrap();
+
+                        // Requests are outstanding on several bearers at the same time
+                        let mut a = [0; 1];
+                        let mut b = [0; 2];
+                        let (ra, rb) = tokio::join!(
+                            client.read_characteristic(&first, &mut a[..]),
+                            client.read_characteristic(&second, &mut b[..]),
+                        );
+                        assert_eq!(ra.unwrap(), 1);
+                        assert_eq!(rb.unwrap(), 2);
+                        assert_eq!(a, [0x12]);
+                        assert_eq!(b, [0x34, 0x56]);
+
+                        let mut a = [0; 1];
+                        let mut b = [0; 2];
+                        let lengths = client
+                            .read_multiple_variable(&[first.handle, second.handle], &mut [&mut a[..], &mut b[..]])
+                            .await
+                            .unwrap();
+                        assert_eq!(lengths, [1, 2]);
+                        assert_eq!(a, [0x12]);
+                        assert_eq!(b, [0x34, 0x56]);
+
+                        // Keep the fixed bearer busy, so the write goes over an enhanced one
+                        let (_, write) = tokio::join!(
+                            client.read_characteristic(&first, &mut a[..]),
+                            async {
+                                tokio::task::yield_now().await;
+                                client.write_characteristic(&first, &[0x13]).await
+                            },
+                        );
+                        write.unwrap();
+                        println!("[central] done");
+                        Ok(())
+                    } => {
+                        r
+                    }
+                }
+            } => {
+                r
+            }
+        }
+    });
+
+    match tokio::time::timeout(Duration::from_secs(30), local).await {
+        Ok(_) => match tokio::join!(central, peripheral) {
+            (Err(e1), Err(e2)) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Err(e), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Err(e)) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            _ => {
+                println!("Test completed successfully");
+            }
+        },
+        Err(e) => {
+            println!("Test timed out: {:?}", e);
+            panic!();
+        }
+    }
+}
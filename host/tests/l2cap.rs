@@ -26,15 +26,13 @@ async fn l2cap_connection_oriented_channels() {
         let controller_peripheral = common::create_controller(&peripheral).await;
 
         let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
-        let stack = trouble_host::new(controller_peripheral, &mut resources)
-            .set_random_address(peripheral_address);
+        let stack = trouble_host::new(controller_peripheral, &mut resources).set_random_address(peripheral_address);
         let Host {
             mut peripheral,
             mut runner,
             ..
         } = stack.build();
 
-
         select! {
             r = runner.run() => {
                 r
@@ -52,35 +50,204 @@ async fn l2cap_connection_oriented_channels() {
                     &mut scan_data[..],
                 ).unwrap();
 
-                loop {
-                    println!("[peripheral] advertising");
-                    let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
-                        adv_data: &adv_data[..adv_data_len],
-                        scan_data: &scan_data[..scan_data_len],
-                    }).await?;
-                    let conn = acceptor.accept().await?;
-                    println!("[peripheral] connected");
+                println!("[peripheral] advertising");
+                let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
+                    adv_data: &adv_data[..adv_data_len],
+                    scan_data: &scan_data[..scan_data_len],
+                }).await?;
+                let conn = acceptor.accept().await?;
+                println!("[peripheral] connected");
+
+                let mut ch1 = L2capChannel::accept(&stack, &conn, &[0x2349], &Default::default()).await?;
+
+                println!("[peripheral] channel created");
+
+                // Size of payload we're expecting
+                let mut rx = [0; PAYLOAD_LEN];
+                for i in 0..10 {
+                    let len = ch1.receive(&stack, &mut rx).await?;
+                    assert_eq!(len, rx.len());
+                    assert_eq!(rx, [i; PAYLOAD_LEN]);
+                }
+                println!("[peripheral] data received");
+
+                for i in 0..10 {
+                    let tx = [i; PAYLOAD_LEN];
+                    ch1.send(&stack, &tx).await?;
+                }
+                println!("[peripheral] data sent");
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
 
-                    let mut ch1 = L2capChannel::accept(&stack, &conn, &[0x2349], &Default::default()).await?;
+    // Spawn central
+    let central = local.spawn_local(async move {
+        let controller_central = common::create_controller(&central).await;
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+
+        let stack = trouble_host::new(controller_central, &mut resources);
+        let Host {
+            mut central,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let config = ConnectConfig {
+                    connect_params: Default::default(),
+                    scan_config: ScanConfig {
+                        active: true,
+                        filter_accept_list: &[(peripheral_address.kind, &peripheral_address.addr)],
+                        ..Default::default()
+                    },
+                };
 
-                    println!("[peripheral] channel created");
+                println!("[central] connecting");
+                let conn = central.connect(&config).await.unwrap();
+                println!("[central] connected");
+                let mut ch1 = L2capChannel::create(&stack, &conn, 0x2349, &Default::default()).await?;
+                println!("[central] channel created");
+                for i in 0..10 {
+                    let tx = [i; PAYLOAD_LEN];
+                    ch1.send(&stack, &tx).await?;
+                }
+                println!("[central] data sent");
+                let mut rx = [0; PAYLOAD_LEN];
+                for i in 0..10 {
+                    let len = ch1.receive(&stack, &mut rx).await?;
+                    assert_eq!(len, rx.len());
+                    assert_eq!(rx, [i; PAYLOAD_LEN]);
+                }
+                println!("[central] data received");
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
 
-                    // Size of payload we're expecting
-                    let mut rx = [0; PAYLOAD_LEN];
+    match tokio::time::timeout(Duration::from_secs(30), local).await {
+        Ok(_) => match tokio::join!(central, peripheral) {
+            (Err(e1), Err(e2)) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Err(e), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Err(e)) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            (Ok(Err(e1)), Ok(Err(e2))) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Ok(Err(e)), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Ok(Err(e))) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            _ => {
+                println!("Test completed successfully");
+            }
+        },
+        Err(e) => {
+            println!("Test timed out: {:?}", e);
+            panic!();
+        }
+    }
+}
+
+/// Verify l2cap enhanced credit based channels using two HCI adapters attached to the test machine.
+#[tokio::test]
+async fn l2cap_enhanced_credit_based_channels() {
+    let _ = env_logger::try_init();
+    let adapters = common::find_controllers();
+    let peripheral = adapters[0].clone();
+    let central = adapters[1].clone();
+
+    let peripheral_address: Address = Address::random([0xff, 0x9f, 0x1a, 0x05, 0xe4, 0xfe]);
+
+    let local = tokio::task::LocalSet::new();
+
+    const PAYLOAD_LEN: usize = 4;
+    const CHANNELS: usize = L2CAP_CHANNELS_MAX;
+
+    // Spawn peripheral
+    let peripheral = local.spawn_local(async move {
+        let controller_peripheral = common::create_controller(&peripheral).await;
+
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+        let stack = trouble_host::new(controller_peripheral, &mut resources).set_random_address(peripheral_address);
+        let Host {
+            mut peripheral,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let mut adv_data = [0; 31];
+                let adv_data_len = AdStructure::encode_slice(
+                    &[AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)],
+                    &mut adv_data[..],
+                ).unwrap();
+
+                let mut scan_data = [0; 31];
+                let scan_data_len = AdStructure::encode_slice(
+                    &[AdStructure::CompleteLocalName(b"trouble-l2cap-int")],
+                    &mut scan_data[..],
+                ).unwrap();
+
+                println!("[peripheral] advertising");
+                let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
+                    adv_data: &adv_data[..adv_data_len],
+                    scan_data: &scan_data[..scan_data_len],
+                }).await?;
+                let conn = acceptor.accept().await?;
+                println!("[peripheral] connected");
+
+                let mut channels = L2capChannel::accept_group(&stack, &conn, &[0x2349], &Default::default()).await?;
+                assert_eq!(channels.len(), CHANNELS);
+                println!("[peripheral] channels created");
+
+                // Each channel carries its own index in the payload.
+                let mut rx = [0; PAYLOAD_LEN];
+                for (n, ch) in channels.iter_mut().enumerate() {
                     for i in 0..10 {
-                        let len = ch1.receive(&stack, &mut rx).await?;
+                        let len = ch.receive(&stack, &mut rx).await?;
                         assert_eq!(len, rx.len());
-                        assert_eq!(rx, [i; PAYLOAD_LEN]);
+                        assert_eq!(rx, [n as u8 + i; PAYLOAD_LEN]);
                     }
-                    println!("[peripheral] data received");
+                }
+                println!("[peripheral] data received");
 
-                    for i in 0..10 {
-                        let tx = [i; PAYLOAD_LEN];
-                        ch1.send(&stack, &tx).await?;
-                    }
-                    println!("[peripheral] data sent");
-                    break;
+                // Signalled after the central has reconfigured its channels, so payloads may exceed the original MTU.
+                let len = channels[0].receive(&stack, &mut rx).await?;
+                assert_eq!(rx[..len], [0xff; PAYLOAD_LEN]);
+
+                let tx = [0xaa; 160];
+                for ch in channels.iter_mut() {
+                    ch.send(&stack, &tx).await?;
                 }
+                println!("[peripheral] data sent");
                 Ok(())
             } => {
                 r
@@ -115,25 +282,199 @@ async fn l2cap_connection_oriented_channels() {
                 };
 
                 println!("[central] connecting");
-                loop {
-                    let conn = central.connect(&config).await.unwrap();
-                    println!("[central] connected");
-                    let mut ch1 = L2capChannel::create(&stack, &conn, 0x2349, &Default::default()).await?;
-                    println!("[central] channel created");
-                    for i in 0..10 {
-                        let tx = [i; PAYLOAD_LEN];
-                        ch1.send(&stack, &tx).await?;
-                    }
-                    println!("[central] data sent");
-                    let mut rx = [0; PAYLOAD_LEN];
+                let conn = central.connect(&config).await.unwrap();
+                println!("[central] connected");
+                let channel_config = L2capChannelConfig {
+                    mtu: Some(128),
+                    mps: Some(128),
+                    ..Default::default()
+                };
+                let mut channels = L2capChannel::create_group(&stack, &conn, 0x2349, CHANNELS, &channel_config).await?;
+                assert_eq!(channels.len(), CHANNELS);
+                println!("[central] channels created");
+                for (n, ch) in channels.iter_mut().enumerate() {
                     for i in 0..10 {
-                        let len = ch1.receive(&stack, &mut rx).await?;
-                        assert_eq!(len, rx.len());
-                        assert_eq!(rx, [i; PAYLOAD_LEN]);
+                        let tx = [n as u8 + i; PAYLOAD_LEN];
+                        ch.send(&stack, &tx).await?;
                     }
-                    println!("[central] data received");
-                    break;
                 }
+                println!("[central] data sent");
+
+                L2capChannel::reconfigure(&stack, &channels, 200, 200).await?;
+                println!("[central] channels reconfigured");
+
+                // Reducing the MTU is never allowed.
+                assert!(L2capChannel::reconfigure(&stack, &channels, 100, 200).await.is_err());
+                channels[0].send(&stack, &[0xff; PAYLOAD_LEN]).await?;
+
+                let mut rx = [0; 200];
+                for ch in channels.iter_mut() {
+                    let len = ch.receive(&stack, &mut rx).await?;
+                    assert_eq!(len, 160);
+                    assert_eq!(rx[..len], [0xaa; 160]);
+                }
+                println!("[central] data received");
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
+
+    match tokio::time::timeout(Duration::from_secs(30), local).await {
+        Ok(_) => match tokio::join!(central, peripheral) {
+            (Err(e1), Err(e2)) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Err(e), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Err(e)) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            (Ok(Err(e1)), Ok(Err(e2))) => {
+                println!("Central error: {:?}", e1);
+                println!("Peripheral error: {:?}", e2);
+                panic!();
+            }
+            (Ok(Err(e)), _) => {
+                println!("Central error: {:?}", e);
+                panic!();
+            }
+            (_, Ok(Err(e))) => {
+                println!("Peripheral error: {:?}", e);
+                panic!();
+            }
+            _ => {
+                println!("Test completed successfully");
+            }
+        },
+        Err(e) => {
+            println!("Test timed out: {:?}", e);
+            panic!();
+        }
+    }
+}
+
+#[tokio::test]
+async fn l2cap_channel_authorization() {
+    let _ = env_logger::try_init();
+    let adapters = common::find_controllers();
+    let peripheral = adapters[0].clone();
+    let central = adapters[1].clone();
+
+    let peripheral_address: Address = Address::random([0xff, 0x9f, 0x1a, 0x05, 0xe4, 0xfd]);
+
+    let local = tokio::task::LocalSet::new();
+
+    const PAYLOAD_LEN: usize = 4;
+
+    // Spawn peripheral
+    let peripheral = local.spawn_local(async move {
+        let controller_peripheral = common::create_controller(&peripheral).await;
+
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+        let stack = trouble_host::new(controller_peripheral, &mut resources).set_random_address(peripheral_address);
+        let Host {
+            mut peripheral,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let mut adv_data = [0; 31];
+                let adv_data_len = AdStructure::encode_slice(
+                    &[AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED)],
+                    &mut adv_data[..],
+                ).unwrap();
+
+                println!("[peripheral] advertising");
+                let acceptor = peripheral.advertise(&Default::default(), Advertisement::ConnectableScannableUndirected {
+                    adv_data: &adv_data[..adv_data_len],
+                    scan_data: &[],
+                }).await?;
+                let conn = acceptor.accept().await?;
+                println!("[peripheral] connected");
+
+                // Requests for the first PSM are refused, the second one has no requirements.
+                let security = [(
+                    0x81,
+                    L2capSecurity {
+                        authorize: Some(|_| false),
+                        ..Default::default()
+                    },
+                )];
+                let config = L2capChannelConfig {
+                    security: &security,
+                    ..Default::default()
+                };
+                let mut ch = L2capChannel::accept(&stack, &conn, &[0x81, 0x83], &config).await?;
+                assert_eq!(ch.psm(), 0x83);
+                println!("[peripheral] channel created");
+
+                let mut rx = [0; PAYLOAD_LEN];
+                let len = ch.receive(&stack, &mut rx).await?;
+                assert_eq!(rx[..len], [0x55; PAYLOAD_LEN]);
+                println!("[peripheral] data received");
+                Ok(())
+            } => {
+                r
+            }
+        }
+    });
+
+    // Spawn central
+    let central = local.spawn_local(async move {
+        let controller_central = common::create_controller(&central).await;
+        let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> = HostResources::new();
+
+        let stack = trouble_host::new(controller_central, &mut resources);
+        let Host {
+            mut central,
+            mut runner,
+            ..
+        } = stack.build();
+
+        select! {
+            r = runner.run() => {
+                r
+            }
+            r = async {
+                let config = ConnectConfig {
+                    connect_params: Default::default(),
+                    scan_config: ScanConfig {
+                        active: true,
+                        filter_accept_list: &[(peripheral_address.kind, &peripheral_address.addr)],
+                        ..Default::default()
+                    },
+                };
+
+                println!("[central] connecting");
+                let conn = central.connect(&config).await.unwrap();
+                println!("[central] connected");
+
+                let refused = L2capChannel::create(&stack, &conn, 0x81, &Default::default()).await;
+                assert!(matches!(
+                    refused,
+                    Err(BleHostError::BleHost(Error::L2capConnectionRefused(
+                        L2capConnectError::InsufficientAuthorization
+                    )))
+                ));
+                println!("[central] channel refused");
+
+                let mut ch = L2capChannel::create(&stack, &conn, 0x83, &Default::default()).await?;
+                ch.send(&stack, &[0x55; PAYLOAD_LEN]).await?;
+                println!("[central] data sent");
+                // NOTE: Ensure that adapter gets polled again
+                tokio::time::sleep(Duration::from_secs(2)).await;
                 Ok(())
             } => {
                 r
@@ -146,28 +487,28 @@ async fn l2cap_connection_oriented_channels() {
             (Err(e1), Err(e2)) => {
                 println!("Central error: {:?}", e1);
                 println!("Peripheral error: {:?}", e2);
-                assert!(false);
+                panic!();
             }
             (Err(e), _) => {
                 println!("Central error: {:?}", e);
-                assert!(false)
+                panic!();
             }
             (_, Err(e)) => {
                 println!("Peripheral error: {:?}", e);
-                assert!(false)
+                panic!();
             }
             (Ok(Err(e1)), Ok(Err(e2))) => {
                 println!("Central error: {:?}", e1);
                 println!("Peripheral error: {:?}", e2);
-                assert!(false);
+                panic!();
             }
             (Ok(Err(e)), _) => {
                 println!("Central error: {:?}", e);
-                assert!(false)
+                panic!();
             }
             (_, Ok(Err(e))) => {
                 println!("Peripheral error: {:?}", e);
-                assert!(false)
+                panic!();
             }
             _ => {
                 println!("Test completed successfully");
@@ -175,7 +516,7 @@ async fn l2cap_connection_oriented_channels() {
         },
         Err(e) => {
             println!("Test timed out: {:?}", e);
-            assert!(false);
+            panic!();
         }
     }
 }
@@ -11,12 +11,21 @@ struct CustomService {
     long_uuid: f32,
     #[characteristic(uuid = "2a38", read, notify)]
     notify: [u8; 8],
+    #[characteristic(
+        uuid = "2a39",
+        read,
+        write,
+        read_security = "encrypted",
+        write_security = "authenticated",
+        write_authorization
+    )]
+    secure: u16,
     non_characteristic_field: u8,
 }
 
 #[tokio::test]
 async fn gatt_service_derive() {
-    let mut table: AttributeTable<NoopRawMutex, 10> = AttributeTable::new();
+    let mut table: AttributeTable<NoopRawMutex, 12> = AttributeTable::new();
     let service = CustomService::new(&mut table);
 
     // Check all fields of service have been generated and are accessible
@@ -25,4 +34,5 @@ async fn gatt_service_derive() {
     let _characteristic_short_uuid = service.short_uuid;
     let _characteristic_long_uuid = service.long_uuid;
     let _notify = service.notify;
+    let _secure = service.secure;
 }